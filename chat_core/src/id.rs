@@ -0,0 +1,299 @@
+//! Public identifiers of users, chats and messages. Keys stay `i64` internally, while the
+//! apis can expose them as opaque strings once a codec is installed with [`set_id_codec`],
+//! so clients can't enumerate or count them. Without a codec ids are plain numbers.
+//!
+//! Fields opt in with `#[serde(with = "chat_core::id")]` (or `id::list`, `id::option`,
+//! `id::option_list`),
+//! path params with [`PublicId`]. Once a codec is installed, paths and ids in strings must
+//! be public ids. Only fields still take JSON numbers, which the database notifications send.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, ops::Deref, sync::OnceLock};
+
+const ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// odd, so multiplying by it is a bijection on u64
+const MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+
+static CODEC: OnceLock<Box<dyn IdCodec>> = OnceLock::new();
+
+/// Turns ids into public identifiers and back.
+pub trait IdCodec: Send + Sync {
+    fn encode(&self, id: i64) -> String;
+    /// None if `id` wasn't produced by `encode`
+    fn decode(&self, id: &str) -> Option<i64>;
+}
+
+/// Scrambles ids with a secret key and writes them as 11 base62 characters. This hides
+/// the ids but is no encryption, don't rely on it for access control.
+#[derive(Debug, Clone)]
+pub struct IdObfuscator {
+    key: u64,
+}
+
+/// Id in a path, e.g. `Path(PublicId(id)): Path<PublicId>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicId(pub u64);
+
+/// Install the codec of the process, returns false if one is installed already.
+pub fn set_id_codec(codec: impl IdCodec + 'static) -> bool {
+    CODEC.set(Box::new(codec)).is_ok()
+}
+
+/// Id as clients see it.
+pub fn encode_id(id: i64) -> String {
+    match CODEC.get() {
+        Some(codec) => codec.encode(id),
+        None => id.to_string(),
+    }
+}
+
+/// Id from a client, a plain number only if no codec is installed.
+pub fn decode_id(id: &str) -> Option<i64> {
+    decode_with(CODEC.get().map(|codec| codec.as_ref()), id)
+}
+
+fn decode_with(codec: Option<&dyn IdCodec>, id: &str) -> Option<i64> {
+    match codec {
+        Some(codec) => codec.decode(id),
+        None => id.parse().ok(),
+    }
+}
+
+impl IdObfuscator {
+    pub fn new(key: u64) -> Self {
+        Self { key }
+    }
+}
+
+impl IdCodec for IdObfuscator {
+    fn encode(&self, id: i64) -> String {
+        let mut v = (id as u64).wrapping_mul(MULTIPLIER);
+        v ^= v >> 32;
+        v ^= self.key;
+        // fixed width, so no id is a plain number
+        let mut buf = [b'0'; 11];
+        for c in buf.iter_mut().rev() {
+            *c = ALPHABET[(v % 62) as usize];
+            v /= 62;
+        }
+        String::from_utf8(buf.to_vec()).expect("alphabet is ascii")
+    }
+
+    fn decode(&self, id: &str) -> Option<i64> {
+        if id.len() != 11 {
+            return None;
+        }
+        let mut v: u64 = 0;
+        for c in id.bytes() {
+            let digit = ALPHABET.iter().position(|&a| a == c)? as u64;
+            v = v.checked_mul(62)?.checked_add(digit)?;
+        }
+        v ^= self.key;
+        v ^= v >> 32;
+        Some(v.wrapping_mul(inverse(MULTIPLIER)) as i64)
+    }
+}
+
+// multiplicative inverse mod 2^64 by newton's method, each step doubles the correct bits
+const fn inverse(n: u64) -> u64 {
+    let mut inv = n;
+    let mut i = 0;
+    while i < 5 {
+        inv = inv.wrapping_mul(2u64.wrapping_sub(n.wrapping_mul(inv)));
+        i += 1;
+    }
+    inv
+}
+
+pub fn serialize<S: Serializer>(id: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    match CODEC.get() {
+        Some(codec) => serializer.serialize_str(&codec.encode(*id)),
+        None => serializer.serialize_i64(*id),
+    }
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    deserializer.deserialize_any(IdVisitor)
+}
+
+struct IdVisitor;
+
+impl<'de> de::Visitor<'de> for IdVisitor {
+    type Value = i64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an id")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<i64, E> {
+        Ok(v)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<i64, E> {
+        i64::try_from(v).map_err(|_| E::custom(format!("invalid id {v}")))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<i64, E> {
+        decode_id(v).ok_or_else(|| E::custom(format!("invalid id {v}")))
+    }
+}
+
+// the wrapped id, so the field helpers can serialize elements
+#[derive(Serialize, Deserialize)]
+struct Id(#[serde(with = "self")] i64);
+
+/// `#[serde(with = "chat_core::id::list")]` for `Vec<i64>`.
+pub mod list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(ids: &[i64], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(ids.iter().map(|id| Id(*id)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<i64>, D::Error> {
+        let ids = Vec::<Id>::deserialize(deserializer)?;
+        Ok(ids.into_iter().map(|id| id.0).collect())
+    }
+}
+
+/// `#[serde(with = "chat_core::id::option")]` for `Option<i64>`.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(id: &Option<i64>, serializer: S) -> Result<S::Ok, S::Error> {
+        id.map(Id).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<i64>, D::Error> {
+        let id = Option::<Id>::deserialize(deserializer)?;
+        Ok(id.map(|id| id.0))
+    }
+}
+
+/// `#[serde(default, with = "chat_core::id::option_list")]` for `Option<Vec<i64>>`.
+pub mod option_list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        ids: &Option<Vec<i64>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let ids: Option<Vec<_>> = ids
+            .as_ref()
+            .map(|ids| ids.iter().map(|id| Id(*id)).collect());
+        ids.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<i64>>, D::Error> {
+        let ids = Option::<Vec<Id>>::deserialize(deserializer)?;
+        Ok(ids.map(|ids| ids.into_iter().map(|id| id.0).collect()))
+    }
+}
+
+impl Deref for PublicId {
+    type Target = u64;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = deserializer.deserialize_any(PublicIdVisitor)?;
+        u64::try_from(id)
+            .map(Self)
+            .map_err(|_| de::Error::custom(format!("invalid id {id}")))
+    }
+}
+
+// unlike fields, paths never come from the database, so numbers are only plain ids
+struct PublicIdVisitor;
+
+impl<'de> de::Visitor<'de> for PublicIdVisitor {
+    type Value = i64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a public id")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<i64, E> {
+        match CODEC.get() {
+            Some(_) => Err(E::custom(format!("invalid id {v}"))),
+            None => Ok(v),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<i64, E> {
+        let v = i64::try_from(v).map_err(|_| E::custom(format!("invalid id {v}")))?;
+        self.visit_i64(v)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<i64, E> {
+        decode_id(v).ok_or_else(|| E::custom(format!("invalid id {v}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obfuscator_should_round_trip() {
+        let codec = IdObfuscator::new(0x5EED);
+        let mut seen = std::collections::HashSet::new();
+        for id in [0, 1, 2, 3, 1000, i64::MAX] {
+            let public = codec.encode(id);
+            assert_eq!(public.len(), 11);
+            assert_eq!(codec.decode(&public), Some(id));
+            assert!(seen.insert(public));
+        }
+        // consecutive ids don't look alike
+        assert_ne!(codec.encode(1)[..6], codec.encode(2)[..6]);
+        // another key gives other ids
+        assert_ne!(IdObfuscator::new(1).encode(1), codec.encode(1));
+        assert_eq!(codec.decode("1"), None);
+        assert_eq!(codec.decode("!!!!!!!!!!!"), None);
+    }
+
+    #[test]
+    fn decode_should_reject_plain_numbers_with_codec() {
+        let codec = IdObfuscator::new(0x5EED);
+        assert_eq!(decode_with(None, "42"), Some(42));
+        assert_eq!(decode_with(Some(&codec), "42"), None);
+        assert_eq!(decode_with(Some(&codec), &codec.encode(42)), Some(42));
+    }
+
+    #[test]
+    fn ids_should_accept_plain_numbers() -> anyhow::Result<()> {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Row {
+            #[serde(with = "super")]
+            id: i64,
+            #[serde(with = "list")]
+            members: Vec<i64>,
+            #[serde(with = "option")]
+            owner_id: Option<i64>,
+        }
+
+        let row: Row = serde_json::from_str(r#"{"id": 1, "members": [1, "2"], "owner_id": null}"#)?;
+        let expected = Row {
+            id: 1,
+            members: vec![1, 2],
+            owner_id: None,
+        };
+        assert_eq!(row, expected);
+        // without a codec ids stay numbers
+        assert_eq!(
+            serde_json::to_string(&row)?,
+            r#"{"id":1,"members":[1,2],"owner_id":null}"#
+        );
+        let id: PublicId = serde_json::from_str(r#""42""#)?;
+        assert_eq!(*id, 42);
+        Ok(())
+    }
+}
@@ -1,16 +1,19 @@
 mod utils;
 
+pub mod id;
 pub mod middlewares;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+pub use id::{decode_id, encode_id, set_id_codec, IdCodec, IdObfuscator, PublicId};
 pub use utils::*;
 use utoipa::ToSchema;
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 pub struct User {
+    #[serde(with = "id")]
     pub id: i64,
     pub ws_id: i64,
     pub fullname: String,
@@ -25,12 +28,30 @@ pub struct User {
 pub struct Workspace {
     pub id: i64,
     pub name: String,
+    #[serde(with = "id")]
     pub owner_id: i64,
+    #[sqlx(json)]
+    pub settings: WorkspaceSettings,
     pub created_at: DateTime<Utc>,
 }
 
+/// Branding and other per workspace preferences, so clients can theme themselves per tenant.
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct WorkspaceSettings {
+    /// url of the workspace logo, either an uploaded file or an external http(s) url
+    pub logo: Option<String>,
+    /// accent color in `#rrggbb` format
+    pub accent_color: Option<String>,
+    /// message shown to users when they join the workspace
+    pub welcome_message: Option<String>,
+    /// record every attachment download in the audit log
+    pub audit_file_access: bool,
+}
+
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 pub struct ChatUser {
+    #[serde(with = "id")]
     pub id: i64,
     pub fullname: String,
     pub email: String,
@@ -48,22 +69,121 @@ pub enum ChatType {
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 pub struct Chat {
+    #[serde(with = "id")]
     pub id: i64,
     pub ws_id: i64,
+    /// None if the owner left the chat
+    #[serde(with = "id::option")]
+    pub owner_id: Option<i64>,
     pub name: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// `/files/...` url of the avatar
+    #[serde(default)]
+    pub avatar_url: Option<String>,
     pub r#type: ChatType,
+    #[serde(with = "id::list")]
     pub members: Vec<i64>,
     pub created_at: DateTime<Utc>,
+    /// set while the chat is archived, no messages can be sent then
+    #[serde(default)]
+    pub archived_at: Option<DateTime<Utc>>,
+    /// set while the chat is scheduled for deletion, it is purged then
+    #[serde(default)]
+    #[sqlx(default)]
+    pub delete_at: Option<DateTime<Utc>>,
+    /// unique name of a channel in its workspace, see `/api/chats/by-handle`
+    #[serde(default)]
+    #[sqlx(default)]
+    pub handle: Option<String>,
+    /// pinned by the user listing the chats, always false outside of chat lists
+    #[serde(default)]
+    #[sqlx(default)]
+    pub pinned: bool,
+    /// messages of others the user listing the chats hasn't read, 0 outside of chat lists
+    #[serde(default)]
+    #[sqlx(default)]
+    pub unread_count: i64,
+    /// messages of the chat, kept up to date as they are sent and deleted
+    #[serde(default)]
+    #[sqlx(default)]
+    pub message_count: i64,
+    /// bumped by each update of the chat fields or members, sent back as `If-Match`
+    /// to not overwrite the updates of others
+    #[serde(default)]
+    #[sqlx(default)]
+    pub version: i64,
+    /// latest message of the chat, only set in chat lists
+    #[serde(default)]
+    #[sqlx(default, json)]
+    pub last_message: Option<LastMessage>,
+}
+
+/// Preview of the latest message of a chat, so chat lists can show it without
+/// fetching the messages.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct LastMessage {
+    #[serde(with = "id")]
+    pub id: i64,
+    #[serde(with = "id")]
+    pub sender_id: i64,
+    pub sender_name: String,
+    /// start of the content, longer ones are cut
+    pub content: String,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
 pub struct Message {
+    #[serde(with = "id")]
     pub id: i64,
+    #[serde(with = "id")]
     pub chat_id: i64,
+    #[serde(with = "id")]
     pub sender_id: i64,
     pub content: String,
     pub files: Vec<String>,
     pub created_at: DateTime<Utc>,
+    /// rendition in the chat's translation language, for readers of another language
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<MessageTranslation>,
+}
+
+/// Machine translation of a message, generated after the message is sent.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct MessageTranslation {
+    /// detected language of the message, e.g. `en`
+    pub source_lang: String,
+    pub lang: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "task_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Open,
+    Done,
+}
+
+/// An action item created from a message, kept attached to it.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Task {
+    pub id: i64,
+    #[serde(with = "id")]
+    pub chat_id: i64,
+    #[serde(with = "id")]
+    pub message_id: i64,
+    pub title: String,
+    #[serde(with = "id::option")]
+    pub assignee_id: Option<i64>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub status: TaskStatus,
+    #[serde(with = "id")]
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
 }
 
 impl User {
@@ -1,4 +1,5 @@
 use super::TokenVerify;
+use crate::User;
 use axum::{
     extract::{FromRequestParts, Query, Request, State},
     http::StatusCode,
@@ -21,6 +22,10 @@ pub async fn verify_token<T>(State(state): State<T>, req: Request, next: Next) -
 where
     T: TokenVerify + Clone + Send + Sync + 'static,
 {
+    // already authenticated by an outer layer, e.g. with a personal access token
+    if req.extensions().get::<User>().is_some() {
+        return next.run(req).await;
+    }
     let (mut parts, body) = req.into_parts();
     let token =
         match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
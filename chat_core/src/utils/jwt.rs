@@ -1,12 +1,24 @@
 use crate::User;
 use jwt_simple::prelude::*;
 
-const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
+/// lifetime of session tokens
+pub const JWT_DURATION: u64 = 60 * 60 * 24 * 7;
 const JWT_ISS: &str = "chat_server";
 const JWT_AUD: &str = "chat_web";
+/// stream tokens only need to outlive opening the event stream
+pub const STREAM_TOKEN_DURATION: u64 = 60;
+const STREAM_TOKEN_AUD: &str = "chat_stream";
 
 pub struct EncodingKey(Ed25519KeyPair);
 
+// the user of a stream token and the origin of the page it was issued to, if any
+#[derive(Serialize, Deserialize)]
+struct StreamClaims {
+    #[serde(flatten)]
+    user: User,
+    origin: Option<String>,
+}
+
 #[allow(unused)]
 pub struct DecodingKey(Ed25519PublicKey);
 
@@ -16,10 +28,35 @@ impl EncodingKey {
     }
 
     pub fn sign(&self, user: impl Into<User>) -> Result<String, jwt_simple::Error> {
-        let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(JWT_DURATION));
+        self.sign_with_duration(user, JWT_DURATION)
+    }
+
+    /// Sign a token valid for `secs` seconds, e.g. for accounts that expire early.
+    pub fn sign_with_duration(
+        &self,
+        user: impl Into<User>,
+        secs: u64,
+    ) -> Result<String, jwt_simple::Error> {
+        let claims = Claims::with_custom_claims(user.into(), Duration::from_secs(secs));
         let claims = claims.with_issuer(JWT_ISS).with_audience(JWT_AUD);
         self.0.sign(claims)
     }
+
+    /// Sign a short-lived token which is only good for opening an event stream, so pages
+    /// can put it in an `EventSource` url instead of their session token.
+    pub fn sign_stream_token(
+        &self,
+        user: impl Into<User>,
+        origin: Option<&str>,
+    ) -> Result<String, jwt_simple::Error> {
+        let claims = StreamClaims {
+            user: user.into(),
+            origin: origin.map(|o| o.to_string()),
+        };
+        let claims = Claims::with_custom_claims(claims, Duration::from_secs(STREAM_TOKEN_DURATION));
+        let claims = claims.with_issuer(JWT_ISS).with_audience(STREAM_TOKEN_AUD);
+        self.0.sign(claims)
+    }
 }
 
 impl DecodingKey {
@@ -38,6 +75,28 @@ impl DecodingKey {
         let claims = self.0.verify_token::<User>(token, Some(opts))?;
         Ok(claims.custom)
     }
+
+    /// Verify a stream token, `origin` is the `Origin` header of the request and has to
+    /// match the origin the token was issued to.
+    pub fn verify_stream_token(
+        &self,
+        token: &str,
+        origin: Option<&str>,
+    ) -> Result<User, jwt_simple::Error> {
+        let opts = VerificationOptions {
+            allowed_issuers: Some(HashSet::from_strings(&[JWT_ISS])),
+            allowed_audiences: Some(HashSet::from_strings(&[STREAM_TOKEN_AUD])),
+            ..Default::default()
+        };
+
+        let claims = self.0.verify_token::<StreamClaims>(token, Some(opts))?;
+        match claims.custom.origin {
+            Some(expected) if origin != Some(expected.as_str()) => Err(jwt_simple::Error::msg(
+                "stream token was issued to another origin",
+            )),
+            _ => Ok(claims.custom.user),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -60,4 +119,33 @@ mod tests {
         assert_eq!(user, user2);
         Ok(())
     }
+
+    #[test]
+    fn stream_token_should_be_bound_to_origin() -> Result<()> {
+        let ek = EncodingKey::load(include_str!("../../fixtures/encoding.pem"))?;
+        let dk = DecodingKey::load(include_str!("../../fixtures/decoding.pem"))?;
+        let user = User::new(1, "Tyr Chen", "tchen@acme.org");
+
+        let token = ek.sign_stream_token(user.clone(), Some("https://chat.acme.org"))?;
+        assert_eq!(
+            dk.verify_stream_token(&token, Some("https://chat.acme.org"))?,
+            user
+        );
+        assert!(dk
+            .verify_stream_token(&token, Some("https://evil.org"))
+            .is_err());
+        assert!(dk.verify_stream_token(&token, None).is_err());
+        // only good for streams, and session tokens aren't stream tokens
+        assert!(dk.verify(&token).is_err());
+        let session = ek.sign(user.clone())?;
+        assert!(dk.verify_stream_token(&session, None).is_err());
+
+        // tokens of clients without an origin work anywhere
+        let token = ek.sign_stream_token(user.clone(), None)?;
+        assert_eq!(
+            dk.verify_stream_token(&token, Some("https://evil.org"))?,
+            user
+        );
+        Ok(())
+    }
 }
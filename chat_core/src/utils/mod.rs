@@ -1,3 +1,3 @@
 mod jwt;
 
-pub use jwt::{DecodingKey, EncodingKey};
+pub use jwt::{DecodingKey, EncodingKey, JWT_DURATION, STREAM_TOKEN_DURATION};
@@ -0,0 +1,441 @@
+use crate::{config::BackupTarget, mailer::sign_v4, AppConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+use tracing::info;
+
+const MANIFEST: &str = "manifest.json";
+
+/// What a backup holds, stored encrypted next to the objects it lists.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    /// e.g. `chat-20240701T090000Z`, the objects of the backup are stored under it
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// plain sql dump of the database
+    pub database: BackupObject,
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupObject {
+    pub key: String,
+    /// sha256 of the decrypted object, checked before anything is restored
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupFile {
+    pub root: FileRoot,
+    /// path relative to the root
+    pub path: String,
+    #[serde(flatten)]
+    pub object: BackupObject,
+}
+
+/// Directories of stored files which are backed up.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileRoot {
+    /// `server.base_dir`, uploaded files
+    Files,
+    /// `storage.cold_dir`, archived workspaces
+    Cold,
+}
+
+// encrypts objects with AES-256-GCM, each with a random nonce and its key as associated
+// data, so objects can't be swapped within or between backups
+struct Sealer {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+enum Store {
+    Dir(PathBuf),
+    S3(S3Store),
+}
+
+struct S3Store {
+    client: reqwest::Client,
+    bucket: String,
+    region: String,
+    host: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+/// Take a backup of the database and the stored files, encrypt and upload it to the
+/// configured target. The dump is a consistent snapshot of the database. Files are
+/// content addressed and listed after the dump, so every file it refers to is included.
+pub async fn create_backup(config: &AppConfig) -> Result<BackupManifest> {
+    let sealer = Sealer::new(config)?;
+    let store = Store::new(&config.backup.target);
+    let created_at = Utc::now();
+    let name = format!("chat-{}", created_at.format("%Y%m%dT%H%M%SZ"));
+
+    info!("Dumping the database for backup {}", name);
+    let output = Command::new("pg_dump")
+        .args(["--no-owner", "--no-privileges", "--clean", "--if-exists"])
+        .arg("--dbname")
+        .arg(&config.server.db_url)
+        .output()
+        .context("failed to run pg_dump")?;
+    if !output.status.success() {
+        bail!(
+            "pg_dump failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let database = upload(&store, &sealer, format!("{name}/database"), output.stdout).await?;
+
+    let mut files = vec![];
+    for (root, dir) in roots(config) {
+        for path in list_files(&dir)? {
+            let rel = path
+                .strip_prefix(&dir)
+                .expect("listed file should be in its root")
+                .to_string_lossy()
+                .to_string();
+            let key = format!("{name}/files/{}", files.len());
+            let object = upload(&store, &sealer, key, fs::read(&path)?).await?;
+            files.push(BackupFile {
+                root,
+                path: rel,
+                object,
+            });
+        }
+    }
+    info!("Uploaded {} files for backup {}", files.len(), name);
+
+    let manifest = BackupManifest {
+        name,
+        created_at,
+        database,
+        files,
+    };
+    let key = format!("{}/{MANIFEST}", manifest.name);
+    let data = sealer.seal(&key, serde_json::to_vec(&manifest)?)?;
+    store.put(&key, data).await?;
+    Ok(manifest)
+}
+
+/// Download the backup `name` and check every object against the manifest, without
+/// restoring or keeping anything.
+pub async fn verify_backup(config: &AppConfig, name: &str) -> Result<BackupManifest> {
+    open_backup(config, name, |_, _| Ok(())).await
+}
+
+/// Replace the database with the one of backup `name` and write back its files, once all
+/// of it is verified. The dump is restored in a single transaction, files which are not
+/// in the backup are kept.
+pub async fn restore_backup(config: &AppConfig, name: &str) -> Result<BackupManifest> {
+    // only readable by us, and removed with the decrypted objects whatever fails
+    let dir = tempfile::Builder::new()
+        .prefix("chat_restore_")
+        .tempdir()
+        .context("failed to create the staging directory")?;
+    let staging = dir.path();
+    let manifest = open_backup(config, name, |i, data| {
+        fs::write(staging.join(i.to_string()), data)?;
+        Ok(())
+    })
+    .await?;
+
+    info!("Restoring the database of backup {}", name);
+    let output = Command::new("psql")
+        .args(["--quiet", "--single-transaction", "-v", "ON_ERROR_STOP=1"])
+        .arg("--dbname")
+        .arg(&config.server.db_url)
+        .arg("--file")
+        .arg(staging.join("0"))
+        .output()
+        .context("failed to run psql")?;
+    if !output.status.success() {
+        bail!("psql failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let roots = roots(config);
+    for (i, file) in manifest.files.iter().enumerate() {
+        let (_, dir) = roots
+            .iter()
+            .find(|(root, _)| *root == file.root)
+            .expect("all roots should be configured");
+        let path = dir.join(&file.path);
+        fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+        fs::copy(staging.join((i + 1).to_string()), path)?;
+    }
+    Ok(manifest)
+}
+
+// download, decrypt and check each object of the backup, the database first and then the
+// files in manifest order, and hand it to `each` with its index
+async fn open_backup(
+    config: &AppConfig,
+    name: &str,
+    mut each: impl FnMut(usize, Vec<u8>) -> Result<()>,
+) -> Result<BackupManifest> {
+    if !is_relative(name) {
+        bail!("invalid backup name {name}");
+    }
+    let sealer = Sealer::new(config)?;
+    let store = Store::new(&config.backup.target);
+    let key = format!("{name}/{MANIFEST}");
+    let manifest: BackupManifest =
+        serde_json::from_slice(&sealer.open(&key, store.get(&key).await?)?)?;
+
+    for file in &manifest.files {
+        if !is_relative(&file.path) {
+            bail!("backup {} has an invalid file path {}", name, file.path);
+        }
+    }
+
+    let objects =
+        std::iter::once(&manifest.database).chain(manifest.files.iter().map(|f| &f.object));
+    for (i, object) in objects.enumerate() {
+        let data = sealer.open(&object.key, store.get(&object.key).await?)?;
+        let sha256 = hex::encode(Sha256::digest(&data));
+        if sha256 != object.sha256 || data.len() as u64 != object.size {
+            bail!("object {} of backup {} is corrupted", object.key, name);
+        }
+        each(i, data)?;
+    }
+    Ok(manifest)
+}
+
+async fn upload(
+    store: &Store,
+    sealer: &Sealer,
+    key: String,
+    data: Vec<u8>,
+) -> Result<BackupObject> {
+    let sha256 = hex::encode(Sha256::digest(&data));
+    let size = data.len() as u64;
+    store.put(&key, sealer.seal(&key, data)?).await?;
+    Ok(BackupObject { key, sha256, size })
+}
+
+fn roots(config: &AppConfig) -> Vec<(FileRoot, PathBuf)> {
+    vec![
+        (FileRoot::Files, config.server.base_dir.clone()),
+        (FileRoot::Cold, config.storage.cold_dir.clone()),
+    ]
+}
+
+// files below `dir`, none if it doesn't exist yet
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut ret = vec![];
+    if !dir.exists() {
+        return Ok(ret);
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            ret.extend(list_files(&path)?);
+        } else {
+            ret.push(path);
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+fn is_relative(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().count() > 0 && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+impl Sealer {
+    fn new(config: &AppConfig) -> Result<Self> {
+        let key = config
+            .backup
+            .key
+            .as_deref()
+            .ok_or_else(|| anyhow!("backup.key is not configured"))?;
+        let key = hex::decode(key.trim()).context("backup.key should be hex encoded")?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_| anyhow!("backup.key should be 32 bytes"))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    // the nonce followed by the encrypted data and its tag
+    fn seal(&self, key: &str, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("failed to generate a nonce"))?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(key.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| anyhow!("failed to encrypt {key}"))?;
+        let mut ret = nonce.to_vec();
+        ret.extend(data);
+        Ok(ret)
+    }
+
+    fn open(&self, key: &str, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            bail!("object {key} is truncated");
+        }
+        let mut sealed = data.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&data).expect("nonce should have its length");
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::from(key.as_bytes()), &mut sealed)
+            .map_err(|_| anyhow!("object {key} can't be decrypted, wrong key or corrupted"))?
+            .len();
+        sealed.truncate(len);
+        Ok(sealed)
+    }
+}
+
+impl Store {
+    fn new(target: &BackupTarget) -> Self {
+        match target {
+            BackupTarget::Dir { path } => Self::Dir(path.clone()),
+            BackupTarget::S3 {
+                bucket,
+                region,
+                endpoint,
+                access_key_id,
+                secret_access_key,
+            } => Self::S3(S3Store {
+                client: reqwest::Client::new(),
+                bucket: bucket.clone(),
+                region: region.clone(),
+                host: endpoint
+                    .clone()
+                    .unwrap_or_else(|| format!("s3.{region}.amazonaws.com")),
+                access_key_id: access_key_id.clone(),
+                secret_access_key: secret_access_key.clone(),
+            }),
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Dir(dir) => {
+                let path = dir.join(key);
+                fs::create_dir_all(path.parent().expect("object path parent should exists"))?;
+                fs::write(path, data)?;
+            }
+            Self::S3(s3) => {
+                let res = s3.request("PUT", key, &data).body(data).send().await?;
+                if !res.status().is_success() {
+                    bail!("failed to upload {key}: {}", res.status());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Dir(dir) => {
+                fs::read(dir.join(key)).with_context(|| format!("failed to read {key}"))
+            }
+            Self::S3(s3) => {
+                let res = s3.request("GET", key, &[]).send().await?;
+                if !res.status().is_success() {
+                    bail!("failed to download {key}: {}", res.status());
+                }
+                Ok(res.bytes().await?.to_vec())
+            }
+        }
+    }
+}
+
+impl S3Store {
+    // path style request, keys only have characters which need no escaping
+    fn request(&self, method: &str, key: &str, payload: &[u8]) -> reqwest::RequestBuilder {
+        let path = format!("/{}/{key}", self.bucket);
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let content_sha256 = hex::encode(Sha256::digest(payload));
+        let headers = [
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", content_sha256.as_str()),
+            ("x-amz-date", amz_date.as_str()),
+        ];
+        let authorization = sign_v4(
+            &self.access_key_id,
+            &self.secret_access_key,
+            &self.region,
+            "s3",
+            (method, &path),
+            &headers,
+            payload,
+            now,
+        );
+        let method =
+            reqwest::Method::from_bytes(method.as_bytes()).expect("method should be valid");
+        self.client
+            .request(method, format!("https://{}{path}", self.host))
+            .header("x-amz-content-sha256", content_sha256)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealer() -> Sealer {
+        Sealer {
+            key: LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[7; 32]).unwrap()),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    #[test]
+    fn sealed_object_should_only_open_under_its_key() -> Result<()> {
+        let sealer = sealer();
+        let sealed = sealer.seal("b/files/0", b"hello".to_vec())?;
+        assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + 5], b"hello");
+        assert_eq!(sealer.open("b/files/0", sealed.clone())?, b"hello");
+        assert!(sealer.open("b/files/1", sealed.clone()).is_err());
+
+        let mut tampered = sealed;
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(sealer.open("b/files/0", tampered).is_err());
+        assert!(sealer.open("b/files/0", vec![1, 2]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn restored_paths_should_stay_in_their_root() {
+        assert!(is_relative("1/abc/def/0123.png"));
+        assert!(!is_relative("../etc/passwd"));
+        assert!(!is_relative("/etc/passwd"));
+        assert!(!is_relative("1/../../etc"));
+        assert!(!is_relative(""));
+    }
+
+    #[tokio::test]
+    async fn backup_names_should_stay_in_the_target() -> Result<()> {
+        let config = AppConfig::load()?;
+        for name in ["../chat-20240701T090000Z", "/tmp/chat", ""] {
+            let err = verify_backup(&config, name).await.unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid backup name {name}"));
+        }
+        Ok(())
+    }
+}
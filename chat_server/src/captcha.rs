@@ -0,0 +1,119 @@
+use crate::{config::CaptchaProvider, AppConfig, AppError};
+use serde::Deserialize;
+use std::{future::Future, pin::Pin, sync::Arc};
+
+pub type CaptchaFuture = Pin<Box<dyn Future<Output = Result<bool, AppError>> + Send>>;
+
+const HCAPTCHA_VERIFY_URL: &str = "https://api.hcaptcha.com/siteverify";
+const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+/// Checks the captcha token a client solved before signing up, one implementation per
+/// provider in `signup.captcha`. Returns false if the token isn't valid.
+pub trait CaptchaVerifier: Send + Sync + 'static {
+    fn verify(&self, token: &str, remote_ip: Option<&str>) -> CaptchaFuture;
+}
+
+pub struct DisabledCaptcha;
+
+/// hCaptcha and Turnstile share the siteverify api, only their endpoints differ.
+pub struct SiteVerifyCaptcha {
+    url: String,
+    secret: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl CaptchaVerifier for DisabledCaptcha {
+    fn verify(&self, _token: &str, _remote_ip: Option<&str>) -> CaptchaFuture {
+        Box::pin(async { Ok(true) })
+    }
+}
+
+impl SiteVerifyCaptcha {
+    pub fn new(url: &str, secret: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            secret: secret.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl CaptchaVerifier for SiteVerifyCaptcha {
+    fn verify(&self, token: &str, remote_ip: Option<&str>) -> CaptchaFuture {
+        if token.is_empty() {
+            return Box::pin(async { Ok(false) });
+        }
+        let mut form = vec![
+            ("secret", self.secret.clone()),
+            ("response", token.to_string()),
+        ];
+        if let Some(ip) = remote_ip {
+            form.push(("remoteip", ip.to_string()));
+        }
+        let req = self.client.post(&self.url).form(&form);
+        Box::pin(async move {
+            let res = req.send().await.map_err(anyhow::Error::from)?;
+            if !res.status().is_success() {
+                let status = res.status();
+                return Err(AppError::AnyError(anyhow::anyhow!(
+                    "captcha verification failed: {status}"
+                )));
+            }
+            let res: SiteVerifyResponse = res.json().await.map_err(anyhow::Error::from)?;
+            Ok(res.success)
+        })
+    }
+}
+
+/// Build the verifier configured in `signup.captcha`.
+pub(crate) fn build_captcha_verifier(config: &AppConfig) -> Arc<dyn CaptchaVerifier> {
+    match &config.signup.captcha {
+        CaptchaProvider::Disabled => Arc::new(DisabledCaptcha),
+        CaptchaProvider::Hcaptcha { secret, verify_url } => Arc::new(SiteVerifyCaptcha::new(
+            verify_url.as_deref().unwrap_or(HCAPTCHA_VERIFY_URL),
+            secret,
+        )),
+        CaptchaProvider::Turnstile { secret, verify_url } => Arc::new(SiteVerifyCaptcha::new(
+            verify_url.as_deref().unwrap_or(TURNSTILE_VERIFY_URL),
+            secret,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{routing::post, Form, Json, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn site_verify_captcha_should_check_token() -> Result<()> {
+        let app = Router::new().route(
+            "/siteverify",
+            post(|Form(form): Form<HashMap<String, String>>| async move {
+                let valid = form.get("secret").map(|s| s.as_str()) == Some("s3cret")
+                    && form.get("response").map(|s| s.as_str()) == Some("solved");
+                Json::<Value>(json!({ "success": valid }))
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let url = format!("http://{addr}/siteverify");
+        let captcha = SiteVerifyCaptcha::new(&url, "s3cret");
+        assert!(captcha.verify("solved", Some("10.0.0.1")).await?);
+        assert!(!captcha.verify("forged", None).await?);
+        assert!(!captcha.verify("", None).await?);
+        assert!(DisabledCaptcha.verify("", None).await?);
+        Ok(())
+    }
+}
@@ -1,18 +1,314 @@
-use std::{env, fs::File, path::PathBuf};
+use std::{collections::HashMap, env, fs::File, path::PathBuf};
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub jobs: JobConfig,
+    #[serde(default)]
+    pub chat: ChatConfig,
+    #[serde(default)]
+    pub mail: MailConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub translation: TranslationConfig,
+    #[serde(default)]
+    pub realtime: RealtimeConfig,
+    #[serde(default)]
+    pub signup: SignupConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    /// rollouts of features with a canary implementation, by feature name
+    #[serde(default)]
+    pub features: HashMap<String, FeatureConfig>,
+}
+
+/// Which workspaces get the canary implementation of a feature.
+#[derive(Debug, Clone, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeatureConfig {
+    /// share of the workspaces in the canary cohort, from 0 to 100
+    pub percent: u8,
+    /// workspaces always in the canary cohort, e.g. our own
+    pub workspaces: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// archived workspaces are compressed and moved here, usually a cheaper volume
+    pub cold_dir: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            cold_dir: PathBuf::from("/tmp/chat_server_cold"),
+        }
+    }
+}
+
+/// Encrypted backups taken by the `backup` subcommand and read back by `restore`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// hex encoded 32 byte AES-256-GCM key, no backups can be taken or restored without it
+    pub key: Option<String>,
+    pub target: BackupTarget,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            key: None,
+            target: BackupTarget::Dir {
+                path: PathBuf::from("/tmp/chat_server_backups"),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackupTarget {
+    /// a local directory, e.g. a mounted volume
+    Dir { path: PathBuf },
+    /// an S3 compatible bucket
+    S3 {
+        bucket: String,
+        region: String,
+        /// host of the service, `s3.<region>.amazonaws.com` by default
+        endpoint: Option<String>,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MailConfig {
+    /// sender of all outgoing emails, e.g. `Chat <noreply@acme.org>`
+    pub from: String,
+    pub provider: MailProvider,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            from: "Chat <noreply@localhost>".to_string(),
+            provider: MailProvider::Log,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MailProvider {
+    /// only log emails, for development and tests
+    Log,
+    Smtp {
+        host: String,
+        #[serde(default = "default_smtp_port")]
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+        /// upgrade the connection with STARTTLS, only disable for local relays
+        #[serde(default = "default_true")]
+        starttls: bool,
+    },
+    /// Amazon SES v2 http api
+    Ses {
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+    },
+}
+
+/// notify_server nodes listed by `GET /api/realtime/endpoints`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RealtimeConfig {
+    pub endpoints: Vec<RealtimeEndpointConfig>,
+    /// how long the probed health of an endpoint is reused
+    pub health_ttl_secs: u64,
+}
+
+impl Default for RealtimeConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![],
+            health_ttl_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RealtimeEndpointConfig {
+    /// base url of the node, its `/status` is probed for health
+    pub url: String,
+    /// e.g. `us-east`, clients in the same region are sent there first
+    pub region: String,
+}
+
+/// Protection of `POST /api/signup` for instances open to the public.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SignupConfig {
+    pub captcha: CaptchaProvider,
+    pub throttle: SignupThrottle,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaptchaProvider {
+    /// signups don't need a captcha token
+    #[default]
+    Disabled,
+    Hcaptcha {
+        secret: String,
+        /// siteverify endpoint, hCaptcha's by default
+        verify_url: Option<String>,
+    },
+    /// Cloudflare Turnstile
+    Turnstile {
+        secret: String,
+        /// siteverify endpoint, Cloudflare's by default
+        verify_url: Option<String>,
+    },
+}
+
+/// Permission decisions beyond the built-in rules of the chat roles.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicyConfig {
+    pub hook: PolicyHookConfig,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PolicyHookConfig {
+    /// only the built-in rules decide
+    #[default]
+    Disabled,
+    /// Open Policy Agent, or a service answering like its data api, e.g. for cedar
+    Opa {
+        /// e.g. `http://localhost:8181/v1/data/chat/allow`
+        url: String,
+    },
+}
+
+/// Signups allowed within `window_secs`, None for no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SignupThrottle {
+    /// from one client address
+    pub max_per_ip: Option<u32>,
+    /// with emails of one domain, e.g. `acme.org`
+    pub max_per_domain: Option<u32>,
+    pub window_secs: u64,
+}
+
+impl Default for SignupThrottle {
+    fn default() -> Self {
+        Self {
+            max_per_ip: Some(10),
+            max_per_domain: None,
+            window_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TranslationConfig {
+    pub provider: TranslationProvider,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranslationProvider {
+    /// chats can't be auto translated
+    #[default]
+    Disabled,
+    /// a LibreTranslate compatible api, e.g. `https://libretranslate.com`
+    Libre {
+        url: String,
+        api_key: Option<String>,
+    },
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatConfig {
+    /// default and upper bound of the per chat pin limit
+    pub max_pins: u32,
+    /// days a deleted chat can be restored before it is purged with its messages
+    #[serde(default = "default_deleted_retention_days")]
+    pub deleted_retention_days: u32,
+    /// hours members are warned before a chat its owner deletes is purged
+    #[serde(default = "default_deletion_grace_hours")]
+    pub deletion_grace_hours: u32,
+    /// most members a chat of each type can have, single chats always have two
+    #[serde(default)]
+    pub max_members: MemberLimits,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MemberLimits {
+    pub group: u32,
+    pub private_channel: u32,
+    pub public_channel: u32,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_pins: 50,
+            deleted_retention_days: default_deleted_retention_days(),
+            deletion_grace_hours: default_deletion_grace_hours(),
+            max_members: MemberLimits::default(),
+        }
+    }
+}
+
+impl Default for MemberLimits {
+    fn default() -> Self {
+        Self {
+            group: 500,
+            private_channel: 10_000,
+            public_channel: 10_000,
+        }
+    }
+}
+
+fn default_deleted_retention_days() -> u32 {
+    30
+}
+
+fn default_deletion_grace_hours() -> u32 {
+    72
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub sk: String,
     pub pk: String,
+    /// user ids allowed to use the admin api
+    #[serde(default)]
+    pub admins: Vec<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -20,6 +316,55 @@ pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
     pub base_dir: PathBuf,
+    /// start in maintenance mode, rejecting all writes
+    #[serde(default)]
+    pub maintenance: bool,
+    /// expose users, chats and messages by opaque ids made with this key instead of their
+    /// numbers. notify_server needs the same key
+    #[serde(default)]
+    pub public_id_key: Option<u64>,
+    /// addresses or networks in CIDR notation of the reverse proxies in front of the
+    /// server, `X-Forwarded-For` is only read from their requests
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// number of workers polling the job queue, 0 disables the embedded workers
+    pub workers: usize,
+    /// how long an idle worker sleeps before polling again
+    pub poll_interval_ms: u64,
+    /// a running job not updated for this long is considered abandoned and retried
+    pub stale_after_secs: u64,
+    pub max_attempts: u32,
+    /// hours between runs of the orphaned data collector
+    #[serde(default = "default_orphan_interval_hours")]
+    pub orphan_interval_hours: u64,
+    /// hour of the day, in UTC, demo workspaces are reset to their snapshot at
+    #[serde(default = "default_demo_reset_hour")]
+    pub demo_reset_hour: u32,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            workers: 2,
+            poll_interval_ms: 1000,
+            stale_after_secs: 300,
+            max_attempts: 5,
+            orphan_interval_hours: default_orphan_interval_hours(),
+            demo_reset_hour: default_demo_reset_hour(),
+        }
+    }
+}
+
+fn default_orphan_interval_hours() -> u64 {
+    24
+}
+
+fn default_demo_reset_hour() -> u32 {
+    3
 }
 
 impl AppConfig {
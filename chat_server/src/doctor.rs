@@ -0,0 +1,285 @@
+use crate::AppConfig;
+use chat_core::{DecodingKey, EncodingKey, User};
+use sqlx::{migrate::Migrator, postgres::PgListener, postgres::PgPoolOptions, PgPool};
+use std::{collections::HashMap, fmt, fs, time::Duration};
+
+static MIGRATOR: Migrator = sqlx::migrate!("../migrations");
+
+const DB_TIMEOUT: Duration = Duration::from_secs(5);
+// channels notify_server listens on, see migrations/*_triggers.sql, members changes are sent
+// by chat_server itself
+const EVENT_CHANNELS: &[&str] = &[
+    "chat_updated",
+    "chat_members_changed",
+    "chat_message_created",
+];
+const EVENT_TRIGGERS: &[&str] = &[
+    "chat_created_trigger",
+    "chat_updated_trigger",
+    "chat_deleted_trigger",
+    "add_to_message_trigger",
+];
+
+/// Result of a single doctor check.
+#[derive(Debug)]
+pub struct Diagnosis {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+    /// what the operator should do if the check failed
+    pub hint: Option<&'static str>,
+}
+
+impl Diagnosis {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            ok: true,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Self {
+            name,
+            ok: false,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.ok { " ok " } else { "fail" };
+        write!(f, "[{}] {}: {}", status, self.name, self.detail)?;
+        if let Some(hint) = self.hint {
+            write!(f, "\n       hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run all startup checks against the given config. The database dependent checks
+/// are skipped if the database is not reachable.
+pub async fn diagnose(config: &AppConfig) -> Vec<Diagnosis> {
+    let mut ret = vec![
+        Diagnosis::ok(
+            "config",
+            format!(
+                "loaded, port {}, {} job worker(s)",
+                config.server.port, config.jobs.workers
+            ),
+        ),
+        check_keys(config),
+        check_storage(config),
+    ];
+
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(DB_TIMEOUT)
+        .connect(&config.server.db_url)
+        .await;
+    match pool {
+        Ok(pool) => {
+            ret.push(Diagnosis::ok("database", "connected"));
+            ret.push(check_migrations(&pool).await);
+            ret.push(check_event_bus(config, &pool).await);
+        }
+        Err(e) => ret.push(Diagnosis::fail(
+            "database",
+            format!("connect failed: {}", e),
+            "check server.db_url and that postgres is running and reachable",
+        )),
+    }
+
+    ret
+}
+
+fn check_keys(config: &AppConfig) -> Diagnosis {
+    const NAME: &str = "jwt keys";
+    let ek = match EncodingKey::load(&config.auth.sk) {
+        Ok(ek) => ek,
+        Err(e) => {
+            return Diagnosis::fail(
+                NAME,
+                format!("load sk failed: {}", e),
+                "auth.sk must be an Ed25519 private key in PEM format",
+            )
+        }
+    };
+    let dk = match DecodingKey::load(&config.auth.pk) {
+        Ok(dk) => dk,
+        Err(e) => {
+            return Diagnosis::fail(
+                NAME,
+                format!("load pk failed: {}", e),
+                "auth.pk must be an Ed25519 public key in PEM format",
+            )
+        }
+    };
+
+    let user = User::new(0, "doctor", "doctor@none.org");
+    match ek.sign(user).and_then(|token| dk.verify(&token)) {
+        Ok(_) => Diagnosis::ok(NAME, "sign/verify round trip succeeded"),
+        Err(e) => Diagnosis::fail(
+            NAME,
+            format!("token signed by sk can't be verified by pk: {}", e),
+            "auth.sk and auth.pk must be the same key pair (also check notify_server's pk)",
+        ),
+    }
+}
+
+fn check_storage(config: &AppConfig) -> Diagnosis {
+    const NAME: &str = "storage";
+    let base_dir = &config.server.base_dir;
+    let probe = base_dir.join(".doctor");
+    let ret = fs::create_dir_all(base_dir)
+        .and_then(|_| fs::write(&probe, b"ok"))
+        .and_then(|_| fs::remove_file(&probe));
+    match ret {
+        Ok(_) => Diagnosis::ok(NAME, format!("{} is writable", base_dir.display())),
+        Err(e) => Diagnosis::fail(
+            NAME,
+            format!("{} is not writable: {}", base_dir.display(), e),
+            "make sure server.base_dir exists and is writable by the chat_server user",
+        ),
+    }
+}
+
+async fn check_migrations(pool: &PgPool) -> Diagnosis {
+    const NAME: &str = "migrations";
+    let applied: Result<Vec<(i64, Vec<u8>)>, _> =
+        sqlx::query_as("SELECT version, checksum FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await;
+    let applied: HashMap<_, _> = match applied {
+        Ok(v) => v.into_iter().collect(),
+        Err(e) => {
+            return Diagnosis::fail(
+                NAME,
+                format!("can't read migration status: {}", e),
+                "run `sqlx migrate run` to initialize the database",
+            )
+        }
+    };
+
+    let mut pending = vec![];
+    let mut modified = vec![];
+    for m in MIGRATOR.iter() {
+        match applied.get(&m.version) {
+            None => pending.push(m.version.to_string()),
+            Some(checksum) if checksum[..] != m.checksum[..] => {
+                modified.push(m.version.to_string())
+            }
+            _ => {}
+        }
+    }
+
+    if !modified.is_empty() {
+        Diagnosis::fail(
+            NAME,
+            format!("applied migrations were modified: {}", modified.join(", ")),
+            "migrations must not be edited after they are applied, restore the original files",
+        )
+    } else if !pending.is_empty() {
+        Diagnosis::fail(
+            NAME,
+            format!("pending migrations: {}", pending.join(", ")),
+            "run `sqlx migrate run` before starting the server",
+        )
+    } else {
+        Diagnosis::ok(NAME, format!("{} applied, up to date", applied.len()))
+    }
+}
+
+async fn check_event_bus(config: &AppConfig, pool: &PgPool) -> Diagnosis {
+    const NAME: &str = "event bus";
+    let triggers: Result<Vec<(String,)>, _> =
+        sqlx::query_as("SELECT tgname::text FROM pg_trigger WHERE tgname = ANY($1)")
+            .bind(EVENT_TRIGGERS)
+            .fetch_all(pool)
+            .await;
+    match triggers {
+        Ok(v) if v.len() == EVENT_TRIGGERS.len() => {}
+        Ok(_) => {
+            return Diagnosis::fail(
+                NAME,
+                "notification triggers are missing",
+                "run `sqlx migrate run`, notify_server won't receive any events without them",
+            )
+        }
+        Err(e) => {
+            return Diagnosis::fail(
+                NAME,
+                format!("can't read triggers: {}", e),
+                "check the database user can read pg_trigger",
+            )
+        }
+    }
+
+    let listen = async {
+        let mut listener = PgListener::connect(&config.server.db_url).await?;
+        listener.listen_all(EVENT_CHANNELS.iter().copied()).await
+    };
+    match tokio::time::timeout(DB_TIMEOUT, listen).await {
+        Ok(Ok(_)) => Diagnosis::ok(
+            NAME,
+            format!("listening on {} succeeded", EVENT_CHANNELS.join(", ")),
+        ),
+        Ok(Err(e)) => Diagnosis::fail(
+            NAME,
+            format!("LISTEN failed: {}", e),
+            "notify_server needs a direct (non-pooled) postgres connection for LISTEN/NOTIFY",
+        ),
+        Err(_) => Diagnosis::fail(
+            NAME,
+            "LISTEN timed out",
+            "notify_server needs a direct (non-pooled) postgres connection for LISTEN/NOTIFY",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn check_keys_should_detect_mismatched_pair() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        assert!(check_keys(&config).ok);
+
+        // a valid public key from another key pair
+        config.auth.pk = "-----BEGIN PUBLIC KEY-----\nMCowBQYDK2VwAyEAcyF3phLB0wnGaT2JmF5YC+LF7wcua3h33B42HUriAWY=\n-----END PUBLIC KEY-----\n".to_string();
+        let ret = check_keys(&config);
+        assert!(!ret.ok);
+        assert!(ret.hint.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn diagnose_should_pass_on_migrated_db() -> Result<()> {
+        let (tdb, _state) = crate::AppState::new_for_test().await?;
+        let mut config = AppConfig::load()?;
+        config.server.db_url = tdb.url();
+
+        let ret = diagnose(&config).await;
+        assert_eq!(ret.len(), 6);
+        for d in ret {
+            assert!(d.ok, "{}", d);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn check_storage_should_work() -> Result<()> {
+        let mut config = AppConfig::load()?;
+        assert!(check_storage(&config).ok);
+
+        config.server.base_dir = "/proc/chat_server".into();
+        assert!(!check_storage(&config).ok);
+        Ok(())
+    }
+}
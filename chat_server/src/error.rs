@@ -1,4 +1,4 @@
-use axum::http::StatusCode;
+use axum::http::{header::RETRY_AFTER, HeaderValue, StatusCode};
 use axum::response::Json;
 use axum::response::{IntoResponse, Response};
 use serde::{Deserialize, Serialize};
@@ -8,6 +8,20 @@ use utoipa::ToSchema;
 #[derive(Debug, ToSchema, Serialize, Deserialize)]
 pub struct ErrorOutput {
     pub error: String,
+    /// seconds to wait before retrying, also sent as `Retry-After`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// the invalid fields of the request body, in english
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// What is wrong with one field of a request body.
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct FieldError {
+    /// e.g. `members`
+    pub field: String,
+    pub message: String,
 }
 
 #[derive(Error, Debug)]
@@ -18,15 +32,90 @@ pub enum AppError {
     #[error("create chat error: {0}")]
     ChatDTOError(String),
 
+    #[error("create chat error: {}", join_messages(.0))]
+    ChatFieldsError(Vec<FieldError>),
+
+    #[error("workspace error: {0}")]
+    WorkspaceError(String),
+
     #[error("create message error: {0}")]
     CreateMessageError(String),
 
     #[error("{0}")]
     ChatFileError(String),
 
+    #[error("pin error: {0}")]
+    PinError(String),
+
+    #[error("pin limit reached: {0}")]
+    PinLimitReached(String),
+
+    #[error("member limit reached: {0}")]
+    MemberLimitReached(String),
+
+    #[error("reaction error: {0}")]
+    ReactionError(String),
+
+    #[error("task error: {0}")]
+    TaskError(String),
+
+    #[error("webhook error: {0}")]
+    WebhookError(String),
+
+    #[error("token error: {0}")]
+    TokenError(String),
+
+    #[error("feature error: {0}")]
+    FeatureError(String),
+
+    #[error("slow mode: wait {0} seconds before sending another message")]
+    SlowMode(u64),
+
+    #[error("too many signups: try again in {0} seconds")]
+    SignupThrottled(u64),
+
+    #[error("captcha error: {0}")]
+    CaptchaError(String),
+
+    #[error("idempotency error: {0}")]
+    IdempotencyError(String),
+
+    #[error("version conflict: {0}")]
+    VersionConflict(String),
+
+    #[error("invalid cursor: {0}")]
+    InvalidCursor(String),
+
     #[error("Not found: {0}")]
     NotFound(String),
 
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("guest error: {0}")]
+    GuestError(String),
+
+    #[error("invite error: {0}")]
+    InviteError(String),
+
+    #[error("join request error: {0}")]
+    JoinRequestError(String),
+
+    #[error("draft error: {0}")]
+    DraftError(String),
+
+    #[error("metadata error: {0}")]
+    MetadataError(String),
+
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("{0}")]
+    ServiceUnavailable(String),
+
     #[error("io error: {0}")]
     IoError(#[from] std::io::Error),
 
@@ -47,10 +136,29 @@ impl ErrorOutput {
     pub fn new(error: impl Into<String>) -> Self {
         Self {
             error: error.into(),
+            retry_after: None,
+            fields: vec![],
         }
     }
 }
 
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+fn join_messages(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.message.as_str())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response<axum::body::Body> {
         let status = match &self {
@@ -60,12 +168,46 @@ impl IntoResponse for AppError {
             Self::HttpHeaderError(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::EmailAlreadyExists(_) => StatusCode::CONFLICT,
             Self::ChatDTOError(_) => StatusCode::BAD_REQUEST,
+            Self::ChatFieldsError(_) => StatusCode::BAD_REQUEST,
+            Self::WorkspaceError(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidCursor(_) => StatusCode::BAD_REQUEST,
+            Self::IdempotencyError(_) => StatusCode::CONFLICT,
+            Self::VersionConflict(_) => StatusCode::CONFLICT,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::QuotaExceeded(_) => StatusCode::PAYMENT_REQUIRED,
+            Self::GuestError(_) => StatusCode::BAD_REQUEST,
+            Self::InviteError(_) => StatusCode::BAD_REQUEST,
+            Self::JoinRequestError(_) => StatusCode::BAD_REQUEST,
+            Self::DraftError(_) => StatusCode::BAD_REQUEST,
+            Self::MetadataError(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
             Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
+            Self::PinError(_) => StatusCode::BAD_REQUEST,
+            Self::ReactionError(_) => StatusCode::BAD_REQUEST,
+            Self::TaskError(_) => StatusCode::BAD_REQUEST,
+            Self::WebhookError(_) => StatusCode::BAD_REQUEST,
+            Self::TokenError(_) => StatusCode::BAD_REQUEST,
+            Self::FeatureError(_) => StatusCode::BAD_REQUEST,
+            Self::PinLimitReached(_) => StatusCode::CONFLICT,
+            Self::MemberLimitReached(_) => StatusCode::CONFLICT,
+            Self::SlowMode(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::SignupThrottled(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::CaptchaError(_) => StatusCode::BAD_REQUEST,
         };
 
-        (status, Json(ErrorOutput::new(self.to_string()))).into_response()
+        let mut output = ErrorOutput::new(self.to_string());
+        if let Self::SlowMode(secs) | Self::SignupThrottled(secs) = self {
+            output.retry_after = Some(secs);
+            let headers = [(RETRY_AFTER, HeaderValue::from(secs))];
+            return (status, headers, Json(output)).into_response();
+        }
+        if let Self::ChatFieldsError(fields) = self {
+            output.fields = fields;
+        }
+        (status, Json(output)).into_response()
     }
 }
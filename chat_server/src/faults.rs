@@ -0,0 +1,110 @@
+//! Fault injection for resilience tests, only built with the `test-util` feature. A share of
+//! the api requests and background jobs fails as if the database was unreachable, so tests
+//! can check that clients and the job queue recover.
+//!
+//! Failures are spread evenly instead of randomly, with 25% every 4th call fails, so
+//! tests can assert exact counts.
+
+use crate::{AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+#[derive(Debug, Default)]
+pub struct Faults {
+    db_error_percent: AtomicU8,
+    calls: AtomicU64,
+}
+
+impl Faults {
+    fn should_fail_db(&self) -> bool {
+        let percent = self.db_error_percent.load(Ordering::Relaxed) as u64;
+        if percent == 0 {
+            return false;
+        }
+        let call = self.calls.fetch_add(1, Ordering::Relaxed);
+        // the n-th failure is due once n / percent of the 100 calls have passed
+        (call + 1) * percent / 100 > call * percent / 100
+    }
+}
+
+impl AppState {
+    /// Fail `percent` (0 to 100) of the api requests and jobs from now on, 0 turns it off.
+    pub fn set_db_error_percent(&self, percent: u8) {
+        self.faults
+            .db_error_percent
+            .store(percent.min(100), Ordering::Relaxed);
+        self.faults.calls.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn inject_db_fault(&self) -> Result<(), AppError> {
+        if self.faults.should_fail_db() {
+            return Err(AppError::SqlxError(sqlx::Error::PoolTimedOut));
+        }
+        Ok(())
+    }
+}
+
+pub(crate) async fn inject_faults(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    match state.inject_db_fault() {
+        Ok(()) => next.run(req).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_router, Job, JobFuture, JobHandler, JobRunner, JobStatus};
+    use anyhow::Result;
+    use axum::{body::Body, http::StatusCode};
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    struct NoopJob;
+
+    impl JobHandler for NoopJob {
+        fn kind(&self) -> &'static str {
+            "noop"
+        }
+
+        fn run(&self, _state: AppState, _job: Job) -> JobFuture {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn db_faults_should_fail_a_share_of_calls() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.set_db_error_percent(25);
+        let failed = (0..100)
+            .filter(|_| state.inject_db_fault().is_err())
+            .count();
+        assert_eq!(failed, 25);
+
+        state.set_db_error_percent(100);
+        let app = get_router(state.clone()).await?;
+        let req = Request::get("/api/signin").body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // a failed job is retried later
+        let job = state.enqueue_job("noop", json!({}), None).await?;
+        let runner = JobRunner::new(state.clone()).register(NoopJob);
+        assert!(runner.run_once().await?);
+        let job = state.get_job_by_id(job.id as _).await?.unwrap();
+        assert_eq!(job.status, JobStatus::Pending);
+        assert!(job.last_error.is_some());
+
+        state.set_db_error_percent(0);
+        assert!(state.inject_db_fault().is_ok());
+        Ok(())
+    }
+}
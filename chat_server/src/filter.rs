@@ -0,0 +1,407 @@
+//! Filter expressions of webhook registrations, e.g.
+//! `event = message.created and chat in (1, 2) and not sender ~ "*@bots.acme.org"`.
+//!
+//! Conditions are `field op value`, combined with `and`, `or`, `not` and parentheses:
+//! - `chat = 1`, `chat != 1`, `chat in (1, 2)`
+//! - `event = message.created`, `event in (message.created, reaction.added)`, the workspace
+//!   membership events `user.joined` and `user.role_changed` have no chat and no content
+//! - `sender = 3`, `sender in (3, 4)`, `sender ~ "*@acme.org"` (glob on the email)
+//! - `content contains "deploy"` (case insensitive), `content ~ "deploy *"`
+//!
+//! An empty expression matches every event. At most `MAX_FILTER_DEPTH` `not`s and
+//! parentheses can be nested.
+
+use crate::AppError;
+use std::fmt;
+
+const MAX_FILTER_DEPTH: usize = 32;
+
+/// A compiled filter expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    All,
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Chat(Vec<i64>),
+    Event(Vec<String>),
+    Sender(Vec<i64>),
+    SenderEmail(String),
+    Contains(String),
+    ContentLike(String),
+}
+
+/// What a filter is matched against.
+#[derive(Debug, Clone, Copy)]
+pub struct EventContext<'a> {
+    pub event: &'a str,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub sender_email: &'a str,
+    pub content: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+    Eq,
+    Ne,
+    Like,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> Result<Self, AppError> {
+        let tokens = tokenize(input)?;
+        if tokens.is_empty() {
+            return Ok(Self::All);
+        }
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let filter = parser.or()?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(t) => Err(invalid(format!("unexpected {t} after the expression"))),
+        }
+    }
+
+    pub fn matches(&self, ctx: &EventContext) -> bool {
+        match self {
+            Self::All => true,
+            Self::And(a, b) => a.matches(ctx) && b.matches(ctx),
+            Self::Or(a, b) => a.matches(ctx) || b.matches(ctx),
+            Self::Not(f) => !f.matches(ctx),
+            Self::Chat(ids) => ids.contains(&ctx.chat_id),
+            Self::Event(events) => events.iter().any(|e| e == ctx.event),
+            Self::Sender(ids) => ids.contains(&ctx.sender_id),
+            Self::SenderEmail(pattern) => glob_match(pattern, &ctx.sender_email.to_lowercase()),
+            Self::Contains(keyword) => ctx.content.to_lowercase().contains(keyword),
+            Self::ContentLike(pattern) => glob_match(pattern, &ctx.content.to_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Word(w) => write!(f, "`{w}`"),
+            Self::Str(s) => write!(f, "\"{s}\""),
+            Self::LParen => f.write_str("`(`"),
+            Self::RParen => f.write_str("`)`"),
+            Self::Comma => f.write_str("`,`"),
+            Self::Eq => f.write_str("`=`"),
+            Self::Ne => f.write_str("`!=`"),
+            Self::Like => f.write_str("`~`"),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // `not`s and parentheses the parser is in
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, AppError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| invalid("unexpected end of the expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Filter, AppError> {
+        let mut filter = self.and()?;
+        while self.keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, AppError> {
+        let mut filter = self.unary()?;
+        while self.keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, AppError> {
+        if self.keyword("not") {
+            return self.nested(|p| Ok(Filter::Not(Box::new(p.unary()?))));
+        }
+        if self.peek() == Some(&Token::LParen) {
+            self.pos += 1;
+            return self.nested(|p| {
+                let filter = p.or()?;
+                match p.next()? {
+                    Token::RParen => Ok(filter),
+                    t => Err(invalid(format!("expected `)` but found {t}"))),
+                }
+            });
+        }
+        self.condition()
+    }
+
+    fn nested(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<Filter, AppError>,
+    ) -> Result<Filter, AppError> {
+        if self.depth == MAX_FILTER_DEPTH {
+            return Err(invalid(format!(
+                "more than {MAX_FILTER_DEPTH} nested `not`s or parentheses"
+            )));
+        }
+        self.depth += 1;
+        let filter = f(self);
+        self.depth -= 1;
+        filter
+    }
+
+    fn condition(&mut self) -> Result<Filter, AppError> {
+        let field = match self.next()? {
+            Token::Word(w) => w.to_lowercase(),
+            t => return Err(invalid(format!("expected a field but found {t}"))),
+        };
+        let op = self.next()?;
+        let filter = match (field.as_str(), &op) {
+            ("chat", Token::Eq | Token::Ne) => Filter::Chat(vec![self.number()?]),
+            ("chat", Token::Word(w)) if w.eq_ignore_ascii_case("in") => {
+                Filter::Chat(self.list(Self::number)?)
+            }
+            ("event", Token::Eq | Token::Ne) => Filter::Event(vec![self.text()?]),
+            ("event", Token::Word(w)) if w.eq_ignore_ascii_case("in") => {
+                Filter::Event(self.list(Self::text)?)
+            }
+            ("sender", Token::Eq | Token::Ne) => Filter::Sender(vec![self.number()?]),
+            ("sender", Token::Word(w)) if w.eq_ignore_ascii_case("in") => {
+                Filter::Sender(self.list(Self::number)?)
+            }
+            ("sender", Token::Like) => Filter::SenderEmail(self.text()?.to_lowercase()),
+            ("content", Token::Word(w)) if w.eq_ignore_ascii_case("contains") => {
+                Filter::Contains(self.text()?.to_lowercase())
+            }
+            ("content", Token::Like) => Filter::ContentLike(self.text()?.to_lowercase()),
+            ("chat" | "event" | "sender" | "content", _) => {
+                return Err(invalid(format!("{op} is not supported on `{field}`")))
+            }
+            _ => return Err(invalid(format!("unknown field `{field}`"))),
+        };
+        Ok(match op {
+            Token::Ne => Filter::Not(Box::new(filter)),
+            _ => filter,
+        })
+    }
+
+    fn list<T>(&mut self, item: fn(&mut Self) -> Result<T, AppError>) -> Result<Vec<T>, AppError> {
+        match self.next()? {
+            Token::LParen => {}
+            t => return Err(invalid(format!("expected `(` but found {t}"))),
+        }
+        let mut items = vec![item(self)?];
+        loop {
+            match self.next()? {
+                Token::Comma => items.push(item(self)?),
+                Token::RParen => return Ok(items),
+                t => return Err(invalid(format!("expected `,` or `)` but found {t}"))),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, AppError> {
+        match self.next()? {
+            Token::Word(w) => w
+                .parse()
+                .map_err(|_| invalid(format!("expected a number but found `{w}`"))),
+            t => Err(invalid(format!("expected a number but found {t}"))),
+        }
+    }
+
+    fn text(&mut self) -> Result<String, AppError> {
+        match self.next()? {
+            Token::Word(w) | Token::Str(w) => Ok(w),
+            t => Err(invalid(format!("expected a value but found {t}"))),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, AppError> {
+    let mut tokens = vec![];
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' | ',' | '=' | '~' => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    ',' => Token::Comma,
+                    '=' => Token::Eq,
+                    _ => Token::Like,
+                });
+            }
+            '!' => {
+                chars.next();
+                match chars.next() {
+                    Some('=') => tokens.push(Token::Ne),
+                    _ => return Err(invalid("expected `=` after `!`")),
+                }
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => s.push(c),
+                            None => return Err(invalid("unterminated string")),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err(invalid("unterminated string")),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()=,~!\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// `*` matches any run of characters and `?` a single one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // position of the last `*` and the text position it is matched up to
+    let mut star = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+fn invalid(msg: impl Into<String>) -> AppError {
+    AppError::WebhookError(format!("Invalid filter: {}", msg.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx<'a>(event: &'a str, content: &'a str) -> EventContext<'a> {
+        EventContext {
+            event,
+            chat_id: 1,
+            sender_id: 2,
+            sender_email: "Alice@acme.org",
+            content,
+        }
+    }
+
+    #[test]
+    fn filter_should_match_events() -> anyhow::Result<()> {
+        let filter = Filter::parse(
+            r#"event = message.created and chat in (1, 2) and (sender ~ "*@acme.org" or sender = 9)"#,
+        )?;
+        assert!(filter.matches(&ctx("message.created", "hello")));
+        assert!(!filter.matches(&ctx("reaction.added", "hello")));
+
+        let filter = Filter::parse(r#"content contains "Deploy" and not chat != 1"#)?;
+        assert!(filter.matches(&ctx("message.created", "deploy done")));
+        assert!(!filter.matches(&ctx("message.created", "hello")));
+
+        let filter = Filter::parse(r#"content ~ "release v?.*""#)?;
+        assert!(filter.matches(&ctx("message.created", "Release v1.2")));
+        assert!(!filter.matches(&ctx("message.created", "release candidate")));
+
+        assert_eq!(Filter::parse("  ")?, Filter::All);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_filter_should_fail() {
+        for input in [
+            "chat",
+            "chat = abc",
+            "chat in (1, 2",
+            "owner = 1",
+            "content = 1",
+            "event = a b",
+            r#"content contains "open"#,
+            "(chat = 1",
+        ] {
+            assert!(Filter::parse(input).is_err(), "{input} should fail");
+        }
+    }
+
+    #[test]
+    fn filter_nesting_should_be_capped() -> anyhow::Result<()> {
+        let nested = |depth| format!("{}chat = 1{}", "(".repeat(depth), ")".repeat(depth));
+        Filter::parse(&nested(MAX_FILTER_DEPTH))?;
+        assert!(Filter::parse(&nested(MAX_FILTER_DEPTH + 1)).is_err());
+        Filter::parse(&format!("{}chat = 1", "not ".repeat(MAX_FILTER_DEPTH)))?;
+        // would overflow the stack without the cap
+        assert!(Filter::parse(&"not ".repeat(100_000)).is_err());
+        assert!(Filter::parse(&"(".repeat(100_000)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn glob_match_should_work() {
+        assert!(glob_match("*@acme.org", "tchen@acme.org"));
+        assert!(glob_match("a*b*c", "axxbyyc"));
+        assert!(!glob_match("*@acme.org", "tchen@acme.com"));
+        assert!(glob_match("*", ""));
+    }
+}
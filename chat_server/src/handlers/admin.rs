@@ -0,0 +1,480 @@
+use crate::{
+    AppError, AppState, ChatHistoryQuery, CreateBulkMessage, CreatePlan, FeatureConfig,
+    ListCapabilityStats, SetWorkspacePlan,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct Maintenance {
+    /// While enabled, all writes return 503 and reads keep working
+    pub enabled: bool,
+    /// Message returned to clients, a default one is used if not provided
+    pub message: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/maintenance",
+    responses(
+        (status = 200, description = "Current maintenance mode", body = Maintenance),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_maintenance_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = state.maintenance_message();
+    Ok(Json(Maintenance {
+        enabled: message.is_some(),
+        message,
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/maintenance",
+    request_body = Maintenance,
+    responses(
+        (status = 200, description = "Maintenance mode updated", body = Maintenance),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn set_maintenance_handler(
+    State(state): State<AppState>,
+    Json(input): Json<Maintenance>,
+) -> Result<impl IntoResponse, AppError> {
+    let message = input.enabled.then(|| {
+        input
+            .message
+            .unwrap_or_else(|| crate::DEFAULT_MAINTENANCE_MESSAGE.to_string())
+    });
+    state.set_maintenance(message.clone());
+    Ok(Json(Maintenance {
+        enabled: message.is_some(),
+        message,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/bulk-messages",
+    request_body = CreateBulkMessage,
+    responses(
+        (status = 202, description = "Bulk message is being sent", body = BulkMessage),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn create_bulk_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateBulkMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let bulk = state
+        .create_bulk_message(&input, user.ws_id as _, user.id as _)
+        .await?;
+    Ok((StatusCode::ACCEPTED, Json(bulk)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/bulk-messages/{id}",
+    params(
+        ("id" = u64, Path, description = "Bulk message id"),
+    ),
+    responses(
+        (status = 200, description = "Progress and per target results", body = BulkMessageReport),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Bulk message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_bulk_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.get_bulk_message_report(id).await? {
+        Some(report) if report.bulk.ws_id == user.ws_id => Ok(Json(report)),
+        _ => Err(AppError::NotFound(format!("bulk message id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/plans",
+    responses(
+        (status = 200, description = "All plans", body = Vec<Plan>),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn list_plans_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let plans = state.list_plans().await?;
+    Ok(Json(plans))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/plans",
+    request_body = CreatePlan,
+    responses(
+        (status = 201, description = "Plan created", body = Plan),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn create_plan_handler(
+    State(state): State<AppState>,
+    Json(input): Json<CreatePlan>,
+) -> Result<impl IntoResponse, AppError> {
+    let plan = state.create_plan(&input).await?;
+    Ok((StatusCode::CREATED, Json(plan)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/workspaces/{id}/plan",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+    ),
+    request_body = SetWorkspacePlan,
+    responses(
+        (status = 200, description = "Usage of the workspace under its new plan", body = WorkspaceUsage),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Workspace or plan not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn set_workspace_plan_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<SetWorkspacePlan>,
+) -> Result<impl IntoResponse, AppError> {
+    let usage = state.set_workspace_plan(id, input.plan_id).await?;
+    Ok(Json(usage))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/workspaces/{id}/usage",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Usage and quotas of the workspace", body = WorkspaceUsage),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_workspace_usage_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let usage = state.get_workspace_usage(id).await?;
+    Ok(Json(usage))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/features",
+    responses(
+        (status = 200, description = "Rollouts of the features with metrics per cohort", body = Vec<Feature>),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn list_features_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.list_features()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/client-capabilities",
+    params(
+        ListCapabilityStats
+    ),
+    responses(
+        (status = 200, description = "Client bootstraps per day and declared capabilities", body = Vec<CapabilityStat>),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn list_capability_stats_handler(
+    State(state): State<AppState>,
+    Query(input): Query<ListCapabilityStats>,
+) -> Result<impl IntoResponse, AppError> {
+    let stats = state.list_capability_stats(&input).await?;
+    Ok(Json(stats))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/orphans",
+    responses(
+        (status = 200, description = "Orphaned data the collector would remove, nothing is removed", body = OrphanReport),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_orphan_report_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let report = state.collect_orphans(true).await?;
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/features/{name}",
+    params(
+        ("name" = String, Path, description = "Feature name"),
+    ),
+    request_body = FeatureConfig,
+    responses(
+        (status = 200, description = "Rollout is updated until the next restart", body = Feature),
+        (status = 400, description = "Invalid rollout", body = ErrorOutput),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn update_feature_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(input): Json<FeatureConfig>,
+) -> Result<impl IntoResponse, AppError> {
+    let feature = state.update_feature(&name, input)?;
+    Ok(Json(feature))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/chats/{id}/history",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ChatHistoryQuery
+    ),
+    responses(
+        (status = 200, description = "Messages of the chat as they were at the given time", body = ChatSnapshot),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_chat_history_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Query(input): Query<ChatHistoryQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.get_chat_by_id(id).await? {
+        Some(chat) if chat.ws_id == user.ws_id => {
+            let snapshot = state.get_chat_snapshot(id, input.at).await?;
+            Ok(Json(snapshot))
+        }
+        _ => Err(AppError::NotFound(format!("chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/search/reindex",
+    responses(
+        (status = 202, description = "Search index is being rebuilt, or the running rebuild", body = SearchReindex),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn create_search_reindex_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let reindex = state.create_search_reindex(true).await?;
+    Ok((StatusCode::ACCEPTED, Json(reindex)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/search/reindex",
+    responses(
+        (status = 200, description = "Progress of the latest search index rebuild", body = SearchReindex),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "The search index was never rebuilt", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_search_reindex_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.get_latest_search_reindex().await? {
+        Some(reindex) => Ok(Json(reindex)),
+        None => Err(AppError::NotFound("search reindex".to_string())),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/admin/workspaces/{id}/demo",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Snapshot of the workspace it is reset to every night", body = DemoWorkspace),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+/// Make the workspace a demo workspace with a snapshot of its current users, chats and
+/// messages, or retake the snapshot of a demo workspace.
+pub(crate) async fn create_demo_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let demo = state.create_demo_workspace(id, user.id as _).await?;
+    Ok(Json(demo))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/admin/workspaces/{id}/demo",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+    ),
+    responses(
+        (status = 200, description = "Snapshot of the demo workspace", body = DemoWorkspace),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Not a demo workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn get_demo_workspace_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.get_demo_workspace(id).await? {
+        Some(demo) => Ok(Json(demo)),
+        None => Err(AppError::NotFound(format!("demo workspace {id}"))),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/admin/workspaces/{id}/demo",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+    ),
+    responses(
+        (status = 204, description = "The workspace is no longer reset"),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Not a demo workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+pub(crate) async fn delete_demo_workspace_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    if !state.delete_demo_workspace(id).await? {
+        return Err(AppError::NotFound(format!("demo workspace {id}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/admin/workspaces/{id}/demo/reset",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+    ),
+    responses(
+        (status = 200, description = "The workspace is reset to its snapshot", body = DemoWorkspace),
+        (status = 403, description = "Not an admin", body = ErrorOutput),
+        (status = 404, description = "Not a demo workspace", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "admin"
+)]
+/// Reset a demo workspace right away instead of waiting for the nightly reset.
+pub(crate) async fn reset_demo_workspace_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.reset_demo_workspace(id).await? {
+        Some(demo) => Ok(Json(demo)),
+        None => Err(AppError::NotFound(format!("demo workspace {id}"))),
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/archive",
+    responses(
+        (status = 200, description = "Archive status of the workspace", body = WorkspaceArchive),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn get_workspace_archive_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "archive the workspace")
+        .await?;
+    let archive = state.get_workspace_archive(ws.id as _).await?;
+    Ok(Json(archive))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/archive",
+    responses(
+        (status = 202, description = "Workspace is read only and being archived", body = WorkspaceArchive),
+        (status = 400, description = "Workspace is already archived", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn archive_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "archive the workspace")
+        .await?;
+    let archive = state.archive_workspace(ws.id as _).await?;
+    Ok((StatusCode::ACCEPTED, Json(archive)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/archive",
+    responses(
+        (status = 202, description = "Workspace is being restored", body = WorkspaceArchive),
+        (status = 400, description = "Workspace is not archived", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn unarchive_workspace_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "unarchive the workspace")
+        .await?;
+    let archive = state.unarchive_workspace(ws.id as _).await?;
+    Ok((StatusCode::ACCEPTED, Json(archive)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/archive/download",
+    responses(
+        (status = 200, description = "Archived messages as gzipped json", body = Vec<u8>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Workspace is not archived", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn download_workspace_archive_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "download the archive")
+        .await?;
+    let data = state.read_workspace_archive(ws.id as _).await?;
+    let disposition = format!(
+        "attachment; filename=\"workspace-{}-archive.json.gz\"",
+        ws.id
+    );
+    let headers = [
+        (header::CONTENT_TYPE, "application/gzip".to_string()),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    Ok((headers, data))
+}
@@ -1,14 +1,30 @@
 use crate::{
+    handlers::client_ip,
     models::{CreateUser, SigninUser},
-    AppError, AppState, ErrorOutput,
+    AppError, AppState, ErrorOutput, SessionMethod,
 };
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::ORIGIN, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{User, STREAM_TOKEN_DURATION};
 use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 use utoipa::ToSchema;
 
 #[derive(Debug, Serialize, ToSchema, Deserialize)]
 pub struct AuthOutput {
-    token: String,
+    pub(crate) token: String,
+}
+
+#[derive(Debug, Serialize, ToSchema, Deserialize)]
+pub struct StreamTokenOutput {
+    /// pass it to notify_server as `/events?stream_token=...`
+    pub(crate) token: String,
+    /// seconds the token can be used to open a stream
+    pub(crate) expires_in: u64,
 }
 
 #[utoipa::path(
@@ -16,6 +32,8 @@ pub struct AuthOutput {
     path = "/api/signup",
     responses(
         (status = 200, description = "User created", body = AuthOutput),
+        (status = 400, description = "Captcha is missing or invalid", body = ErrorOutput),
+        (status = 429, description = "Too many signups from the address or email domain", body = ErrorOutput),
     ),
     tag = "user"
 )]
@@ -24,12 +42,29 @@ pub struct AuthOutput {
 /// - If the email already exists, it will return 409.
 /// - Otherwise, it will return 201 with a token.
 /// - If the workspace doesn't exist, it will create one.
+/// - With `signup.captcha` configured, `captcha_token` has to be a solved captcha.
+/// - Signups per client address and email domain are throttled by `signup.throttle`.
 pub(crate) async fn signup_handler(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
     Json(input): Json<CreateUser>,
 ) -> Result<impl IntoResponse, AppError> {
+    let ip = client_ip(&headers, addr, &state.config.server.trusted_proxies);
+    let token = input.captcha_token.as_deref().unwrap_or_default();
+    let remote_ip = ip.map(|ip| ip.to_string());
+    if !state.captcha.verify(token, remote_ip.as_deref()).await? {
+        return Err(AppError::CaptchaError(
+            "Captcha is missing or invalid".to_string(),
+        ));
+    }
+    state
+        .throttle_signup(ip, &input.email, &state.config.signup.throttle)
+        .await?;
     let user = state.create_user(&input).await?;
-    let token = state.ek.sign(user)?;
+    let token = state
+        .create_session(user, SessionMethod::Password, None)
+        .await?;
     let body = Json(AuthOutput { token });
     Ok((StatusCode::CREATED, body))
 }
@@ -39,6 +74,7 @@ pub(crate) async fn signup_handler(
     path = "/api/signin",
     responses(
         (status = 200, description = "User signed in", body = AuthOutput),
+        (status = 403, description = "Invalid email or password, or the workspace requires single sign-on", body = ErrorOutput),
     ),
     tag = "user"
 )]
@@ -50,7 +86,10 @@ pub(crate) async fn signin_handler(
 
     match user {
         Some(user) => {
-            let token = state.ek.sign(user)?;
+            state.verify_password_signin(&user).await?;
+            let token = state
+                .create_session(user, SessionMethod::Password, None)
+                .await?;
             Ok((StatusCode::OK, Json(AuthOutput { token })).into_response())
         }
         None => {
@@ -60,6 +99,36 @@ pub(crate) async fn signin_handler(
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/events/token",
+    responses(
+        (status = 200, description = "Stream token for the event stream", body = StreamTokenOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+/// Issue a short-lived token for notify_server's event stream. Browsers can't set headers
+/// on an `EventSource`, so the token goes in its url instead of the session token.
+///
+/// - The token is bound to the `Origin` of the request, pages of other origins can't use it.
+/// - It only opens streams, the api doesn't accept it.
+/// - Reconnects need a new token once it expired.
+pub(crate) async fn create_stream_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let origin = headers.get(ORIGIN).and_then(|v| v.to_str().ok());
+    let token = state.ek.sign_stream_token(user, origin)?;
+    Ok(Json(StreamTokenOutput {
+        token,
+        expires_in: STREAM_TOKEN_DURATION,
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -70,7 +139,7 @@ mod tests {
     async fn signup_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateUser::new("acme", "Tian Chen", "tyr@acme.org", "123456");
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state), HeaderMap::new(), None, Json(input))
             .await?
             .into_response();
         assert_eq!(ret.status(), StatusCode::CREATED);
@@ -85,7 +154,7 @@ mod tests {
         let (_tdb, state) = AppState::new_for_test().await?;
         let input = CreateUser::new("acme", "Tyr Chen", "tchen@acme.org", "123456");
 
-        let ret = signup_handler(State(state), Json(input))
+        let ret = signup_handler(State(state), HeaderMap::new(), None, Json(input))
             .await
             .into_response();
         assert_eq!(ret.status(), StatusCode::CONFLICT);
@@ -129,4 +198,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn stream_token_should_be_bound_to_origin() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, "https://chat.acme.org".parse()?);
+        let ret = create_stream_token_handler(Extension(user), State(state.clone()), headers)
+            .await?
+            .into_response();
+        assert_eq!(ret.status(), StatusCode::OK);
+        let body = ret.into_body().collect().await?.to_bytes();
+        let ret: StreamTokenOutput = serde_json::from_slice(&body)?;
+        assert_eq!(ret.expires_in, STREAM_TOKEN_DURATION);
+
+        let user = state
+            .dk
+            .verify_stream_token(&ret.token, Some("https://chat.acme.org"))?;
+        assert_eq!(user.id, 1);
+        assert!(state.dk.verify(&ret.token).is_err());
+        Ok(())
+    }
 }
@@ -1,17 +1,29 @@
-use crate::{AppError, AppState, ChatDTO};
+use crate::{
+    AddChatMember, AppError, AppState, Capability, ChatAction, ChatDTO, ChatPatchDTO,
+    ClientCapabilities, ConvertChat, ListChannels, ListChats, OnboardingStep, RemoveChatMember,
+    Resource, SearchChats, TransferChat, UpdateChatRole, IDEMPOTENCY_KEY_HEADER,
+};
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Multipart, Path, Query, State},
+    http::{
+        header::{ETAG, IF_MATCH},
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
     response::IntoResponse,
     Extension, Json,
 };
 use chat_core::User;
+use chat_core::{Chat, ChatType, PublicId};
 
 #[utoipa::path(
     get,
     path = "/api/chats",
+    params(
+        ListChats
+    ),
     responses(
-        (status = 200, description = "List of chats", body = Vec<Chat>),
+        (status = 200, description = "A page of chats", body = ChatPage),
+        (status = 400, description = "Invalid cursor", body = ErrorOutput),
     ),
     security(
         ("token" = [])
@@ -20,17 +32,64 @@ use chat_core::User;
 )]
 pub(crate) async fn list_chat_handler(
     Extension(user): Extension<User>,
+    Extension(caps): Extension<ClientCapabilities>,
+    State(state): State<AppState>,
+    Query(input): Query<ListChats>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut page = state
+        .fetch_chat_page(user.ws_id as _, user.id as _, &input)
+        .await?;
+    page.chats = page
+        .chats
+        .into_iter()
+        .map(|chat| caps.downgrade_chat(chat))
+        .collect();
+    Ok((StatusCode::OK, Json(page)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/search",
+    params(
+        SearchChats
+    ),
+    responses(
+        (status = 200, description = "A page of chats matching the query, best first", body = ChatPage),
+        (status = 400, description = "Invalid cursor", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn search_chats_handler(
+    Extension(user): Extension<User>,
+    Extension(caps): Extension<ClientCapabilities>,
     State(state): State<AppState>,
+    Query(input): Query<SearchChats>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.fetch_chats(user.ws_id as _).await?;
-    Ok((StatusCode::OK, Json(chat)))
+    let mut page = state
+        .search_chats(user.ws_id as _, user.id as _, &input)
+        .await?;
+    page.chats = page
+        .chats
+        .into_iter()
+        .map(|chat| caps.downgrade_chat(chat))
+        .collect();
+    Ok(Json(page))
 }
 
 #[utoipa::path(
     post,
     path = "/api/chats",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Key of the request, retries with it get the chat created first"),
+    ),
     responses(
-        (status = 201, description = "Chat created", body = Chat),
+        (status = 201, description = "Chat created, or the existing single chat of the two members", body = Chat, headers(
+            ("Idempotent-Replayed" = bool, description = "Set if the chat was created by an earlier request with the key"),
+        )),
+        (status = 409, description = "Key used for another chat, or its chat is still being created", body = ErrorOutput),
     ),
     security(
         ("token" = [])
@@ -40,10 +99,60 @@ pub(crate) async fn list_chat_handler(
 pub(crate) async fn create_chat_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(input): Json<ChatDTO>,
 ) -> Result<impl IntoResponse, AppError> {
-    let chat = state.create_chat(input, user.ws_id as _).await?;
-    Ok((StatusCode::CREATED, Json(chat)))
+    let key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .map(|v| match v.to_str().map(str::trim) {
+            Ok(key) if !key.is_empty() && key.len() <= 255 => Ok(key),
+            _ => Err(AppError::ChatDTOError(
+                "Idempotency-Key must be 1 to 255 visible characters".to_string(),
+            )),
+        })
+        .transpose()?;
+    let (chat, replayed) = match key {
+        Some(key) => {
+            state
+                .create_chat_once(input, user.ws_id as _, user.id as _, key)
+                .await?
+        }
+        None => {
+            let chat = state
+                .create_chat(input, user.ws_id as _, user.id as _)
+                .await?;
+            (chat, false)
+        }
+    };
+    // a replayed creation completed the onboarding steps already
+    if !replayed
+        && matches!(
+            chat.r#type,
+            ChatType::PublicChannel | ChatType::PrivateChannel
+        )
+    {
+        state
+            .complete_onboarding_step(
+                user.ws_id as _,
+                user.id as _,
+                OnboardingStep::CreatedFirstChannel,
+            )
+            .await?;
+        if chat.members.iter().any(|id| *id != user.id) {
+            state
+                .complete_onboarding_step(
+                    user.ws_id as _,
+                    user.id as _,
+                    OnboardingStep::InvitedTeammates,
+                )
+                .await?;
+        }
+    }
+    let mut ret = HeaderMap::new();
+    if replayed {
+        ret.insert("idempotent-replayed", HeaderValue::from_static("true"));
+    }
+    Ok((StatusCode::CREATED, ret, Json(chat)))
 }
 
 #[utoipa::path(
@@ -53,7 +162,9 @@ pub(crate) async fn create_chat_handler(
         ("id" = u64, Path, description = "Chat id")
     ),
     responses(
-        (status = 200, description = "Chat found", body = Chat),
+        (status = 200, description = "Chat found", body = Chat, headers(
+            ("ETag" = String, description = "Version of the chat, for `If-Match` of updates"),
+        )),
         (status = 404, description = "Chat not found", body = ErrorOutput),
     ),
     security(
@@ -62,26 +173,68 @@ pub(crate) async fn create_chat_handler(
     tag = "chat"
 )]
 pub(crate) async fn get_chat_handler(
+    Extension(caps): Extension<ClientCapabilities>,
     State(state): State<AppState>,
-    Path(id): Path<u64>,
+    Path(PublicId(id)): Path<PublicId>,
 ) -> Result<impl IntoResponse, AppError> {
     let chat = state.get_chat_by_id(id as _).await?;
     match chat {
-        Some(chat) => Ok(Json(chat)),
+        Some(chat) => Ok((chat_etag(&chat)?, Json(caps.downgrade_chat(chat)))),
         None => Err(AppError::NotFound(format!("chat id {id}"))),
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/chats/by-handle/{ws_id}/{handle}",
+    params(
+        ("ws_id" = u64, Path, description = "Workspace id"),
+        ("handle" = String, Path, description = "Handle of the channel, with or without #"),
+    ),
+    responses(
+        (status = 200, description = "Channel with the handle, or which had it before a rename", body = Chat),
+        (status = 404, description = "No channel with the handle the user can see", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn get_chat_by_handle_handler(
+    Extension(user): Extension<User>,
+    Extension(caps): Extension<ClientCapabilities>,
+    State(state): State<AppState>,
+    Path((ws_id, handle)): Path<(u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = match user.ws_id as u64 == ws_id {
+        true => {
+            state
+                .get_chat_by_handle(ws_id, &handle, user.id as _)
+                .await?
+        }
+        false => None,
+    };
+    match chat {
+        Some(chat) => Ok(Json(caps.downgrade_chat(chat))),
+        None => Err(AppError::NotFound(format!("chat handle {handle}"))),
+    }
+}
+
 #[utoipa::path(
     patch,
     path = "/api/chats/{id}",
     params(
         ("id" = u64, Path, description = "Chat id"),
+        ("If-Match" = Option<String>, Header, description = "ETag of the chat, it is only updated if it is still at that version"),
     ),
-    request_body = ChatDTO,
+    request_body = ChatPatchDTO,
     responses(
-        (status = 200, description = "Chat is updated", body = Chat),
+        (status = 200, description = "Chat is updated", body = Chat, headers(
+            ("ETag" = String, description = "New version of the chat"),
+        )),
+        (status = 403, description = "Not an owner or admin of the chat", body = ErrorOutput),
         (status = 404, description = "Chat not found", body = ErrorOutput),
+        (status = 409, description = "The chat was updated since the given version", body = ErrorOutput),
     ),
     security(
         ("token" = [])
@@ -89,17 +242,42 @@ pub(crate) async fn get_chat_handler(
     tag = "chat"
 )]
 pub(crate) async fn update_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
-    Path(id): Path<u64>,
-    Json(input): Json<ChatDTO>,
+    Path(PublicId(id)): Path<PublicId>,
+    headers: HeaderMap,
+    Json(mut input): Json<ChatPatchDTO>,
 ) -> impl IntoResponse {
-    let chat = state.update_chat(id as _, input).await?;
+    state
+        .authorize(user.id as _, ChatAction::UpdateChat, Resource::chat(id))
+        .await?;
+    if let Some(version) = if_match_version(&headers)? {
+        input.version = Some(version);
+    }
+    let before = state.get_chat_by_id(id as _).await?;
+    let chat = state
+        .update_chat(id as _, user.ws_id as _, input, user.id as _)
+        .await?;
     match chat {
-        Some(chat) => Ok(Json(chat)),
+        Some(chat) => {
+            let added = before.is_some_and(|b| chat.members.iter().any(|m| !b.members.contains(m)));
+            if added {
+                state
+                    .complete_onboarding_step(
+                        user.ws_id as _,
+                        user.id as _,
+                        OnboardingStep::InvitedTeammates,
+                    )
+                    .await?;
+            }
+            Ok((chat_etag(&chat)?, Json(chat)))
+        }
         None => Err(AppError::NotFound(format!("chat id {id}"))),
     }
 }
 
+/// Members are told and the chat is purged once `chat.deletion_grace_hours` have passed,
+/// unless the owner keeps it with `DELETE /api/chats/{id}/deletion`.
 #[utoipa::path(
     delete,
     path = "/api/chats/{id}",
@@ -107,7 +285,8 @@ pub(crate) async fn update_chat_handler(
         ("id" = u64, Path, description = "Chat id"),
     ),
     responses(
-        (status = 200, description = "Chat is deleted", body = String),
+        (status = 202, description = "Chat is scheduled for deletion, or the existing schedule", body = ChatDeletion),
+        (status = 403, description = "Not the owner of the chat, or the chat is on legal hold", body = ErrorOutput),
         (status = 404, description = "Chat not found", body = ErrorOutput),
     ),
     security(
@@ -116,12 +295,659 @@ pub(crate) async fn update_chat_handler(
     tag = "chat"
 )]
 pub(crate) async fn delete_chat_handler(
+    Extension(user): Extension<User>,
     State(state): State<AppState>,
-    Path(id): Path<u64>,
-) -> impl IntoResponse {
-    let chat_id = state.delete_chat(id as _).await?;
-    match chat_id {
-        Some(_) => Ok(format!("chat id {} has been deleted", id)),
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::DeleteChat, Resource::chat(id))
+        .await?;
+    match state
+        .schedule_chat_deletion(id, user.ws_id as _, user.id as _)
+        .await?
+    {
+        Some(deletion) => Ok((StatusCode::ACCEPTED, Json(deletion))),
         None => Err(AppError::NotFound(format!("chat id {id}"))),
     }
 }
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/deletion",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Chat is kept", body = Chat),
+        (status = 403, description = "Not the owner of the chat", body = ErrorOutput),
+        (status = 404, description = "Chat is not scheduled for deletion", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn cancel_chat_deletion_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::KeepChat, Resource::chat(id))
+        .await?;
+    match state
+        .cancel_chat_deletion(id, user.ws_id as _, user.id as _)
+        .await?
+    {
+        Some(chat) => Ok(Json(chat)),
+        None => Err(AppError::NotFound(format!("chat deletion {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/members",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Members of the chat", body = Vec<ChatMember>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_chat_members_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let members = state.list_chat_members(id).await?;
+    Ok(Json(members))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/members",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = AddChatMember,
+    responses(
+        (status = 201, description = "Member is added", body = Chat),
+        (status = 400, description = "Members of the chat can't be changed", body = ErrorOutput),
+        (status = 409, description = "The chat has as many members as its type allows", body = ErrorOutput),
+        (status = 404, description = "Chat or user not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn add_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<AddChatMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let member_id = input.user_id as u64;
+    state
+        .verify_chat_member_change(id, member_id, false)
+        .await?;
+    state
+        .add_chat_member(id, member_id, Some(user.id as _))
+        .await?;
+    if input.user_id != user.id {
+        state
+            .complete_onboarding_step(
+                user.ws_id as _,
+                user.id as _,
+                OnboardingStep::InvitedTeammates,
+            )
+            .await?;
+    }
+    let chat = state.get_chat_by_id(id).await?;
+    match chat {
+        Some(chat) => Ok((StatusCode::CREATED, Json(chat))),
+        None => Err(AppError::NotFound(format!("chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/members/{user_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("user_id" = u64, Path, description = "Id of the member to remove"),
+        RemoveChatMember
+    ),
+    responses(
+        (status = 200, description = "Member is removed", body = Chat),
+        (status = 400, description = "Members of the chat can't be changed", body = ErrorOutput),
+        (status = 403, description = "Not allowed to remove the member", body = ErrorOutput),
+        (status = 404, description = "Chat or member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn remove_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, user_id)): Path<(PublicId, PublicId)>,
+    Query(input): Query<RemoveChatMember>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, user_id) = (*id, *user_id);
+    // anyone can leave, removing others needs a higher role than theirs
+    if user_id == user.id as u64 && input.ban {
+        return Err(AppError::ChatDTOError(
+            "Members can't ban themselves".to_string(),
+        ));
+    }
+    if user_id != user.id as u64 {
+        let target = state.get_chat_role(id, user_id).await?;
+        let resource = Resource::member(id, user_id, target);
+        state
+            .authorize(user.id as _, ChatAction::RemoveMembers, resource)
+            .await?;
+    }
+    state.verify_chat_member_change(id, user_id, true).await?;
+    // banned first, so they can't be added back in between
+    if input.ban {
+        state
+            .ban_chat_member(id, user_id, input.reason.as_deref(), user.id as _)
+            .await?;
+    }
+    state.remove_chat_member(id, user_id, user.id as _).await?;
+    let chat = state.get_chat_by_id(id).await?;
+    match chat {
+        Some(chat) => Ok(Json(chat)),
+        None => Err(AppError::NotFound(format!("chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/bans",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Users banned from the chat, latest first", body = Vec<ChatBan>),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_chat_bans_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::ManageBans, Resource::chat(id))
+        .await?;
+    let bans = state.list_chat_bans(id).await?;
+    Ok(Json(bans))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/bans/{user_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("user_id" = u64, Path, description = "Id of the banned user"),
+    ),
+    responses(
+        (status = 204, description = "User can be added to the chat again"),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+        (status = 404, description = "User isn't banned", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn unban_chat_member_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, user_id)): Path<(PublicId, PublicId)>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, user_id) = (*id, *user_id);
+    state
+        .authorize(user.id as _, ChatAction::ManageBans, Resource::chat(id))
+        .await?;
+    if !state.unban_chat_member(id, user_id).await? {
+        return Err(AppError::NotFound(format!("chat ban {user_id}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/pin",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 204, description = "Chat is pinned to the top of the chat list"),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn pin_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state.pin_chat(id, user.id as _).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/pin",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 204, description = "Chat is no longer pinned"),
+        (status = 404, description = "Chat is not pinned", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn unpin_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    if state.unpin_chat(id, user.id as _).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound(format!("chat pin {id}")))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/avatar",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body(
+        content_type = "multipart/form-data",
+        content = UploadFile
+    ),
+    responses(
+        (status = 200, description = "Chat avatar is updated", body = Chat),
+        (status = 400, description = "Not a supported image", body = ErrorOutput),
+        (status = 403, description = "Not an owner or admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn upload_chat_avatar_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::ChangeAvatar, Resource::chat(id))
+        .await?;
+    let data = match multipart.next_field().await {
+        Ok(Some(field)) => field.bytes().await.ok(),
+        _ => None,
+    };
+    let Some(data) = data else {
+        return Err(AppError::ChatFileError(
+            "Avatar image is missing".to_string(),
+        ));
+    };
+    match state.set_chat_avatar(id, user.ws_id as _, &data).await? {
+        Some(chat) => Ok(Json(chat)),
+        None => Err(AppError::NotFound(format!("chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/archive",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Chat is archived", body = Chat),
+        (status = 403, description = "Not an owner or admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn archive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::ArchiveChat, Resource::chat(id))
+        .await?;
+    match state.set_chat_archived(id, user.ws_id as _, true).await? {
+        Some(chat) => Ok(Json(chat)),
+        None => Err(AppError::NotFound(format!("chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/unarchive",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Chat is unarchived", body = Chat),
+        (status = 403, description = "Not an owner or admin of the chat", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn unarchive_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::UnarchiveChat, Resource::chat(id))
+        .await?;
+    match state.set_chat_archived(id, user.ws_id as _, false).await? {
+        Some(chat) => Ok(Json(chat)),
+        None => Err(AppError::NotFound(format!("chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/restore",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Chat is restored", body = Chat),
+        (status = 403, description = "Not an owner or admin of the chat", body = ErrorOutput),
+        (status = 404, description = "No deleted chat to restore", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn restore_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::RestoreChat, Resource::chat(id))
+        .await?;
+    match state.restore_chat(id, user.ws_id as _).await? {
+        Some(chat) => Ok(Json(chat)),
+        None => Err(AppError::NotFound(format!("deleted chat id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/{id}/channels",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+        ListChannels,
+    ),
+    responses(
+        (status = 200, description = "Public channels the user can join", body = Vec<DirectoryChannel>),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_channel_directory_handler(
+    Extension(user): Extension<User>,
+    Extension(caps): Extension<ClientCapabilities>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(input): Query<ListChannels>,
+) -> Result<impl IntoResponse, AppError> {
+    if user.ws_id as u64 != id {
+        return Err(AppError::NotFound(format!("workspace id {id}")));
+    }
+    let mut channels = state
+        .list_channel_directory(id, user.id as _, &input)
+        .await?;
+    if !caps.supports(Capability::ChatAvatars) {
+        channels.iter_mut().for_each(|c| c.avatar_url = None);
+    }
+    Ok(Json(channels))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Joined the chat", body = Chat),
+        (status = 403, description = "The chat is not a public channel", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.join_chat(id, &user).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/leave",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Left the chat", body = Chat),
+        (status = 204, description = "Left the chat as its last member, the chat is deleted"),
+        (status = 400, description = "Single chats can't be left", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn leave_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.leave_chat(id, user.id as _).await? {
+        Some(chat) => Ok(Json(chat).into_response()),
+        None => Ok(StatusCode::NO_CONTENT.into_response()),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/members/{user_id}/role",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("user_id" = u64, Path, description = "Id of the member"),
+    ),
+    request_body = UpdateChatRole,
+    responses(
+        (status = 200, description = "Role is updated", body = ChatMember),
+        (status = 400, description = "The owner's role can't be changed", body = ErrorOutput),
+        (status = 403, description = "Not the chat owner", body = ErrorOutput),
+        (status = 404, description = "Member not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn update_chat_role_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, user_id)): Path<(PublicId, PublicId)>,
+    Json(input): Json<UpdateChatRole>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, user_id) = (*id, *user_id);
+    state
+        .authorize(
+            user.id as _,
+            ChatAction::ChangeMemberRoles,
+            Resource::chat(id),
+        )
+        .await?;
+    let member = state
+        .update_chat_member_role(id, user_id, input.role)
+        .await?;
+    Ok(Json(member))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/convert",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = ConvertChat,
+    responses(
+        (status = 200, description = "The single chat is now a group", body = Chat),
+        (status = 400, description = "Not a single chat or no new members", body = ErrorOutput),
+        (status = 404, description = "Chat or user not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn convert_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<ConvertChat>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.convert_chat(id, &input, user.id as _).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/transfer",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = TransferChat,
+    responses(
+        (status = 200, description = "Ownership is transferred", body = Chat),
+        (status = 400, description = "The user is not a member", body = ErrorOutput),
+        (status = 403, description = "Not the chat owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn transfer_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<TransferChat>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::TransferChat, Resource::chat(id))
+        .await?;
+    let chat = state
+        .transfer_chat_ownership(id, &input, user.id as _)
+        .await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/{id}/chats",
+    params(
+        ("id" = u64, Path, description = "User id"),
+    ),
+    responses(
+        (status = 200, description = "Chats of the user, only the ones shared with the current user unless it is an admin", body = Vec<Chat>),
+        (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_user_chats_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let is_admin = state.is_admin(user.id);
+    let target = state.find_user_by_id(id as _).await?;
+    match target {
+        Some(target) if is_admin || target.ws_id == user.ws_id => {
+            let viewer = (!is_admin).then_some(user.id as u64);
+            let chats = state.fetch_user_chats(id, viewer).await?;
+            Ok(Json(chats))
+        }
+        _ => Err(AppError::NotFound(format!("user id {id}"))),
+    }
+}
+
+fn chat_etag(chat: &Chat) -> Result<[(HeaderName, HeaderValue); 1], AppError> {
+    let etag = HeaderValue::from_str(&format!("\"{}\"", chat.version))?;
+    Ok([(ETAG, etag)])
+}
+
+// the version of a chat ETag, `*` matches any version
+fn if_match_version(headers: &HeaderMap) -> Result<Option<i64>, AppError> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map(str::trim).unwrap_or_default();
+    if value == "*" {
+        return Ok(None);
+    }
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| AppError::ChatDTOError("If-Match must be an ETag of the chat".to_string()))
+}
@@ -0,0 +1,245 @@
+use crate::{
+    lookup_txt, AppError, AppState, CreateWorkspaceDomain, DomainEmailChallenge,
+    FindSignupWorkspace, VerifyDomain,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/domains",
+    responses(
+        (status = 200, description = "Email domains of the workspace", body = Vec<WorkspaceDomain>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_domains_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage domains")
+        .await?;
+    let domains = state.list_workspace_domains(ws.id as _).await?;
+    Ok(Json(domains))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/domains",
+    request_body = CreateWorkspaceDomain,
+    responses(
+        (status = 201, description = "Domain added, waiting for verification", body = WorkspaceDomain),
+        (status = 400, description = "Invalid or already claimed domain", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn add_domain_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateWorkspaceDomain>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage domains")
+        .await?;
+    let domain = state.add_workspace_domain(ws.id as _, &input).await?;
+    Ok((StatusCode::CREATED, Json(domain)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/domains/{id}/verify",
+    params(
+        ("id" = u64, Path, description = "Domain id"),
+    ),
+    request_body = Option<VerifyDomain>,
+    responses(
+        (status = 200, description = "Domain verified", body = WorkspaceDomain),
+        (status = 400, description = "Verification TXT record not found or invalid code", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Domain not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn verify_domain_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    input: Option<Json<VerifyDomain>>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage domains")
+        .await?;
+    if let Some(code) = input.and_then(|Json(input)| input.code) {
+        let domain = state
+            .verify_workspace_domain_by_code(ws.id as _, id, &code)
+            .await?;
+        return Ok(Json(domain));
+    }
+    let domain = state
+        .get_workspace_domain(ws.id as _, id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("domain id {id}")))?;
+    let records = match domain.verified_at {
+        Some(_) => vec![],
+        None => lookup_txt(&domain.domain).await?,
+    };
+    let domain = state
+        .verify_workspace_domain(ws.id as _, id, &records)
+        .await?;
+    Ok(Json(domain))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/domains/{id}/email-challenge",
+    params(
+        ("id" = u64, Path, description = "Domain id"),
+    ),
+    request_body = DomainEmailChallenge,
+    responses(
+        (status = 202, description = "Verification code is being mailed", body = WorkspaceDomain),
+        (status = 400, description = "Email is not on the domain", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Domain not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn domain_email_challenge_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Json(input): Json<DomainEmailChallenge>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage domains")
+        .await?;
+    let domain = state
+        .start_domain_email_challenge(ws.id as _, id, &input.email, &user)
+        .await?;
+    Ok((StatusCode::ACCEPTED, Json(domain)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/domains/{id}",
+    params(
+        ("id" = u64, Path, description = "Domain id"),
+    ),
+    responses(
+        (status = 200, description = "Domain is deleted", body = String),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Domain not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn delete_domain_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage domains")
+        .await?;
+    match state.delete_workspace_domain(ws.id as _, id).await? {
+        true => Ok(format!("domain id {id} has been deleted")),
+        false => Err(AppError::NotFound(format!("domain id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/signup/workspace",
+    params(
+        FindSignupWorkspace
+    ),
+    responses(
+        (status = 200, description = "Workspace offered for the email domain", body = SignupWorkspace),
+        (status = 404, description = "No workspace verified the email domain", body = ErrorOutput),
+    ),
+    tag = "user"
+)]
+pub(crate) async fn signup_workspace_handler(
+    State(state): State<AppState>,
+    Query(input): Query<FindSignupWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.find_signup_workspace(&input.email).await? {
+        Some(ws) => Ok(Json(ws)),
+        None => Err(AppError::NotFound(format!(
+            "workspace for email {}",
+            input.email
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn domain_handlers_should_require_workspace_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(2).await?.unwrap();
+        let ret = list_domains_handler(Extension(user), State(state)).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn signup_workspace_handler_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let input = CreateWorkspaceDomain {
+            domain: "acme.org".to_string(),
+            auto_join: false,
+        };
+        let res = add_domain_handler(Extension(owner), State(state.clone()), Json(input))
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = res.into_body().collect().await?.to_bytes();
+        let domain: crate::WorkspaceDomain = serde_json::from_slice(&body)?;
+
+        let query = || {
+            Query(FindSignupWorkspace {
+                email: "new@acme.org".to_string(),
+            })
+        };
+        let ret = signup_workspace_handler(State(state.clone()), query()).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        state
+            .verify_workspace_domain(1, domain.id as _, &[domain.txt_record])
+            .await?;
+        let res = signup_workspace_handler(State(state), query())
+            .await?
+            .into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{AppError, AppState, SaveChatDraft};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/draft",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "The caller's draft in the chat", body = ChatDraft),
+        (status = 404, description = "No draft in the chat", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn get_chat_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.get_chat_draft(id, user.id as _).await? {
+        Some(draft) => Ok(Json(draft)),
+        None => Err(AppError::NotFound(format!("draft of chat {id}"))),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/draft",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = SaveChatDraft,
+    responses(
+        (status = 200, description = "The draft as saved, the newer one if the save was late", body = ChatDraft),
+        (status = 400, description = "Draft too long or invalid device id", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+/// Save the caller's draft in the chat while they type.
+///
+/// - Saves of a device must have increasing `seq`, a late one is ignored.
+/// - The caller's other devices get a `DraftUpdated` event when it changed, and skip
+///   the ones with their own `device_id`.
+pub(crate) async fn save_chat_draft_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<SaveChatDraft>,
+) -> Result<impl IntoResponse, AppError> {
+    let draft = state.save_chat_draft(id, user.id as _, &input).await?;
+    Ok(Json(draft))
+}
@@ -0,0 +1,105 @@
+use crate::{AppError, AppState, ChatSettings, ExportChat, ExportFormat};
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/settings",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Chat settings", body = ChatSettings),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn get_chat_settings_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let settings = state.get_chat_settings(id).await?;
+    Ok(Json(settings))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/settings",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = ChatSettings,
+    responses(
+        (status = 200, description = "Chat settings updated", body = ChatSettings),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn update_chat_settings_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<ChatSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "update chat settings")
+        .await?;
+    let settings = state.update_chat_settings(id, &input).await?;
+    state
+        .record_audit(
+            ws.id as _,
+            user.id as _,
+            "chat.settings",
+            Some(id),
+            &settings,
+        )
+        .await?;
+    Ok(Json(settings))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/export",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ExportChat,
+    ),
+    responses(
+        (status = 200, description = "Chat, members and history as a json file, or as ndjson records", body = ChatExport),
+        (status = 403, description = "Export is not allowed", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn export_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Query(input): Query<ExportChat>,
+) -> Result<Response, AppError> {
+    if input.format == ExportFormat::Ndjson {
+        let stream = state.stream_chat_export(id, &user).await?;
+        let disposition = format!("attachment; filename=\"chat-{id}-export.ndjson\"");
+        let headers = [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ];
+        return Ok((headers, Body::from_stream(stream)).into_response());
+    }
+    let export = state.export_chat(id, &user).await?;
+    let disposition = format!("attachment; filename=\"chat-{id}-export.json\"");
+    Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(export)).into_response())
+}
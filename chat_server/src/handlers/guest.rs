@@ -0,0 +1,123 @@
+use super::AuthOutput;
+use crate::{AppError, AppState, CreateGuestLink, OnboardingStep, RedeemGuestLink, SessionMethod};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+use chrono::Utc;
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/guest-links",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = CreateGuestLink,
+    responses(
+        (status = 201, description = "Guest link created", body = GuestLink),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn create_guest_link_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<CreateGuestLink>,
+) -> Result<impl IntoResponse, AppError> {
+    let link = state.create_guest_link(id, &input, user.id as _).await?;
+    state
+        .complete_onboarding_step(
+            user.ws_id as _,
+            user.id as _,
+            OnboardingStep::InvitedTeammates,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(link)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/guest-links",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Guest links of the chat", body = Vec<GuestLink>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_guest_links_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let links = state.list_guest_links(id).await?;
+    Ok(Json(links))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/guest-links/{link_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("link_id" = u64, Path, description = "Guest link id"),
+    ),
+    responses(
+        (status = 200, description = "Guest link revoked", body = GuestLink),
+        (status = 404, description = "Guest link not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn revoke_guest_link_handler(
+    State(state): State<AppState>,
+    Path((id, link_id)): Path<(PublicId, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = *id;
+    match state.revoke_guest_link(id, link_id).await? {
+        Some(link) => Ok(Json(link)),
+        None => Err(AppError::NotFound(format!("guest link id {link_id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/guest-links/{token}/redeem",
+    params(
+        ("token" = String, Path, description = "Guest link token"),
+    ),
+    request_body = RedeemGuestLink,
+    responses(
+        (status = 201, description = "Guest account created", body = AuthOutput),
+        (status = 400, description = "Link has expired", body = ErrorOutput),
+        (status = 404, description = "Link not found", body = ErrorOutput),
+    ),
+    tag = "user"
+)]
+/// Join a channel through a guest link.
+///
+/// A guest account is created and added to the channel, the returned token expires
+/// together with the link.
+pub(crate) async fn redeem_guest_link_handler(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    Json(input): Json<RedeemGuestLink>,
+) -> Result<impl IntoResponse, AppError> {
+    let (user, guest) = state.redeem_guest_link(&token, &input).await?;
+    let secs = (guest.expires_at - Utc::now()).num_seconds().max(1);
+    let token = state
+        .create_session(user, SessionMethod::GuestLink, Some(secs as _))
+        .await?;
+    Ok((StatusCode::CREATED, Json(AuthOutput { token })))
+}
@@ -0,0 +1,93 @@
+use crate::{AppError, AppState, CreateLegalHold};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/legal-holds",
+    responses(
+        (status = 200, description = "Legal holds of the workspace", body = Vec<LegalHold>),
+        (status = 403, description = "Not a workspace owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_legal_holds_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_admin(&user, user.ws_id as _, "manage legal holds")
+        .await?;
+    let holds = state.list_legal_holds(ws.id as _).await?;
+    Ok(Json(holds))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/legal-holds",
+    request_body = CreateLegalHold,
+    responses(
+        (status = 201, description = "Legal hold placed", body = LegalHold),
+        (status = 400, description = "The hold already exists", body = ErrorOutput),
+        (status = 403, description = "Not a workspace owner or admin", body = ErrorOutput),
+        (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn create_legal_hold_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateLegalHold>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_admin(&user, user.ws_id as _, "manage legal holds")
+        .await?;
+    let hold = state
+        .create_legal_hold(ws.id as _, &input, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(hold)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/legal-holds/{id}",
+    params(
+        ("id" = u64, Path, description = "Legal hold id"),
+    ),
+    responses(
+        (status = 204, description = "Legal hold released"),
+        (status = 403, description = "Not a workspace owner or admin", body = ErrorOutput),
+        (status = 404, description = "Legal hold not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn release_legal_hold_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_admin(&user, user.ws_id as _, "manage legal holds")
+        .await?;
+    match state
+        .release_legal_hold(ws.id as _, id, user.id as _)
+        .await?
+    {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(AppError::NotFound(format!("legal hold id {id}"))),
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{AppError, AppState, ChatAction, CreateChatInvite, CreateJoinRequest, Resource};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/invites",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = CreateChatInvite,
+    responses(
+        (status = 201, description = "Invite created", body = ChatInvite),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn create_chat_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<CreateChatInvite>,
+) -> Result<impl IntoResponse, AppError> {
+    let invite = state.create_chat_invite(id, &input, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(invite)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/invites",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Invites of the chat", body = Vec<ChatInvite>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_chat_invites_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let invites = state.list_chat_invites(id).await?;
+    Ok(Json(invites))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/invites/{invite_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("invite_id" = u64, Path, description = "Invite id"),
+    ),
+    responses(
+        (status = 200, description = "Invite revoked", body = ChatInvite),
+        (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn revoke_chat_invite_handler(
+    State(state): State<AppState>,
+    Path((id, invite_id)): Path<(PublicId, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = *id;
+    match state.revoke_chat_invite(id, invite_id).await? {
+        Some(invite) => Ok(Json(invite)),
+        None => Err(AppError::NotFound(format!("invite id {invite_id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/invites/{token}/accept",
+    params(
+        ("token" = String, Path, description = "Invite token"),
+    ),
+    responses(
+        (status = 200, description = "Joined the chat", body = Chat),
+        (status = 400, description = "Invite expired, revoked or used up", body = ErrorOutput),
+        (status = 404, description = "Invite not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+/// Join the chat of an invite, the invite has to be of the caller's workspace.
+///
+/// Members of the chat get the chat back without using the invite up.
+pub(crate) async fn accept_chat_invite_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let chat = state.accept_chat_invite(&token, &user).await?;
+    Ok(Json(chat))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join-request",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = CreateJoinRequest,
+    responses(
+        (status = 201, description = "Join request pending", body = JoinRequest),
+        (status = 400, description = "Not a private channel, or already a member", body = ErrorOutput),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+/// Ask the owner and admins of a private channel to be added to it.
+///
+/// They get a `JoinRequest` event, and so does the caller once it is approved or denied.
+pub(crate) async fn request_to_join_chat_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<CreateJoinRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let request = state.request_to_join_chat(id, &input, &user).await?;
+    Ok((StatusCode::CREATED, Json(request)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/join-requests",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Pending join requests of the chat", body = Vec<JoinRequest>),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_join_requests_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(
+            user.id as _,
+            ChatAction::ManageJoinRequests,
+            Resource::chat(id),
+        )
+        .await?;
+    let requests = state.list_join_requests(id).await?;
+    Ok(Json(requests))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join-requests/{request_id}/approve",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("request_id" = u64, Path, description = "Join request id"),
+    ),
+    responses(
+        (status = 200, description = "User added to the chat", body = JoinRequest),
+        (status = 400, description = "Request already decided", body = ErrorOutput),
+        (status = 404, description = "Join request not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn approve_join_request_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, request_id)): Path<(PublicId, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    decide_join_request(&state, &user, *id, request_id, true).await
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/join-requests/{request_id}/deny",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("request_id" = u64, Path, description = "Join request id"),
+    ),
+    responses(
+        (status = 200, description = "Join request denied", body = JoinRequest),
+        (status = 400, description = "Request already decided", body = ErrorOutput),
+        (status = 404, description = "Join request not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn deny_join_request_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, request_id)): Path<(PublicId, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+    decide_join_request(&state, &user, *id, request_id, false).await
+}
+
+async fn decide_join_request(
+    state: &AppState,
+    user: &User,
+    id: u64,
+    request_id: u64,
+    approve: bool,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(
+            user.id as _,
+            ChatAction::ManageJoinRequests,
+            Resource::chat(id),
+        )
+        .await?;
+    match state
+        .decide_join_request(id, request_id, approve, user.id as _)
+        .await?
+    {
+        Some(request) => Ok(Json(request)),
+        None => Err(AppError::NotFound(format!("join request id {request_id}"))),
+    }
+}
@@ -1,16 +1,24 @@
 use axum::{
-    extract::{Multipart, Path, Query, State},
-    http::{HeaderMap, StatusCode},
+    extract::{ConnectInfo, Multipart, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     Extension, Json,
 };
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
 
 use tokio::fs;
-use tracing::{info, warn};
+use tracing::warn;
 use utoipa::ToSchema;
 
-use crate::{AppError, AppState, ChatFile, CreateMessage, ListMessages};
-use chat_core::User;
+use crate::{
+    models::networks_contain, AppError, AppState, Capability, ChatFile, ClientCapabilities,
+    CreateMessage, ListMessages, MessageFields, OnboardingStep,
+};
+use chat_core::{PublicId, User};
 
 #[derive(ToSchema)]
 #[allow(unused)]
@@ -23,10 +31,15 @@ pub struct UploadFile {
     path = "/api/chats/{id}/messages",
     params(
         ("id" = u64, Path, description = "Chat id"),
+        ("X-Client-Time" = Option<String>, Header, description = "Client clock when sending, RFC 3339"),
     ),
     request_body = CreateMessage,
     responses(
-        (status = 201, description = "New message", body = Message),
+        (status = 201, description = "New message", body = Message, headers(
+            ("X-Server-Time" = String, description = "Server clock, RFC 3339"),
+            ("X-Clock-Skew-Ms" = i64, description = "How far the client clock is ahead of the server, if X-Client-Time was sent"),
+        )),
+        (status = 429, description = "Slow mode, the sender has to wait retry_after seconds", body = ErrorOutput),
         (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     security(
@@ -37,12 +50,21 @@ pub struct UploadFile {
 pub(crate) async fn send_message_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
-    Path(id): Path<u64>,
+    Path(PublicId(id)): Path<PublicId>,
+    headers: HeaderMap,
     Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
+    let clock = clock_headers(&headers, Utc::now());
     let msg = state.create_message(input, id, user.id as _).await?;
+    state
+        .complete_onboarding_step(
+            user.ws_id as _,
+            user.id as _,
+            OnboardingStep::SentFirstMessage,
+        )
+        .await?;
 
-    Ok((StatusCode::CREATED, Json(msg)))
+    Ok((StatusCode::CREATED, clock, Json(msg)))
 }
 
 #[utoipa::path(
@@ -54,7 +76,7 @@ pub(crate) async fn send_message_handler(
 
     ),
     responses(
-        (status = 200, description = "List of messages", body = Vec<Message>),
+        (status = 200, description = "List of messages", body = Vec<HydratedMessage>),
         (status = 400, description = "Invalid input", body = ErrorOutput),
     ),
     security(
@@ -62,12 +84,24 @@ pub(crate) async fn send_message_handler(
     ),
     tag = "message"
 )]
+/// List the messages of a chat, newest first.
+///
+/// `fields=minimal` leaves out translations, `fields=full` embeds reactions and sender
+/// profiles as well.
 pub(crate) async fn list_message_handler(
+    Extension(user): Extension<User>,
+    Extension(caps): Extension<ClientCapabilities>,
     State(state): State<AppState>,
-    Path(id): Path<u64>,
+    Path(PublicId(id)): Path<PublicId>,
     Query(input): Query<ListMessages>,
 ) -> Result<impl IntoResponse, AppError> {
-    let messages = state.list_messages(input, id).await?;
+    let fields = input.fields;
+    let mut messages = state.list_messages(input, id).await?;
+    if fields != MessageFields::Minimal && caps.supports(Capability::Translations) {
+        let locale = state.get_user_preferences(user.id as _).await?.locale;
+        messages = state.with_translations(messages, locale).await?;
+    }
+    let messages = state.hydrate_messages(messages, fields).await?;
     Ok(Json(messages))
 }
 
@@ -93,26 +127,71 @@ pub(crate) async fn file_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
     Path((ws_id, path)): Path<(i64, String)>,
+    req_headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
 ) -> Result<impl IntoResponse, AppError> {
     if user.ws_id != ws_id {
         return Err(AppError::NotFound(
             "File doesn't exist or you don't have permission".to_string(),
         ));
     }
+    let url = format!("/files/{ws_id}/{path}");
     let base_dir = state.config.server.base_dir.join(ws_id.to_string());
     let path = base_dir.join(path);
-    if !path.exists() {
-        return Err(AppError::NotFound("File doesn't exist".to_string()));
-    }
+    // TODO: streaming
+    let body = if path.exists() {
+        fs::read(&path).await?
+    } else {
+        // offloaded to cold storage with an archived workspace
+        let file = ChatFile::from_str(&url)?;
+        state
+            .read_archived_file(&file)
+            .await?
+            .ok_or_else(|| AppError::NotFound("File doesn't exist".to_string()))?
+    };
+    let ip = client_ip(&req_headers, addr, &state.config.server.trusted_proxies)
+        .map(|ip| ip.to_string());
+    state
+        .record_file_access(ws_id as _, user.id as _, &url, ip)
+        .await?;
 
     let mime = mime_guess::from_path(&path).first_or_octet_stream();
-    // TODO: streaming
-    let body = fs::read(path).await?;
     let mut headers = HeaderMap::new();
     headers.insert("content-type", mime.to_string().parse().unwrap());
     Ok((headers, body))
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/file-access/{ws_id}/{path}",
+    params(
+        ("ws_id" = u64, Path, description = "workspace id"),
+        ("path" = String, Path, description = "relative path of the file")
+    ),
+    responses(
+        (status = 200, description = "Downloads of the file, newest first", body = Vec<FileAccess>),
+        (status = 403, description = "Not the file owner or the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn file_access_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((ws_id, path)): Path<(i64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    if user.ws_id != ws_id {
+        return Err(AppError::NotFound(
+            "File doesn't exist or you don't have permission".to_string(),
+        ));
+    }
+    let url = format!("/files/{ws_id}/{path}");
+    let history = state.list_file_access(ws_id as _, &url, &user).await?;
+    Ok(Json(history))
+}
+
 #[utoipa::path(
     post,
     path = "/api/upload",
@@ -135,7 +214,6 @@ pub(crate) async fn upload_handler(
     mut multipart: Multipart,
 ) -> Result<impl IntoResponse, AppError> {
     let ws_id = user.ws_id as u64;
-    let base_dir = &state.config.server.base_dir;
     let mut files = vec![];
     while let Some(field) = multipart.next_field().await.unwrap() {
         let filename = field.file_name().map(|name| name.to_string());
@@ -144,16 +222,123 @@ pub(crate) async fn upload_handler(
             continue;
         };
 
-        let file = ChatFile::new(ws_id, &filename, &data);
-        let path = file.path(base_dir);
-        if path.exists() {
-            info!("File {} already exists: {:?}", filename, path);
-        } else {
-            fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
-            fs::write(path, data).await?;
-        }
+        let file = state.save_chat_file(ws_id, &filename, &data).await?;
         files.push(file.url());
     }
 
     Ok(Json(files))
 }
+
+// the server time, and the skew of the client clock if it sent `X-Client-Time`, so clients
+// can correct the relative timestamps they render
+pub(crate) fn clock_headers(headers: &HeaderMap, now: DateTime<Utc>) -> HeaderMap {
+    let mut ret = HeaderMap::new();
+    let server_time = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+    if let Ok(v) = HeaderValue::from_str(&server_time) {
+        ret.insert("x-server-time", v);
+    }
+    let client_time = headers
+        .get("x-client-time")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc3339(v.trim()).ok());
+    if let Some(client_time) = client_time {
+        let skew = (client_time.with_timezone(&Utc) - now).num_milliseconds();
+        ret.insert("x-clock-skew-ms", HeaderValue::from(skew));
+    }
+    ret
+}
+
+// the peer, or if it is one of `trusted_proxies` the rightmost address in X-Forwarded-For
+// not added by a trusted proxy. Addresses left of that are set by the client.
+pub(crate) fn client_ip(
+    headers: &HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    trusted_proxies: &[String],
+) -> Option<IpAddr> {
+    let mut ip = addr?.0.ip();
+    let forwarded = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+    for hop in forwarded.into_iter().rev() {
+        if !networks_contain(trusted_proxies, ip) {
+            break;
+        }
+        match hop.trim().parse::<IpAddr>() {
+            Ok(hop) => ip = hop,
+            Err(_) => break,
+        }
+    }
+    Some(ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_headers_should_report_client_skew() {
+        let now = DateTime::parse_from_rfc3339("2024-07-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let ret = clock_headers(&HeaderMap::new(), now);
+        assert_eq!(ret["x-server-time"], "2024-07-01T10:00:00.000Z");
+        assert!(ret.get("x-clock-skew-ms").is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-client-time",
+            HeaderValue::from_static("2024-07-01T11:59:30+02:00"),
+        );
+        let ret = clock_headers(&headers, now);
+        assert_eq!(ret["x-clock-skew-ms"], "-30000");
+
+        headers.insert("x-client-time", HeaderValue::from_static("yesterday"));
+        assert!(clock_headers(&headers, now)
+            .get("x-clock-skew-ms")
+            .is_none());
+    }
+
+    #[test]
+    fn client_ip_should_only_trust_configured_proxies() {
+        let peer = |ip: &str| Some(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 443)));
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        let trusted = vec!["10.0.0.0/8".to_string()];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("10.9.9.9, 203.0.113.7, 10.0.0.2"),
+        );
+
+        // a client sending the header itself
+        assert_eq!(
+            client_ip(&headers, peer("198.51.100.1"), &[]),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            client_ip(&headers, peer("198.51.100.1"), &trusted),
+            ip("198.51.100.1")
+        );
+        // behind two proxies, the client set the leftmost address
+        assert_eq!(
+            client_ip(&headers, peer("10.0.0.1"), &trusted),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            client_ip(&HeaderMap::new(), peer("10.0.0.1"), &trusted),
+            ip("10.0.0.1")
+        );
+
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("evil, 10.0.0.2"),
+        );
+        assert_eq!(
+            client_ip(&headers, peer("10.0.0.1"), &trusted),
+            ip("10.0.0.2")
+        );
+        assert_eq!(client_ip(&headers, None, &trusted), None);
+    }
+}
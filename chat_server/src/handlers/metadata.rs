@@ -0,0 +1,62 @@
+use crate::{AppError, AppState, ChatAction, ChatMetadata, Resource};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/metadata",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Metadata of the chat, an empty object if none is set", body = ChatMetadata),
+        (status = 404, description = "Chat not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn get_chat_metadata_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let metadata = state.get_chat_metadata(id).await?;
+    Ok(Json(metadata))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/metadata",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = ChatMetadata,
+    responses(
+        (status = 200, description = "Metadata is replaced", body = ChatMetadata),
+        (status = 400, description = "Not a JSON object or too large", body = ErrorOutput),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+/// Replace the metadata integrations keep in the chat, e.g. bot state or the ids of linked
+/// CRM records and tickets. Every member can read it.
+pub(crate) async fn set_chat_metadata_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<ChatMetadata>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(user.id as _, ChatAction::UpdateChat, Resource::chat(id))
+        .await?;
+    let metadata = state.set_chat_metadata(id, &input).await?;
+    Ok(Json(metadata))
+}
@@ -1,13 +1,53 @@
+mod admin;
+mod archive;
 mod auth;
 mod chat;
+mod domain;
+mod draft;
+mod export;
+mod guest;
+mod hold;
+mod invite;
 mod messages;
+mod metadata;
+mod notification;
+mod onboarding;
+mod pin;
+mod reaction;
+mod read;
+mod realtime;
+mod task;
+mod template;
+mod token;
+mod user;
+mod webhook;
 mod workspace;
 
 use axum::response::IntoResponse;
 
+pub(crate) use admin::*;
+pub(crate) use archive::*;
 pub(crate) use auth::*;
 pub(crate) use chat::*;
+pub(crate) use domain::*;
+pub(crate) use draft::*;
+pub(crate) use export::*;
+pub(crate) use guest::*;
+pub(crate) use hold::*;
+pub(crate) use invite::*;
 pub(crate) use messages::*;
+pub(crate) use metadata::*;
+pub(crate) use notification::*;
+pub(crate) use onboarding::*;
+pub(crate) use pin::*;
+pub(crate) use reaction::*;
+pub(crate) use read::*;
+pub(crate) use realtime::*;
+pub(crate) use task::*;
+pub(crate) use template::*;
+pub(crate) use token::*;
+pub(crate) use user::*;
+pub(crate) use webhook::*;
 pub(crate) use workspace::*;
 
 pub(crate) async fn index_handler() -> impl IntoResponse {
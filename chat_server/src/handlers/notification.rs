@@ -0,0 +1,59 @@
+use crate::{AppError, AppState, ChatNotificationSettings};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/notifications",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Notification settings of the user for the chat", body = ChatNotificationSettings),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn get_chat_notifications_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let settings = state
+        .get_chat_notification_settings(id, user.id as _)
+        .await?;
+    Ok(Json(settings))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/notifications",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = ChatNotificationSettings,
+    responses(
+        (status = 200, description = "Notification settings after the update", body = ChatNotificationSettings),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn update_chat_notifications_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<ChatNotificationSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let settings = state
+        .set_chat_notification_settings(id, user.id as _, &input)
+        .await?;
+    Ok(Json(settings))
+}
@@ -0,0 +1,69 @@
+use crate::{AppError, AppState};
+use axum::{extract::State, response::IntoResponse, Extension, Json};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/onboarding",
+    responses(
+        (status = 200, description = "Onboarding checklist of the current user", body = Onboarding),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn get_onboarding_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let onboarding = state.get_onboarding(user.ws_id as _, user.id as _).await?;
+    Ok(Json(onboarding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handlers::*, ChatDTO, CreateMessage, Onboarding, OnboardingStep};
+    use anyhow::Result;
+    use axum::extract::Path;
+    use chat_core::PublicId;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn onboarding_should_be_updated_by_handlers() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let input = ChatDTO {
+            name: Some("launch".to_string()),
+            topic: None,
+            members: vec![1, 2],
+            public: true,
+        };
+        create_chat_handler(Extension(user.clone()), State(state.clone()), Json(input)).await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        send_message_handler(
+            Extension(user.clone()),
+            State(state.clone()),
+            Path(PublicId(1)),
+            axum::http::HeaderMap::new(),
+            Json(input),
+        )
+        .await?;
+
+        let res = get_onboarding_handler(Extension(user), State(state))
+            .await?
+            .into_response();
+        let body = res.into_body().collect().await?.to_bytes();
+        let onboarding: Onboarding = serde_json::from_slice(&body)?;
+        assert!(onboarding.completed);
+        assert_eq!(
+            onboarding.steps[0].step,
+            OnboardingStep::CreatedFirstChannel
+        );
+        Ok(())
+    }
+}
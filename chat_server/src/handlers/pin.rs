@@ -0,0 +1,131 @@
+use crate::{AppError, AppState, PinLimit, PinMessage, ReorderPins};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/pins",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Pinned messages, top first", body = PinList),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn list_pins_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.list_pins(id).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/pins",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = PinMessage,
+    responses(
+        (status = 200, description = "Pinned messages after pinning", body = PinList),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+        (status = 409, description = "Pin limit reached", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn pin_message_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<PinMessage>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.pin_message(id, &input, user.id as _).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/pins/{message_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Pinned message id"),
+    ),
+    responses(
+        (status = 200, description = "Pinned messages after unpinning", body = PinList),
+        (status = 404, description = "Message is not pinned", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn unpin_message_handler(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(PublicId, PublicId)>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, message_id) = (*id, *message_id);
+    let pins = state.unpin_message(id, message_id).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/pins/order",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = ReorderPins,
+    responses(
+        (status = 200, description = "Pinned messages in the new order", body = PinList),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn reorder_pins_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<ReorderPins>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.reorder_pins(id, &input).await?;
+    Ok(Json(pins))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/pins/limit",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = PinLimit,
+    responses(
+        (status = 200, description = "Pinned messages with the new limit", body = PinList),
+        (status = 400, description = "Invalid input", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn set_pin_limit_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<PinLimit>,
+) -> Result<impl IntoResponse, AppError> {
+    let pins = state.set_pin_limit(id, input.limit).await?;
+    Ok(Json(pins))
+}
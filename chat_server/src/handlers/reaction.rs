@@ -0,0 +1,204 @@
+use crate::{AppError, AppState, CreateReactionTrigger, ReactionAnalyticsQuery};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/messages/{message_id}/reactions",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id"),
+    ),
+    responses(
+        (status = 200, description = "Reactions of the message", body = MessageReactions),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn list_reactions_handler(
+    State(state): State<AppState>,
+    Path((id, message_id)): Path<(PublicId, PublicId)>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, message_id) = (*id, *message_id);
+    let reactions = state.list_reactions(id, message_id).await?;
+    Ok(Json(reactions))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/messages/{message_id}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji to react with"),
+    ),
+    responses(
+        (status = 200, description = "Reactions after reacting, with the triggers run", body = MessageReactions),
+        (status = 400, description = "Invalid emoji", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn add_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, message_id, emoji)): Path<(PublicId, PublicId, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, message_id) = (*id, *message_id);
+    let reactions = state
+        .add_reaction(id, message_id, user.id as _, &emoji)
+        .await?;
+    Ok(Json(reactions))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/messages/{message_id}/reactions/{emoji}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("message_id" = u64, Path, description = "Message id"),
+        ("emoji" = String, Path, description = "Emoji to remove"),
+    ),
+    responses(
+        (status = 200, description = "Reactions after removing", body = MessageReactions),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "message"
+)]
+pub(crate) async fn remove_reaction_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, message_id, emoji)): Path<(PublicId, PublicId, String)>,
+) -> Result<impl IntoResponse, AppError> {
+    let (id, message_id) = (*id, *message_id);
+    let reactions = state
+        .remove_reaction(id, message_id, user.id as _, &emoji)
+        .await?;
+    Ok(Json(reactions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/reaction-triggers",
+    responses(
+        (status = 200, description = "Reaction triggers of the workspace", body = Vec<ReactionTrigger>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_reaction_triggers_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage reaction triggers")
+        .await?;
+    let triggers = state.list_reaction_triggers(ws.id as _).await?;
+    Ok(Json(triggers))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/reaction-triggers",
+    request_body = CreateReactionTrigger,
+    responses(
+        (status = 201, description = "Reaction trigger created", body = ReactionTrigger),
+        (status = 400, description = "Invalid trigger", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn create_reaction_trigger_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateReactionTrigger>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage reaction triggers")
+        .await?;
+    let trigger = state
+        .create_reaction_trigger(ws.id as _, &input, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(trigger)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/reaction-triggers/{id}",
+    params(
+        ("id" = u64, Path, description = "Reaction trigger id"),
+    ),
+    responses(
+        (status = 200, description = "Reaction trigger is deleted", body = String),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Reaction trigger not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn delete_reaction_trigger_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage reaction triggers")
+        .await?;
+    match state.delete_reaction_trigger(ws.id as _, id).await? {
+        true => Ok(format!("reaction trigger id {id} has been deleted")),
+        false => Err(AppError::NotFound(format!("reaction trigger id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspaces/{id}/analytics/reactions",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+        ReactionAnalyticsQuery,
+    ),
+    responses(
+        (status = 200, description = "Most used reactions of the workspace's channels", body = ReactionAnalytics),
+        (status = 400, description = "Invalid number of days", body = ErrorOutput),
+        (status = 403, description = "Not an owner or admin of the workspace", body = ErrorOutput),
+        (status = 404, description = "Workspace not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn reaction_analytics_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+    Query(query): Query<ReactionAnalyticsQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_admin(&user, id, "view analytics")
+        .await?;
+    let analytics = state.reaction_analytics(ws.id as _, query.days).await?;
+    Ok(Json(analytics))
+}
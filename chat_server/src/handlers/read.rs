@@ -0,0 +1,171 @@
+use crate::{AppError, AppState, ChatFolder, MarkChatRead};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/unread",
+    responses(
+        (status = 200, description = "Unread messages per chat, chats without any are left out", body = Vec<ChatRead>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_unread_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let unread = state.list_unread_counts(user.id as _).await?;
+    Ok(Json(unread))
+}
+
+/// Also routed as `/api/chats/read-all`.
+#[utoipa::path(
+    post,
+    path = "/api/chats/read_all",
+    responses(
+        (status = 200, description = "Chats marked as read", body = ReadState),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn read_all_chats_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state.mark_chats_read(user.id as _, None).await?;
+    Ok(Json(read))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/folders/{folder}/read",
+    params(
+        ("folder" = ChatFolder, Path, description = "Folder of the chat list"),
+    ),
+    responses(
+        (status = 200, description = "Chats of the folder marked as read", body = ReadState),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn read_folder_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(folder): Path<ChatFolder>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state.mark_chats_read(user.id as _, Some(folder)).await?;
+    Ok(Json(read))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/read",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = MarkChatRead,
+    responses(
+        (status = 200, description = "Read state of the chat", body = ChatRead),
+        (status = 404, description = "Message not found in the chat", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+/// Mark the messages of the chat as read up to `last_read_id`, or all of them.
+///
+/// - The marker only moves forward, an older message leaves it where it is.
+/// - The caller's other clients get a `ChatRead` event when it moved.
+pub(crate) async fn mark_chat_read_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<MarkChatRead>,
+) -> Result<impl IntoResponse, AppError> {
+    let read = state.mark_chat_read(id as _, user.id as _, &input).await?;
+    Ok(Json(read))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppState, SessionMethod};
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn mark_chat_read_should_need_membership() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = crate::get_router(state.clone()).await?;
+        let req = |token: &str| {
+            Request::builder()
+                .method("PUT")
+                .uri("/api/chats/2/read")
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from("{}"))
+        };
+
+        // chat 2 has members 1, 2 and 3
+        let user = state.find_user_by_id(4).await?.expect("user should exist");
+        let token = state
+            .create_session(user, SessionMethod::Password, None)
+            .await?;
+        let res = app.clone().oneshot(req(&token)?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let (reads,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM chat_reads WHERE user_id = 4")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(reads, 0);
+
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let token = state
+            .create_session(user, SessionMethod::Password, None)
+            .await?;
+        let res = app.oneshot(req(&token)?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_all_should_mark_every_chat_read() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = crate::get_router(state.clone()).await?;
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let token = state
+            .create_session(user, SessionMethod::Password, None)
+            .await?;
+        let req = |uri: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        assert!(!state.list_unread_counts(2).await?.is_empty());
+        let res = app.clone().oneshot(req("/api/chats/read_all")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(state.list_unread_counts(2).await?.is_empty());
+
+        // the hyphenated path is kept as an alias
+        let res = app.oneshot(req("/api/chats/read-all")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(())
+    }
+}
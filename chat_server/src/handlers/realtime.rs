@@ -0,0 +1,30 @@
+use crate::{AppState, ListRealtimeEndpoints};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+
+#[utoipa::path(
+    get,
+    path = "/api/realtime/endpoints",
+    params(
+        ListRealtimeEndpoints
+    ),
+    responses(
+        (status = 200, description = "Realtime endpoints, the one to connect to first", body = Vec<RealtimeEndpoint>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+/// List the notify_server nodes clients can stream events from, healthy nodes in the
+/// client's region first. Clients try them in order and fall back to the next one.
+pub(crate) async fn list_realtime_endpoints_handler(
+    State(state): State<AppState>,
+    Query(input): Query<ListRealtimeEndpoints>,
+) -> impl IntoResponse {
+    let endpoints = state.list_realtime_endpoints(input.region.as_deref()).await;
+    Json(endpoints)
+}
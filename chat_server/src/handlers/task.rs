@@ -0,0 +1,110 @@
+use crate::{AppError, AppState, CreateTask, ListTasks, UpdateTask};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/tasks",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ListTasks,
+    ),
+    responses(
+        (status = 200, description = "Tasks of the chat", body = Vec<Task>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_chat_tasks_handler(
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Query(input): Query<ListTasks>,
+) -> Result<impl IntoResponse, AppError> {
+    let tasks = state.list_chat_tasks(id, &input).await?;
+    Ok(Json(tasks))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/chats/{id}/tasks",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = CreateTask,
+    responses(
+        (status = 201, description = "Message converted to a task", body = Task),
+        (status = 400, description = "Invalid task", body = ErrorOutput),
+        (status = 404, description = "Message not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn create_task_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<CreateTask>,
+) -> Result<impl IntoResponse, AppError> {
+    let task = state.create_task(id, &input, user.id as _).await?;
+    Ok((StatusCode::CREATED, Json(task)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/chats/{id}/tasks/{task_id}",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+        ("task_id" = u64, Path, description = "Task id"),
+    ),
+    request_body = UpdateTask,
+    responses(
+        (status = 200, description = "Task updated", body = Task),
+        (status = 400, description = "Invalid task", body = ErrorOutput),
+        (status = 404, description = "Task not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn update_task_handler(
+    State(state): State<AppState>,
+    Path((id, task_id)): Path<(PublicId, u64)>,
+    Json(input): Json<UpdateTask>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = *id;
+    let task = state.update_task(id, task_id, &input).await?;
+    Ok(Json(task))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/tasks",
+    params(
+        ListTasks,
+    ),
+    responses(
+        (status = 200, description = "Tasks assigned to the current user", body = Vec<Task>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_my_tasks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListTasks>,
+) -> Result<impl IntoResponse, AppError> {
+    let tasks = state.list_user_tasks(user.id as _, &input).await?;
+    Ok(Json(tasks))
+}
@@ -0,0 +1,119 @@
+use crate::{AppError, AppState, ChannelFromTemplate, CreateChannelTemplate};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/channel-templates",
+    responses(
+        (status = 200, description = "Channel templates of the workspace", body = Vec<ChannelTemplate>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn list_channel_templates_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let templates = state.list_channel_templates(user.ws_id as _).await?;
+    Ok(Json(templates))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/channel-templates",
+    request_body = CreateChannelTemplate,
+    responses(
+        (status = 201, description = "Channel template created", body = ChannelTemplate),
+        (status = 400, description = "Invalid template", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn create_channel_template_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateChannelTemplate>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage channel templates")
+        .await?;
+    let template = state
+        .create_channel_template(ws.id as _, &input, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(template)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/channel-templates/{id}",
+    params(
+        ("id" = u64, Path, description = "Channel template id"),
+    ),
+    responses(
+        (status = 200, description = "Channel template is deleted", body = String),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Channel template not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn delete_channel_template_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage channel templates")
+        .await?;
+    match state.delete_channel_template(ws.id as _, id).await? {
+        true => Ok(format!("channel template id {id} has been deleted")),
+        false => Err(AppError::NotFound(format!("channel template id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspaces/{id}/channels/from-template/{tpl}",
+    params(
+        ("id" = u64, Path, description = "Workspace id"),
+        ("tpl" = String, Path, description = "Channel template name"),
+    ),
+    request_body = ChannelFromTemplate,
+    responses(
+        (status = 201, description = "Channel created from the template", body = Chat),
+        (status = 400, description = "Invalid channel", body = ErrorOutput),
+        (status = 404, description = "Workspace or template not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn create_channel_from_template_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path((id, tpl)): Path<(u64, String)>,
+    Json(input): Json<ChannelFromTemplate>,
+) -> Result<impl IntoResponse, AppError> {
+    // users only see their own workspace
+    if id != user.ws_id as u64 {
+        return Err(AppError::NotFound(format!("workspace id {id}")));
+    }
+    let chat = state
+        .create_channel_from_template(id, &tpl, &input, &user)
+        .await?;
+    Ok((StatusCode::CREATED, Json(chat)))
+}
@@ -0,0 +1,75 @@
+use crate::{AppError, AppState, CreatePersonalToken};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::User;
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/tokens",
+    responses(
+        (status = 200, description = "Personal access tokens of the current user", body = Vec<PersonalToken>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_personal_tokens_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let tokens = state.list_personal_tokens(user.id as _).await?;
+    Ok(Json(tokens))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/users/me/tokens",
+    request_body = CreatePersonalToken,
+    responses(
+        (status = 201, description = "Token created, the secret is only shown once", body = NewPersonalToken),
+        (status = 400, description = "Invalid name, scopes or expiry", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn create_personal_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreatePersonalToken>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = state.create_personal_token(user.id as _, &input).await?;
+    Ok((StatusCode::CREATED, Json(token)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/users/me/tokens/{id}",
+    params(
+        ("id" = u64, Path, description = "Token id"),
+    ),
+    responses(
+        (status = 200, description = "Token is revoked", body = String),
+        (status = 404, description = "Token not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn revoke_personal_token_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    match state.revoke_personal_token(user.id as _, id).await? {
+        true => Ok(format!("token id {id} has been deleted")),
+        false => Err(AppError::NotFound(format!("token id {id}"))),
+    }
+}
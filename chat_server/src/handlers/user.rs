@@ -0,0 +1,80 @@
+use crate::{AppError, AppState, UserPreferences};
+use axum::{extract::State, response::IntoResponse, Extension, Json};
+use chat_core::User;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct SystemMessagesOutput {
+    pub locale: String,
+    /// system message templates keyed by kind, with `{name}` placeholders
+    pub templates: BTreeMap<String, String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/users/me/preferences",
+    responses(
+        (status = 200, description = "Preferences of current user", body = UserPreferences),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn get_preferences_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let prefs = state.get_user_preferences(user.id as _).await?;
+    Ok(Json(prefs))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/users/me/preferences",
+    request_body = UserPreferences,
+    responses(
+        (status = 200, description = "Preferences updated", body = UserPreferences),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn update_preferences_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<UserPreferences>,
+) -> Result<impl IntoResponse, AppError> {
+    let prefs = state.update_user_preferences(user.id as _, &input).await?;
+    Ok(Json(prefs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/i18n/system-messages",
+    responses(
+        (status = 200, description = "System message templates in the user's locale", body = SystemMessagesOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn system_messages_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let locale = state.get_user_preferences(user.id as _).await?.locale;
+    let templates = locale
+        .system_messages()
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    Ok(Json(SystemMessagesOutput {
+        locale: locale.to_string(),
+        templates,
+    }))
+}
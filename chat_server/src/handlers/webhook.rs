@@ -0,0 +1,195 @@
+use crate::{AppError, AppState, ChatAction, CreateWebhook, Resource, SetChatArchivalWebhook};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User};
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/webhooks",
+    responses(
+        (status = 200, description = "Webhooks of the workspace", body = Vec<Webhook>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_webhooks_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage webhooks")
+        .await?;
+    let webhooks = state.list_webhooks(ws.id as _).await?;
+    Ok(Json(webhooks))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "Webhook registered", body = Webhook),
+        (status = 400, description = "Invalid url or filter", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn create_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<CreateWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage webhooks")
+        .await?;
+    let webhook = state
+        .create_webhook(ws.id as _, &input, user.id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/webhooks/{id}",
+    params(
+        ("id" = u64, Path, description = "Webhook id"),
+    ),
+    responses(
+        (status = 200, description = "Webhook is deleted", body = String),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "Webhook not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn delete_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "manage webhooks")
+        .await?;
+    match state.delete_webhook(ws.id as _, id).await? {
+        true => Ok(format!("webhook id {id} has been deleted")),
+        false => Err(AppError::NotFound(format!("webhook id {id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/chats/{id}/archival-webhook",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 200, description = "Archival webhook of the chat", body = ChatArchivalWebhook),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+        (status = 404, description = "The chat has no archival webhook", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn get_chat_archival_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(
+            user.id as _,
+            ChatAction::ManageArchivalWebhook,
+            Resource::chat(id),
+        )
+        .await?;
+    match state.get_chat_archival_webhook(id).await? {
+        Some(webhook) => Ok(Json(webhook)),
+        None => Err(AppError::NotFound(format!("archival webhook of chat {id}"))),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/chats/{id}/archival-webhook",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    request_body = SetChatArchivalWebhook,
+    responses(
+        (status = 200, description = "Archival webhook is set", body = ChatArchivalWebhook),
+        (status = 400, description = "Invalid url or delay", body = ErrorOutput),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+/// Mirror the messages of the chat to an external records system. Each message is posted
+/// `delay_secs` after it is sent, signed with the secret in `X-Chat-Signature`.
+pub(crate) async fn set_chat_archival_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+    Json(input): Json<SetChatArchivalWebhook>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(
+            user.id as _,
+            ChatAction::ManageArchivalWebhook,
+            Resource::chat(id),
+        )
+        .await?;
+    let webhook = state
+        .set_chat_archival_webhook(id, &input, user.id as _)
+        .await?;
+    Ok(Json(webhook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/chats/{id}/archival-webhook",
+    params(
+        ("id" = u64, Path, description = "Chat id"),
+    ),
+    responses(
+        (status = 204, description = "Archival webhook is removed"),
+        (status = 403, description = "Not a chat owner or admin", body = ErrorOutput),
+        (status = 404, description = "The chat has no archival webhook", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "chat"
+)]
+pub(crate) async fn delete_chat_archival_webhook_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    state
+        .authorize(
+            user.id as _,
+            ChatAction::ManageArchivalWebhook,
+            Resource::chat(id),
+        )
+        .await?;
+    if !state.delete_chat_archival_webhook(id).await? {
+        return Err(AppError::NotFound(format!("archival webhook of chat {id}")));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
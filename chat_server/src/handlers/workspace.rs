@@ -1,6 +1,31 @@
-use crate::{AppError, AppState};
-use axum::{extract::State, response::IntoResponse, Extension, Json};
-use chat_core::User;
+use super::client_ip;
+use crate::{
+    AppError, AppState, Capability, ClientCapabilities, ListAuditLogs, SecurityPolicy,
+    TransferWorkspace,
+};
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chat_core::{PublicId, User, Workspace, WorkspaceSettings};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use utoipa::ToSchema;
+
+/// Everything a client needs right after signing in.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct BootstrapOutput {
+    pub user: User,
+    pub workspace: Workspace,
+    /// capabilities of the client the server honors, all of them without
+    /// `X-Client-Capabilities`
+    pub capabilities: Vec<Capability>,
+    /// clock of the server, for clients to correct the skew of the device clock
+    pub server_time: DateTime<Utc>,
+}
 
 #[utoipa::path(
     get,
@@ -21,3 +46,330 @@ pub(crate) async fn list_chat_users_handler(
     let users = state.fetch_chat_users(user.ws_id as _).await?;
     Ok(Json(users))
 }
+
+#[utoipa::path(
+    get,
+    path = "/api/bootstrap",
+    responses(
+        (status = 200, description = "Current user and workspace", body = BootstrapOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+/// Load the current user and workspace, clients call it once when they start.
+///
+/// - Clients declare the payload features they support in `X-Client-Capabilities`, e.g.
+///   `translations, chat_avatars`, fields of other features are left out of responses.
+/// - The declared capabilities are counted for `/api/admin/client-capabilities`.
+pub(crate) async fn bootstrap_handler(
+    Extension(user): Extension<User>,
+    Extension(caps): Extension<ClientCapabilities>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let workspace = state
+        .find_workspace_by_id(user.ws_id as _)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("workspace id {}", user.ws_id)))?;
+    state.record_client_capabilities(&caps).await?;
+    Ok(Json(BootstrapOutput {
+        user,
+        workspace,
+        capabilities: caps.supported(),
+        server_time: Utc::now(),
+    }))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/workspace/settings",
+    request_body = WorkspaceSettings,
+    responses(
+        (status = 200, description = "Workspace settings updated", body = Workspace),
+        (status = 400, description = "Invalid settings", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn update_workspace_settings_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<WorkspaceSettings>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws_id = state
+        .verify_workspace_owner(&user, "update settings")
+        .await?
+        .id as u64;
+    let ws = state.update_workspace_settings(ws_id, &input).await?;
+    match ws {
+        Some(ws) => Ok(Json(ws)),
+        None => Err(AppError::NotFound(format!("workspace id {ws_id}"))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/security-policy",
+    responses(
+        (status = 200, description = "Security policy of the workspace", body = SecurityPolicy),
+        (status = 403, description = "Not a workspace owner or admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn get_security_policy_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_admin(&user, user.ws_id as _, "view the security policy")
+        .await?;
+    let policy = state.get_security_policy(ws.id as _).await?;
+    Ok(Json(policy))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/workspace/security-policy",
+    request_body = SecurityPolicy,
+    responses(
+        (status = 200, description = "Security policy updated", body = SecurityPolicy),
+        (status = 400, description = "Invalid policy", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+/// Replace the security policy of the workspace, it applies to the next request of every
+/// member.
+///
+/// - The IP allowlist has to include the address of this request.
+/// - Sessions signed in before a limit was set count from their sign in.
+pub(crate) async fn update_security_policy_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(input): Json<SecurityPolicy>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "update the security policy")
+        .await?;
+    let ip =
+        client_ip(&headers, addr, &state.config.server.trusted_proxies).map(|ip| ip.to_string());
+    let policy = state
+        .update_security_policy(ws.id as _, input, user.id as _, ip.as_deref())
+        .await?;
+    Ok(Json(policy))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/audit-logs",
+    params(
+        ListAuditLogs
+    ),
+    responses(
+        (status = 200, description = "Audit logs, newest first", body = Vec<AuditLog>),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_audit_logs_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Query(input): Query<ListAuditLogs>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "view audit logs")
+        .await?;
+    let logs = state.list_audit_logs(ws.id as _, &input).await?;
+    Ok(Json(logs))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/admins",
+    responses(
+        (status = 200, description = "Admins of the workspace, oldest first", body = Vec<WorkspaceAdmin>),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn list_workspace_admins_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let admins = state.list_workspace_admins(user.ws_id as _).await?;
+    Ok(Json(admins))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/workspace/admins/{user_id}",
+    params(
+        ("user_id" = u64, Path, description = "User id"),
+    ),
+    responses(
+        (status = 200, description = "User is an admin", body = WorkspaceAdmin),
+        (status = 400, description = "User can't be an admin", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn add_workspace_admin_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(user_id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.verify_workspace_owner(&user, "manage admins").await?;
+    let admin = state.add_workspace_admin(&ws, user_id).await?;
+    Ok(Json(admin))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/admins/{user_id}",
+    params(
+        ("user_id" = u64, Path, description = "User id"),
+    ),
+    responses(
+        (status = 204, description = "User is no longer an admin"),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "User is not an admin", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn remove_workspace_admin_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(PublicId(user_id)): Path<PublicId>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state.verify_workspace_owner(&user, "manage admins").await?;
+    if state.remove_workspace_admin(ws.id as _, user_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound(format!("workspace admin {user_id}")))
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/workspace/transfer",
+    responses(
+        (status = 200, description = "Pending ownership transfer", body = WorkspaceTransfer),
+        (status = 404, description = "No transfer pending for the user", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn get_workspace_transfer_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    // only visible to the owner and the new owner
+    match state.get_workspace_transfer(user.ws_id as _).await? {
+        Some(t) if t.from_id == user.id || t.to_id == user.id => Ok(Json(t)),
+        _ => Err(AppError::NotFound("workspace transfer".to_string())),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/transfer",
+    request_body = TransferWorkspace,
+    responses(
+        (status = 201, description = "Transfer is waiting for the new owner", body = WorkspaceTransfer),
+        (status = 400, description = "User can't own the workspace", body = ErrorOutput),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "User not found", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn create_workspace_transfer_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Json(input): Json<TransferWorkspace>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "transfer the workspace")
+        .await?;
+    let transfer = state
+        .create_workspace_transfer(&ws, input.user_id as _)
+        .await?;
+    Ok((StatusCode::CREATED, Json(transfer)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/workspace/transfer",
+    responses(
+        (status = 204, description = "Transfer is cancelled"),
+        (status = 403, description = "Not the workspace owner", body = ErrorOutput),
+        (status = 404, description = "No transfer pending", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn cancel_workspace_transfer_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .verify_workspace_owner(&user, "transfer the workspace")
+        .await?;
+    if state.cancel_workspace_transfer(ws.id as _).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::NotFound("workspace transfer".to_string()))
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/workspace/transfer/accept",
+    responses(
+        (status = 200, description = "The user owns the workspace", body = Workspace),
+        (status = 404, description = "No transfer pending for the user", body = ErrorOutput),
+    ),
+    security(
+        ("token" = [])
+    ),
+    tag = "user"
+)]
+pub(crate) async fn accept_workspace_transfer_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let ws = state
+        .accept_workspace_transfer(user.ws_id as _, user.id as _)
+        .await?;
+    Ok(Json(ws))
+}
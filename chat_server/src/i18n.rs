@@ -0,0 +1,709 @@
+//! A small gettext style message catalog. Messages are keyed by their english text
+//! (the msgid), `{name}` placeholders are carried over to the translation.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    #[serde(rename = "en")]
+    En,
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+}
+
+// (msgid, zh-CN)
+const MESSAGES: &[(&str, &str)] = &[
+    (
+        "Avatar image is missing",
+        "缺少头像图片",
+    ),
+    (
+        "Avatar must be a png, jpeg, gif or webp image",
+        "头像必须是 png、jpeg、gif 或 webp 图片",
+    ),
+    (
+        "Avatar must be at most {size} KiB",
+        "头像最大为 {size} KiB",
+    ),
+    (
+        "create chat error: Chat must have at least 2 members",
+        "创建聊天失败：聊天至少需要 2 名成员",
+    ),
+    (
+        "create chat error: Group chat with more than 8 members must have a name",
+        "创建聊天失败：超过 8 名成员的群聊必须有名称",
+    ),
+    (
+        "create chat error: Some members do not exist",
+        "创建聊天失败：部分成员不存在",
+    ),
+    (
+        "create chat error: Some members are not in the workspace",
+        "创建聊天失败：部分成员不在该工作区",
+    ),
+    (
+        "create chat error: Topic must be at most 250 characters",
+        "创建聊天失败：主题最多 250 个字符",
+    ),
+    (
+        "create message error: Content cannot be empty",
+        "发送消息失败：内容不能为空",
+    ),
+    (
+        "create message error: File {file} doesn't exist",
+        "发送消息失败：文件 {file} 不存在",
+    ),
+    (
+        "permission denied: User {user} is not a member of chat {chat}",
+        "权限不足：用户 {user} 不是聊天 {chat} 的成员",
+    ),
+    (
+        "workspace error: Logo must be an uploaded file or a http(s) url",
+        "工作区错误：Logo 必须是已上传的文件或 http(s) 地址",
+    ),
+    (
+        "workspace error: Accent color must be in #rrggbb format",
+        "工作区错误：主题色必须是 #rrggbb 格式",
+    ),
+    (
+        "workspace error: Welcome message must be at most {max} characters",
+        "工作区错误：欢迎语最多 {max} 个字符",
+    ),
+    (
+        "pin error: pin limit must be between 1 and {max}",
+        "置顶失败：置顶数量上限必须在 1 到 {max} 之间",
+    ),
+    (
+        "pin limit reached: chat {chat} already has {limit} pins, unpin one or replace message {message}",
+        "置顶已达上限：聊天 {chat} 已有 {limit} 条置顶，请先取消一条或替换消息 {message}",
+    ),
+    (
+        "member limit reached: group chats can have at most {limit} members",
+        "成员数已达上限：群聊最多只能有 {limit} 名成员",
+    ),
+    (
+        "member limit reached: private channels can have at most {limit} members",
+        "成员数已达上限：私有频道最多只能有 {limit} 名成员",
+    ),
+    (
+        "version conflict: chat {chat} was updated to version {version}, reload it",
+        "版本冲突：聊天 {chat} 已更新到版本 {version}，请重新加载",
+    ),
+    (
+        "member limit reached: public channels can have at most {limit} members",
+        "成员数已达上限：公开频道最多只能有 {limit} 名成员",
+    ),
+    (
+        "slow mode: wait {seconds} seconds before sending another message",
+        "慢速模式：请等待 {seconds} 秒后再发送消息",
+    ),
+    (
+        "too many signups: try again in {seconds} seconds",
+        "注册过于频繁：请在 {seconds} 秒后重试",
+    ),
+    (
+        "captcha error: Captcha is missing or invalid",
+        "验证码错误：验证码缺失或无效",
+    ),
+    ("guest error: Link has expired", "访客错误：链接已过期"),
+    (
+        "unauthorized: guest access has expired",
+        "未授权：访客权限已过期",
+    ),
+    (
+        "permission denied: guests can only access chat {id}",
+        "权限不足：访客只能访问聊天 {id}",
+    ),
+    (
+        "permission denied: exporting chat {id} is not allowed",
+        "权限不足：不允许导出聊天 {id}",
+    ),
+    (
+        "permission denied: only the workspace owner can update chat settings",
+        "权限不足：只有工作区所有者可以修改聊天设置",
+    ),
+    (
+        "permission denied: only the workspace owner can view audit logs",
+        "权限不足：只有工作区所有者可以查看审计日志",
+    ),
+    (
+        "permission denied: only the workspace owner can manage domains",
+        "权限不足：只有工作区所有者可以管理域名",
+    ),
+    ("workspace error: Invalid domain {domain}", "工作区错误：无效的域名 {domain}"),
+    (
+        "workspace error: Domain {domain} is already added",
+        "工作区错误：域名 {domain} 已添加",
+    ),
+    (
+        "workspace error: Domain {domain} is already verified by a workspace",
+        "工作区错误：域名 {domain} 已被其他工作区验证",
+    ),
+    (
+        "workspace error: TXT record {record} not found on {domain}",
+        "工作区错误：未在 {domain} 上找到 TXT 记录 {record}",
+    ),
+    (
+        "workspace error: Email {email} is not on domain {domain}",
+        "工作区错误：邮箱 {email} 不属于域名 {domain}",
+    ),
+    (
+        "workspace error: Invalid or expired verification code",
+        "工作区错误：验证码无效或已过期",
+    ),
+    ("Not found: domain id {id}", "未找到：域名 {id}"),
+    ("Not found: archival webhook of chat {id}", "未找到：聊天 {id} 的归档 Webhook"),
+    ("reaction error: Invalid emoji {emoji}", "表情回应错误：无效的表情 {emoji}"),
+    (
+        "reaction error: Rate limit must be between 1 and 600 per minute",
+        "表情回应错误：频率限制必须在每分钟 1 到 600 次之间",
+    ),
+    (
+        "reaction error: Webhook url must be a http(s) url",
+        "表情回应错误：Webhook 必须是 http(s) 地址",
+    ),
+    ("reaction error: Days must be between 1 and 365", "表情回应错误：天数必须在 1 到 365 之间"),
+    (
+        "permission denied: only the workspace owner can manage reaction triggers",
+        "权限不足：只有工作区所有者可以管理表情触发器",
+    ),
+    ("task error: message {id} is already a task", "任务错误：消息 {id} 已经是任务"),
+    ("task error: message {id} is not a task", "任务错误：消息 {id} 不是任务"),
+    (
+        "task error: Title must be between 1 and 256 characters",
+        "任务错误：标题长度必须在 1 到 256 个字符之间",
+    ),
+    (
+        "task error: Assignee {user} is not a member of chat {chat}",
+        "任务错误：负责人 {user} 不是聊天 {chat} 的成员",
+    ),
+    ("Not found: task id {id}", "未找到：任务 {id}"),
+    (
+        "workspace error: Template name must be between 1 and 64 characters",
+        "工作区错误：模板名称长度必须在 1 到 64 个字符之间",
+    ),
+    (
+        "workspace error: Name pattern must be between 1 and 64 characters",
+        "工作区错误：名称模式长度必须在 1 到 64 个字符之间",
+    ),
+    (
+        "workspace error: Some members are not in the workspace",
+        "工作区错误：部分成员不在工作区中",
+    ),
+    (
+        "workspace error: Some groups are not in the workspace",
+        "工作区错误：部分群组不在工作区中",
+    ),
+    ("workspace error: Template {name} already exists", "工作区错误：模板 {name} 已存在"),
+    (
+        "permission denied: only the workspace owner can manage channel templates",
+        "权限不足：只有工作区所有者可以管理频道模板",
+    ),
+    ("Not found: channel template id {id}", "未找到：频道模板 {id}"),
+    ("Not found: channel template {name}", "未找到：频道模板 {name}"),
+    (
+        "permission denied: only the file owner or the workspace owner can view its access history",
+        "权限不足：只有文件所有者或工作区所有者可以查看其访问记录",
+    ),
+    (
+        "webhook error: Webhook url must be a http(s) url",
+        "Webhook 错误：Webhook 必须是 http(s) 地址",
+    ),
+    ("webhook error: Invalid filter: {reason}", "Webhook 错误：无效的过滤条件：{reason}"),
+    (
+        "webhook error: Delay must be 0 to {max} seconds",
+        "Webhook 错误：延迟必须在 0 到 {max} 秒之间",
+    ),
+    (
+        "permission denied: only chat owners and admins can manage the archival webhook",
+        "权限不足：只有聊天所有者和管理员可以管理归档 Webhook",
+    ),
+    (
+        "permission denied: only the workspace owner can manage webhooks",
+        "权限不足：只有工作区所有者可以管理 Webhook",
+    ),
+    ("Not found: webhook id {id}", "未找到：Webhook {id}"),
+    (
+        "token error: Name must be between 1 and 64 characters",
+        "令牌错误：名称长度必须在 1 到 64 个字符之间",
+    ),
+    ("token error: At least one scope is required", "令牌错误：至少需要一个权限范围"),
+    ("token error: Unknown scope {scope}", "令牌错误：未知的权限范围 {scope}"),
+    (
+        "token error: Scope {scope} can't be combined with other scopes",
+        "令牌错误：权限范围 {scope} 不能与其他权限范围组合",
+    ),
+    (
+        "token error: Expiry must be between 1 and {max} days",
+        "令牌错误：有效期必须在 1 到 {max} 天之间",
+    ),
+    ("Not found: token id {id}", "未找到：令牌 {id}"),
+    (
+        "permission denied: only the workspace owner can archive the workspace",
+        "权限不足：只有工作区所有者可以归档工作区",
+    ),
+    (
+        "permission denied: only the workspace owner can unarchive the workspace",
+        "权限不足：只有工作区所有者可以取消归档工作区",
+    ),
+    (
+        "permission denied: only the workspace owner can download the archive",
+        "权限不足：只有工作区所有者可以下载归档",
+    ),
+    ("workspace error: Workspace is already archived", "工作区错误：工作区已归档"),
+    (
+        "workspace error: Workspace is not archived or is still being archived",
+        "工作区错误：工作区未归档或仍在归档中",
+    ),
+    ("permission denied: workspace is archived", "权限不足：工作区已归档"),
+    (
+        "permission denied: workspace is archived, unarchive it to make changes",
+        "权限不足：工作区已归档，取消归档后才能修改",
+    ),
+    ("Not found: archive of workspace id {id}", "未找到：工作区 {id} 的归档"),
+    (
+        "permission denied: encryption can't be turned off for a chat",
+        "权限不足：聊天的加密无法关闭",
+    ),
+    (
+        "permission denied: {feature} is disabled in encrypted chats",
+        "权限不足：加密聊天中已禁用 {feature}",
+    ),
+    (
+        "create message error: A bulk message must have between 1 and {max} targets",
+        "发送消息失败：群发消息的目标数量必须在 1 到 {max} 之间",
+    ),
+    ("create message error: Workspace is archived", "发送消息失败：工作区已归档"),
+    (
+        "create message error: Can't send a direct message to yourself",
+        "发送消息失败：不能给自己发送私信",
+    ),
+    ("Not found: bulk message id {id}", "未找到：群发消息 {id}"),
+    (
+        "quota exceeded: workspace has reached its {resource} limit",
+        "超出配额：工作区的 {resource} 已达上限",
+    ),
+    ("workspace error: Plan name cannot be empty", "工作区错误：套餐名称不能为空"),
+    ("workspace error: Plan limits cannot be negative", "工作区错误：套餐限制不能为负数"),
+    ("Not found: plan id {id}", "未找到：套餐 {id}"),
+    (
+        "create chat error: Members of a single chat can't be changed",
+        "创建聊天失败：单聊的成员无法修改",
+    ),
+    (
+        "create chat error: Members of a single chat can't be changed, convert it to a group first",
+        "创建聊天失败：单聊的成员无法修改，请先将其转换为群聊",
+    ),
+    (
+        "create chat error: Only single chats can be converted to a group",
+        "创建聊天失败：只有单聊可以转换为群聊",
+    ),
+    (
+        "create chat error: A group needs at least one member besides the two of the single chat",
+        "创建聊天失败：群聊至少需要单聊双方之外的一名成员",
+    ),
+    ("Not found: chat member {id}", "未找到：聊天成员 {id}"),
+    (
+        "create chat error: A single chat with these members already exists",
+        "创建聊天失败：这些成员之间已存在单聊",
+    ),
+    (
+        "create chat error: The chat owner can't be removed, transfer the ownership first",
+        "创建聊天失败：无法移除聊天所有者，请先转让所有权",
+    ),
+    (
+        "create chat error: The chat owner's role can only change by transferring the ownership",
+        "创建聊天失败：聊天所有者的角色只能通过转让所有权来更改",
+    ),
+    (
+        "permission denied: only chat owners and admins can update the chat",
+        "权限不足：只有聊天所有者和管理员可以更新聊天",
+    ),
+    (
+        "permission denied: only the chat owner can delete the chat",
+        "权限不足：只有聊天所有者可以删除聊天",
+    ),
+    (
+        "permission denied: only the chat owner can keep the chat",
+        "权限不足：只有聊天所有者可以保留聊天",
+    ),
+    (
+        "permission denied: only chat owners and admins can post in the chat",
+        "权限不足：只有聊天所有者和管理员可以在此聊天中发言",
+    ),
+    (
+        "permission denied: only chat owners and admins can archive the chat",
+        "权限不足：只有聊天所有者和管理员可以归档聊天",
+    ),
+    (
+        "permission denied: only chat owners and admins can unarchive the chat",
+        "权限不足：只有聊天所有者和管理员可以取消归档聊天",
+    ),
+    (
+        "permission denied: only chat owners and admins can restore the chat",
+        "权限不足：只有聊天所有者和管理员可以恢复聊天",
+    ),
+    (
+        "permission denied: only chat owners and admins can change the chat avatar",
+        "权限不足：只有聊天所有者和管理员可以更改聊天头像",
+    ),
+    ("create message error: Chat is archived", "发送消息失败：聊天已归档"),
+    (
+        "permission denied: only chat owners and admins can remove members",
+        "权限不足：只有聊天所有者和管理员可以移除成员",
+    ),
+    (
+        "permission denied: only the chat owner can change member roles",
+        "权限不足：只有聊天所有者可以更改成员角色",
+    ),
+    ("permission denied: only the chat owner can remove admins", "权限不足：只有聊天所有者可以移除管理员"),
+    (
+        "permission denied: only chat owners and admins can manage bans",
+        "权限不足：只有聊天所有者和管理员可以管理封禁",
+    ),
+    (
+        "permission denied: User {user} is banned from chat {chat}",
+        "权限不足：用户 {user} 已被禁止加入聊天 {chat}",
+    ),
+    (
+        "create chat error: Members can't ban themselves",
+        "创建聊天失败：成员不能封禁自己",
+    ),
+    ("Not found: chat ban {user}", "未找到：封禁 {user}"),
+    (
+        "permission denied: only the chat owner can transfer the chat",
+        "权限不足：只有聊天所有者可以转让聊天",
+    ),
+    ("create chat error: The member already owns the chat", "创建聊天失败：该成员已是聊天所有者"),
+    (
+        "create chat error: The ownership can only be transferred to a member of the chat",
+        "创建聊天失败：所有权只能转让给聊天成员",
+    ),
+    (
+        "permission denied: only the workspace owner can manage admins",
+        "权限不足：只有工作区所有者可以管理管理员",
+    ),
+    (
+        "permission denied: only the workspace owner can transfer the workspace",
+        "权限不足：只有工作区所有者可以转让工作区",
+    ),
+    ("workspace error: The owner can't be an admin", "工作区错误：所有者不能成为管理员"),
+    (
+        "workspace error: The workspace is already owned by this user",
+        "工作区错误：该用户已经是工作区所有者",
+    ),
+    ("workspace error: Guests can't manage the workspace", "工作区错误：访客无法管理工作区"),
+    ("Not found: workspace transfer", "未找到：工作区转让"),
+    ("Not found: workspace admin {id}", "未找到：工作区管理员 {id}"),
+    ("Not found: search reindex", "未找到：搜索索引重建"),
+    (
+        "feature error: Percent must be between 0 and 100",
+        "功能错误：百分比必须在 0 到 100 之间",
+    ),
+    (
+        "permission denied: only public channels can be joined",
+        "权限不足：只能加入公开频道",
+    ),
+    (
+        "permission denied: only workspace owners and admins can view analytics",
+        "权限不足：只有工作区所有者和管理员可以查看统计",
+    ),
+    (
+        "permission denied: only workspace owners and admins can manage legal holds",
+        "权限不足：只有工作区所有者和管理员可以管理法律保留",
+    ),
+    (
+        "permission denied: Chat {chat} is on legal hold",
+        "权限不足：聊天 {chat} 处于法律保留状态",
+    ),
+    (
+        "workspace error: Legal hold already exists",
+        "工作区错误：法律保留已存在",
+    ),
+    (
+        "workspace error: Session timeouts must be at least {secs} seconds",
+        "工作区错误：会话时限至少为 {secs} 秒",
+    ),
+    (
+        "workspace error: IP allowlist can have at most {max} entries",
+        "工作区错误：IP 白名单最多 {max} 条",
+    ),
+    (
+        "workspace error: Invalid IP address or network {net}",
+        "工作区错误：无效的 IP 地址或网段 {net}",
+    ),
+    (
+        "workspace error: IP allowlist must include your address {ip}",
+        "工作区错误：IP 白名单必须包含你的地址 {ip}",
+    ),
+    (
+        "permission denied: workspace requires single sign-on",
+        "权限不足：工作区要求单点登录",
+    ),
+    (
+        "permission denied: address {ip} is not allowed by the workspace",
+        "权限不足：工作区不允许地址 {ip}",
+    ),
+    ("unauthorized: session has expired", "未授权：会话已过期"),
+    (
+        "permission denied: only workspace owners and admins can view the security policy",
+        "权限不足：只有工作区所有者和管理员可以查看安全策略",
+    ),
+    (
+        "permission denied: only the workspace owner can update the security policy",
+        "权限不足：只有工作区所有者可以修改安全策略",
+    ),
+    (
+        "invite error: Invite must expire in 60 to {max} seconds",
+        "邀请错误：邀请必须在 60 到 {max} 秒内过期",
+    ),
+    (
+        "invite error: Max uses must be at least 1",
+        "邀请错误：最大使用次数至少为 1",
+    ),
+    (
+        "invite error: Single chats can't have invites",
+        "邀请错误：单聊不能创建邀请",
+    ),
+    ("invite error: Invite has been revoked", "邀请错误：邀请已撤销"),
+    ("invite error: Invite has expired", "邀请错误：邀请已过期"),
+    ("invite error: Invite has been used up", "邀请错误：邀请次数已用完"),
+    ("Not found: invite {token}", "未找到：邀请 {token}"),
+    ("Not found: invite id {id}", "未找到：邀请 {id}"),
+    (
+        "join request error: Only private channels take join requests",
+        "加入申请错误：只有私有频道可以申请加入",
+    ),
+    (
+        "join request error: User is already a member of the chat",
+        "加入申请错误：用户已是聊天成员",
+    ),
+    (
+        "join request error: Message must be at most {max} characters",
+        "加入申请错误：留言最多 {max} 个字符",
+    ),
+    (
+        "join request error: Join request has already been decided",
+        "加入申请错误：加入申请已处理",
+    ),
+    (
+        "permission denied: only chat owners and admins can manage join requests",
+        "权限不足：只有聊天所有者和管理员可以处理加入申请",
+    ),
+    ("Not found: join request id {id}", "未找到：加入申请 {id}"),
+    ("invalid cursor: {cursor}", "无效的游标：{cursor}"),
+    ("Not found: deleted chat id {id}", "未找到：已删除的聊天 {id}"),
+    ("Not found: chat deletion {id}", "未找到：聊天删除计划 {id}"),
+    ("Not found: chat pin {id}", "未找到：置顶的聊天 {id}"),
+    ("Not found: chat id {id}", "未找到：聊天 {id}"),
+    ("Not found: chat handle {handle}", "未找到：频道 {handle}"),
+    ("Not found: user id {id}", "未找到：用户 {id}"),
+    ("Not found: workspace id {id}", "未找到：工作区 {id}"),
+    ("Not found: demo workspace {id}", "未找到：演示工作区 {id}"),
+    (
+        "permission denied: only the workspace owner can update settings",
+        "权限不足：只有工作区所有者可以修改设置",
+    ),
+];
+
+// (key, en, zh-CN)
+const SYSTEM_MESSAGES: &[(&str, &str, &str)] = &[
+    (
+        "member_joined",
+        "{user} joined the chat",
+        "{user} 加入了聊天",
+    ),
+    ("member_left", "{user} left the chat", "{user} 离开了聊天"),
+    (
+        "member_added",
+        "{actor} added {user}",
+        "{actor} 添加了 {user}",
+    ),
+    (
+        "member_removed",
+        "{actor} removed {user}",
+        "{actor} 移除了 {user}",
+    ),
+    (
+        "chat_renamed",
+        "{actor} renamed the chat to {name}",
+        "{actor} 将聊天重命名为 {name}",
+    ),
+];
+
+impl Locale {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::ZhCn => "zh-CN",
+        }
+    }
+
+    /// Translate an english message, returns it unchanged if there's no translation.
+    pub fn translate(&self, msg: &str) -> String {
+        if *self == Self::En {
+            return msg.to_string();
+        }
+
+        for (msgid, zh) in MESSAGES {
+            if let Some(args) = match_template(msgid, msg) {
+                return render(zh, &args);
+            }
+        }
+        msg.to_string()
+    }
+
+    /// Templates for system messages, keyed by message kind.
+    pub fn system_messages(&self) -> BTreeMap<&'static str, &'static str> {
+        SYSTEM_MESSAGES
+            .iter()
+            .map(|(key, en, zh)| match self {
+                Self::En => (*key, *en),
+                Self::ZhCn => (*key, *zh),
+            })
+            .collect()
+    }
+
+    /// Render a system message for this locale, e.g. `("member_joined", &[("user", "Alice")])`.
+    pub fn system_message(&self, key: &str, args: &[(&str, &str)]) -> Option<String> {
+        self.system_messages()
+            .get(key)
+            .map(|template| render(template, args))
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Self::En),
+            "zh-CN" => Ok(Self::ZhCn),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+impl TryFrom<String> for Locale {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn render(template: &str, args: &[(&str, &str)]) -> String {
+    args.iter().fold(template.to_string(), |s, (name, value)| {
+        s.replace(&format!("{{{}}}", name), value)
+    })
+}
+
+/// Match `msg` against a template with `{name}` placeholders, returning the values of
+/// the placeholders if it matches.
+fn match_template<'a>(template: &'a str, msg: &'a str) -> Option<Vec<(&'a str, &'a str)>> {
+    let mut args = vec![];
+    let mut rest = msg;
+    let mut template = template;
+    let mut pending: Option<&str> = None;
+    loop {
+        let (literal, next) = match template.find('{') {
+            Some(start) => {
+                let end = start + template[start..].find('}')?;
+                (&template[..start], Some((&template[start + 1..end], end)))
+            }
+            None => (template, None),
+        };
+
+        match pending.take() {
+            // a placeholder is followed by this literal, consume up to it
+            Some(name) => {
+                let pos = if literal.is_empty() && next.is_none() {
+                    rest.len()
+                } else {
+                    rest.find(literal)?
+                };
+                if pos == 0 {
+                    return None;
+                }
+                args.push((name, &rest[..pos]));
+                rest = &rest[pos + literal.len()..];
+            }
+            None => rest = rest.strip_prefix(literal)?,
+        }
+
+        match next {
+            Some((name, end)) => {
+                pending = Some(name);
+                template = &template[end + 1..];
+            }
+            None => return rest.is_empty().then_some(args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translate_should_work() {
+        let msg = "create chat error: Chat must have at least 2 members";
+        assert_eq!(Locale::En.translate(msg), msg);
+        assert_eq!(
+            Locale::ZhCn.translate(msg),
+            "创建聊天失败：聊天至少需要 2 名成员"
+        );
+
+        let msg = "permission denied: User 1 is not a member of chat 5";
+        assert_eq!(
+            Locale::ZhCn.translate(msg),
+            "权限不足：用户 1 不是聊天 5 的成员"
+        );
+        assert_eq!(
+            Locale::ZhCn.translate("Not found: chat id 10"),
+            "未找到：聊天 10"
+        );
+
+        // unknown messages are returned as is
+        assert_eq!(Locale::ZhCn.translate("hello"), "hello");
+    }
+
+    #[test]
+    fn match_template_should_work() {
+        assert_eq!(
+            match_template("a {x} b {y}", "a 1 b 2"),
+            Some(vec![("x", "1"), ("y", "2")])
+        );
+        assert_eq!(match_template("a {x} b", "a  b"), None);
+        assert_eq!(match_template("a {x} b", "a 1 c"), None);
+        assert_eq!(match_template("abc", "abc"), Some(vec![]));
+        assert_eq!(match_template("abc", "abcd"), None);
+    }
+
+    #[test]
+    fn system_message_should_work() {
+        let args = [("user", "Alice")];
+        assert_eq!(
+            Locale::En.system_message("member_joined", &args).unwrap(),
+            "Alice joined the chat"
+        );
+        assert_eq!(
+            Locale::ZhCn.system_message("member_joined", &args).unwrap(),
+            "Alice 加入了聊天"
+        );
+        assert!(Locale::En.system_message("unknown", &args).is_none());
+        assert_eq!(
+            Locale::En.system_messages().len(),
+            Locale::ZhCn.system_messages().len()
+        );
+    }
+}
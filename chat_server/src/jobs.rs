@@ -0,0 +1,137 @@
+use crate::{AppError, AppState, Job};
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Duration};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<(), AppError>> + Send>>;
+
+/// A handler for one kind of background job. Other modules enqueue work with
+/// `AppState::enqueue_job(kind, payload, run_at)` and register a handler here.
+pub trait JobHandler: Send + Sync + 'static {
+    /// Name stored in `jobs.kind`, must be unique within a runner.
+    fn kind(&self) -> &'static str;
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture;
+}
+
+/// Worker pool pulling jobs from the `jobs` table.
+#[derive(Clone)]
+pub struct JobRunner {
+    state: AppState,
+    handlers: HashMap<&'static str, Arc<dyn JobHandler>>,
+}
+
+impl JobRunner {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub fn register(mut self, handler: impl JobHandler) -> Self {
+        self.handlers.insert(handler.kind(), Arc::new(handler));
+        self
+    }
+
+    /// Spawn `jobs.workers` tasks polling the queue until the process exits.
+    pub fn spawn(self) {
+        let workers = self.state.config.jobs.workers;
+        let interval = Duration::from_millis(self.state.config.jobs.poll_interval_ms);
+        let runner = Arc::new(self);
+        for i in 0..workers {
+            let runner = runner.clone();
+            tokio::spawn(async move {
+                info!("Job worker {} started", i);
+                loop {
+                    match runner.run_once().await {
+                        Ok(true) => continue,
+                        Ok(false) => {}
+                        Err(e) => warn!("Job worker {} failed to poll jobs: {}", i, e),
+                    }
+                    sleep(interval).await;
+                }
+            });
+        }
+    }
+
+    /// Claim and run a single due job. Returns false if there was nothing to do.
+    pub async fn run_once(&self) -> Result<bool, AppError> {
+        let Some(job) = self.state.claim_job().await? else {
+            return Ok(false);
+        };
+
+        let id = job.id as u64;
+        // resilience tests fail a share of the jobs as if the database was unreachable
+        #[cfg(feature = "test-util")]
+        if let Err(e) = self.state.inject_db_fault() {
+            warn!("Job {} ({}) failed: {}", id, job.kind, e);
+            self.state.fail_job(id, &e.to_string()).await?;
+            return Ok(true);
+        }
+        let ret = match self.handlers.get(job.kind.as_str()) {
+            Some(handler) => handler.run(self.state.clone(), job.clone()).await,
+            None => Err(AppError::AnyError(anyhow::anyhow!(
+                "no handler registered for job kind {}",
+                job.kind
+            ))),
+        };
+
+        match ret {
+            Ok(()) => self.state.complete_job(id).await?,
+            Err(e) => {
+                warn!("Job {} ({}) failed: {}", id, job.kind, e);
+                self.state.fail_job(id, &e.to_string()).await?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JobStatus;
+    use anyhow::Result;
+    use serde_json::json;
+
+    struct EchoJob;
+
+    impl JobHandler for EchoJob {
+        fn kind(&self) -> &'static str {
+            "echo"
+        }
+
+        fn run(&self, _state: AppState, job: Job) -> JobFuture {
+            Box::pin(async move {
+                match job.payload["fail"].as_bool() {
+                    Some(true) => Err(AppError::NotFound("echo".to_string())),
+                    _ => Ok(()),
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn job_runner_should_dispatch_by_kind() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let runner = JobRunner::new(state.clone()).register(EchoJob);
+
+        let ok = state.enqueue_job("echo", json!({}), None).await?;
+        let failed = state
+            .enqueue_job("echo", json!({"fail": true}), None)
+            .await?;
+        let unknown = state.enqueue_job("unknown", json!({}), None).await?;
+
+        while runner.run_once().await? {}
+
+        let ok = state.get_job_by_id(ok.id as _).await?.unwrap();
+        assert_eq!(ok.status, JobStatus::Done);
+        let failed = state.get_job_by_id(failed.id as _).await?.unwrap();
+        assert_eq!(failed.status, JobStatus::Pending);
+        assert_eq!(failed.last_error.as_deref(), Some("Not found: echo"));
+        let unknown = state.get_job_by_id(unknown.id as _).await?.unwrap();
+        assert_eq!(unknown.attempts, 1);
+        Ok(())
+    }
+}
@@ -1,32 +1,77 @@
+mod backup;
+mod captcha;
 mod config;
+mod doctor;
 mod error;
+#[cfg(feature = "test-util")]
+mod faults;
+mod filter;
 mod handlers;
+mod i18n;
+mod jobs;
+mod mailer;
 mod middlewares;
 mod models;
 mod openapi;
+mod pipeline;
+mod policy;
+mod rollout;
+mod translator;
 
 use anyhow::Context;
 use chat_core::{
     middlewares::{set_layer, verify_token, TokenVerify},
-    DecodingKey, EncodingKey, User,
+    DecodingKey, EncodingKey, IdObfuscator, User,
 };
 use handlers::*;
-use middlewares::verify_chat;
+use middlewares::{
+    enforce_security_policy, localize_errors, negotiate_capabilities, reject_writes_in_maintenance,
+    restrict_archived, restrict_guests, verify_admin, verify_chat, verify_personal_token,
+};
 use openapi::OpenApiRouter;
 use sqlx::PgPool;
-use std::{fmt, ops::Deref, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Deref,
+    sync::{Arc, RwLock},
+};
 use tokio::fs;
 
-pub use error::{AppError, ErrorOutput};
+pub use backup::{
+    create_backup, restore_backup, verify_backup, BackupFile, BackupManifest, BackupObject,
+    FileRoot,
+};
+pub use captcha::{CaptchaFuture, CaptchaVerifier, DisabledCaptcha, SiteVerifyCaptcha};
+pub use doctor::{diagnose, Diagnosis};
+pub use error::{AppError, ErrorOutput, FieldError};
+pub use filter::{EventContext, Filter};
+pub use i18n::Locale;
+pub use jobs::{JobFuture, JobHandler, JobRunner};
+pub use mailer::{Email, LogMailer, MailFuture, Mailer, SendEmailJob, SesMailer, SmtpMailer};
 pub use models::*;
+pub use pipeline::{
+    ArchivalStage, EnqueueStage, MentionStage, MessagePipeline, MessageStage, OutboxStage,
+    PersistStage, PostPolicyStage, SendContext, SlowModeStage, StageFuture, ValidateStage,
+    ARCHIVAL_STAGE, ENQUEUE_STAGE, MENTION_STAGE, OUTBOX_STAGE, PERSIST_STAGE, POST_POLICY_STAGE,
+    SLOW_MODE_STAGE, VALIDATE_STAGE,
+};
+pub use policy::{
+    decide, ChatAction, Decision, NoPolicyHook, OpaPolicyHook, PolicyFuture, PolicyHook,
+    PolicyRequest, Resource, Subject,
+};
+pub use rollout::{canary, Cohort, CohortMetrics, Feature};
+pub use translator::{
+    DisabledTranslator, LibreTranslator, TranslateFuture, Translation, Translator,
+};
 
 use axum::{
-    middleware::from_fn_with_state,
-    routing::{get, post},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, patch, post, put},
     Router,
 };
 
-pub use config::AppConfig;
+pub use config::{AppConfig, FeatureConfig};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
@@ -39,8 +84,27 @@ pub struct AppStateInner {
     pub(crate) dk: DecodingKey,
     pub(crate) ek: EncodingKey,
     pub(crate) pool: PgPool,
+    pub(crate) mailer: Arc<dyn Mailer>,
+    pub(crate) translator: Arc<dyn Translator>,
+    pub(crate) captcha: Arc<dyn CaptchaVerifier>,
+    pub(crate) policy_hook: Arc<dyn PolicyHook>,
+    // message returned for writes while in maintenance mode, None if not in maintenance
+    pub(crate) maintenance: RwLock<Option<String>>,
+    // rollouts of the features, with request metrics of their cohorts
+    pub(crate) features: RwLock<HashMap<String, rollout::FeatureRollout>>,
+    // stages new messages go through
+    pub(crate) message_pipeline: RwLock<MessagePipeline>,
+    // last probed health of the realtime endpoints, by url
+    pub(crate) realtime_health: RwLock<HashMap<String, RealtimeEndpoint>>,
+    // parsed filters of the webhooks, by webhook id
+    pub(crate) webhook_filters: RwLock<HashMap<i64, Arc<Filter>>>,
+    #[cfg(feature = "test-util")]
+    pub(crate) faults: faults::Faults,
 }
 
+pub(crate) const DEFAULT_MAINTENANCE_MESSAGE: &str =
+    "Service is under maintenance, please try again later";
+
 pub async fn get_router(state: AppState) -> Result<Router, AppError> {
     let chat = Router::new()
         .route(
@@ -51,18 +115,270 @@ pub async fn get_router(state: AppState) -> Result<Router, AppError> {
                 .post(send_message_handler),
         )
         .route("/:id/messages", get(list_message_handler))
+        .route(
+            "/:id/members",
+            get(list_chat_members_handler).post(add_chat_member_handler),
+        )
+        .route("/:id/members/:user_id", delete(remove_chat_member_handler))
+        .route("/:id/bans", get(list_chat_bans_handler))
+        .route("/:id/bans/:user_id", delete(unban_chat_member_handler))
+        .route("/:id/leave", post(leave_chat_handler))
+        .route("/:id/deletion", delete(cancel_chat_deletion_handler))
+        .route("/:id/pin", put(pin_chat_handler).delete(unpin_chat_handler))
+        .route("/:id/read", put(mark_chat_read_handler))
+        .route(
+            "/:id/draft",
+            get(get_chat_draft_handler).put(save_chat_draft_handler),
+        )
+        .route(
+            "/:id/metadata",
+            get(get_chat_metadata_handler).put(set_chat_metadata_handler),
+        )
+        .route(
+            "/:id/notifications",
+            get(get_chat_notifications_handler).put(update_chat_notifications_handler),
+        )
+        .route("/:id/avatar", post(upload_chat_avatar_handler))
+        .route("/:id/archive", post(archive_chat_handler))
+        .route("/:id/unarchive", post(unarchive_chat_handler))
+        .route("/:id/members/:user_id/role", put(update_chat_role_handler))
+        .route("/:id/transfer", post(transfer_chat_handler))
+        .route("/:id/convert", post(convert_chat_handler))
+        .route(
+            "/:id/archival-webhook",
+            get(get_chat_archival_webhook_handler)
+                .put(set_chat_archival_webhook_handler)
+                .delete(delete_chat_archival_webhook_handler),
+        )
+        .route(
+            "/:id/pins",
+            get(list_pins_handler).post(pin_message_handler),
+        )
+        .route("/:id/pins/order", put(reorder_pins_handler))
+        .route("/:id/pins/limit", put(set_pin_limit_handler))
+        .route("/:id/pins/:message_id", delete(unpin_message_handler))
+        .route(
+            "/:id/messages/:message_id/reactions",
+            get(list_reactions_handler),
+        )
+        .route(
+            "/:id/messages/:message_id/reactions/:emoji",
+            put(add_reaction_handler).delete(remove_reaction_handler),
+        )
+        .route(
+            "/:id/tasks",
+            get(list_chat_tasks_handler).post(create_task_handler),
+        )
+        .route("/:id/tasks/:task_id", patch(update_task_handler))
+        .route(
+            "/:id/settings",
+            get(get_chat_settings_handler).put(update_chat_settings_handler),
+        )
+        .route("/:id/export", get(export_chat_handler))
+        .route(
+            "/:id/guest-links",
+            get(list_guest_links_handler).post(create_guest_link_handler),
+        )
+        .route(
+            "/:id/guest-links/:link_id",
+            delete(revoke_guest_link_handler),
+        )
+        .route(
+            "/:id/invites",
+            get(list_chat_invites_handler).post(create_chat_invite_handler),
+        )
+        .route(
+            "/:id/invites/:invite_id",
+            delete(revoke_chat_invite_handler),
+        )
+        .route("/:id/join-requests", get(list_join_requests_handler))
+        .route(
+            "/:id/join-requests/:request_id/approve",
+            post(approve_join_request_handler),
+        )
+        .route(
+            "/:id/join-requests/:request_id/deny",
+            post(deny_join_request_handler),
+        )
         .layer(from_fn_with_state(state.clone(), verify_chat))
+        // joining is for users who aren't members yet, restoring for chats which are deleted
+        .route("/:id/join", post(join_chat_handler))
+        .route("/:id/join-request", post(request_to_join_chat_handler))
+        .route("/:id/restore", post(restore_chat_handler))
+        .route("/search", get(search_chats_handler))
+        .route("/by-handle/:ws_id/:handle", get(get_chat_by_handle_handler))
+        .route("/unread", get(list_unread_handler))
+        .route("/read_all", post(read_all_chats_handler))
+        .route("/read-all", post(read_all_chats_handler))
+        .route("/folders/:folder/read", post(read_folder_handler))
         .route("/", get(list_chat_handler).post(create_chat_handler));
 
+    let admin = Router::new()
+        .route(
+            "/maintenance",
+            get(get_maintenance_handler).put(set_maintenance_handler),
+        )
+        .route("/bulk-messages", post(create_bulk_message_handler))
+        .route("/bulk-messages/:id", get(get_bulk_message_handler))
+        .route("/plans", get(list_plans_handler).post(create_plan_handler))
+        .route("/chats/:id/history", get(get_chat_history_handler))
+        .route(
+            "/search/reindex",
+            get(get_search_reindex_handler).post(create_search_reindex_handler),
+        )
+        .route("/workspaces/:id/plan", put(set_workspace_plan_handler))
+        .route("/workspaces/:id/usage", get(get_workspace_usage_handler))
+        .route(
+            "/workspaces/:id/demo",
+            get(get_demo_workspace_handler)
+                .put(create_demo_workspace_handler)
+                .delete(delete_demo_workspace_handler),
+        )
+        .route(
+            "/workspaces/:id/demo/reset",
+            post(reset_demo_workspace_handler),
+        )
+        .route("/features", get(list_features_handler))
+        .route("/features/:name", put(update_feature_handler))
+        .route("/client-capabilities", get(list_capability_stats_handler))
+        .route("/orphans", get(get_orphan_report_handler))
+        .layer(from_fn_with_state(state.clone(), verify_admin))
+        .layer(from_fn_with_state(state.clone(), verify_token::<AppState>));
+
     let api = Router::new()
+        .route("/bootstrap", get(bootstrap_handler))
+        .route("/events/token", post(create_stream_token_handler))
+        .route("/onboarding", get(get_onboarding_handler))
+        .route("/realtime/endpoints", get(list_realtime_endpoints_handler))
         .route("/users", get(list_chat_users_handler))
+        .route("/users/:id/chats", get(list_user_chats_handler))
+        .route(
+            "/users/me/preferences",
+            get(get_preferences_handler).put(update_preferences_handler),
+        )
+        .route("/users/me/tasks", get(list_my_tasks_handler))
+        .route(
+            "/users/me/tokens",
+            get(list_personal_tokens_handler).post(create_personal_token_handler),
+        )
+        .route(
+            "/users/me/tokens/:id",
+            delete(revoke_personal_token_handler),
+        )
+        .route("/i18n/system-messages", get(system_messages_handler))
+        .route(
+            "/workspace/settings",
+            put(update_workspace_settings_handler),
+        )
+        .route("/workspace/audit-logs", get(list_audit_logs_handler))
+        .route(
+            "/workspace/security-policy",
+            get(get_security_policy_handler).put(update_security_policy_handler),
+        )
+        .route("/workspace/admins", get(list_workspace_admins_handler))
+        .route(
+            "/workspace/legal-holds",
+            get(list_legal_holds_handler).post(create_legal_hold_handler),
+        )
+        .route(
+            "/workspace/legal-holds/:id",
+            delete(release_legal_hold_handler),
+        )
+        .route(
+            "/workspaces/:id/channels",
+            get(list_channel_directory_handler),
+        )
+        .route(
+            "/workspaces/:id/analytics/reactions",
+            get(reaction_analytics_handler),
+        )
+        .route(
+            "/workspace/admins/:user_id",
+            put(add_workspace_admin_handler).delete(remove_workspace_admin_handler),
+        )
+        .route(
+            "/workspace/transfer",
+            get(get_workspace_transfer_handler)
+                .post(create_workspace_transfer_handler)
+                .delete(cancel_workspace_transfer_handler),
+        )
+        .route(
+            "/workspace/transfer/accept",
+            post(accept_workspace_transfer_handler),
+        )
+        .route(
+            "/workspace/archive",
+            get(get_workspace_archive_handler)
+                .post(archive_workspace_handler)
+                .delete(unarchive_workspace_handler),
+        )
+        .route(
+            "/workspace/archive/download",
+            get(download_workspace_archive_handler),
+        )
+        .route(
+            "/workspace/reaction-triggers",
+            get(list_reaction_triggers_handler).post(create_reaction_trigger_handler),
+        )
+        .route(
+            "/workspace/reaction-triggers/:id",
+            delete(delete_reaction_trigger_handler),
+        )
+        .route(
+            "/workspace/domains",
+            get(list_domains_handler).post(add_domain_handler),
+        )
+        .route("/workspace/domains/:id", delete(delete_domain_handler))
+        .route("/workspace/domains/:id/verify", post(verify_domain_handler))
+        .route(
+            "/workspace/domains/:id/email-challenge",
+            post(domain_email_challenge_handler),
+        )
+        .route(
+            "/workspace/webhooks",
+            get(list_webhooks_handler).post(create_webhook_handler),
+        )
+        .route("/workspace/webhooks/:id", delete(delete_webhook_handler))
+        .route(
+            "/workspace/channel-templates",
+            get(list_channel_templates_handler).post(create_channel_template_handler),
+        )
+        .route(
+            "/workspace/channel-templates/:id",
+            delete(delete_channel_template_handler),
+        )
+        .route(
+            "/workspaces/:id/channels/from-template/:tpl",
+            post(create_channel_from_template_handler),
+        )
         .nest("/chats", chat)
+        .route("/invites/:token/accept", post(accept_chat_invite_handler))
         .route("/upload", post(upload_handler))
         .route("/files/:ws_id/*path", get(file_handler))
+        .route("/file-access/:ws_id/*path", get(file_access_handler))
+        .layer(from_fn_with_state(state.clone(), restrict_archived))
+        .layer(from_fn_with_state(state.clone(), restrict_guests))
+        .layer(from_fn_with_state(state.clone(), enforce_security_policy))
+        .layer(from_fn_with_state(state.clone(), localize_errors))
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+        .layer(from_fn_with_state(state.clone(), verify_personal_token))
         // routes doesn't need token verification
+        .route("/signup", post(signup_handler))
+        .route("/signup/workspace", get(signup_workspace_handler))
+        .route(
+            "/guest-links/:token/redeem",
+            post(redeem_guest_link_handler),
+        )
+        // routes below keep working in maintenance mode
+        .layer(from_fn_with_state(
+            state.clone(),
+            reject_writes_in_maintenance,
+        ))
         .route("/signin", post(signin_handler))
-        .route("/signup", post(signup_handler));
+        .nest("/admin", admin);
+    let api = api.layer(from_fn(negotiate_capabilities));
+    #[cfg(feature = "test-util")]
+    let api = api.layer(from_fn_with_state(state.clone(), faults::inject_faults));
 
     let app = Router::new()
         .openapi()
@@ -91,6 +407,18 @@ impl TokenVerify for AppState {
 }
 
 impl AppState {
+    pub fn is_admin(&self, user_id: i64) -> bool {
+        self.config.auth.admins.contains(&user_id)
+    }
+
+    pub fn maintenance_message(&self) -> Option<String> {
+        self.maintenance.read().unwrap().clone()
+    }
+
+    pub fn set_maintenance(&self, message: Option<String>) {
+        *self.maintenance.write().unwrap() = message;
+    }
+
     pub async fn try_new(config: AppConfig) -> Result<Self, AppError> {
         fs::create_dir_all(&config.server.base_dir)
             .await
@@ -100,12 +428,35 @@ impl AppState {
         let pool = PgPool::connect(&config.server.db_url)
             .await
             .context("connect to db failed")?;
+        let mailer = mailer::build_mailer(&config)?;
+        let translator = translator::build_translator(&config);
+        let captcha = captcha::build_captcha_verifier(&config);
+        let policy_hook = policy::build_policy_hook(&config);
+        let maintenance = config
+            .server
+            .maintenance
+            .then(|| DEFAULT_MAINTENANCE_MESSAGE.to_string());
+        let features = rollout::load_features(&config.features);
+        if let Some(key) = config.server.public_id_key {
+            chat_core::set_id_codec(IdObfuscator::new(key));
+        }
         Ok(Self {
             inner: Arc::new(AppStateInner {
                 config,
                 ek,
                 dk,
                 pool,
+                mailer,
+                translator,
+                captcha,
+                policy_hook,
+                maintenance: RwLock::new(maintenance),
+                features: RwLock::new(features),
+                message_pipeline: Default::default(),
+                realtime_health: Default::default(),
+                webhook_filters: Default::default(),
+                #[cfg(feature = "test-util")]
+                faults: Default::default(),
             }),
         })
     }
@@ -115,6 +466,7 @@ impl fmt::Debug for AppStateInner {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AppStateInner")
             .field("config", &self.config)
+            .field("maintenance", &self.maintenance)
             .finish()
     }
 }
@@ -133,12 +485,28 @@ mod test_util {
             let post = config.server.db_url.rfind('/').expect("invalid db_url");
             let server_url = &config.server.db_url[..post];
             let (tdb, pool) = get_test_pool(Some(server_url)).await;
+            let mailer = mailer::build_mailer(&config)?;
+            let translator = translator::build_translator(&config);
+            let captcha = captcha::build_captcha_verifier(&config);
+            let policy_hook = policy::build_policy_hook(&config);
+            let features = rollout::load_features(&config.features);
             let state = Self {
                 inner: Arc::new(AppStateInner {
                     config,
                     ek,
                     dk,
                     pool,
+                    mailer,
+                    translator,
+                    captcha,
+                    policy_hook,
+                    maintenance: RwLock::new(None),
+                    features: RwLock::new(features),
+                    message_pipeline: Default::default(),
+                    realtime_health: Default::default(),
+                    webhook_filters: Default::default(),
+                    #[cfg(feature = "test-util")]
+                    faults: Default::default(),
                 }),
             };
             Ok((tdb, state))
@@ -1,5 +1,12 @@
 use anyhow::Result;
-use chat_server::{get_router, AppConfig, AppState};
+use chat_server::{
+    create_backup, diagnose, get_router, restore_backup, verify_backup, AppConfig, AppState,
+    ArchiveMessageJob, ArchiveWorkspaceJob, BulkMessageJob, CollectOrphansJob,
+    DeleteScheduledChatJob, JobRunner, PurgeChatJob, ReactionWebhookJob, ResetDemoWorkspacesJob,
+    SearchReindexJob, SearchReindexStatus, SendEmailJob, TaskReminderJob, TranslateMessageJob,
+    UnarchiveWorkspaceJob, WebhookJob,
+};
+use std::{env, net::SocketAddr, process};
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
@@ -10,14 +17,103 @@ async fn main() -> Result<()> {
     tracing_subscriber::registry().with(layer).init();
 
     let config = AppConfig::load()?;
+    if env::args().nth(1).as_deref() == Some("doctor") {
+        let diagnoses = diagnose(&config).await;
+        for d in &diagnoses {
+            println!("{}", d);
+        }
+        process::exit(if diagnoses.iter().all(|d| d.ok) { 0 } else { 1 });
+    }
+
+    // rebuild the search index in the foreground, e.g. after upgrading a large deployment
+    if env::args().nth(1).as_deref() == Some("reindex") {
+        let state = AppState::try_new(config).await?;
+        let mut reindex = state.create_search_reindex(false).await?;
+        while reindex.status == SearchReindexStatus::Running {
+            reindex = state.run_search_reindex_batch(reindex.id as _).await?;
+            println!("indexed {}/{} messages", reindex.indexed, reindex.total);
+        }
+        return Ok(());
+    }
+
+    // recount the messages of every chat, the counters are kept up to date afterwards
+    if env::args().nth(1).as_deref() == Some("backfill-counts") {
+        let state = AppState::try_new(config).await?;
+        let mut after_id = 0;
+        while let Some(id) = state.backfill_message_counts(after_id).await? {
+            println!("recounted messages of chats up to id {id}");
+            after_id = id;
+        }
+        return Ok(());
+    }
+
+    // encrypted backups, restored with `restore <name>` once they are verified
+    match env::args().nth(1).as_deref() {
+        Some("backup") => {
+            let manifest = create_backup(&config).await?;
+            println!(
+                "created backup {} with {} files",
+                manifest.name,
+                manifest.files.len()
+            );
+            return Ok(());
+        }
+        Some(cmd @ ("verify-backup" | "restore")) => {
+            let Some(name) = env::args().nth(2) else {
+                eprintln!("usage: chat-server {cmd} <backup name>");
+                process::exit(2);
+            };
+            let manifest = if cmd == "restore" {
+                restore_backup(&config, &name).await?
+            } else {
+                verify_backup(&config, &name).await?
+            };
+            println!(
+                "{} backup {} of {} with {} files",
+                if cmd == "restore" {
+                    "restored"
+                } else {
+                    "verified"
+                },
+                manifest.name,
+                manifest.created_at,
+                manifest.files.len()
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+
     let addr = format!("0.0.0.0:{}", config.server.port);
 
     let state = AppState::try_new(config).await?;
+    JobRunner::new(state.clone())
+        .register(SendEmailJob)
+        .register(ReactionWebhookJob)
+        .register(TaskReminderJob)
+        .register(WebhookJob)
+        .register(ArchiveWorkspaceJob)
+        .register(UnarchiveWorkspaceJob)
+        .register(BulkMessageJob)
+        .register(SearchReindexJob)
+        .register(PurgeChatJob)
+        .register(DeleteScheduledChatJob)
+        .register(TranslateMessageJob)
+        .register(CollectOrphansJob)
+        .register(ArchiveMessageJob)
+        .register(ResetDemoWorkspacesJob)
+        .spawn();
+    state.schedule_orphan_collection().await?;
+    state.schedule_demo_resets().await?;
     let app = get_router(state).await?;
     let listener = TcpListener::bind(&addr).await?;
     info!("Listening on: {}", addr);
 
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
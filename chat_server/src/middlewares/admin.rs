@@ -0,0 +1,65 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+
+/// Only allow users listed in `auth.admins`, must run after `verify_token`.
+pub async fn verify_admin(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    if !state.is_admin(user.id) {
+        let err = AppError::PermissionDenied(format!("user {} is not an admin", user.id));
+        return err.into_response();
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{
+        body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router,
+    };
+    use chat_core::middlewares::verify_token;
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    #[tokio::test]
+    async fn verify_admin_middleware_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let app = Router::new()
+            .route("/admin", get(handler))
+            .layer(from_fn_with_state(state.clone(), verify_admin))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        // user 1 is an admin in chat.yml
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let req = Request::builder()
+            .uri("/admin")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let req = Request::builder()
+            .uri("/admin")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+
+/// Keep archived workspaces read only for their owner and closed to everyone else, must
+/// run after `verify_token`.
+pub async fn restrict_archived(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    let ws_id = user.ws_id as u64;
+    match state.get_workspace_archive(ws_id).await {
+        Ok(archive) if archive.status.is_none() => return next.run(req).await,
+        Ok(_) => {}
+        Err(e) => return e.into_response(),
+    }
+
+    let is_owner = match state.find_workspace_by_id(ws_id).await {
+        Ok(ws) => ws.is_some_and(|ws| ws.owner_id == user.id),
+        Err(e) => return e.into_response(),
+    };
+    if !is_owner {
+        return AppError::PermissionDenied("workspace is archived".to_string()).into_response();
+    }
+    if !owner_allows(req.method(), req.uri().path()) {
+        let err = AppError::PermissionDenied(
+            "workspace is archived, unarchive it to make changes".to_string(),
+        );
+        return err.into_response();
+    }
+
+    next.run(req).await
+}
+
+// paths are relative to /api
+fn owner_allows(method: &Method, path: &str) -> bool {
+    match path {
+        // unarchive
+        "/workspace/archive" => matches!(*method, Method::GET | Method::HEAD | Method::DELETE),
+        _ => matches!(*method, Method::GET | Method::HEAD),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{body::Body, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn archived_workspace_should_be_read_only_for_owner() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_workspace_owner(1, 1).await?;
+        state.archive_workspace(1).await?;
+        let app = crate::get_router(state.clone()).await?;
+
+        let owner = state.find_user_by_id(1).await?.expect("user should exist");
+        let member = state.find_user_by_id(2).await?.expect("user should exist");
+        let req = |user: &User, method: &str, uri: &str| {
+            let token = state.ek.sign(user.clone())?;
+            Ok::<_, anyhow::Error>(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("Authorization", format!("Bearer {}", token))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"content": "hello"}"#))?,
+            )
+        };
+
+        let res = app
+            .clone()
+            .oneshot(req(&owner, "GET", "/api/chats")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app
+            .clone()
+            .oneshot(req(&owner, "POST", "/api/chats/1")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app
+            .clone()
+            .oneshot(req(&member, "GET", "/api/chats")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[test]
+    fn owner_allows_should_only_allow_reads() {
+        assert!(owner_allows(&Method::GET, "/chats/1/messages"));
+        assert!(!owner_allows(&Method::POST, "/chats/1"));
+        assert!(!owner_allows(&Method::DELETE, "/chats/1"));
+        assert!(owner_allows(&Method::DELETE, "/workspace/archive"));
+        assert!(!owner_allows(&Method::POST, "/workspace/archive"));
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{ClientCapabilities, CLIENT_CAPABILITIES_HEADER};
+use axum::{extract::Request, middleware::Next, response::Response};
+
+/// Make the capabilities declared in `X-Client-Capabilities` available to handlers as an
+/// extension.
+pub async fn negotiate_capabilities(mut req: Request, next: Next) -> Response {
+    let value = req
+        .headers()
+        .get(CLIENT_CAPABILITIES_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let caps = ClientCapabilities::from_header(value);
+    req.extensions_mut().insert(caps);
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::AppState;
+    use anyhow::Result;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn negotiate_capabilities_should_downgrade_payloads() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        sqlx::query("UPDATE chats SET avatar_url = '/files/1/avatar.png' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        let app = crate::get_router(state.clone()).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let get = |uri: &str, caps: Option<&str>| {
+            let builder = Request::builder()
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token));
+            let builder = match caps {
+                Some(caps) => builder.header("X-Client-Capabilities", caps),
+                None => builder,
+            };
+            builder.body(Body::empty())
+        };
+        let json = |res: axum::response::Response| async move {
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), usize::MAX).await?;
+            Ok::<Value, anyhow::Error>(serde_json::from_slice(&body)?)
+        };
+
+        let res = app
+            .clone()
+            .oneshot(get("/api/bootstrap", Some("translations, threads"))?)
+            .await?;
+        assert_eq!(
+            json(res).await?["capabilities"],
+            serde_json::json!(["translations"])
+        );
+        let res = app
+            .clone()
+            .oneshot(get("/api/chats/1", Some("translations"))?)
+            .await?;
+        assert_eq!(json(res).await?["avatar_url"], Value::Null);
+        // clients without the header get everything
+        let res = app.oneshot(get("/api/chats/1", None)?).await?;
+        assert_eq!(json(res).await?["avatar_url"], "/files/1/avatar.png");
+        Ok(())
+    }
+}
@@ -4,25 +4,36 @@ use axum::{
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use chat_core::User;
+use chat_core::{decode_id, User};
+use std::collections::HashMap;
 
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
     let (mut parts, body) = req.into_parts();
-    let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
+    // routes may have more params than the chat id, e.g. /:id/pins/:message_id
+    let Path(params) = Path::<HashMap<String, String>>::from_request_parts(&mut parts, &state)
         .await
         .unwrap();
+    let Some(chat_id) = params
+        .get("id")
+        .and_then(|id| decode_id(id))
+        .map(|id| id as u64)
+    else {
+        return AppError::NotFound("chat id is missing or invalid".to_string()).into_response();
+    };
 
+    // chats of other workspaces are treated like chats the user is not a member of
     let user = parts.extensions.get::<User>().unwrap();
-    if !state
-        .is_chat_member(chat_id, user.id as _)
-        .await
-        .unwrap_or_default()
-    {
-        let err = AppError::CreateMessageError(format!(
-            "User {} are not a member of chat {chat_id}",
-            user.id
-        ));
-        return err.into_response();
+    match state.get_chat_by_id(chat_id).await {
+        Ok(Some(chat)) if chat.ws_id == user.ws_id && chat.members.contains(&user.id) => {}
+        Ok(Some(_)) => {
+            let err = AppError::PermissionDenied(format!(
+                "User {} is not a member of chat {chat_id}",
+                user.id
+            ));
+            return err.into_response();
+        }
+        Ok(None) => return AppError::NotFound(format!("chat id {chat_id}")).into_response(),
+        Err(e) => return e.into_response(),
     }
 
     let req = Request::from_parts(parts, body);
@@ -54,7 +65,7 @@ mod tests {
             .route("/chat/:id/messages", get(handler))
             .layer(from_fn_with_state(state.clone(), verify_chat))
             .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
-            .with_state(state);
+            .with_state(state.clone());
 
         // user in chat
         let req = Request::builder()
@@ -64,14 +75,53 @@ mod tests {
         let res = app.clone().oneshot(req).await?;
         assert_eq!(res.status(), StatusCode::OK);
 
-        // user not in chat
+        // chat doesn't exist
         let req = Request::builder()
             .uri("/chat/5/messages")
             .header("Authorization", format!("Bearer {}", token))
             .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // user not in chat
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        let token = state.ek.sign(user)?;
+        let req = Request::builder()
+            .uri("/chat/2/messages")
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
         let res = app.oneshot(req).await?;
-        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chats_of_other_workspaces_should_be_forbidden() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let app = crate::get_router(state.clone()).await?;
+        let input = crate::CreateUser::new("foo", "Eve Chen", "eve@foo.org", "123456");
+        let outsider = state.create_user(&input).await?;
+        // rejected even when added to the chat's members
+        state.add_chat_member(1, outsider.id as _, None).await?;
+        let token = state.ek.sign(outsider)?;
 
+        for (method, uri) in [
+            ("GET", "/api/chats/1"),
+            ("PATCH", "/api/chats/1"),
+            ("DELETE", "/api/chats/1"),
+        ] {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    r#"{"name": "hijacked", "members": [1, 2], "public": false}"#,
+                ))?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN, "{method} {uri}");
+        }
         Ok(())
     }
 }
@@ -0,0 +1,131 @@
+use crate::{AppError, AppState, Guest, GuestAccess};
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::{decode_id, User};
+
+/// Keep guest accounts inside the chat they were invited to, must run after `verify_token`.
+/// Regular users are passed through untouched.
+pub async fn restrict_guests(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    let guest = match state.find_guest(user.id as _).await {
+        Ok(Some(guest)) => guest,
+        Ok(None) => return next.run(req).await,
+        Err(e) => return e.into_response(),
+    };
+
+    if guest.is_expired() {
+        return AppError::Unauthorized("guest access has expired".to_string()).into_response();
+    }
+    if !guest_allows(&guest, req.method(), req.uri().path()) {
+        let err =
+            AppError::PermissionDenied(format!("guests can only access chat {}", guest.chat_id));
+        return err.into_response();
+    }
+
+    next.run(req).await
+}
+
+// paths are relative to /api
+fn guest_allows(guest: &Guest, method: &Method, path: &str) -> bool {
+    let is_read = matches!(*method, Method::GET | Method::HEAD);
+    let can_write = guest.access == GuestAccess::ReadWrite;
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let is_chat = |id: &str| decode_id(id) == Some(guest.chat_id);
+    match segments.as_slice() {
+        ["bootstrap"] | ["i18n", "system-messages"] => is_read,
+        ["users", "me", "preferences"] => true,
+        ["upload"] => can_write,
+        ["events", "token"] => *method == Method::POST,
+        // send message
+        ["chats", id] if is_chat(id) => is_read || (can_write && *method == Method::POST),
+        ["chats", id, rest @ ..] if is_chat(id) => {
+            is_read
+                && !rest.contains(&"guest-links")
+                && !rest.contains(&"invites")
+                && rest.last() != Some(&"export")
+        }
+        ["files", _, ..] => is_read,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateGuestLink, RedeemGuestLink};
+    use anyhow::Result;
+    use axum::{body::Body, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn guest_should_be_restricted_to_its_chat() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateGuestLink {
+            access: GuestAccess::ReadOnly,
+            expires_in_secs: 3600,
+        };
+        let link = state.create_guest_link(1, &input, 1).await?;
+        let input = RedeemGuestLink {
+            fullname: "Customer".to_string(),
+        };
+        let (user, _) = state.redeem_guest_link(&link.token, &input).await?;
+        let token = state.ek.sign(user)?;
+        let app = crate::get_router(state.clone()).await?;
+
+        let req = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"content": "hello"}"#))
+        };
+        let res = app
+            .clone()
+            .oneshot(req("GET", "/api/chats/1/messages?limit=10")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req("GET", "/api/chats/2")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let res = app.clone().oneshot(req("GET", "/api/users")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // read only guests can't send messages
+        let res = app.clone().oneshot(req("POST", "/api/chats/1")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        state.revoke_guest_link(1, link.id as _).await?;
+        let res = app
+            .oneshot(req("GET", "/api/chats/1/messages?limit=10")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[test]
+    fn guest_allows_should_respect_access() {
+        let mut guest = Guest {
+            user_id: 10,
+            link_id: Some(1),
+            chat_id: 1,
+            access: GuestAccess::ReadWrite,
+            expires_at: chrono::Utc::now(),
+        };
+        assert!(guest_allows(&guest, &Method::POST, "/chats/1"));
+        assert!(!guest_allows(&guest, &Method::PATCH, "/chats/1"));
+        assert!(!guest_allows(&guest, &Method::GET, "/chats/1/guest-links"));
+        assert!(!guest_allows(&guest, &Method::GET, "/chats/1/export"));
+        assert!(!guest_allows(&guest, &Method::GET, "/chats/10"));
+        assert!(!guest_allows(&guest, &Method::GET, "/chats"));
+        assert!(guest_allows(&guest, &Method::POST, "/upload"));
+        assert!(guest_allows(&guest, &Method::POST, "/events/token"));
+
+        guest.access = GuestAccess::ReadOnly;
+        assert!(!guest_allows(&guest, &Method::POST, "/chats/1"));
+        assert!(!guest_allows(&guest, &Method::POST, "/upload"));
+        assert!(guest_allows(&guest, &Method::GET, "/files/1/a/b/c.png"));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{AppState, ErrorOutput, Locale};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::header::CONTENT_LENGTH,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chat_core::User;
+use tracing::warn;
+
+// error bodies are tiny, anything larger is not an ErrorOutput
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+/// Translate error messages into the locale of the current user, must run after `verify_token`.
+pub async fn localize_errors(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let user_id = req.extensions().get::<User>().map(|u| u.id);
+    let res = next.run(req).await;
+    let Some(user_id) = user_id else {
+        return res;
+    };
+    if !(res.status().is_client_error() || res.status().is_server_error()) {
+        return res;
+    }
+
+    let locale = match state.get_user_preferences(user_id as _).await {
+        Ok(prefs) => prefs.locale,
+        Err(e) => {
+            warn!("get preferences of user {} failed: {}", user_id, e);
+            return res;
+        }
+    };
+    if locale == Locale::En {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let bytes = match to_bytes(body, MAX_ERROR_BODY).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("read error body failed: {}", e);
+            return parts.into_response();
+        }
+    };
+    match serde_json::from_slice::<ErrorOutput>(&bytes) {
+        Ok(output) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            let output = ErrorOutput {
+                error: locale.translate(&output.error),
+                ..output
+            };
+            (parts, Json(output)).into_response()
+        }
+        Err(_) => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppError, TimeFormat, UserPreferences};
+    use anyhow::Result;
+    use axum::{http::StatusCode, middleware::from_fn_with_state, routing::get, Router};
+    use chat_core::middlewares::verify_token;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    async fn handler() -> Result<(), AppError> {
+        Err(AppError::NotFound("chat id 10".to_string()))
+    }
+
+    #[tokio::test]
+    async fn localize_errors_should_translate_for_user_locale() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let prefs = UserPreferences {
+            locale: Locale::ZhCn,
+            time_format: TimeFormat::H24,
+            ..Default::default()
+        };
+        state.update_user_preferences(1, &prefs).await?;
+
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(from_fn_with_state(state.clone(), localize_errors))
+            .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+            .with_state(state.clone());
+
+        for (id, expected) in [(1, "未找到：聊天 10"), (2, "Not found: chat id 10")] {
+            let user = state.find_user_by_id(id).await?.expect("user should exist");
+            let token = state.ek.sign(user)?;
+            let req = Request::builder()
+                .uri("/")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let body = res.into_body().collect().await?.to_bytes();
+            let ret: ErrorOutput = serde_json::from_slice(&body)?;
+            assert_eq!(ret.error, expected);
+        }
+
+        Ok(())
+    }
+}
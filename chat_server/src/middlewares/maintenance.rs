@@ -0,0 +1,70 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// Reject every request that may write while the server is in maintenance mode.
+/// Reads keep working so users can still browse their chats.
+pub async fn reject_writes_in_maintenance(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if !is_read {
+        if let Some(msg) = state.maintenance_message() {
+            return AppError::ServiceUnavailable(msg).into_response();
+        }
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{
+        body::Body, http::StatusCode, middleware::from_fn_with_state, routing::get, Router,
+    };
+    use tower::ServiceExt;
+
+    async fn handler(_req: Request) -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    #[tokio::test]
+    async fn maintenance_should_only_reject_writes() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let app = Router::new()
+            .route("/", get(handler).post(handler))
+            .layer(from_fn_with_state(
+                state.clone(),
+                reject_writes_in_maintenance,
+            ))
+            .with_state(state.clone());
+
+        let post = || Request::post("/").body(Body::empty());
+        let res = app.clone().oneshot(post()?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        state.set_maintenance(Some("upgrading".to_string()));
+        let res = app.clone().oneshot(post()?).await?;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let res = app
+            .clone()
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        state.set_maintenance(None);
+        let res = app.oneshot(post()?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
@@ -1,3 +1,19 @@
+mod admin;
+mod archive;
+mod capability;
 mod chat;
+mod guest;
+mod i18n;
+mod maintenance;
+mod security;
+mod token;
 
+pub use admin::verify_admin;
+pub use archive::restrict_archived;
+pub use capability::negotiate_capabilities;
 pub use chat::verify_chat;
+pub use guest::restrict_guests;
+pub use i18n::localize_errors;
+pub use maintenance::reject_writes_in_maintenance;
+pub use security::enforce_security_policy;
+pub use token::verify_personal_token;
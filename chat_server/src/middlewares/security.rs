@@ -0,0 +1,117 @@
+use crate::{handlers::client_ip, AppError, AppState, PersonalToken};
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::User;
+use std::net::SocketAddr;
+
+/// Enforce the security policy of the user's workspace, must run after `verify_token`.
+/// The IP allowlist applies to every request, session limits only to session tokens.
+pub async fn enforce_security_policy(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    let policy = match state.get_security_policy(user.ws_id as _).await {
+        Ok(policy) => policy,
+        Err(e) => return e.into_response(),
+    };
+
+    if !policy.ip_allowlist.is_empty() {
+        let addr = req.extensions().get::<ConnectInfo<SocketAddr>>().cloned();
+        let ip = client_ip(req.headers(), addr, &state.config.server.trusted_proxies)
+            .map(|ip| ip.to_string());
+        if !ip.as_deref().is_some_and(|ip| policy.allows_ip(ip)) {
+            let err = AppError::PermissionDenied(format!(
+                "address {} is not allowed by the workspace",
+                ip.unwrap_or_else(|| "unknown".to_string())
+            ));
+            return err.into_response();
+        }
+    }
+
+    // personal access tokens have their own expiry
+    if req.extensions().get::<PersonalToken>().is_none() {
+        let token = session_token(&req).unwrap_or_default();
+        if let Err(e) = state.verify_session(user, &token, &policy).await {
+            return e.into_response();
+        }
+    }
+
+    next.run(req).await
+}
+
+// the token verify_token accepted, from the Authorization header or the query
+fn session_token(req: &Request) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let query = req
+        .uri()
+        .query()
+        .and_then(|q| q.split('&').find_map(|kv| kv.strip_prefix("access_token=")));
+    bearer.or(query).map(|t| t.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AppState, SecurityPolicy, SessionMethod};
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+    };
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn enforce_security_policy_should_check_ip_and_session() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let policy = SecurityPolicy {
+            idle_timeout_secs: Some(600),
+            ip_allowlist: vec!["10.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        state
+            .update_security_policy(1, policy, 1, Some("10.0.0.1"))
+            .await?;
+        let app = crate::get_router(state.clone()).await?;
+
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let session = state
+            .create_session(user.clone(), SessionMethod::Password, None)
+            .await?;
+        let req = |token: &str, ip: &str| {
+            let peer = SocketAddr::new(ip.parse().unwrap(), 50000);
+            Request::builder()
+                .uri("/api/chats")
+                .header("Authorization", format!("Bearer {}", token))
+                .extension(ConnectInfo(peer))
+                .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(req(&session, "10.1.1.1")?).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req(&session, "192.168.0.1")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // no proxies are trusted, an allowed address in the header changes nothing
+        let mut spoofed = req(&session, "192.168.0.1")?;
+        spoofed
+            .headers_mut()
+            .insert("X-Forwarded-For", "10.0.0.1".parse()?);
+        let res = app.clone().oneshot(spoofed).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // a token without a session
+        let token = state.ek.sign(user)?;
+        let res = app.oneshot(req(&token, "10.1.1.1")?).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{
+    AppError, AppState, PersonalToken, PERSONAL_TOKEN_PREFIX, SCOPE_MESSAGES_READ,
+    SCOPE_MESSAGES_WRITE, SCOPE_READ_ONLY,
+};
+use axum::{
+    extract::{Request, State},
+    http::{header, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chat_core::decode_id;
+
+/// Authenticate personal access tokens and keep them to their scopes, must run before
+/// `verify_token` which then lets the request through. Other tokens are passed untouched.
+/// Personal tokens are long lived, so they are only taken from the Authorization header,
+/// never from urls which end up in logs and browser history.
+pub async fn verify_personal_token(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if has_query_personal_token(&req) {
+        let err = AppError::Unauthorized(
+            "personal access tokens must be sent in the Authorization header".to_string(),
+        );
+        return err.into_response();
+    }
+    let Some(secret) = personal_token(&req) else {
+        return next.run(req).await;
+    };
+    let (user, token) = match state.verify_personal_token(&secret).await {
+        Ok(v) => v,
+        Err(e) => return e.into_response(),
+    };
+    if !token_allows(&token, req.method(), req.uri().path()) {
+        let err = AppError::PermissionDenied(format!(
+            "token {} doesn't have the scope for this request",
+            token.prefix
+        ));
+        return err.into_response();
+    }
+
+    req.extensions_mut().insert(user);
+    req.extensions_mut().insert(token);
+    next.run(req).await
+}
+
+fn personal_token(req: &Request) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .filter(|t| t.starts_with(PERSONAL_TOKEN_PREFIX))
+        .map(|t| t.to_string())
+}
+
+fn has_query_personal_token(req: &Request) -> bool {
+    req.uri().query().is_some_and(|q| {
+        q.split('&')
+            .filter_map(|kv| kv.strip_prefix("access_token="))
+            .any(|t| t.starts_with(PERSONAL_TOKEN_PREFIX))
+    })
+}
+
+// paths are relative to /api
+fn token_allows(token: &PersonalToken, method: &Method, path: &str) -> bool {
+    let is_read = matches!(*method, Method::GET | Method::HEAD);
+    let can_read = token.has_scope(SCOPE_MESSAGES_READ);
+    let can_write = token.has_scope(SCOPE_MESSAGES_WRITE);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        // tokens can't see or mint other tokens
+        ["users", "me", "tokens", ..] => false,
+        _ if token.has_scope(SCOPE_READ_ONLY) => is_read,
+        // send message
+        ["chats", id] if *method == Method::POST && decode_id(id).is_some() => can_write,
+        ["upload"] if *method == Method::POST => can_write,
+        ["chats", ..] | ["files", ..] | ["users"] => is_read && can_read,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreatePersonalToken;
+    use anyhow::Result;
+    use axum::{body::Body, http::StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn personal_token_should_be_limited_to_its_scopes() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreatePersonalToken {
+            name: "cli".to_string(),
+            scopes: vec![SCOPE_MESSAGES_READ.to_string()],
+            expires_in_days: 30,
+        };
+        let token = state.create_personal_token(1, &input).await?.secret;
+        let app = crate::get_router(state.clone()).await?;
+
+        let req = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"content": "hello", "files": []}"#))
+        };
+        let res = app
+            .clone()
+            .oneshot(req("GET", "/api/chats/1/messages?limit=10")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = app.clone().oneshot(req("POST", "/api/chats/1")?).await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        // tokens can't mint other tokens
+        let res = app
+            .clone()
+            .oneshot(req("GET", "/api/users/me/tokens")?)
+            .await?;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn personal_token_should_be_rejected_in_query() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreatePersonalToken {
+            name: "cli".to_string(),
+            scopes: vec![SCOPE_MESSAGES_READ.to_string()],
+            expires_in_days: 30,
+        };
+        let token = state.create_personal_token(1, &input).await?.secret;
+        let app = crate::get_router(state.clone()).await?;
+
+        let req = Request::builder()
+            .uri(format!(
+                "/api/chats/1/messages?limit=10&access_token={token}"
+            ))
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        // even with the header set as well
+        let req = Request::builder()
+            .uri(format!(
+                "/api/chats/1/messages?limit=10&access_token={token}"
+            ))
+            .header("Authorization", format!("Bearer {}", token))
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        Ok(())
+    }
+
+    #[test]
+    fn token_allows_should_respect_scopes() {
+        let mut token = PersonalToken {
+            id: 1,
+            user_id: 1,
+            name: "cli".to_string(),
+            prefix: "pat_12345678".to_string(),
+            scopes: vec![SCOPE_MESSAGES_WRITE.to_string()],
+            expires_at: chrono::Utc::now(),
+            last_used_at: None,
+            created_at: chrono::Utc::now(),
+        };
+        assert!(token_allows(&token, &Method::POST, "/chats/1"));
+        assert!(token_allows(&token, &Method::POST, "/upload"));
+        assert!(!token_allows(&token, &Method::GET, "/chats/1/messages"));
+        assert!(!token_allows(&token, &Method::PATCH, "/chats/1"));
+
+        token.scopes = vec![SCOPE_MESSAGES_READ.to_string()];
+        assert!(token_allows(&token, &Method::GET, "/chats/1/messages"));
+        assert!(token_allows(&token, &Method::GET, "/files/1/abc/def/x.png"));
+        assert!(!token_allows(&token, &Method::POST, "/chats/1"));
+        assert!(!token_allows(&token, &Method::GET, "/workspace/audit-logs"));
+
+        token.scopes = vec![SCOPE_READ_ONLY.to_string()];
+        assert!(token_allows(&token, &Method::GET, "/workspace/audit-logs"));
+        assert!(token_allows(&token, &Method::GET, "/chats/1/export"));
+        assert!(!token_allows(&token, &Method::POST, "/chats/1"));
+        assert!(!token_allows(&token, &Method::PUT, "/chats/1/settings"));
+        assert!(!token_allows(&token, &Method::DELETE, "/chats/1"));
+        assert!(!token_allows(&token, &Method::GET, "/users/me/tokens"));
+    }
+}
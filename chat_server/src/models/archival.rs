@@ -0,0 +1,269 @@
+use crate::{is_valid_webhook_url, AppError, AppState, Job, JobFuture, JobHandler};
+use chat_core::Message;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Job kind delivering a message to the archival webhook of its chat.
+pub const ARCHIVE_MESSAGE_JOB: &str = "archive_message";
+
+/// Header with the hex HMAC-SHA256 of the request body, keyed by the webhook secret.
+pub const ARCHIVAL_SIGNATURE_HEADER: &str = "x-chat-signature";
+
+pub const MESSAGE_FINALIZED_EVENT: &str = "message.finalized";
+
+// messages can be held back a week at most
+const MAX_DELAY_SECS: i32 = 60 * 60 * 24 * 7;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatArchivalWebhook {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub url: String,
+    /// key of the `X-Chat-Signature` HMAC-SHA256 of each delivery
+    pub secret: String,
+    /// seconds after it is sent a message is delivered, edits until then are included
+    pub delay_secs: i32,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct SetChatArchivalWebhook {
+    pub url: String,
+    /// 0 to a week, 5 minutes by default
+    #[serde(default = "default_delay_secs")]
+    pub delay_secs: i32,
+}
+
+fn default_delay_secs() -> i32 {
+    300
+}
+
+/// Runs `archive_message` jobs enqueued for the messages of chats with an archival webhook.
+pub struct ArchiveMessageJob;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ArchiveMessage {
+    message_id: i64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn get_chat_archival_webhook(
+        &self,
+        chat_id: u64,
+    ) -> Result<Option<ChatArchivalWebhook>, AppError> {
+        let webhook = sqlx::query_as(
+            r#"
+            SELECT chat_id, url, secret, delay_secs, created_by, updated_at
+            FROM chat_archival_webhooks
+            WHERE chat_id = $1
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    /// Attach an archival webhook to the chat or change its url and delay, the secret is
+    /// kept when the webhook is changed.
+    pub async fn set_chat_archival_webhook(
+        &self,
+        chat_id: u64,
+        input: &SetChatArchivalWebhook,
+        user_id: u64,
+    ) -> Result<ChatArchivalWebhook, AppError> {
+        let url = input.url.trim();
+        if !is_valid_webhook_url(url) {
+            return Err(AppError::WebhookError(
+                "Webhook url must be a http(s) url".to_string(),
+            ));
+        }
+        if !(0..=MAX_DELAY_SECS).contains(&input.delay_secs) {
+            return Err(AppError::WebhookError(format!(
+                "Delay must be 0 to {MAX_DELAY_SECS} seconds"
+            )));
+        }
+
+        let webhook = sqlx::query_as(
+            r#"
+            INSERT INTO chat_archival_webhooks (chat_id, url, delay_secs, created_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (chat_id) DO UPDATE
+            SET url = EXCLUDED.url, delay_secs = EXCLUDED.delay_secs,
+                created_by = EXCLUDED.created_by, updated_at = NOW()
+            RETURNING chat_id, url, secret, delay_secs, created_by, updated_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(url)
+        .bind(input.delay_secs)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(webhook)
+    }
+
+    /// Returns false if the chat has no archival webhook. Deliveries already enqueued are
+    /// dropped when they run.
+    pub async fn delete_chat_archival_webhook(&self, chat_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM chat_archival_webhooks WHERE chat_id = $1")
+            .bind(chat_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Schedule the delivery of a new message if its chat has an archival webhook.
+    pub async fn enqueue_message_archival(&self, message: &Message) -> Result<(), AppError> {
+        let Some(webhook) = self.get_chat_archival_webhook(message.chat_id as _).await? else {
+            return Ok(());
+        };
+        let job = ArchiveMessage {
+            message_id: message.id,
+        };
+        let run_at = Utc::now() + Duration::seconds(webhook.delay_secs as i64);
+        self.enqueue_job(ARCHIVE_MESSAGE_JOB, job, Some(run_at))
+            .await?;
+        Ok(())
+    }
+
+    /// Deliver the current version of a message to the archival webhook of its chat.
+    /// Returns false if there is nothing to deliver, the message was deleted or the
+    /// webhook removed since.
+    pub async fn archive_message(&self, message_id: u64) -> Result<bool, AppError> {
+        let message: Option<Message> = sqlx::query_as(
+            "SELECT id, chat_id, sender_id, content, files, created_at FROM messages WHERE id = $1",
+        )
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(message) = message else {
+            return Ok(false);
+        };
+        let Some(webhook) = self.get_chat_archival_webhook(message.chat_id as _).await? else {
+            return Ok(false);
+        };
+
+        let body = json!({
+            "event": MESSAGE_FINALIZED_EVENT,
+            "chat_id": message.chat_id,
+            "message": message,
+            "finalized_at": Utc::now(),
+        });
+        let body = serde_json::to_vec(&body).map_err(anyhow::Error::from)?;
+        let res = reqwest::Client::new()
+            .post(&webhook.url)
+            .timeout(std::time::Duration::from_secs(10))
+            .header("content-type", "application/json")
+            .header(
+                ARCHIVAL_SIGNATURE_HEADER,
+                sign_payload(&webhook.secret, &body),
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(anyhow::Error::from)?;
+        if !res.status().is_success() {
+            return Err(AppError::AnyError(anyhow::anyhow!(
+                "archival webhook of chat {} responded {}",
+                message.chat_id,
+                res.status()
+            )));
+        }
+        Ok(true)
+    }
+}
+
+impl JobHandler for ArchiveMessageJob {
+    fn kind(&self) -> &'static str {
+        ARCHIVE_MESSAGE_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: ArchiveMessage =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            state.archive_message(job.message_id as _).await?;
+            Ok(())
+        })
+    }
+}
+
+/// `sha256=<hex hmac>` of the body, receivers compute the same to verify a delivery.
+pub fn sign_payload(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts any key size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateMessage;
+    use anyhow::Result;
+
+    #[test]
+    fn sign_payload_should_work() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_payload("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn archival_webhook_should_enqueue_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SetChatArchivalWebhook {
+            url: "ftp://records.example.com".to_string(),
+            delay_secs: 60,
+        };
+        assert!(state.set_chat_archival_webhook(2, &input, 1).await.is_err());
+
+        let input = SetChatArchivalWebhook {
+            url: "https://records.example.com/chat".to_string(),
+            delay_secs: 60,
+        };
+        let webhook = state.set_chat_archival_webhook(2, &input, 1).await?;
+        assert_eq!(webhook.secret.len(), 64);
+        // changing the webhook keeps the secret
+        let input = SetChatArchivalWebhook {
+            delay_secs: 600,
+            ..input
+        };
+        let updated = state.set_chat_archival_webhook(2, &input, 1).await?;
+        assert_eq!((updated.secret, updated.delay_secs), (webhook.secret, 600));
+
+        let input = CreateMessage {
+            content: "for the records".to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input, 2, 1).await?;
+        let (payload, delay): (serde_json::Value, f64) = sqlx::query_as(
+            "SELECT payload, EXTRACT(EPOCH FROM run_at - NOW())::float8 FROM jobs WHERE kind = $1",
+        )
+        .bind(ARCHIVE_MESSAGE_JOB)
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!(payload, json!({ "message_id": message.id }));
+        assert!(delay > 590.0, "{delay}");
+
+        // nothing to deliver once the webhook is removed
+        assert!(state.delete_chat_archival_webhook(2).await?);
+        assert!(!state.archive_message(message.id as _).await?);
+        assert!(!state.delete_chat_archival_webhook(2).await?);
+        Ok(())
+    }
+}
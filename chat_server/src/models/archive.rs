@@ -0,0 +1,403 @@
+use crate::{AppError, AppState, ChatFile, Job, JobFuture, JobHandler};
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::FromRow;
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::fs;
+use utoipa::ToSchema;
+
+/// Job kind offloading an archived workspace to cold storage.
+pub const ARCHIVE_WORKSPACE_JOB: &str = "archive_workspace";
+/// Job kind moving an unarchived workspace back from cold storage.
+pub const UNARCHIVE_WORKSPACE_JOB: &str = "unarchive_workspace";
+
+/// gzipped json of the offloaded rows, in the workspace's cold storage directory
+const ARCHIVE_FILE: &str = "messages.json.gz";
+
+const WS_CHATS: &str = "chat_id IN (SELECT id FROM chats WHERE ws_id = $1)";
+const WS_MESSAGES: &str =
+    "message_id IN (SELECT m.id FROM messages m JOIN chats c ON c.id = m.chat_id WHERE c.ws_id = $1)";
+
+/// Tables offloaded with a workspace and the condition selecting its rows, in the order
+/// they are restored. All of them are removed by deleting the messages.
+const ARCHIVED_TABLES: &[(&str, &str)] = &[
+    ("messages", WS_CHATS),
+    ("message_pins", WS_CHATS),
+    ("message_reactions", WS_MESSAGES),
+    ("reaction_trigger_runs", WS_MESSAGES),
+    ("tasks", WS_CHATS),
+];
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "workspace_archive_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveStatus {
+    /// writes are rejected while messages and files are offloaded
+    Archiving,
+    Archived,
+    /// messages and files are moved back, the workspace stays read only until done
+    Restoring,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceArchive {
+    pub ws_id: i64,
+    /// None while the workspace is active
+    pub status: Option<ArchiveStatus>,
+    pub archived_at: Option<DateTime<Utc>>,
+}
+
+/// Runs `archive_workspace` jobs enqueued when a workspace is archived.
+pub struct ArchiveWorkspaceJob;
+
+/// Runs `unarchive_workspace` jobs enqueued when a workspace is unarchived.
+pub struct UnarchiveWorkspaceJob;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkspaceJob {
+    ws_id: u64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn get_workspace_archive(&self, ws_id: u64) -> Result<WorkspaceArchive, AppError> {
+        let archive = sqlx::query_as(
+            "SELECT id AS ws_id, archive_status AS status, archived_at FROM workspaces WHERE id = $1",
+        )
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        archive.ok_or_else(|| AppError::NotFound(format!("workspace id {ws_id}")))
+    }
+
+    /// Archive a workspace: writes are rejected right away and its messages and files are
+    /// offloaded to cold storage by a background job.
+    pub async fn archive_workspace(&self, ws_id: u64) -> Result<WorkspaceArchive, AppError> {
+        let archive = self
+            .set_archive_status(ws_id, None, Some(ArchiveStatus::Archiving))
+            .await?
+            .ok_or_else(|| AppError::WorkspaceError("Workspace is already archived".to_string()))?;
+        self.enqueue_job(ARCHIVE_WORKSPACE_JOB, WorkspaceJob { ws_id }, None)
+            .await?;
+        Ok(archive)
+    }
+
+    /// Unarchive a workspace, it becomes writable once its data is back from cold storage.
+    pub async fn unarchive_workspace(&self, ws_id: u64) -> Result<WorkspaceArchive, AppError> {
+        let archive = self
+            .set_archive_status(
+                ws_id,
+                Some(ArchiveStatus::Archived),
+                Some(ArchiveStatus::Restoring),
+            )
+            .await?
+            .ok_or_else(|| {
+                AppError::WorkspaceError(
+                    "Workspace is not archived or is still being archived".to_string(),
+                )
+            })?;
+        self.enqueue_job(UNARCHIVE_WORKSPACE_JOB, WorkspaceJob { ws_id }, None)
+            .await?;
+        Ok(archive)
+    }
+
+    /// Gzipped json of the messages of an archived workspace, keyed by table.
+    pub async fn read_workspace_archive(&self, ws_id: u64) -> Result<Vec<u8>, AppError> {
+        let archive = self.get_workspace_archive(ws_id).await?;
+        if archive.status != Some(ArchiveStatus::Archived) {
+            return Err(AppError::NotFound(format!(
+                "archive of workspace id {ws_id}"
+            )));
+        }
+        Ok(fs::read(self.cold_dir(ws_id).join(ARCHIVE_FILE)).await?)
+    }
+
+    /// Content of a file offloaded to cold storage, None if it isn't there.
+    pub async fn read_archived_file(&self, file: &ChatFile) -> Result<Option<Vec<u8>>, AppError> {
+        let path = cold_path(file, &self.config.storage.cold_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(gunzip(&fs::read(path).await?)?))
+    }
+
+    /// Offload the messages of an archiving workspace, with their pins, reactions and
+    /// tasks, and their files to cold storage, then drop them from the database.
+    pub async fn offload_workspace(&self, ws_id: u64) -> Result<(), AppError> {
+        let archive = self.get_workspace_archive(ws_id).await?;
+        if archive.status != Some(ArchiveStatus::Archiving) {
+            return Ok(());
+        }
+        let cold_dir = self.cold_dir(ws_id);
+        fs::create_dir_all(&cold_dir).await?;
+
+        let mut tables = Map::new();
+        for (table, condition) in ARCHIVED_TABLES {
+            let (rows,): (Value,) = sqlx::query_as(&format!(
+                "SELECT COALESCE(jsonb_agg(t), '[]') FROM {table} t WHERE {condition}"
+            ))
+            .bind(ws_id as i64)
+            .fetch_one(&self.pool)
+            .await?;
+            tables.insert(table.to_string(), rows);
+        }
+        let data = serde_json::to_vec(&tables).map_err(anyhow::Error::from)?;
+        // written under a temporary name so a crash never leaves a truncated archive
+        let tmp = cold_dir.join(format!("{ARCHIVE_FILE}.tmp"));
+        fs::write(&tmp, gzip(&data)?).await?;
+        fs::rename(&tmp, cold_dir.join(ARCHIVE_FILE)).await?;
+
+        for file in message_files(&tables, ws_id) {
+            let hot = file.path(&self.config.server.base_dir);
+            // moved by an earlier attempt
+            if !hot.exists() {
+                continue;
+            }
+            let cold = cold_path(&file, &self.config.storage.cold_dir);
+            if let Some(parent) = cold.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(&cold, gzip(&fs::read(&hot).await?)?).await?;
+            fs::remove_file(&hot).await?;
+        }
+
+        let mut tx = self.pool.begin().await?;
+        // offloaded messages are not deleted ones, keeps them out of the message history
+        sqlx::query("SET LOCAL chat.archiving = 'on'")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&format!("DELETE FROM messages WHERE {WS_CHATS}"))
+            .bind(ws_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "UPDATE workspaces SET archive_status = 'archived', archived_at = NOW() WHERE id = $1",
+        )
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Move the messages and files of an unarchiving workspace back from cold storage.
+    pub async fn restore_workspace(&self, ws_id: u64) -> Result<(), AppError> {
+        let archive = self.get_workspace_archive(ws_id).await?;
+        let cold_dir = self.cold_dir(ws_id);
+        match archive.status {
+            Some(ArchiveStatus::Restoring) => {}
+            // restored by an earlier attempt which failed to clean up
+            None if cold_dir.exists() => {
+                fs::remove_dir_all(&cold_dir).await?;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+
+        let data = gunzip(&fs::read(cold_dir.join(ARCHIVE_FILE)).await?)?;
+        let tables: Map<String, Value> =
+            serde_json::from_slice(&data).map_err(anyhow::Error::from)?;
+        for file in message_files(&tables, ws_id) {
+            let cold = cold_path(&file, &self.config.storage.cold_dir);
+            if !cold.exists() {
+                continue;
+            }
+            let hot = file.path(&self.config.server.base_dir);
+            if let Some(parent) = hot.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::write(&hot, gunzip(&fs::read(&cold).await?)?).await?;
+        }
+
+        let mut tx = self.pool.begin().await?;
+        // keeps the message trigger from notifying restored messages as new ones
+        sqlx::query("SET LOCAL chat.restoring = 'on'")
+            .execute(&mut *tx)
+            .await?;
+        for (table, _) in ARCHIVED_TABLES {
+            let rows = tables.get(*table).cloned().unwrap_or_else(|| json!([]));
+            sqlx::query(&format!(
+                "INSERT INTO {table} SELECT * FROM jsonb_populate_recordset(NULL::{table}, $1)"
+            ))
+            .bind(rows)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE workspaces SET archive_status = NULL, archived_at = NULL WHERE id = $1",
+        )
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        fs::remove_dir_all(&cold_dir).await?;
+        Ok(())
+    }
+
+    async fn set_archive_status(
+        &self,
+        ws_id: u64,
+        from: Option<ArchiveStatus>,
+        to: Option<ArchiveStatus>,
+    ) -> Result<Option<WorkspaceArchive>, AppError> {
+        let archive = sqlx::query_as(
+            r#"
+            UPDATE workspaces SET archive_status = $3
+            WHERE id = $1 AND archive_status IS NOT DISTINCT FROM $2
+            RETURNING id AS ws_id, archive_status AS status, archived_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(from)
+        .bind(to)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(archive)
+    }
+
+    fn cold_dir(&self, ws_id: u64) -> PathBuf {
+        self.config.storage.cold_dir.join(ws_id.to_string())
+    }
+}
+
+impl JobHandler for ArchiveWorkspaceJob {
+    fn kind(&self) -> &'static str {
+        ARCHIVE_WORKSPACE_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: WorkspaceJob =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            state.offload_workspace(job.ws_id).await
+        })
+    }
+}
+
+impl JobHandler for UnarchiveWorkspaceJob {
+    fn kind(&self) -> &'static str {
+        UNARCHIVE_WORKSPACE_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: WorkspaceJob =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            state.restore_workspace(job.ws_id).await
+        })
+    }
+}
+
+/// Distinct files of the workspace attached to the archived messages.
+fn message_files(tables: &Map<String, Value>, ws_id: u64) -> Vec<ChatFile> {
+    let mut files: Vec<ChatFile> = vec![];
+    let messages = tables.get("messages").and_then(Value::as_array);
+    for message in messages.into_iter().flatten() {
+        let urls = message["files"].as_array().into_iter().flatten();
+        for file in urls.filter_map(|url| ChatFile::from_str(url.as_str()?).ok()) {
+            if file.ws_id == ws_id && !files.iter().any(|f| f.url() == file.url()) {
+                files.push(file);
+            }
+        }
+    }
+    files
+}
+
+fn cold_path(file: &ChatFile, cold_dir: &Path) -> PathBuf {
+    let mut path = file.path(cold_dir).into_os_string();
+    path.push(".gz");
+    path.into()
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, AppError> {
+    let mut ret = Vec::new();
+    GzDecoder::new(data).read_to_end(&mut ret)?;
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateMessage, JobRunner, ListMessages};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn archive_workspace_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // unique content, so other tests never share the file
+        let content = format!("archived at {}", Utc::now().timestamp_nanos_opt().unwrap());
+        let file = ChatFile::new(1, "notes.txt", content.as_bytes());
+        let hot = file.path(&state.config.server.base_dir);
+        std::fs::create_dir_all(hot.parent().expect("file path parent should exist"))?;
+        std::fs::write(&hot, &content)?;
+        let input = CreateMessage {
+            content: "meeting notes".to_string(),
+            files: vec![file.url()],
+        };
+        state.create_message(input, 1, 1).await?;
+
+        let archive = state.archive_workspace(1).await?;
+        assert_eq!(archive.status, Some(ArchiveStatus::Archiving));
+        assert!(state.archive_workspace(1).await.is_err());
+        assert!(state.unarchive_workspace(1).await.is_err());
+
+        let runner = JobRunner::new(state.clone())
+            .register(ArchiveWorkspaceJob)
+            .register(UnarchiveWorkspaceJob);
+        assert!(runner.run_once().await?);
+        let archive = state.get_workspace_archive(1).await?;
+        assert_eq!(archive.status, Some(ArchiveStatus::Archived));
+        assert!(archive.archived_at.is_some());
+        let messages = state
+            .list_messages(
+                ListMessages {
+                    last_id: None,
+                    limit: 100,
+                    fields: Default::default(),
+                },
+                1,
+            )
+            .await?;
+        assert!(messages.is_empty());
+        assert!(!hot.exists());
+        let data = state.read_archived_file(&file).await?;
+        assert_eq!(data.as_deref(), Some(content.as_bytes()));
+        let tables: Map<String, Value> =
+            serde_json::from_slice(&gunzip(&state.read_workspace_archive(1).await?)?)?;
+        assert_eq!(tables["messages"].as_array().map(Vec::len), Some(11));
+
+        state.unarchive_workspace(1).await?;
+        assert!(runner.run_once().await?);
+        let archive = state.get_workspace_archive(1).await?;
+        assert_eq!(archive.status, None);
+        let messages = state
+            .list_messages(
+                ListMessages {
+                    last_id: None,
+                    limit: 100,
+                    fields: Default::default(),
+                },
+                1,
+            )
+            .await?;
+        assert_eq!(messages.len(), 11);
+        assert_eq!(std::fs::read_to_string(&hot)?, content);
+        assert_eq!(state.read_archived_file(&file).await?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{AppError, AppState};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct AuditLog {
+    pub id: i64,
+    pub ws_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub actor_id: i64,
+    /// e.g. `chat.export`
+    pub action: String,
+    pub target_id: Option<i64>,
+    pub details: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListAuditLogs {
+    pub last_id: Option<u64>,
+    pub limit: u64,
+    pub action: Option<String>,
+}
+
+/// A download of a file, recorded when the workspace audits file access.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct FileAccess {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub ip: Option<String>,
+    pub accessed_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn record_audit(
+        &self,
+        ws_id: u64,
+        actor_id: u64,
+        action: &str,
+        target_id: Option<u64>,
+        details: impl Serialize,
+    ) -> Result<AuditLog, AppError> {
+        let details = serde_json::to_value(details).map_err(anyhow::Error::from)?;
+        let log = sqlx::query_as(
+            r#"
+            INSERT INTO audit_logs (ws_id, actor_id, action, target_id, details)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, ws_id, actor_id, action, target_id, details, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(actor_id as i64)
+        .bind(action)
+        .bind(target_id.map(|id| id as i64))
+        .bind(details)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(log)
+    }
+
+    /// List audit logs of a workspace, newest first.
+    pub async fn list_audit_logs(
+        &self,
+        ws_id: u64,
+        input: &ListAuditLogs,
+    ) -> Result<Vec<AuditLog>, AppError> {
+        let last_id = input.last_id.unwrap_or(i64::MAX as _);
+        let limit = input.limit.clamp(1, 100) as i64;
+
+        let logs = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, actor_id, action, target_id, details, created_at
+            FROM audit_logs
+            WHERE ws_id = $1 AND id < $2 AND ($3::varchar IS NULL OR action = $3)
+            ORDER BY id DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(last_id as i64)
+        .bind(&input.action)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(logs)
+    }
+
+    /// Record that `user_id` downloaded `file` (its `/files/...` url) if the workspace
+    /// audits file access.
+    pub async fn record_file_access(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        file: &str,
+        ip: Option<String>,
+    ) -> Result<Option<AuditLog>, AppError> {
+        let audited = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .is_some_and(|ws| ws.settings.audit_file_access);
+        if !audited {
+            return Ok(None);
+        }
+        let details = json!({ "file": file, "ip": ip });
+        let log = self
+            .record_audit(ws_id, user_id, "file.download", None, details)
+            .await?;
+        Ok(Some(log))
+    }
+
+    /// Access history of `file`, newest first. Only the workspace owner and users who
+    /// sent the file in a message can see it.
+    pub async fn list_file_access(
+        &self,
+        ws_id: u64,
+        file: &str,
+        user: &User,
+    ) -> Result<Vec<FileAccess>, AppError> {
+        let is_owner = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .is_some_and(|ws| ws.owner_id == user.id);
+        if !is_owner {
+            let (is_sender,): (bool,) = sqlx::query_as(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM messages m JOIN chats c ON c.id = m.chat_id
+                    WHERE c.ws_id = $1 AND m.sender_id = $2 AND $3 = ANY(m.files)
+                )
+                "#,
+            )
+            .bind(ws_id as i64)
+            .bind(user.id)
+            .bind(file)
+            .fetch_one(&self.pool)
+            .await?;
+            if !is_sender {
+                return Err(AppError::PermissionDenied(
+                    "only the file owner or the workspace owner can view its access history"
+                        .to_string(),
+                ));
+            }
+        }
+
+        let history = sqlx::query_as(
+            r#"
+            SELECT actor_id AS user_id, details ->> 'ip' AS ip, created_at AS accessed_at
+            FROM audit_logs
+            WHERE ws_id = $1 AND action = 'file.download' AND details ->> 'file' = $2
+            ORDER BY id DESC
+            LIMIT 1000
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(file)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chat_core::WorkspaceSettings;
+
+    #[tokio::test]
+    async fn record_and_list_audit_logs_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state
+            .record_audit(1, 1, "chat.export", Some(1), json!({"allowed": true}))
+            .await?;
+        state
+            .record_audit(1, 2, "chat.settings", Some(1), json!({}))
+            .await?;
+
+        let input = ListAuditLogs {
+            last_id: None,
+            limit: 10,
+            action: None,
+        };
+        let logs = state.list_audit_logs(1, &input).await?;
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].action, "chat.settings");
+
+        let input = ListAuditLogs {
+            action: Some("chat.export".to_string()),
+            ..input
+        };
+        let logs = state.list_audit_logs(1, &input).await?;
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].details, json!({"allowed": true}));
+        assert!(state.list_audit_logs(2, &input).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_access_should_be_audited_when_enabled() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let file = "/files/1/2aa/e6c/35c94fcfb415dbe95f408b9ce91ee846ed.txt";
+        let ip = Some("10.0.0.1".to_string());
+        assert!(state
+            .record_file_access(1, 2, file, ip.clone())
+            .await?
+            .is_none());
+
+        let settings = WorkspaceSettings {
+            audit_file_access: true,
+            ..Default::default()
+        };
+        state.update_workspace_settings(1, &settings).await?;
+        state.record_file_access(1, 2, file, ip.clone()).await?;
+        state.record_file_access(1, 3, file, None).await?;
+
+        state.update_workspace_owner(1, 1).await?;
+        let owner = state.find_user_by_id(1).await?.unwrap();
+        let history = state.list_file_access(1, file, &owner).await?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].user_id, 3);
+        assert_eq!(history[1].ip, ip);
+
+        let user = state.find_user_by_id(2).await?.unwrap();
+        assert!(state.list_file_access(1, file, &user).await.is_err());
+        Ok(())
+    }
+}
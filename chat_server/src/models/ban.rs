@@ -0,0 +1,137 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatBan {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub banned_by: i64,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoveChatMember {
+    /// keep the member from being added back or joining again until unbanned
+    pub ban: bool,
+    /// why the member is banned, shown to the owner and admins
+    pub reason: Option<String>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Ban `user_id` from the chat, banning them again updates the reason.
+    pub async fn ban_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        reason: Option<&str>,
+        banned_by: u64,
+    ) -> Result<ChatBan, AppError> {
+        let ban = sqlx::query_as(
+            r#"
+            INSERT INTO chat_bans (chat_id, user_id, banned_by, reason)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (chat_id, user_id) DO UPDATE
+            SET banned_by = EXCLUDED.banned_by, reason = EXCLUDED.reason
+            RETURNING chat_id, user_id, banned_by, reason, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(banned_by as i64)
+        .bind(reason.map(str::trim).filter(|r| !r.is_empty()))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(ban)
+    }
+
+    /// Let a banned user be added to the chat again, returns false if they weren't banned.
+    pub async fn unban_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM chat_bans WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Users banned from the chat, latest first.
+    pub async fn list_chat_bans(&self, chat_id: u64) -> Result<Vec<ChatBan>, AppError> {
+        let bans = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, banned_by, reason, created_at
+            FROM chat_bans
+            WHERE chat_id = $1
+            ORDER BY created_at DESC, user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(bans)
+    }
+
+    /// Fail if `user_id` is banned from the chat.
+    pub async fn verify_not_banned(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        let banned = sqlx::query("SELECT 1 FROM chat_bans WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .fetch_optional(&self.pool)
+            .await?;
+        if banned.is_some() {
+            return Err(AppError::PermissionDenied(format!(
+                "User {user_id} is banned from chat {chat_id}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateJoinRequest;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn banned_users_should_not_get_back_in() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ban = state.ban_chat_member(1, 5, Some(" spam "), 1).await?;
+        assert_eq!(ban.reason.as_deref(), Some("spam"));
+        state.remove_chat_member(1, 5, 1).await?;
+
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        let err = state.join_chat(1, &user).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: User 5 is banned from chat 1"
+        );
+        assert!(state.verify_chat_member_change(1, 5, false).await.is_err());
+        // banning from one chat doesn't keep them out of others
+        state.ban_chat_member(2, 4, None, 1).await?;
+        let user = state.find_user_by_id(4).await?.expect("user should exist");
+        let input = CreateJoinRequest::default();
+        assert!(state.request_to_join_chat(2, &input, &user).await.is_err());
+        state.verify_chat_member_change(1, 4, false).await?;
+
+        let bans = state.list_chat_bans(1).await?;
+        assert_eq!(bans.iter().map(|b| b.user_id).collect::<Vec<_>>(), [5]);
+        assert!(state.unban_chat_member(1, 5).await?);
+        assert!(!state.unban_chat_member(1, 5).await?);
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        let chat = state.join_chat(1, &user).await?;
+        assert!(chat.members.contains(&5));
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{AppError, AppState, ChatDTO, CreateMessage, Job, JobFuture, JobHandler};
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Job kind sending a bulk message to its pending targets.
+pub const BULK_MESSAGE_JOB: &str = "bulk_message";
+
+const MAX_TARGETS: usize = 1000;
+/// targets sent by a single job, the rest is left to a follow up job so none runs for long
+const TARGETS_PER_JOB: i64 = 50;
+
+const BULK_COLUMNS: &str = r#"
+    b.id, b.ws_id, b.sender_id, b.content, b.files, b.created_at, b.finished_at,
+    (SELECT COUNT(*) FROM bulk_message_targets t WHERE t.bulk_id = b.id) AS total,
+    (SELECT COUNT(*) FROM bulk_message_targets t WHERE t.bulk_id = b.id AND t.status = 'sent') AS sent,
+    (SELECT COUNT(*) FROM bulk_message_targets t WHERE t.bulk_id = b.id AND t.status = 'failed') AS failed
+"#;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct BulkMessage {
+    pub id: i64,
+    pub ws_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub sender_id: i64,
+    pub content: String,
+    pub files: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// set once every target is sent or failed
+    pub finished_at: Option<DateTime<Utc>>,
+    pub total: i64,
+    pub sent: i64,
+    pub failed: i64,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "bulk_target_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum BulkTargetStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct BulkMessageTarget {
+    pub id: i64,
+    #[serde(with = "chat_core::id::option")]
+    pub chat_id: Option<i64>,
+    /// receives the message as a direct message from the sender
+    #[serde(with = "chat_core::id::option")]
+    pub user_id: Option<i64>,
+    pub status: BulkTargetStatus,
+    #[serde(with = "chat_core::id::option")]
+    pub message_id: Option<i64>,
+    pub error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Progress of a bulk message with the result of each target.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct BulkMessageReport {
+    #[serde(flatten)]
+    pub bulk: BulkMessage,
+    pub targets: Vec<BulkMessageTarget>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateBulkMessage {
+    pub content: String,
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// chats of the sender's workspace to post to
+    #[serde(default, with = "chat_core::id::list")]
+    pub chat_ids: Vec<i64>,
+    /// users of the sender's workspace to send a direct message to
+    #[serde(default, with = "chat_core::id::list")]
+    pub user_ids: Vec<i64>,
+}
+
+/// Runs `bulk_message` jobs enqueued for new bulk messages.
+pub struct BulkMessageJob;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BulkJob {
+    bulk_id: i64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Record a message for many chats or users, it is sent in the background.
+    pub async fn create_bulk_message(
+        &self,
+        input: &CreateBulkMessage,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<BulkMessage, AppError> {
+        if input.content.is_empty() {
+            return Err(AppError::CreateMessageError(
+                "Content cannot be empty".to_string(),
+            ));
+        }
+        let targets = input.chat_ids.len() + input.user_ids.len();
+        if targets == 0 || targets > MAX_TARGETS {
+            return Err(AppError::CreateMessageError(format!(
+                "A bulk message must have between 1 and {MAX_TARGETS} targets"
+            )));
+        }
+        if self.get_workspace_archive(ws_id).await?.status.is_some() {
+            return Err(AppError::CreateMessageError(
+                "Workspace is archived".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let (bulk_id,): (i64,) = sqlx::query_as(
+            r#"
+            INSERT INTO bulk_messages (ws_id, sender_id, content, files)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(&input.content)
+        .bind(&input.files)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO bulk_message_targets (bulk_id, chat_id, user_id)
+            SELECT $1, c, NULL FROM unnest($2::bigint[]) AS c
+            UNION ALL
+            SELECT $1, NULL, u FROM unnest($3::bigint[]) AS u
+            "#,
+        )
+        .bind(bulk_id)
+        .bind(dedup(&input.chat_ids))
+        .bind(dedup(&input.user_ids))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.enqueue_job(BULK_MESSAGE_JOB, BulkJob { bulk_id }, None)
+            .await?;
+        self.get_bulk_message(bulk_id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("bulk message id {bulk_id}")))
+    }
+
+    pub async fn get_bulk_message(&self, id: u64) -> Result<Option<BulkMessage>, AppError> {
+        let bulk = sqlx::query_as(&format!(
+            "SELECT {BULK_COLUMNS} FROM bulk_messages b WHERE b.id = $1"
+        ))
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(bulk)
+    }
+
+    pub async fn get_bulk_message_report(
+        &self,
+        id: u64,
+    ) -> Result<Option<BulkMessageReport>, AppError> {
+        let Some(bulk) = self.get_bulk_message(id).await? else {
+            return Ok(None);
+        };
+        let targets = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, status, message_id, error, updated_at
+            FROM bulk_message_targets
+            WHERE bulk_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(BulkMessageReport { bulk, targets }))
+    }
+
+    /// Send a bulk message to a batch of its pending targets. Returns whether targets are
+    /// left for another run.
+    pub async fn send_bulk_message(&self, id: u64) -> Result<bool, AppError> {
+        let Some(bulk) = self.get_bulk_message(id).await? else {
+            return Ok(false);
+        };
+        let targets: Vec<BulkMessageTarget> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, status, message_id, error, updated_at
+            FROM bulk_message_targets
+            WHERE bulk_id = $1 AND status = 'pending'
+            ORDER BY id
+            LIMIT $2
+            "#,
+        )
+        .bind(id as i64)
+        .bind(TARGETS_PER_JOB)
+        .fetch_all(&self.pool)
+        .await?;
+
+        for target in &targets {
+            // a failed target is reported, it doesn't fail the others
+            let (status, message_id, error) = match self.send_bulk_target(&bulk, target).await {
+                Ok(message) => (BulkTargetStatus::Sent, Some(message.id), None),
+                Err(e) => (BulkTargetStatus::Failed, None, Some(e.to_string())),
+            };
+            sqlx::query(
+                r#"
+                UPDATE bulk_message_targets SET status = $2, message_id = $3, error = $4, updated_at = NOW()
+                WHERE id = $1
+                "#,
+            )
+            .bind(target.id)
+            .bind(status)
+            .bind(message_id)
+            .bind(error)
+            .execute(&self.pool)
+            .await?;
+        }
+
+        let ret = sqlx::query(
+            r#"
+            UPDATE bulk_messages SET finished_at = NOW()
+            WHERE id = $1 AND finished_at IS NULL
+            AND NOT EXISTS (SELECT 1 FROM bulk_message_targets WHERE bulk_id = $1 AND status = 'pending')
+            "#,
+        )
+        .bind(id as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(ret.rows_affected() == 0 && bulk.finished_at.is_none())
+    }
+
+    async fn send_bulk_target(
+        &self,
+        bulk: &BulkMessage,
+        target: &BulkMessageTarget,
+    ) -> Result<Message, AppError> {
+        let chat_id = match (target.chat_id, target.user_id) {
+            (Some(chat_id), _) => match self.get_chat_by_id(chat_id as _).await? {
+                Some(chat) if chat.ws_id == bulk.ws_id => chat_id,
+                _ => return Err(AppError::NotFound(format!("chat id {chat_id}"))),
+            },
+            (_, Some(user_id)) => self.direct_chat(bulk, user_id).await?,
+            _ => unreachable!("a target is either a chat or a user"),
+        };
+        let input = CreateMessage {
+            content: bulk.content.clone(),
+            files: bulk.files.clone(),
+        };
+        self.create_message(input, chat_id as _, bulk.sender_id as _)
+            .await
+    }
+
+    /// The direct chat of the sender with `user_id`, created if they never talked.
+    async fn direct_chat(&self, bulk: &BulkMessage, user_id: i64) -> Result<i64, AppError> {
+        if user_id == bulk.sender_id {
+            return Err(AppError::CreateMessageError(
+                "Can't send a direct message to yourself".to_string(),
+            ));
+        }
+        match self.find_user_by_id(user_id).await? {
+            Some(user) if user.ws_id == bulk.ws_id => {}
+            _ => return Err(AppError::NotFound(format!("user id {user_id}"))),
+        }
+
+        // create_chat returns the existing single chat of the two
+        let input = ChatDTO {
+            name: None,
+            topic: None,
+            members: vec![bulk.sender_id, user_id],
+            public: false,
+        };
+        let chat = self
+            .create_chat(input, bulk.ws_id as _, bulk.sender_id as _)
+            .await?;
+        Ok(chat.id)
+    }
+}
+
+impl JobHandler for BulkMessageJob {
+    fn kind(&self) -> &'static str {
+        BULK_MESSAGE_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: BulkJob = serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            if state.send_bulk_message(job.bulk_id as _).await? {
+                state.enqueue_job(BULK_MESSAGE_JOB, job, None).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+fn dedup(ids: &[i64]) -> Vec<i64> {
+    ids.iter().fold(Vec::new(), |mut acc, id| {
+        if !acc.contains(id) {
+            acc.push(*id);
+        }
+        acc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JobRunner;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn bulk_message_should_report_each_target() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateBulkMessage {
+            content: "Office is closed on Friday".to_string(),
+            files: vec![],
+            chat_ids: vec![1, 2, 2, 999],
+            user_ids: vec![2, 3, 1],
+        };
+        let bulk = state.create_bulk_message(&input, 1, 1).await?;
+        assert_eq!((bulk.total, bulk.sent, bulk.failed), (6, 0, 0));
+
+        let runner = JobRunner::new(state.clone()).register(BulkMessageJob);
+        assert!(runner.run_once().await?);
+        let report = state
+            .get_bulk_message_report(bulk.id as _)
+            .await?
+            .expect("bulk message should exist");
+        assert_eq!((report.bulk.sent, report.bulk.failed), (4, 2));
+        assert!(report.bulk.finished_at.is_some());
+        let failed: Vec<_> = report
+            .targets
+            .iter()
+            .filter(|t| t.status == BulkTargetStatus::Failed)
+            .map(|t| (t.chat_id, t.user_id))
+            .collect();
+        assert_eq!(failed, vec![(Some(999), None), (None, Some(1))]);
+
+        // the existing direct chat with user 2 is reused
+        let dm = report
+            .targets
+            .iter()
+            .find(|t| t.user_id == Some(2))
+            .unwrap();
+        let messages = state
+            .list_messages(
+                crate::ListMessages {
+                    last_id: None,
+                    limit: 1,
+                    fields: Default::default(),
+                },
+                3,
+            )
+            .await?;
+        assert_eq!(Some(messages[0].id), dm.message_id);
+        assert!(!runner.run_once().await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_bulk_message_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateBulkMessage {
+            content: "hello".to_string(),
+            ..Default::default()
+        };
+        assert!(state.create_bulk_message(&input, 1, 1).await.is_err());
+        let input = CreateBulkMessage {
+            content: "".to_string(),
+            chat_ids: vec![1],
+            ..Default::default()
+        };
+        assert!(state.create_bulk_message(&input, 1, 1).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{AppError, AppState};
+use chat_core::{Chat, Message};
+use chrono::{Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{collections::BTreeSet, str::FromStr};
+use utoipa::{IntoParams, ToSchema};
+
+/// Header clients list their capabilities in, comma separated.
+pub const CLIENT_CAPABILITIES_HEADER: &str = "x-client-capabilities";
+
+/// Payload features a client can declare support for. Clients which don't send
+/// `X-Client-Capabilities` are assumed to support all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// `translation` of messages
+    Translations,
+    /// `avatar_url` of chats
+    ChatAvatars,
+}
+
+/// What the client of a request declared, unknown capabilities are ignored so clients can
+/// declare features of newer servers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientCapabilities(Option<BTreeSet<Capability>>);
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct CapabilityStat {
+    pub day: NaiveDate,
+    /// false for clients without the header
+    pub declared: bool,
+    pub capabilities: Vec<String>,
+    /// bootstraps of clients with these capabilities
+    pub clients: i64,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListCapabilityStats {
+    /// 30 by default and at most 90
+    pub days: Option<i64>,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[Self::Translations, Self::ChatAvatars];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Translations => "translations",
+            Self::ChatAvatars => "chat_avatars",
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|c| c.as_str() == s)
+            .copied()
+            .ok_or(())
+    }
+}
+
+impl ClientCapabilities {
+    /// Parse the value of `X-Client-Capabilities`, None if the client didn't send it.
+    pub fn from_header(value: Option<&str>) -> Self {
+        Self(value.map(|v| {
+            v.split(',')
+                .filter_map(|c| c.trim().to_ascii_lowercase().parse().ok())
+                .collect()
+        }))
+    }
+
+    pub fn is_declared(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.0
+            .as_ref()
+            .is_none_or(|caps| caps.contains(&capability))
+    }
+
+    /// The capabilities the server honors for the client.
+    pub fn supported(&self) -> Vec<Capability> {
+        Capability::ALL
+            .iter()
+            .copied()
+            .filter(|c| self.supports(*c))
+            .collect()
+    }
+
+    /// Leave out the fields of chats the client can't handle.
+    pub fn downgrade_chat(&self, mut chat: Chat) -> Chat {
+        if !self.supports(Capability::ChatAvatars) {
+            chat.avatar_url = None;
+        }
+        chat
+    }
+
+    /// Leave out the fields of messages the client can't handle.
+    pub fn downgrade_message(&self, mut message: Message) -> Message {
+        if !self.supports(Capability::Translations) {
+            message.translation = None;
+        }
+        message
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Count a client bootstrap for today's capability stats.
+    pub async fn record_client_capabilities(
+        &self,
+        caps: &ClientCapabilities,
+    ) -> Result<(), AppError> {
+        let mut capabilities: Vec<_> = caps
+            .0
+            .iter()
+            .flatten()
+            .map(|c| c.as_str().to_string())
+            .collect();
+        capabilities.sort();
+        sqlx::query(
+            r#"
+            INSERT INTO client_capability_stats (day, declared, capabilities, clients)
+            VALUES (CURRENT_DATE, $1, $2, 1)
+            ON CONFLICT (day, declared, capabilities) DO UPDATE
+            SET clients = client_capability_stats.clients + 1
+            "#,
+        )
+        .bind(caps.is_declared())
+        .bind(&capabilities)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Capability stats of the last days, newest first and the most common set first.
+    pub async fn list_capability_stats(
+        &self,
+        input: &ListCapabilityStats,
+    ) -> Result<Vec<CapabilityStat>, AppError> {
+        let days = input.days.unwrap_or(30).clamp(1, 90);
+        let since = Utc::now().date_naive() - Duration::days(days - 1);
+        let stats = sqlx::query_as(
+            r#"
+            SELECT day, declared, capabilities, clients
+            FROM client_capability_stats
+            WHERE day >= $1
+            ORDER BY day DESC, clients DESC, capabilities
+            "#,
+        )
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn client_capabilities_should_parse_header() {
+        let caps = ClientCapabilities::from_header(None);
+        assert!(!caps.is_declared());
+        assert_eq!(caps.supported(), Capability::ALL);
+
+        let caps = ClientCapabilities::from_header(Some(" Translations, threads,,"));
+        assert!(caps.is_declared());
+        assert_eq!(caps.supported(), [Capability::Translations]);
+        assert!(!caps.supports(Capability::ChatAvatars));
+    }
+
+    #[tokio::test]
+    async fn capability_stats_should_count_bootstraps() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let legacy = ClientCapabilities::from_header(None);
+        let modern = ClientCapabilities::from_header(Some("chat_avatars,translations"));
+        state.record_client_capabilities(&legacy).await?;
+        state.record_client_capabilities(&modern).await?;
+        state.record_client_capabilities(&modern).await?;
+
+        let stats = state
+            .list_capability_stats(&ListCapabilityStats::default())
+            .await?;
+        let stats: Vec<_> = stats
+            .into_iter()
+            .map(|s| (s.declared, s.capabilities, s.clients))
+            .collect();
+        let both = vec!["chat_avatars".to_string(), "translations".to_string()];
+        assert_eq!(stats, [(true, both, 2), (false, vec![], 1)]);
+        Ok(())
+    }
+}
@@ -1,248 +1,2997 @@
-use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use crate::{image_ext, AppError, AppState, FieldError, Job, JobFuture, JobHandler};
+use chat_core::{Chat, ChatType, User};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, PgExecutor, Postgres, QueryBuilder, Transaction};
+use utoipa::{IntoParams, ToSchema};
+
+/// Header of chat creation requests which can be retried without creating the chat twice.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Job kind purging a deleted chat once its retention period has passed.
+pub const PURGE_CHAT_JOB: &str = "purge_chat";
+
+/// Job kind purging a chat scheduled for deletion once its grace period has passed.
+pub const DELETE_SCHEDULED_CHAT_JOB: &str = "delete_scheduled_chat";
+
+const MAX_AVATAR_SIZE: usize = 1024 * 1024;
+
+// longest handle derived from a channel name
+const MAX_HANDLE_LEN: usize = 80;
+
+// chats recounted by a single batch of the message count backfill
+const CHATS_PER_BATCH: i64 = 100;
+
+// characters of the latest message shown in chat lists
+const LAST_MESSAGE_PREVIEW_LEN: i32 = 120;
 
 #[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
 pub struct ChatDTO {
     pub name: Option<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(with = "chat_core::id::list")]
     pub members: Vec<i64>,
     pub public: bool,
 }
 
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListChats {
+    /// also list archived chats
+    #[serde(default)]
+    pub include_archived: bool,
+    /// chats per page, 50 by default and at most 100
+    pub limit: Option<u64>,
+    /// `next_cursor` of the previous page, only valid with the same `sort`
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: ChatSort,
+}
+
+/// Order of the chats after the pinned ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatSort {
+    /// most recently changed or messaged first
+    #[default]
+    Updated,
+    /// most recently messaged first, chats without messages by their creation
+    Activity,
+}
+
+/// A page of chats, the ones pinned by the user first, then in the requested order.
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct ChatPage {
+    pub chats: Vec<Chat>,
+    /// cursor of the next page, None on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct SearchChats {
+    /// start or words of the chat names, ignoring case
+    #[serde(default)]
+    pub q: String,
+    /// chats per page, 20 by default and at most 100
+    pub limit: Option<u64>,
+    /// `next_cursor` of the previous page, only valid with the same `q`
+    pub cursor: Option<String>,
+}
+
+// position of a chat in the search results
+#[derive(Debug, Clone, Copy, PartialEq, FromRow)]
+struct SearchCursor {
+    prefix_miss: bool,
+    distance: f32,
+    id: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    chat: Chat,
+    #[sqlx(flatten)]
+    cursor: SearchCursor,
+}
+
+// position of a chat in the chat list
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChatCursor {
+    pinned: bool,
+    sort_at: DateTime<Utc>,
+    id: i64,
+}
+
+#[derive(Debug, FromRow)]
+struct ChatRow {
+    #[sqlx(flatten)]
+    chat: Chat,
+    sort_at: DateTime<Utc>,
+}
+
+/// Changes to a chat, fields which are not given keep their current value.
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct ChatPatchDTO {
+    pub name: Option<String>,
+    /// an empty topic removes it
+    pub topic: Option<String>,
+    /// the full new member list
+    #[serde(default, with = "chat_core::id::option_list")]
+    pub members: Option<Vec<i64>>,
+    pub public: Option<bool>,
+    /// only update the chat if it is still at this version, also taken from `If-Match`
+    #[serde(default)]
+    pub version: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRole {
+    Owner,
+    Admin,
+    #[default]
+    Member,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct AddChatMember {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct UpdateChatRole {
+    /// making a member the owner transfers the ownership, the old owner becomes an admin
+    pub role: ChatRole,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ConvertChat {
+    /// users joining the two members of the single chat, at least one
+    #[serde(with = "chat_core::id::list")]
+    pub members: Vec<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct TransferChat {
+    /// the member becoming the owner
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatMember {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub role: ChatRole,
+    pub joined_at: DateTime<Utc>,
+    #[serde(default, with = "chat_core::id::option")]
+    pub invited_by: Option<i64>,
+    /// messages the member sent to the chat
+    pub sent_count: i64,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListChannels {
+    /// only the channels whose name or topic contains it, ignoring case
+    pub query: Option<String>,
+    /// channels to list, 50 by default and at most 100
+    pub limit: Option<u64>,
+}
+
+/// A public channel of the workspace the user can join.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct DirectoryChannel {
+    #[serde(with = "chat_core::id")]
+    pub id: i64,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub avatar_url: Option<String>,
+    pub member_count: i64,
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A chat scheduled for deletion, its members were told when it will be purged.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatDeletion {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub delete_at: DateTime<Utc>,
+}
+
+/// Runs `purge_chat` jobs enqueued for deleted chats.
+pub struct PurgeChatJob;
+
+/// Runs `delete_scheduled_chat` jobs enqueued for chats scheduled for deletion.
+pub struct DeleteScheduledChatJob;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PurgeChat {
+    chat_id: i64,
+}
+
 #[allow(dead_code)]
 impl AppState {
-    pub async fn create_chat(&self, input: ChatDTO, ws_id: u64) -> Result<Chat, AppError> {
-        self.valid_chat_dto(&input).await?;
+    /// Create a chat owned by `user_id`, the creator. Duplicate members are dropped and
+    /// the creator is added to the members if they are not among them.
+    pub async fn create_chat(
+        &self,
+        mut input: ChatDTO,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Chat, AppError> {
+        let user_id = user_id as i64;
+        input.members = dedup_members(input.members, Some(user_id));
+        self.valid_chat_dto(&input, ws_id).await?;
         let chat_type = get_chat_type(&input);
-        let chat = sqlx::query_as(
+        let owner_id = user_id;
+
+        let dm_pair = get_dm_pair(&chat_type, &input.members);
+
+        let mut tx = self.pool.begin().await?;
+        let id: Option<(i64,)> = sqlx::query_as(
             r#"
-            INSERT INTO chats (ws_id, name, type, members)
-            VALUES ($1, $2, $3, $4)
-            RETURNING id, ws_id, name, type, members, created_at
+            INSERT INTO chats (ws_id, name, topic, type, dm_pair)
+            VALUES ($1, $2, NULLIF($3, ''), $4, $5)
+            ON CONFLICT (ws_id, dm_pair) WHERE dm_pair IS NOT NULL AND deleted_at IS NULL
+            DO NOTHING
+            RETURNING id
             "#,
         )
         .bind(ws_id as i64)
         .bind(input.name)
+        .bind(input.topic)
         .bind(chat_type)
-        .bind(input.members)
-        .fetch_one(&self.pool)
+        .bind(&dm_pair)
+        .fetch_optional(&mut *tx)
+        .await?;
+        // the two users already have a single chat
+        let Some((id,)) = id else {
+            tx.rollback().await?;
+            let (id,): (i64,) = sqlx::query_as(
+                "SELECT id FROM chats WHERE ws_id = $1 AND dm_pair = $2 AND deleted_at IS NULL",
+            )
+            .bind(ws_id as i64)
+            .bind(&dm_pair)
+            .fetch_one(&self.pool)
+            .await?;
+            let chat = self.get_chat_by_id(id as _).await?;
+            return Ok(chat.expect("chat should exist"));
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, role, invited_by)
+            SELECT $1, m, CASE WHEN m = $3 THEN 'owner'::chat_role ELSE 'member'::chat_role END, $4
+            FROM unnest($2::bigint[]) AS m
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(id)
+        .bind(&input.members)
+        .bind(owner_id)
+        .bind(user_id)
+        .execute(&mut *tx)
         .await?;
+        sync_chat_handle(&mut tx, id).await?;
+        let chat = fetch_chat(&mut *tx, id).await?.expect("chat should exist");
+        tx.commit().await?;
 
         Ok(chat)
     }
 
-    async fn valid_chat_dto(&self, input: &ChatDTO) -> Result<(), AppError> {
+    /// Create a chat like `create_chat`, once per idempotency `key` of the user. Retries
+    /// with the same key and input get the chat created first and true, a key is kept for
+    /// a day.
+    pub async fn create_chat_once(
+        &self,
+        input: ChatDTO,
+        ws_id: u64,
+        user_id: u64,
+        key: &str,
+    ) -> Result<(Chat, bool), AppError> {
+        let body = serde_json::to_vec(&input).map_err(anyhow::Error::from)?;
+        let fingerprint = hex::encode(Sha256::digest(body));
+        // a key older than a day is taken over like a new one
+        let reserved = sqlx::query(
+            r#"
+            INSERT INTO chat_idempotency_keys (user_id, key, fingerprint)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, key) DO UPDATE
+            SET fingerprint = EXCLUDED.fingerprint, chat_id = NULL, created_at = NOW()
+            WHERE chat_idempotency_keys.created_at < NOW() - INTERVAL '1 day'
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(key)
+        .bind(&fingerprint)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        if !reserved {
+            let (used, chat_id): (String, Option<i64>) = sqlx::query_as(
+                "SELECT fingerprint, chat_id FROM chat_idempotency_keys WHERE user_id = $1 AND key = $2",
+            )
+            .bind(user_id as i64)
+            .bind(key)
+            .fetch_one(&self.pool)
+            .await?;
+            if used != fingerprint {
+                return Err(AppError::IdempotencyError(format!(
+                    "key {key} was used for another chat"
+                )));
+            }
+            let Some(chat_id) = chat_id else {
+                return Err(AppError::IdempotencyError(format!(
+                    "the chat of key {key} is still being created"
+                )));
+            };
+            let chat = self
+                .get_chat_by_id(chat_id as _)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+            return Ok((chat, true));
+        }
+
+        match self.create_chat(input, ws_id, user_id).await {
+            Ok(chat) => {
+                sqlx::query(
+                    "UPDATE chat_idempotency_keys SET chat_id = $3 WHERE user_id = $1 AND key = $2",
+                )
+                .bind(user_id as i64)
+                .bind(key)
+                .bind(chat.id)
+                .execute(&self.pool)
+                .await?;
+                Ok((chat, false))
+            }
+            // the client can retry a request which failed with the same key
+            Err(e) => {
+                sqlx::query("DELETE FROM chat_idempotency_keys WHERE user_id = $1 AND key = $2")
+                    .bind(user_id as i64)
+                    .bind(key)
+                    .execute(&self.pool)
+                    .await?;
+                Err(e)
+            }
+        }
+    }
+
+    // checks all fields and reports every invalid one, except for the member limit
+    async fn valid_chat_dto(&self, input: &ChatDTO, ws_id: u64) -> Result<(), AppError> {
         let len = input.members.len();
+        let mut errors = vec![];
         if len < 2 {
-            return Err(AppError::ChatDTOError(
-                "Chat must have at least 2 members".to_string(),
+            errors.push(FieldError::new(
+                "members",
+                "Chat must have at least 2 members",
+            ));
+        }
+        if input
+            .topic
+            .as_ref()
+            .is_some_and(|t| t.chars().count() > 250)
+        {
+            errors.push(FieldError::new(
+                "topic",
+                "Topic must be at most 250 characters",
             ));
         }
         if len > 8 && input.name.is_none() {
-            return Err(AppError::ChatDTOError(
-                "Group chat with more than 8 members must have a name".to_string(),
+            errors.push(FieldError::new(
+                "name",
+                "Group chat with more than 8 members must have a name",
             ));
         }
-        let users = self.fetch_chat_user_by_ids(&input.members).await?;
+        let users: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT id, ws_id FROM users WHERE id = ANY($1)")
+                .bind(&input.members)
+                .fetch_all(&self.pool)
+                .await?;
         if users.len() != len {
-            return Err(AppError::ChatDTOError(
-                "Some members do not exist".to_string(),
+            errors.push(FieldError::new("members", "Some members do not exist"));
+        }
+        if users
+            .iter()
+            .any(|(_, user_ws_id)| *user_ws_id != ws_id as i64)
+        {
+            errors.push(FieldError::new(
+                "members",
+                "Some members are not in the workspace",
             ));
         }
+        if !errors.is_empty() {
+            return Err(AppError::ChatFieldsError(errors));
+        }
+        self.verify_member_limit(&get_chat_type(input), len)?;
         Ok(())
     }
 
-    pub async fn update_chat(&self, id: u64, input: ChatDTO) -> Result<Option<Chat>, AppError> {
-        self.valid_chat_dto(&input).await?;
-        let chat_type = get_chat_type(&input);
-        let chat = sqlx::query_as(
-            r#"
-            UPDATE chats SET name = $1, type = $2, members = $3
-            WHERE id = $4
-            RETURNING id, ws_id, name, type, members, created_at
-            "#,
+    /// Update the fields given in `input` and only those. If members are given, the ones
+    /// not among them are removed and new ones are added as regular members invited by
+    /// `user_id`. Fails with a conflict if `input.version` is given and the chat was
+    /// updated since. Returns None if workspace `ws_id` has no such chat.
+    pub async fn update_chat(
+        &self,
+        id: u64,
+        ws_id: u64,
+        mut input: ChatPatchDTO,
+        user_id: u64,
+    ) -> Result<Option<Chat>, AppError> {
+        input.members = input.members.map(|members| dedup_members(members, None));
+        // concurrent updates wait for each other, each one sees the chat the last one left
+        let mut tx = self.pool.begin().await?;
+        let locked: Option<i64> = sqlx::query_scalar(
+            "SELECT id FROM chats WHERE id = $1 AND ws_id = $2 AND deleted_at IS NULL FOR UPDATE",
         )
-        .bind(input.name)
-        .bind(chat_type)
-        .bind(input.members)
         .bind(id as i64)
-        .fetch_optional(&self.pool)
+        .bind(ws_id as i64)
+        .fetch_optional(&mut *tx)
         .await?;
+        if locked.is_none() {
+            return Ok(None);
+        }
+        let chat = match fetch_chat(&mut *tx, id as _).await? {
+            Some(chat) => chat,
+            None => return Ok(None),
+        };
+        if input.version.is_some_and(|version| version != chat.version) {
+            return Err(AppError::VersionConflict(format!(
+                "chat {id} was updated to version {}, reload it",
+                chat.version
+            )));
+        }
+        // the chat as it will be, to validate it and derive its type
+        let merged = ChatDTO {
+            name: input.name.clone().or(chat.name),
+            topic: input.topic.clone().or(chat.topic),
+            members: input.members.clone().unwrap_or(chat.members),
+            public: input
+                .public
+                .unwrap_or(chat.r#type == ChatType::PublicChannel),
+        };
+        self.valid_chat_dto(&merged, chat.ws_id as _).await?;
+        if chat
+            .owner_id
+            .is_some_and(|owner_id| !merged.members.contains(&owner_id))
+        {
+            return Err(AppError::ChatDTOError(
+                "The chat owner can't be removed, transfer the ownership first".to_string(),
+            ));
+        }
+        let chat_type = get_chat_type(&merged);
+        let dm_pair = get_dm_pair(&chat_type, &merged.members);
+        let dm_pair_changed = chat_type != chat.r#type || input.members.is_some();
+
+        let mut query = QueryBuilder::<Postgres>::new("UPDATE chats SET ");
+        let mut columns = query.separated(", ");
+        columns.push("version = version + 1");
+        let mut changed = false;
+        if let Some(name) = input.name {
+            columns.push("name = ").push_bind_unseparated(name);
+            changed = true;
+        }
+        if let Some(topic) = input.topic {
+            columns
+                .push("topic = NULLIF(")
+                .push_bind_unseparated(topic)
+                .push_unseparated(", '')");
+            changed = true;
+        }
+        if chat_type != chat.r#type {
+            columns.push("type = ").push_bind_unseparated(chat_type);
+            changed = true;
+        }
+        if dm_pair_changed {
+            columns.push("dm_pair = ").push_bind_unseparated(dm_pair);
+            changed = true;
+        }
+        if changed {
+            query
+                .push(" WHERE id = ")
+                .push_bind(id as i64)
+                .push(" AND ws_id = ")
+                .push_bind(ws_id as i64);
+            query
+                .build()
+                .execute(&mut *tx)
+                .await
+                .map_err(single_chat_exists)?;
+            sync_chat_handle(&mut tx, id as _).await?;
+        }
+
+        if let Some(members) = input.members {
+            let removed: Vec<i64> = sqlx::query_scalar(
+                "DELETE FROM chat_members WHERE chat_id = $1 AND NOT (user_id = ANY($2)) RETURNING user_id",
+            )
+            .bind(id as i64)
+            .bind(&members)
+            .fetch_all(&mut *tx)
+            .await?;
+            let added = add_members(&mut tx, id, &members, user_id).await?;
+            notify_members_changed(&mut *tx, id, &added, &removed, user_id).await?;
+        }
+        let chat = fetch_chat(&mut *tx, id as _).await?;
+        tx.commit().await?;
 
         Ok(chat)
     }
 
-    pub async fn delete_chat(&self, id: u64) -> Result<Option<u64>, AppError> {
+    /// Hide the chat of workspace `ws_id` until it is restored, or purged with its messages
+    /// once the retention period has passed. Chats on legal hold can't be deleted.
+    pub async fn delete_chat(&self, id: u64, ws_id: u64) -> Result<Option<u64>, AppError> {
+        if self.is_chat_on_hold(id).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "Chat {id} is on legal hold"
+            )));
+        }
         let chat_id: Option<(i64,)> = sqlx::query_as(
             r#"
-            DELETE FROM chats
-            WHERE id = $1
+            UPDATE chats
+            SET deleted_at = NOW()
+            WHERE id = $1 AND ws_id = $2 AND deleted_at IS NULL
             RETURNING id
             "#,
         )
         .bind(id as i64)
+        .bind(ws_id as i64)
         .fetch_optional(&self.pool)
         .await?;
 
+        if chat_id.is_some() {
+            let retention = self.config.chat.deleted_retention_days;
+            let run_at = Utc::now() + Duration::days(retention as i64);
+            let job = PurgeChat { chat_id: id as i64 };
+            self.enqueue_job(PURGE_CHAT_JOB, job, Some(run_at)).await?;
+        }
         Ok(chat_id.map(|r| r.0 as u64))
     }
 
-    pub async fn fetch_chats(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
-        let chats = sqlx::query_as(
+    /// First phase of deleting a chat: the chat stays usable for `chat.deletion_grace_hours`
+    /// and its members are told, then it is purged. Scheduling a chat again keeps the
+    /// first schedule. Returns None if workspace `ws_id` has no such chat.
+    pub async fn schedule_chat_deletion(
+        &self,
+        id: u64,
+        ws_id: u64,
+        actor_id: u64,
+    ) -> Result<Option<ChatDeletion>, AppError> {
+        if self.is_chat_on_hold(id).await? {
+            return Err(AppError::PermissionDenied(format!(
+                "Chat {id} is on legal hold"
+            )));
+        }
+        let mut tx = self.pool.begin().await?;
+        let scheduled: Option<ChatDeletion> = sqlx::query_as(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
-            FROM chats
-            WHERE ws_id = $1
+            UPDATE chats
+            SET delete_at = NOW() + make_interval(hours => $2)
+            WHERE id = $1 AND ws_id = $3 AND deleted_at IS NULL AND delete_at IS NULL
+            RETURNING id AS chat_id, delete_at
             "#,
         )
+        .bind(id as i64)
+        .bind(self.config.chat.deletion_grace_hours as i32)
         .bind(ws_id as i64)
-        .fetch_all(&self.pool)
+        .fetch_optional(&mut *tx)
         .await?;
+        let Some(deletion) = scheduled else {
+            return Ok(sqlx::query_as(
+                r#"
+                SELECT id AS chat_id, delete_at FROM chats
+                WHERE id = $1 AND ws_id = $2 AND deleted_at IS NULL AND delete_at IS NOT NULL
+                "#,
+            )
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?);
+        };
+        notify_chat_deletion(&mut *tx, id, actor_id).await?;
+        tx.commit().await?;
+        let job = PurgeChat { chat_id: id as i64 };
+        self.enqueue_job(DELETE_SCHEDULED_CHAT_JOB, job, Some(deletion.delete_at))
+            .await?;
 
-        Ok(chats)
+        Ok(Some(deletion))
     }
 
-    pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
-        let chat = sqlx::query_as(
+    /// Keep a chat scheduled for deletion, returns None if workspace `ws_id` has no such
+    /// chat scheduled.
+    pub async fn cancel_chat_deletion(
+        &self,
+        id: u64,
+        ws_id: u64,
+        actor_id: u64,
+    ) -> Result<Option<Chat>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ret = sqlx::query(
             r#"
-            SELECT id, ws_id, name, type, members, created_at
-            FROM chats
-            WHERE id = $1
+            UPDATE chats SET delete_at = NULL
+            WHERE id = $1 AND ws_id = $2 AND deleted_at IS NULL AND delete_at IS NOT NULL
             "#,
         )
         .bind(id as i64)
-        .fetch_optional(&self.pool)
+        .bind(ws_id as i64)
+        .execute(&mut *tx)
         .await?;
+        if ret.rows_affected() == 0 {
+            return Ok(None);
+        }
+        notify_chat_deletion(&mut *tx, id, actor_id).await?;
+        tx.commit().await?;
+        self.get_chat_by_id(id).await
+    }
 
-        Ok(chat)
+    /// Second phase of deleting a chat: remove a chat whose grace period has passed with
+    /// its messages, reads and memberships. Members get the chat removed like for any
+    /// deleted chat, files no other message uses are left to the orphan collection.
+    /// Returns false if the chat is not due.
+    pub async fn purge_scheduled_chat(&self, id: u64) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let due: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM chats WHERE id = $1 AND delete_at <= NOW() FOR UPDATE")
+                .bind(id as i64)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if due.is_none() {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM messages WHERE chat_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
     }
 
-    pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
-        let is_member = sqlx::query(
+    /// Bring back a deleted chat within the retention period, returns None if workspace
+    /// `ws_id` has no such chat.
+    pub async fn restore_chat(&self, id: u64, ws_id: u64) -> Result<Option<Chat>, AppError> {
+        let ret = sqlx::query(
             r#"
-            SELECT 1
-            FROM chats
-            WHERE id = $1 AND $2 = ANY(members)
+            UPDATE chats
+            SET deleted_at = NULL
+            WHERE id = $1 AND ws_id = $3 AND deleted_at > NOW() - make_interval(days => $2)
             "#,
         )
-        .bind(chat_id as i64)
-        .bind(user_id as i64)
-        .fetch_optional(&self.pool)
+        .bind(id as i64)
+        .bind(self.config.chat.deleted_retention_days as i32)
+        .bind(ws_id as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(single_chat_exists)?;
+        if ret.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.get_chat_by_id(id).await
+    }
+
+    /// Permanently remove a chat deleted longer than the retention period ago, together
+    /// with its messages. The append only message history is kept. Returns false if the
+    /// chat is not due.
+    pub async fn purge_deleted_chat(&self, id: u64) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let due: Option<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM chats
+            WHERE id = $1 AND deleted_at <= NOW() - make_interval(days => $2)
+            FOR UPDATE
+            "#,
+        )
+        .bind(id as i64)
+        .bind(self.config.chat.deleted_retention_days as i32)
+        .fetch_optional(&mut *tx)
         .await?;
+        if due.is_none() {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM messages WHERE chat_id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chats WHERE id = $1")
+            .bind(id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
 
-        Ok(is_member.is_some())
+        Ok(true)
     }
-}
 
-fn get_chat_type(input: &ChatDTO) -> ChatType {
-    match (&input.name, &input.members.len()) {
-        (None, 2) => ChatType::Single,
-        (None, _) => ChatType::Group,
-        (Some(_), _) => {
-            if input.public {
-                ChatType::PublicChannel
-            } else {
-                ChatType::PrivateChannel
-            }
+    /// Recount the messages of the chats after `after_id` and of their members, a batch
+    /// at a time. Returns the id of the last recounted chat, None once there are no more.
+    /// The chats are locked while counting so messages sent meanwhile are not missed.
+    pub async fn backfill_message_counts(&self, after_id: i64) -> Result<Option<i64>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ids: Vec<i64> = sqlx::query_scalar(
+            "SELECT id FROM chats WHERE id > $1 ORDER BY id LIMIT $2 FOR UPDATE",
+        )
+        .bind(after_id)
+        .bind(CHATS_PER_BATCH)
+        .fetch_all(&mut *tx)
+        .await?;
+        if ids.is_empty() {
+            return Ok(None);
         }
+
+        sqlx::query(
+            r#"
+            UPDATE chats c
+            SET message_count = (SELECT COUNT(*) FROM messages m WHERE m.chat_id = c.id)
+            WHERE c.id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"
+            UPDATE chat_members cm
+            SET sent_count = (
+                SELECT COUNT(*) FROM messages m
+                WHERE m.chat_id = cm.chat_id AND m.sender_id = cm.user_id
+            )
+            WHERE cm.chat_id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(ids.last().copied())
     }
-}
 
-#[cfg(test)]
-impl ChatDTO {
-    pub fn new(name: &str, members: &[i64], public: bool) -> Self {
-        let name = if name.is_empty() {
-            None
-        } else {
-            Some(name.to_string())
-        };
-        Self {
-            name,
-            members: members.to_vec(),
-            public,
-        }
+    /// Chats of the workspace, archived ones only if `include_archived` is set. The chats
+    /// pinned by `user_id` come first, only the chats they are in have a `last_message`.
+    pub async fn fetch_chats(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        include_archived: bool,
+    ) -> Result<Vec<Chat>, AppError> {
+        let chats = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.owner_id, c.name, c.topic, c.avatar_url, c.type, chat_member_ids(c.id) AS members,
+                c.created_at, c.archived_at, c.delete_at, c.handle, c.message_count, c.version, p.user_id IS NOT NULL AS pinned,
+                chat_unread_count(c.id, $2) AS unread_count,
+                CASE WHEN $2 = ANY(chat_member_ids(c.id)) THEN chat_last_message(c.id, $4) END
+                    AS last_message
+            FROM chats c
+            LEFT JOIN chat_pins p ON p.chat_id = c.id AND p.user_id = $2
+            WHERE c.ws_id = $1 AND c.deleted_at IS NULL AND ($3 OR c.archived_at IS NULL)
+            ORDER BY pinned DESC, c.id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(include_archived)
+        .bind(LAST_MESSAGE_PREVIEW_LEN)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(chats)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
+    /// Public channels of the workspace `user_id` is not in, the most populated first.
+    /// Archived channels are left out.
+    pub async fn list_channel_directory(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        input: &ListChannels,
+    ) -> Result<Vec<DirectoryChannel>, AppError> {
+        let limit = input.limit.unwrap_or(50).clamp(1, 100);
+        let query = input
+            .query
+            .as_deref()
+            .map(str::trim)
+            .filter(|q| !q.is_empty())
+            .map(str::to_lowercase);
+        let channels = sqlx::query_as(
+            r#"
+            SELECT c.id, c.name, c.topic, c.avatar_url,
+                (SELECT COUNT(*) FROM chat_members m WHERE m.chat_id = c.id) AS member_count,
+                (SELECT MAX(created_at) FROM messages WHERE chat_id = c.id) AS last_message_at,
+                c.created_at
+            FROM chats c
+            WHERE c.ws_id = $1 AND c.type = 'public_channel' AND c.deleted_at IS NULL
+                AND c.archived_at IS NULL
+                AND NOT EXISTS (SELECT 1 FROM chat_members m WHERE m.chat_id = c.id AND m.user_id = $2)
+                AND ($3::text IS NULL OR strpos(lower(c.name), $3) > 0
+                    OR strpos(lower(COALESCE(c.topic, '')), $3) > 0)
+            ORDER BY member_count DESC, c.id
+            LIMIT $4
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
 
-    #[tokio::test]
-    async fn create_single_chat_should_work() -> Result<()> {
-        let (_tdb, state) = AppState::new_for_test().await?;
-        let input = ChatDTO::new("", &[1, 2], false);
-        let chat = state
-            .create_chat(input, 1)
-            .await
-            .expect("create chat failed");
-        assert_eq!(chat.ws_id, 1);
-        assert_eq!(chat.members.len(), 2);
-        assert_eq!(chat.r#type, ChatType::Single);
-        Ok(())
+        Ok(channels)
     }
 
-    #[tokio::test]
-    async fn create_public_named_chat_should_work() -> Result<()> {
-        let (_tdb, state) = AppState::new_for_test().await?;
-        let input = ChatDTO::new("general", &[1, 2, 3], true);
-        let chat = state
-            .create_chat(input, 1)
-            .await
-            .expect("create chat failed");
-        assert_eq!(chat.ws_id, 1);
-        assert_eq!(chat.members.len(), 3);
-        assert_eq!(chat.r#type, ChatType::PublicChannel);
-        Ok(())
+    /// One page of the chats of the workspace, in the order of `fetch_chats` but most
+    /// recently updated first.
+    pub async fn fetch_chat_page(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        input: &ListChats,
+    ) -> Result<ChatPage, AppError> {
+        let limit = input.limit.unwrap_or(50).clamp(1, 100) as usize;
+        let cursor = input
+            .cursor
+            .as_deref()
+            .map(ChatCursor::decode)
+            .transpose()?;
+        let mut rows: Vec<ChatRow> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.owner_id, c.name, c.topic, c.avatar_url, c.type, chat_member_ids(c.id) AS members,
+                c.created_at, c.archived_at, c.delete_at, c.handle, c.message_count, c.version, p.user_id IS NOT NULL AS pinned,
+                chat_unread_count(c.id, $2) AS unread_count,
+                CASE WHEN $2 = ANY(chat_member_ids(c.id)) THEN chat_last_message(c.id, $9) END
+                    AS last_message,
+                s.sort_at
+            FROM chats c
+            LEFT JOIN chat_pins p ON p.chat_id = c.id AND p.user_id = $2
+            CROSS JOIN LATERAL (
+                SELECT CASE WHEN $8 THEN COALESCE(c.last_message_at, c.created_at)
+                    ELSE c.updated_at END AS sort_at
+            ) s
+            WHERE c.ws_id = $1 AND c.deleted_at IS NULL AND ($3 OR c.archived_at IS NULL)
+                AND ($4::bool IS NULL OR (p.user_id IS NOT NULL, s.sort_at, c.id) < ($4, $5, $6))
+            ORDER BY pinned DESC, s.sort_at DESC, c.id DESC
+            LIMIT $7
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(input.include_archived)
+        .bind(cursor.map(|c| c.pinned))
+        .bind(cursor.map(|c| c.sort_at))
+        .bind(cursor.map(|c| c.id))
+        .bind(limit as i64 + 1)
+        .bind(input.sort == ChatSort::Activity)
+        .bind(LAST_MESSAGE_PREVIEW_LEN)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // the extra row only tells if there is a next page
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|row| {
+                ChatCursor {
+                    pinned: row.chat.pinned,
+                    sort_at: row.sort_at,
+                    id: row.chat.id,
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+        Ok(ChatPage {
+            chats: rows.into_iter().map(|row| row.chat).collect(),
+            next_cursor,
+        })
     }
 
-    #[tokio::test]
-    async fn chat_get_by_id_should_work() -> Result<()> {
-        let (_tdb, state) = AppState::new_for_test().await?;
-        let chat = state
-            .get_chat_by_id(1)
-            .await
-            .expect("get chat by id failed")
-            .unwrap();
+    /// Named chats of the workspace `user_id` can see, i.e. public channels and the chats
+    /// they are in, whose name starts with `q` or contains words like it. Chats whose name
+    /// starts with it come first, then the closest matches.
+    pub async fn search_chats(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        input: &SearchChats,
+    ) -> Result<ChatPage, AppError> {
+        let limit = input.limit.unwrap_or(20).clamp(1, 100) as usize;
+        let cursor = input
+            .cursor
+            .as_deref()
+            .map(SearchCursor::decode)
+            .transpose()?;
+        let q = input.q.trim().to_lowercase();
+        if q.is_empty() {
+            return Ok(ChatPage {
+                chats: vec![],
+                next_cursor: None,
+            });
+        }
+        let prefix = format!("{}%", escape_like(&q));
+        let mut rows: Vec<SearchRow> = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.owner_id, c.name, c.topic, c.avatar_url, c.type, chat_member_ids(c.id) AS members,
+                c.created_at, c.archived_at, c.delete_at, c.handle, c.message_count, c.version, s.prefix_miss,
+                s.distance
+            FROM chats c
+            CROSS JOIN LATERAL (
+                SELECT lower(c.name) NOT LIKE $4 AS prefix_miss,
+                    (1 - word_similarity($3, lower(c.name)))::real AS distance
+            ) s
+            WHERE c.ws_id = $1 AND c.deleted_at IS NULL AND c.name IS NOT NULL
+                AND (c.type = 'public_channel' OR EXISTS (
+                    SELECT 1 FROM chat_members m WHERE m.chat_id = c.id AND m.user_id = $2
+                ))
+                AND (lower(c.name) LIKE $4 OR $3 <% lower(c.name))
+                AND ($5::bool IS NULL OR (s.prefix_miss, s.distance, c.id) > ($5, $6, $7))
+            ORDER BY s.prefix_miss, s.distance, c.id
+            LIMIT $8
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(&q)
+        .bind(prefix)
+        .bind(cursor.map(|c| c.prefix_miss))
+        .bind(cursor.map(|c| c.distance))
+        .bind(cursor.map(|c| c.id))
+        .bind(limit as i64 + 1)
+        .fetch_all(&self.pool)
+        .await?;
 
-        assert_eq!(chat.id, 1);
-        assert_eq!(chat.name.unwrap(), "general");
-        assert_eq!(chat.ws_id, 1);
-        assert_eq!(chat.members.len(), 5);
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|r| r.cursor.encode())
+        } else {
+            None
+        };
+        Ok(ChatPage {
+            chats: rows.into_iter().map(|r| r.chat).collect(),
+            next_cursor,
+        })
+    }
 
+    /// Pin the chat to the top of the user's chat list, pinning it again does nothing.
+    pub async fn pin_chat(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+        sqlx::query(
+            "INSERT INTO chat_pins (user_id, chat_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_id as i64)
+        .bind(chat_id as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns false if the chat is not pinned by the user.
+    pub async fn unpin_chat(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM chat_pins WHERE user_id = $1 AND chat_id = $2")
+            .bind(user_id as i64)
+            .bind(chat_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Store an uploaded image as the avatar of a chat, returns None if workspace `ws_id`
+    /// has no such chat.
+    pub async fn set_chat_avatar(
+        &self,
+        id: u64,
+        ws_id: u64,
+        data: &[u8],
+    ) -> Result<Option<Chat>, AppError> {
+        if data.len() > MAX_AVATAR_SIZE {
+            return Err(AppError::ChatFileError(format!(
+                "Avatar must be at most {} KiB",
+                MAX_AVATAR_SIZE / 1024
+            )));
+        }
+        let Some(ext) = image_ext(data) else {
+            return Err(AppError::ChatFileError(
+                "Avatar must be a png, jpeg, gif or webp image".to_string(),
+            ));
+        };
+        let file = self
+            .save_chat_file(ws_id, &format!("avatar.{ext}"), data)
+            .await?;
+        let ret = sqlx::query(
+            "UPDATE chats SET avatar_url = $1 WHERE id = $2 AND ws_id = $3 AND deleted_at IS NULL",
+        )
+        .bind(file.url())
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.get_chat_by_id(id).await
+    }
+
+    /// Archive or unarchive a chat, returns None if workspace `ws_id` has no such chat.
+    pub async fn set_chat_archived(
+        &self,
+        id: u64,
+        ws_id: u64,
+        archived: bool,
+    ) -> Result<Option<Chat>, AppError> {
+        let ret = sqlx::query(
+            r#"
+            UPDATE chats
+            SET archived_at = CASE WHEN $2 THEN COALESCE(archived_at, NOW()) ELSE NULL END
+            WHERE id = $1 AND ws_id = $3 AND deleted_at IS NULL
+            "#,
+        )
+        .bind(id as i64)
+        .bind(archived)
+        .bind(ws_id as i64)
+        .execute(&self.pool)
+        .await?;
+        if ret.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.get_chat_by_id(id).await
+    }
+
+    /// The chat of the workspace with `handle`, or which had it before it was renamed.
+    /// A leading `#` is ignored, private channels are only found by their members.
+    pub async fn get_chat_by_handle(
+        &self,
+        ws_id: u64,
+        handle: &str,
+        user_id: u64,
+    ) -> Result<Option<Chat>, AppError> {
+        let handle = handle.trim_start_matches('#').to_lowercase();
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(
+                (SELECT id FROM chats WHERE ws_id = $1 AND handle = $2 AND deleted_at IS NULL),
+                (SELECT chat_id FROM chat_handle_redirects WHERE ws_id = $1 AND handle = $2)
+            )
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(&handle)
+        .fetch_one(&self.pool)
+        .await?;
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let chat = self.get_chat_by_id(id as _).await?;
+        Ok(chat.filter(|c| {
+            c.r#type == ChatType::PublicChannel || c.members.contains(&(user_id as i64))
+        }))
+    }
+
+    pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
+        fetch_chat(&self.pool, id as _).await
+    }
+
+    pub async fn is_chat_member(&self, chat_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let is_member = sqlx::query(
+            r#"
+            SELECT 1
+            FROM chat_members m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.chat_id = $1 AND m.user_id = $2 AND c.deleted_at IS NULL
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(is_member.is_some())
+    }
+
+    /// Chats `user_id` is a member of. If `viewer_id` is given, only the chats both
+    /// users are members of are returned.
+    pub async fn fetch_user_chats(
+        &self,
+        user_id: u64,
+        viewer_id: Option<u64>,
+    ) -> Result<Vec<Chat>, AppError> {
+        let chats = sqlx::query_as(
+            r#"
+            SELECT c.id, c.ws_id, c.owner_id, c.name, c.topic, c.avatar_url, c.type, chat_member_ids(c.id) AS members, c.created_at,
+                c.archived_at, c.delete_at, c.handle, c.message_count, c.version
+            FROM chat_members m
+            JOIN chats c ON c.id = m.chat_id
+            WHERE m.user_id = $1 AND c.deleted_at IS NULL
+            AND ($2::bigint IS NULL OR EXISTS (
+                SELECT 1 FROM chat_members v WHERE v.chat_id = m.chat_id AND v.user_id = $2
+            ))
+            ORDER BY c.id
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(viewer_id.map(|id| id as i64))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(chats)
+    }
+
+    pub async fn list_chat_members(&self, chat_id: u64) -> Result<Vec<ChatMember>, AppError> {
+        let members = sqlx::query_as(
+            r#"
+            SELECT user_id, role, joined_at, invited_by, sent_count
+            FROM chat_members
+            WHERE chat_id = $1
+            ORDER BY user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(members)
+    }
+
+    /// Add a regular member to a chat, does nothing if the user is already a member.
+    pub async fn add_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        invited_by: Option<u64>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let added: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, invited_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            RETURNING user_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(invited_by.map(|id| id as i64))
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(added) = added {
+            let actor_id = invited_by.unwrap_or(user_id);
+            notify_members_changed(&mut *tx, chat_id, &[added], &[], actor_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Add `user` to a public channel of their workspace, joining a channel they are already
+    /// in does nothing.
+    pub async fn join_chat(&self, chat_id: u64, user: &User) -> Result<Chat, AppError> {
+        let chat = match self.get_chat_by_id(chat_id).await? {
+            Some(chat) if chat.ws_id == user.ws_id => chat,
+            _ => return Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        };
+        if chat.r#type != ChatType::PublicChannel {
+            return Err(AppError::PermissionDenied(
+                "only public channels can be joined".to_string(),
+            ));
+        }
+        if chat.members.contains(&user.id) {
+            return Ok(chat);
+        }
+        self.verify_not_banned(chat_id, user.id as _).await?;
+        self.verify_member_limit(&chat.r#type, chat.members.len() + 1)?;
+        self.add_chat_member(chat_id, user.id as _, None).await?;
+        match self.get_chat_by_id(chat_id).await? {
+            Some(chat) => Ok(chat),
+            None => Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        }
+    }
+
+    /// Check that `user_id` can be added to, or removed from, an existing chat and return
+    /// the chat. Single chats keep their two members and every chat keeps at least two.
+    pub async fn verify_chat_member_change(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        remove: bool,
+    ) -> Result<Chat, AppError> {
+        let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+            return Err(AppError::NotFound(format!("chat id {chat_id}")));
+        };
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::ChatDTOError(
+                "Members of a single chat can't be changed, convert it to a group first"
+                    .to_string(),
+            ));
+        }
+        let is_member = chat.members.contains(&(user_id as i64));
+        if remove {
+            if !is_member {
+                return Err(AppError::NotFound(format!("chat member {user_id}")));
+            }
+            if chat.owner_id == Some(user_id as i64) {
+                return Err(AppError::ChatDTOError(
+                    "The chat owner can't be removed, transfer the ownership first".to_string(),
+                ));
+            }
+            if chat.members.len() <= 2 {
+                return Err(AppError::ChatDTOError(
+                    "Chat must have at least 2 members".to_string(),
+                ));
+            }
+        } else {
+            match self.find_user_by_id(user_id as _).await? {
+                Some(user) if user.ws_id == chat.ws_id => {}
+                _ => return Err(AppError::NotFound(format!("user id {user_id}"))),
+            }
+            if !is_member {
+                self.verify_not_banned(chat_id, user_id).await?;
+                self.verify_member_limit(&chat.r#type, chat.members.len() + 1)?;
+            }
+        }
+        Ok(chat)
+    }
+
+    /// Fail if a chat of `chat_type` can't have `members` members, see `chat.max_members`.
+    pub(crate) fn verify_member_limit(
+        &self,
+        chat_type: &ChatType,
+        members: usize,
+    ) -> Result<(), AppError> {
+        let limits = &self.config.chat.max_members;
+        let (kind, limit) = match chat_type {
+            ChatType::Single => ("single chats", 2),
+            ChatType::Group => ("group chats", limits.group),
+            ChatType::PrivateChannel => ("private channels", limits.private_channel),
+            ChatType::PublicChannel => ("public channels", limits.public_channel),
+        };
+        if members > limit as usize {
+            return Err(AppError::MemberLimitReached(format!(
+                "{kind} can have at most {limit} members"
+            )));
+        }
+        Ok(())
+    }
+
+    pub async fn get_chat_role(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatRole>, AppError> {
+        let role: Option<(ChatRole,)> =
+            sqlx::query_as("SELECT role FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+                .bind(chat_id as i64)
+                .bind(user_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(role.map(|r| r.0))
+    }
+
+    /// Change the role of a member, making someone the owner demotes the current owner to
+    /// an admin.
+    pub async fn update_chat_member_role(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        role: ChatRole,
+    ) -> Result<ChatMember, AppError> {
+        match self.get_chat_role(chat_id, user_id).await? {
+            None => return Err(AppError::NotFound(format!("chat member {user_id}"))),
+            Some(ChatRole::Owner) if role != ChatRole::Owner => {
+                return Err(AppError::ChatDTOError(
+                    "The chat owner's role can only change by transferring the ownership"
+                        .to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if role == ChatRole::Owner {
+            sqlx::query(
+                "UPDATE chat_members SET role = 'admin' WHERE chat_id = $1 AND role = 'owner' AND user_id <> $2",
+            )
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        let member = sqlx::query_as(
+            r#"
+            UPDATE chat_members SET role = $3
+            WHERE chat_id = $1 AND user_id = $2
+            RETURNING user_id, role, joined_at, invited_by, sent_count
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(role)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(member)
+    }
+
+    /// Turn a single chat into a group by adding members, the conversation is kept and
+    /// the members are notified like for any member change.
+    pub async fn convert_chat(
+        &self,
+        id: u64,
+        input: &ConvertChat,
+        user_id: u64,
+    ) -> Result<Chat, AppError> {
+        let Some(chat) = self.get_chat_by_id(id).await? else {
+            return Err(AppError::NotFound(format!("chat id {id}")));
+        };
+        if chat.r#type != ChatType::Single {
+            return Err(AppError::ChatDTOError(
+                "Only single chats can be converted to a group".to_string(),
+            ));
+        }
+        let mut members = chat.members.clone();
+        for &member in &input.members {
+            if members.contains(&member) {
+                continue;
+            }
+            match self.find_user_by_id(member).await? {
+                Some(user) if user.ws_id == chat.ws_id => members.push(member),
+                _ => return Err(AppError::NotFound(format!("user id {member}"))),
+            }
+        }
+        if members.len() == chat.members.len() {
+            return Err(AppError::ChatDTOError(
+                "A group needs at least one member besides the two of the single chat".to_string(),
+            ));
+        }
+
+        let input = ChatPatchDTO {
+            members: Some(members),
+            ..Default::default()
+        };
+        self.update_chat(id, chat.ws_id as _, input, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {id}")))
+    }
+
+    /// Hand the ownership of a chat to another member and record it in the audit log, the
+    /// old owner becomes an admin.
+    pub async fn transfer_chat_ownership(
+        &self,
+        chat_id: u64,
+        input: &TransferChat,
+        actor_id: u64,
+    ) -> Result<Chat, AppError> {
+        let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+            return Err(AppError::NotFound(format!("chat id {chat_id}")));
+        };
+        if chat.owner_id == Some(input.user_id) {
+            return Err(AppError::ChatDTOError(
+                "The member already owns the chat".to_string(),
+            ));
+        }
+        if !chat.members.contains(&input.user_id) {
+            return Err(AppError::ChatDTOError(
+                "The ownership can only be transferred to a member of the chat".to_string(),
+            ));
+        }
+
+        self.update_chat_member_role(chat_id, input.user_id as _, ChatRole::Owner)
+            .await?;
+        let details = json!({ "from": chat.owner_id, "to": input.user_id });
+        self.record_audit(
+            chat.ws_id as _,
+            actor_id,
+            "chat.transfer",
+            Some(chat_id),
+            details,
+        )
+        .await?;
+
+        self.get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))
+    }
+
+    /// Returns false if the user is not a member of the chat.
+    pub async fn remove_chat_member(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        removed_by: u64,
+    ) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let ret = sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        let removed = ret.rows_affected() > 0;
+        if removed {
+            notify_members_changed(&mut *tx, chat_id, &[], &[user_id as _], removed_by).await?;
+        }
+        tx.commit().await?;
+
+        Ok(removed)
+    }
+
+    /// Take `user_id` out of the chat. A leaving owner hands the chat to the oldest admin,
+    /// or else the oldest member. The chat is deleted when its last member leaves, then
+    /// None is returned.
+    pub async fn leave_chat(&self, chat_id: u64, user_id: u64) -> Result<Option<Chat>, AppError> {
+        let chat = match self.get_chat_by_id(chat_id).await? {
+            Some(chat) if chat.members.contains(&(user_id as i64)) => chat,
+            _ => return Err(AppError::NotFound(format!("chat member {user_id}"))),
+        };
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::ChatDTOError(
+                "Members of a single chat can't be changed".to_string(),
+            ));
+        }
+        if chat.members.len() == 1 {
+            self.delete_chat(chat_id, chat.ws_id as _).await?;
+            return Ok(None);
+        }
+
+        let mut tx = self.pool.begin().await?;
+        if chat.owner_id == Some(user_id as i64) {
+            sqlx::query(
+                r#"
+                UPDATE chat_members SET role = 'owner'
+                WHERE chat_id = $1 AND user_id = (
+                    SELECT user_id FROM chat_members
+                    WHERE chat_id = $1 AND user_id <> $2
+                    ORDER BY role = 'admin' DESC, joined_at, user_id
+                    LIMIT 1
+                )
+                "#,
+            )
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id as i64)
+            .bind(user_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        notify_members_changed(&mut *tx, chat_id, &[], &[user_id as _], user_id).await?;
+        let chat = fetch_chat(&mut *tx, chat_id as _).await?;
+        tx.commit().await?;
+
+        Ok(chat)
+    }
+}
+
+impl JobHandler for PurgeChatJob {
+    fn kind(&self) -> &'static str {
+        PURGE_CHAT_JOB
+    }
+
+    // a chat restored and deleted again is purged by the job of the later delete, a chat
+    // put on hold after its deletion is checked again a retention period later
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: PurgeChat =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            if state.is_chat_on_hold(job.chat_id as _).await? {
+                let retention = state.config.chat.deleted_retention_days;
+                let run_at = Utc::now() + Duration::days(retention as i64);
+                state.enqueue_job(PURGE_CHAT_JOB, job, Some(run_at)).await?;
+                return Ok(());
+            }
+            state.purge_deleted_chat(job.chat_id as _).await?;
+            Ok(())
+        })
+    }
+}
+
+impl SearchCursor {
+    fn encode(&self) -> String {
+        let cursor = format!("{}:{}:{}", self.prefix_miss as u8, self.distance, self.id);
+        hex::encode(cursor)
+    }
+
+    fn decode(cursor: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::InvalidCursor(cursor.to_string());
+        let cursor = hex::decode(cursor).map_err(|_| invalid())?;
+        let cursor = String::from_utf8(cursor).map_err(|_| invalid())?;
+        let mut parts = cursor.split(':');
+        let (Some(prefix_miss), Some(distance), Some(id), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            prefix_miss: prefix_miss == "1",
+            distance: distance.parse().map_err(|_| invalid())?,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+// make `s` match itself literally in a LIKE pattern
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl JobHandler for DeleteScheduledChatJob {
+    fn kind(&self) -> &'static str {
+        DELETE_SCHEDULED_CHAT_JOB
+    }
+
+    // a chat kept and scheduled again is purged by the job of the later schedule, a chat
+    // put on hold meanwhile is checked again a grace period later
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: PurgeChat =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            if state.is_chat_on_hold(job.chat_id as _).await? {
+                let grace = state.config.chat.deletion_grace_hours;
+                let run_at = Utc::now() + Duration::hours(grace as i64);
+                state
+                    .enqueue_job(DELETE_SCHEDULED_CHAT_JOB, job, Some(run_at))
+                    .await?;
+                return Ok(());
+            }
+            state.purge_scheduled_chat(job.chat_id as _).await?;
+            Ok(())
+        })
+    }
+}
+
+impl ChatCursor {
+    fn encode(&self) -> String {
+        let cursor = format!(
+            "{}:{}:{}",
+            self.pinned as u8,
+            self.sort_at.timestamp_micros(),
+            self.id
+        );
+        hex::encode(cursor)
+    }
+
+    fn decode(cursor: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::InvalidCursor(cursor.to_string());
+        let cursor = hex::decode(cursor).map_err(|_| invalid())?;
+        let cursor = String::from_utf8(cursor).map_err(|_| invalid())?;
+        let mut parts = cursor.split(':');
+        let (Some(pinned), Some(sort_at), Some(id), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let sort_at = sort_at
+            .parse()
+            .ok()
+            .and_then(DateTime::from_timestamp_micros)
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            pinned: pinned == "1",
+            sort_at,
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl ChatRole {
+    pub(crate) fn rank(&self) -> u8 {
+        match self {
+            Self::Owner => 2,
+            Self::Admin => 1,
+            Self::Member => 0,
+        }
+    }
+}
+
+async fn fetch_chat<'e>(executor: impl PgExecutor<'e>, id: i64) -> Result<Option<Chat>, AppError> {
+    let chat = sqlx::query_as(
+        r#"
+        SELECT id, ws_id, owner_id, name, topic, avatar_url, type, chat_member_ids(id) AS members,
+            created_at,
+            archived_at,
+            delete_at,
+            handle,
+            message_count,
+            version
+        FROM chats
+        WHERE id = $1 AND deleted_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(executor)
+    .await?;
+
+    Ok(chat)
+}
+
+// handle of a channel named `name`: its letters and digits in lowercase, anything else
+// between them collapsed into a dash
+fn chat_handle(name: &str) -> String {
+    let mut handle = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            handle.push(c);
+        } else if !handle.is_empty() && !handle.ends_with('-') {
+            handle.push('-');
+        }
+    }
+    let handle: String = handle
+        .trim_end_matches('-')
+        .chars()
+        .take(MAX_HANDLE_LEN)
+        .collect();
+    match handle.trim_end_matches('-') {
+        "" => "channel".to_string(),
+        handle => handle.to_string(),
+    }
+}
+
+// give a channel the handle of its name, -2, -3, ... if it's taken, and keep its previous
+// handle as a redirect. Other chats have no handle
+pub(crate) async fn sync_chat_handle(
+    tx: &mut Transaction<'_, Postgres>,
+    chat_id: i64,
+) -> Result<(), AppError> {
+    let (ws_id, name, chat_type, handle): (i64, Option<String>, ChatType, Option<String>) =
+        sqlx::query_as("SELECT ws_id, name, type, handle FROM chats WHERE id = $1")
+            .bind(chat_id)
+            .fetch_one(&mut **tx)
+            .await?;
+    let is_channel = matches!(
+        chat_type,
+        ChatType::PublicChannel | ChatType::PrivateChannel
+    );
+    let Some(name) = name.filter(|_| is_channel) else {
+        return Ok(());
+    };
+    let base = chat_handle(&name);
+    let of_base = |h: &str| {
+        h == base
+            || h.strip_prefix(base.as_str())
+                .and_then(|s| s.strip_prefix('-'))
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+    };
+    if handle.as_deref().is_some_and(of_base) {
+        return Ok(());
+    }
+
+    let taken: Vec<String> = sqlx::query_scalar(
+        "SELECT handle FROM chats WHERE ws_id = $1 AND id <> $2 AND (handle = $3 OR handle LIKE $3 || '-%')",
+    )
+    .bind(ws_id)
+    .bind(chat_id)
+    .bind(&base)
+    .fetch_all(&mut **tx)
+    .await?;
+    let new_handle = (1..)
+        .map(|n| match n {
+            1 => base.clone(),
+            n => format!("{base}-{n}"),
+        })
+        .find(|h| !taken.contains(h))
+        .expect("a free handle exists");
+
+    if let Some(old) = handle {
+        sqlx::query(
+            r#"
+            INSERT INTO chat_handle_redirects (ws_id, handle, chat_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (ws_id, handle) DO UPDATE
+            SET chat_id = EXCLUDED.chat_id, created_at = NOW()
+            "#,
+        )
+        .bind(ws_id)
+        .bind(old)
+        .bind(chat_id)
+        .execute(&mut **tx)
+        .await?;
+    }
+    sqlx::query("DELETE FROM chat_handle_redirects WHERE ws_id = $1 AND handle = $2")
+        .bind(ws_id)
+        .bind(&new_handle)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE chats SET handle = $1 WHERE id = $2")
+        .bind(&new_handle)
+        .bind(chat_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+// returns the members which weren't in the chat yet
+async fn add_members(
+    tx: &mut Transaction<'_, Postgres>,
+    chat_id: u64,
+    members: &[i64],
+    invited_by: u64,
+) -> Result<Vec<i64>, AppError> {
+    let added = sqlx::query_scalar(
+        r#"
+        INSERT INTO chat_members (chat_id, user_id, invited_by)
+        SELECT $1, m, $3
+        FROM unnest($2::bigint[]) AS m
+        ON CONFLICT DO NOTHING
+        RETURNING user_id
+        "#,
+    )
+    .bind(chat_id as i64)
+    .bind(members)
+    .bind(invited_by as i64)
+    .fetch_all(&mut **tx)
+    .await?;
+
+    Ok(added)
+}
+
+/// Tell notify_server who was added to or removed from a chat by `actor_id`, with the
+/// system messages to show for it. Within a transaction it's sent on commit.
+pub(crate) async fn notify_members_changed<'e>(
+    executor: impl PgExecutor<'e>,
+    chat_id: u64,
+    added: &[i64],
+    removed: &[i64],
+    actor_id: u64,
+) -> Result<(), AppError> {
+    if added.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+    let actor_id = actor_id as i64;
+    let system_message =
+        |key: &str, user_id: &i64| json!({ "key": key, "actor_id": actor_id, "user_id": user_id });
+    let system_messages: Vec<_> = added
+        .iter()
+        .map(|id| match *id == actor_id {
+            true => system_message("member_joined", id),
+            false => system_message("member_added", id),
+        })
+        .chain(removed.iter().map(|id| match *id == actor_id {
+            true => system_message("member_left", id),
+            false => system_message("member_removed", id),
+        }))
+        .collect();
+    let changes = json!({
+        "added": added,
+        "removed": removed,
+        "actor_id": actor_id,
+        "system_messages": system_messages,
+    });
+    sqlx::query(
+        r#"
+        SELECT pg_notify(
+            'chat_members_changed',
+            (jsonb_build_object('chat', chat_json(c, chat_member_ids(c.id))) || $2::jsonb)::text
+        )
+        FROM chats c
+        WHERE c.id = $1
+        "#,
+    )
+    .bind(chat_id as i64)
+    .bind(changes)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// tell the members the chat was scheduled for deletion, or that it was kept when
+// `delete_at` is back to null
+async fn notify_chat_deletion<'e>(
+    executor: impl PgExecutor<'e>,
+    chat_id: u64,
+    actor_id: u64,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        SELECT pg_notify(
+            'chat_deletion',
+            jsonb_build_object(
+                'chat', chat_json(c, chat_member_ids(c.id)),
+                'delete_at', c.delete_at,
+                'actor_id', $2::bigint
+            )::text
+        )
+        FROM chats c
+        WHERE c.id = $1
+        "#,
+    )
+    .bind(chat_id as i64)
+    .bind(actor_id as i64)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+// members of a single chat in ascending order, None for other chats
+pub(crate) fn get_dm_pair(chat_type: &ChatType, members: &[i64]) -> Option<Vec<i64>> {
+    if *chat_type != ChatType::Single {
+        return None;
+    }
+    let mut pair = members.to_vec();
+    pair.sort();
+    Some(pair)
+}
+
+// members in their first order without duplicates, `creator` first if missing
+fn dedup_members(members: Vec<i64>, creator: Option<i64>) -> Vec<i64> {
+    let mut ret = Vec::with_capacity(members.len() + 1);
+    if let Some(creator) = creator.filter(|id| !members.contains(id)) {
+        ret.push(creator);
+    }
+    for id in members {
+        if !ret.contains(&id) {
+            ret.push(id);
+        }
+    }
+    ret
+}
+
+// chats_dm_pair_index allows one single chat per pair of users
+fn single_chat_exists(e: sqlx::Error) -> AppError {
+    match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            AppError::ChatDTOError("A single chat with these members already exists".to_string())
+        }
+        e => e.into(),
+    }
+}
+
+fn get_chat_type(input: &ChatDTO) -> ChatType {
+    match (&input.name, &input.members.len()) {
+        (None, 2) => ChatType::Single,
+        (None, _) => ChatType::Group,
+        (Some(_), _) => {
+            if input.public {
+                ChatType::PublicChannel
+            } else {
+                ChatType::PrivateChannel
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl ChatDTO {
+    pub fn new(name: &str, members: &[i64], public: bool) -> Self {
+        let name = if name.is_empty() {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        Self {
+            name,
+            topic: None,
+            members: members.to_vec(),
+            public,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatAction, ChatFile, CreateMessage, CreateUser, Resource};
+    use anyhow::Result;
+    use sqlx::postgres::PgListener;
+
+    #[tokio::test]
+    async fn create_single_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ChatDTO::new("", &[1, 2], false);
+        let chat = state
+            .create_chat(input, 1, 1)
+            .await
+            .expect("create chat failed");
+        assert_eq!(chat.ws_id, 1);
+        assert_eq!(chat.members.len(), 2);
+        assert_eq!(chat.r#type, ChatType::Single);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn single_chat_should_be_unique_per_member_pair() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 3 is the single chat of 1 and 2
+        let chat = state
+            .create_chat(ChatDTO::new("", &[2, 1], false), 1, 2)
+            .await?;
+        assert_eq!(chat.id, 3);
+        let chat = state
+            .create_chat(ChatDTO::new("", &[1, 3], false), 1, 1)
+            .await?;
+        assert_ne!(chat.id, 3);
+        let again = state
+            .create_chat(ChatDTO::new("", &[3, 1], false), 1, 3)
+            .await?;
+        assert_eq!(again, chat);
+
+        // the group of 1, 3 and 4 can't become a second single chat of 1 and 3
+        let input = ChatPatchDTO {
+            members: Some(vec![1, 3]),
+            ..Default::default()
+        };
+        let err = state.update_chat(4, 1, input, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: A single chat with these members already exists"
+        );
+
+        // a deleted single chat makes room for a new one and can't come back then
+        state.delete_chat(3, 1).await?;
+        let chat = state
+            .create_chat(ChatDTO::new("", &[1, 2], false), 1, 1)
+            .await?;
+        assert_ne!(chat.id, 3);
+        assert!(state.restore_chat(3, 1).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_public_named_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ChatDTO::new("general", &[1, 2, 3], true);
+        let chat = state
+            .create_chat(input, 1, 1)
+            .await
+            .expect("create chat failed");
+        assert_eq!(chat.ws_id, 1);
+        assert_eq!(chat.members.len(), 3);
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_chat_once_should_replay_retries() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ChatDTO::new("launch", &[1, 2, 3], true);
+        let (chat, replayed) = state.create_chat_once(input.clone(), 1, 1, "k1").await?;
+        assert!(!replayed);
+        let (retried, replayed) = state.create_chat_once(input.clone(), 1, 1, "k1").await?;
+        assert!(replayed);
+        assert_eq!(retried.id, chat.id);
+        assert_eq!(state.fetch_chats(1, 1, false).await?.len(), 5);
+
+        // keys are per user
+        let (other, replayed) = state.create_chat_once(input, 1, 2, "k1").await?;
+        assert!(!replayed);
+        assert_ne!(other.id, chat.id);
+
+        let input = ChatDTO::new("other", &[1, 2], true);
+        let err = state.create_chat_once(input, 1, 1, "k1").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "idempotency error: key k1 was used for another chat"
+        );
+
+        // a failed creation frees the key
+        let input = ChatDTO::new("lonely", &[1], true);
+        assert!(state.create_chat_once(input, 1, 1, "k2").await.is_err());
+        let input = ChatDTO::new("lonely", &[1, 2], true);
+        let (_, replayed) = state.create_chat_once(input, 1, 1, "k2").await?;
+        assert!(!replayed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_get_by_id_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state
+            .get_chat_by_id(1)
+            .await
+            .expect("get chat by id failed")
+            .unwrap();
+
+        assert_eq!(chat.id, 1);
+        assert_eq!(chat.name.unwrap(), "general");
+        assert_eq!(chat.ws_id, 1);
+        assert_eq!(chat.members.len(), 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_fetch_all_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chats = state
+            .fetch_chats(1, 1, false)
+            .await
+            .expect("fetch all chats failed");
+
+        assert_eq!(chats.len(), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_is_member_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let is_member = state.is_chat_member(1, 1).await.expect("is member failed");
+        assert!(is_member);
+
+        // user 6 doesn't exist
+        let is_member = state.is_chat_member(1, 6).await.expect("is member failed");
+        assert!(!is_member);
+
+        // chat 10 doesn't exist
+        let is_member = state.is_chat_member(10, 1).await.expect("is member failed");
+        assert!(!is_member);
+
+        // user 4 is not a member of chat 2
+        let is_member = state.is_chat_member(2, 4).await.expect("is member failed");
+        assert!(!is_member);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_dto_should_be_validated_per_field() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ChatDTO::new("dups", &[2, 3, 2, 3], false);
+        let chat = state.create_chat(input, 1, 1).await?;
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        assert_eq!(chat.owner_id, Some(1));
+
+        let input = CreateUser::new("foo", "Eve Chen", "eve@foo.org", "123456");
+        let outsider = state.create_user(&input).await?;
+        let input = ChatDTO::new("mixed", &[1, 2, outsider.id], false);
+        let err = state.create_chat(input, 1, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Some members are not in the workspace"
+        );
+
+        let mut input = ChatDTO::new("", &[1, 2, 3, 4, 5, 99, 100, 101, 102], false);
+        input.topic = Some("a".repeat(251));
+        let Err(AppError::ChatFieldsError(fields)) = state.create_chat(input, 1, 1).await else {
+            panic!("expected field errors");
+        };
+        let names: Vec<_> = fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(names, vec!["topic", "name", "members"]);
+        assert_eq!(fields[2].message, "Some members do not exist");
         Ok(())
     }
 
     #[tokio::test]
-    async fn chat_fetch_all_should_work() -> Result<()> {
+    async fn chat_members_should_be_managed_in_join_table() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let chats = state.fetch_chats(1).await.expect("fetch all chats failed");
+        let input = ChatDTO::new("team", &[2, 3, 4], false);
+        let chat = state.create_chat(input, 1, 1).await?;
+        // the creator is always added and owns the chat
+        assert_eq!(chat.members, vec![1, 2, 3, 4]);
+        let members = state.list_chat_members(chat.id as _).await?;
+        assert_eq!(members[0].role, ChatRole::Owner);
+        assert_eq!(members[1].role, ChatRole::Member);
+        assert_eq!(members[1].invited_by, Some(1));
 
-        assert_eq!(chats.len(), 4);
+        let input = ChatPatchDTO {
+            members: Some(vec![1, 2, 4, 5]),
+            ..Default::default()
+        };
+        let chat = state.update_chat(chat.id as _, 1, input, 2).await?.unwrap();
+        assert_eq!(chat.members, vec![1, 2, 4, 5]);
+
+        state.add_chat_member(chat.id as _, 3, Some(2)).await?;
+        assert!(state.is_chat_member(chat.id as _, 3).await?);
+        assert!(state.remove_chat_member(chat.id as _, 3, 1).await?);
+        assert!(!state.remove_chat_member(chat.id as _, 3, 1).await?);
 
+        // deleted chats keep their members so they can be restored
+        state.delete_chat(chat.id as _, 1).await?;
+        assert!(state.get_chat_by_id(chat.id as _).await?.is_none());
+        assert_eq!(state.list_chat_members(chat.id as _).await?.len(), 4);
         Ok(())
     }
 
     #[tokio::test]
-    async fn chat_is_member_should_work() -> Result<()> {
+    async fn deleted_chat_should_be_restorable_until_purged() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
-        let is_member = state.is_chat_member(1, 1).await.expect("is member failed");
-        assert!(is_member);
+        assert_eq!(state.delete_chat(1, 1).await?, Some(1));
+        assert_eq!(state.delete_chat(1, 1).await?, None);
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        assert!(!state.is_chat_member(1, 1).await?);
+        let chats = state.fetch_chats(1, 1, true).await?;
+        assert!(chats.iter().all(|c| c.id != 1));
+        let input = crate::CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        let ret = state.create_message(input, 1, 1).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
 
-        // user 6 doesn't exist
-        let is_member = state.is_chat_member(1, 6).await.expect("is member failed");
-        assert!(!is_member);
+        let chat = state
+            .restore_chat(1, 1)
+            .await?
+            .expect("chat should be restored");
+        assert_eq!(chat.members.len(), 5);
+        assert!(state.restore_chat(1, 1).await?.is_none());
 
-        // chat 10 doesn't exist
-        let is_member = state.is_chat_member(10, 1).await.expect("is member failed");
-        assert!(!is_member);
+        // not due before the retention period has passed, and can't be restored after it
+        state.delete_chat(1, 1).await?;
+        assert!(!state.purge_deleted_chat(1).await?);
+        sqlx::query("UPDATE chats SET deleted_at = NOW() - interval '31 days' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        assert!(state.restore_chat(1, 1).await?.is_none());
 
-        // user 4 is not a member of chat 2
-        let is_member = state.is_chat_member(2, 4).await.expect("is member failed");
-        assert!(!is_member);
+        sqlx::query("UPDATE jobs SET run_at = NOW() WHERE kind = $1")
+            .bind(PURGE_CHAT_JOB)
+            .execute(&state.pool)
+            .await?;
+        let runner = crate::JobRunner::new(state.clone()).register(PurgeChatJob);
+        assert!(runner.run_once().await?);
+        assert!(runner.run_once().await?);
+        let (messages,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages WHERE chat_id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(messages, 0);
+        assert!(state.list_chat_members(1).await?.is_empty());
+        assert!(!state.purge_deleted_chat(1).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_should_only_change_given_fields() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ChatPatchDTO {
+            name: Some("renamed".to_string()),
+            ..Default::default()
+        };
+        let chat = state.update_chat(2, 1, input, 1).await?.unwrap();
+        assert_eq!(chat.name.as_deref(), Some("renamed"));
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        assert_eq!(chat.r#type, ChatType::PrivateChannel);
+
+        let input = ChatPatchDTO {
+            public: Some(true),
+            ..Default::default()
+        };
+        let chat = state.update_chat(2, 1, input, 1).await?.unwrap();
+        assert_eq!(chat.r#type, ChatType::PublicChannel);
+        assert_eq!(chat.name.as_deref(), Some("renamed"));
+
+        let chat = state
+            .update_chat(2, 1, ChatPatchDTO::default(), 1)
+            .await?
+            .unwrap();
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        assert!(state
+            .update_chat(100, 1, ChatPatchDTO::default(), 1)
+            .await?
+            .is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_chat_should_reject_stale_versions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert_eq!(chat.version, 1);
+
+        let input = ChatPatchDTO {
+            name: Some("renamed".to_string()),
+            version: Some(1),
+            ..Default::default()
+        };
+        let chat = state.update_chat(2, 1, input, 1).await?.unwrap();
+        assert_eq!(chat.version, 2);
+
+        // a second admin editing the chat they read before the rename
+        let input = ChatPatchDTO {
+            topic: Some("Mockups".to_string()),
+            version: Some(1),
+            ..Default::default()
+        };
+        let err = state.update_chat(2, 1, input, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "version conflict: chat 2 was updated to version 2, reload it"
+        );
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert_eq!(chat.topic, None);
+
+        // updates without a version still apply
+        let input = ChatPatchDTO {
+            topic: Some("Mockups".to_string()),
+            ..Default::default()
+        };
+        let chat = state.update_chat(2, 1, input, 1).await?.unwrap();
+        assert_eq!(chat.version, 3);
+        let chat = state
+            .update_chat(2, 1, ChatPatchDTO::default(), 1)
+            .await?
+            .unwrap();
+        assert_eq!(chat.version, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_writes_should_be_scoped_to_workspace() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // chat 2 is in workspace 1, workspace 2 is foo
+        let input = ChatPatchDTO {
+            name: Some("taken over".to_string()),
+            ..Default::default()
+        };
+        assert!(state.update_chat(2, 2, input, 1).await?.is_none());
+        assert!(state.delete_chat(2, 2).await?.is_none());
+        assert!(state.schedule_chat_deletion(2, 2, 1).await?.is_none());
+        assert!(state.set_chat_archived(2, 2, true).await?.is_none());
+        let data = b"\x89PNG\r\n\x1a\navatar";
+        assert!(state.set_chat_avatar(2, 2, data).await?.is_none());
+
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert_ne!(chat.name.as_deref(), Some("taken over"));
+        assert_eq!(chat.version, 1);
+        assert!(chat.delete_at.is_none());
+        assert!(chat.archived_at.is_none());
+        assert!(chat.avatar_url.is_none());
+
+        // a deletion scheduled or done in workspace 1 can only be undone from it
+        state.schedule_chat_deletion(2, 1, 1).await?.unwrap();
+        assert!(state.cancel_chat_deletion(2, 2, 1).await?.is_none());
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert!(chat.delete_at.is_some());
+        state.delete_chat(2, 1).await?.unwrap();
+        assert!(state.restore_chat(2, 2).await?.is_none());
+        assert!(state.get_chat_by_id(2).await?.is_none());
+        assert!(state.restore_chat(2, 1).await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_topic_should_be_set_and_cleared() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut input = ChatDTO::new("design", &[1, 2], false);
+        input.topic = Some("Mockups and reviews".to_string());
+        let chat = state.create_chat(input, 1, 1).await?;
+        assert_eq!(chat.topic.as_deref(), Some("Mockups and reviews"));
+        assert_eq!(
+            state.get_chat_by_id(chat.id as _).await?,
+            Some(chat.clone())
+        );
+
+        // other changes keep the topic
+        let input = ChatPatchDTO {
+            name: Some("design team".to_string()),
+            ..Default::default()
+        };
+        let updated = state.update_chat(chat.id as _, 1, input, 1).await?.unwrap();
+        assert_eq!(updated.topic, chat.topic);
+
+        let input = ChatPatchDTO {
+            topic: Some("x".repeat(251)),
+            ..Default::default()
+        };
+        let err = state
+            .update_chat(chat.id as _, 1, input, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Topic must be at most 250 characters"
+        );
+        let input = ChatPatchDTO {
+            topic: Some("".to_string()),
+            ..Default::default()
+        };
+        let updated = state.update_chat(chat.id as _, 1, input, 1).await?.unwrap();
+        assert_eq!(updated.topic, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_avatar_should_be_set() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let data = b"\x89PNG\r\n\x1a\navatar";
+        let chat = state.set_chat_avatar(1, 1, data).await?.unwrap();
+        let url = chat.avatar_url.expect("avatar url should be set");
+        assert!(url.starts_with("/files/1/") && url.ends_with(".png"));
+        let file: ChatFile = url.parse()?;
+        assert!(file.path(&state.config.server.base_dir).exists());
+        assert_eq!(
+            state.get_chat_by_id(1).await?.unwrap().avatar_url,
+            Some(url)
+        );
+
+        let err = state.set_chat_avatar(1, 1, b"<svg/>").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Avatar must be a png, jpeg, gif or webp image"
+        );
+        assert!(state.set_chat_avatar(100, 1, data).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_page_should_paginate_by_last_update() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.pin_chat(3, 1).await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        state.create_message(input, 2, 1).await?;
+
+        let mut input = ListChats {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = state.fetch_chat_page(1, 1, &input).await?;
+        let ids: Vec<_> = page.chats.iter().map(|c| c.id).collect();
+        // chats without changes since the fixture tie on updated_at
+        assert_eq!(ids, [3, 2]);
+        input.cursor = page.next_cursor;
+        let page = state.fetch_chat_page(1, 1, &input).await?;
+        let ids: Vec<_> = page.chats.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [4, 1]);
+        assert_eq!(page.next_cursor, None);
+
+        input.cursor = Some("zz".to_string());
+        let err = state.fetch_chat_page(1, 1, &input).await.unwrap_err();
+        assert_eq!(err.to_string(), "invalid cursor: zz");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_page_should_include_unread_counts() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let unread = |page: ChatPage| -> Vec<_> {
+            let mut counts: Vec<_> = page.chats.iter().map(|c| (c.id, c.unread_count)).collect();
+            counts.sort();
+            counts
+        };
+        let page = state.fetch_chat_page(1, 2, &ListChats::default()).await?;
+        // user 2 sent 2 of the 10 messages in chat 1 and is no member of chat 4
+        assert_eq!(unread(page), [(1, 8), (2, 0), (3, 0), (4, 0)]);
+
+        state.mark_chats_read(2, None).await?;
+        let page = state.fetch_chat_page(1, 2, &ListChats::default()).await?;
+        assert_eq!(unread(page), [(1, 0), (2, 0), (3, 0), (4, 0)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_lists_should_include_last_message() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "a".repeat(200),
+            files: vec![],
+        };
+        state.create_message(input, 4, 3).await?;
+
+        let page = state.fetch_chat_page(1, 1, &ListChats::default()).await?;
+        let last = |id: i64| {
+            page.chats
+                .iter()
+                .find(|c| c.id == id)
+                .and_then(|c| c.last_message.clone())
+        };
+        let message = last(1).expect("chat 1 should have a last message");
+        assert_eq!(message.id, 10);
+        assert_eq!(message.sender_name, "Tyr Chen");
+        assert_eq!(message.content, "Hello, world!");
+        let message = last(4).expect("chat 4 should have a last message");
+        assert_eq!(message.sender_id, 3);
+        assert_eq!(message.content.len(), LAST_MESSAGE_PREVIEW_LEN as usize);
+        assert!(last(2).is_none());
+
+        let chats = state.fetch_chats(1, 1, false).await?;
+        assert_eq!(chats[0].last_message.as_ref().map(|m| m.id), Some(10));
+        assert!(state
+            .get_chat_by_id(1)
+            .await?
+            .unwrap()
+            .last_message
+            .is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_lists_should_hide_last_message_from_non_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateMessage {
+            content: "private plans".to_string(),
+            files: vec![],
+        };
+        state.create_message(input, 4, 3).await?;
+
+        // user 5 is only in chat 1, user 2 isn't in the group chat 4
+        let page = state.fetch_chat_page(1, 5, &ListChats::default()).await?;
+        for chat in &page.chats {
+            assert_eq!(
+                chat.last_message.is_some(),
+                chat.id == 1,
+                "chat {}",
+                chat.id
+            );
+        }
+        let chats = state.fetch_chats(1, 2, false).await?;
+        let chat = chats.iter().find(|c| c.id == 4).unwrap();
+        assert!(chat.last_message.is_none());
+        let chat = chats.iter().find(|c| c.id == 1).unwrap();
+        assert!(chat.last_message.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_page_should_sort_by_activity() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for chat_id in [1, 4] {
+            let input = CreateMessage {
+                content: "hello".to_string(),
+                files: vec![],
+            };
+            state.create_message(input, chat_id, 1).await?;
+        }
+        // renaming touches updated_at but is no activity
+        let input = ChatPatchDTO {
+            name: Some("renamed".to_string()),
+            ..Default::default()
+        };
+        state.update_chat(2, 1, input, 1).await?;
+
+        let mut input = ListChats {
+            sort: ChatSort::Activity,
+            ..Default::default()
+        };
+        let page = state.fetch_chat_page(1, 1, &input).await?;
+        let ids: Vec<_> = page.chats.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [4, 1, 3, 2]);
+
+        input.limit = Some(2);
+        let page = state.fetch_chat_page(1, 1, &input).await?;
+        input.cursor = page.next_cursor;
+        let page = state.fetch_chat_page(1, 1, &input).await?;
+        let ids: Vec<_> = page.chats.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [3, 2]);
+        Ok(())
+    }
+
+    async fn next_change(listener: &mut PgListener) -> Result<serde_json::Value> {
+        let timeout = std::time::Duration::from_secs(5);
+        let notif = tokio::time::timeout(timeout, listener.recv()).await??;
+        Ok(serde_json::from_str(notif.payload())?)
+    }
+
+    #[tokio::test]
+    async fn chat_deletion_should_be_scheduled_then_purged() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_deletion").await?;
+
+        let deletion = state
+            .schedule_chat_deletion(1, 1, 1)
+            .await?
+            .expect("chat should be scheduled");
+        let grace = deletion.delete_at - Utc::now();
+        assert!(grace > Duration::hours(71), "{grace}");
+        let change = next_change(&mut listener).await?;
+        assert_eq!(change["chat"]["members"], json!([1, 2, 3, 4, 5]));
+        assert_eq!(change["actor_id"], 1);
+        assert!(change["delete_at"].is_string());
+        // the chat stays usable meanwhile and scheduling again keeps the schedule
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert_eq!(chat.delete_at, Some(deletion.delete_at));
+        assert_eq!(state.schedule_chat_deletion(1, 1, 1).await?, Some(deletion));
+        assert!(!state.purge_scheduled_chat(1).await?);
+
+        let chat = state
+            .cancel_chat_deletion(1, 1, 1)
+            .await?
+            .expect("chat exists");
+        assert_eq!(chat.delete_at, None);
+        assert!(next_change(&mut listener).await?["delete_at"].is_null());
+        assert!(state.cancel_chat_deletion(1, 1, 1).await?.is_none());
+
+        state.schedule_chat_deletion(1, 1, 1).await?;
+        sqlx::query("UPDATE chats SET delete_at = NOW() - INTERVAL '1 minute' WHERE id = 1")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE jobs SET run_at = NOW() WHERE kind = $1")
+            .bind(DELETE_SCHEDULED_CHAT_JOB)
+            .execute(&state.pool)
+            .await?;
+        // the job of the canceled schedule purges the chat as it is due again
+        let runner = crate::JobRunner::new(state.clone()).register(DeleteScheduledChatJob);
+        assert!(runner.run_once().await?);
+        assert!(state.get_chat_by_id(1).await?.is_none());
+        let (messages,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages WHERE chat_id = 1")
+            .fetch_one(&state.pool)
+            .await?;
+        assert_eq!(messages, 0);
+        assert!(state.restore_chat(1, 1).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn member_changes_should_be_notified_with_diff() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_members_changed").await?;
+
+        // chat 4 is {1, 3, 4}
+        let input = ChatPatchDTO {
+            members: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        state.update_chat(4, 1, input, 1).await?;
+        let payload = next_change(&mut listener).await?;
+        assert_eq!(payload["chat"]["members"], json!([1, 2, 3]));
+        assert_eq!(payload["added"], json!([2]));
+        assert_eq!(payload["removed"], json!([4]));
+        assert_eq!(
+            payload["system_messages"],
+            json!([
+                { "key": "member_added", "actor_id": 1, "user_id": 2 },
+                { "key": "member_removed", "actor_id": 1, "user_id": 4 },
+            ])
+        );
+
+        // nothing changed, nothing is sent
+        state.add_chat_member(4, 2, Some(1)).await?;
+        state.leave_chat(4, 3).await?;
+        let payload = next_change(&mut listener).await?;
+        assert_eq!(payload["removed"], json!([3]));
+        assert_eq!(payload["system_messages"][0]["key"], "member_left");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pinned_chats_should_be_listed_first() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.pin_chat(3, 1).await?;
+        state.pin_chat(3, 1).await?;
+        let chats = state.fetch_chats(1, 1, false).await?;
+        let ids: Vec<_> = chats.iter().map(|c| (c.id, c.pinned)).collect();
+        assert_eq!(ids, [(3, true), (1, false), (2, false), (4, false)]);
+        // pins are per user
+        assert!(!state.fetch_chats(1, 2, false).await?[2].pinned);
+
+        assert!(state.unpin_chat(3, 1).await?);
+        assert!(!state.unpin_chat(3, 1).await?);
+        assert_eq!(state.fetch_chats(1, 1, false).await?[0].id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_chat_should_only_allow_public_channels() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        state.leave_chat(1, 5).await?;
+        let chat = state.join_chat(1, &user).await?;
+        assert!(chat.members.contains(&5));
+        assert_eq!(state.join_chat(1, &user).await?, chat);
+        assert_eq!(state.get_chat_role(1, 5).await?, Some(ChatRole::Member));
+
+        for id in [2, 3, 4] {
+            let err = state.join_chat(id, &user).await.unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "permission denied: only public channels can be joined"
+            );
+        }
+        let other = state
+            .create_user(&CreateUser::new("other", "Eve", "eve@other.org", "123456"))
+            .await?;
+        assert!(matches!(
+            state.join_chat(1, &other).await,
+            Err(AppError::NotFound(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn channel_directory_should_list_joinable_channels() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ListChannels::default();
+        // user 5 is in the only public channel
+        assert!(state.list_channel_directory(1, 5, &input).await?.is_empty());
+
+        let mut dto = ChatDTO::new("Design Review", &[1, 2, 3], true);
+        dto.topic = Some("mockups and feedback".to_string());
+        let design = state.create_chat(dto, 1, 1).await?;
+        let random = state
+            .create_chat(ChatDTO::new("random", &[1, 2], true), 1, 1)
+            .await?;
+        let channels = state.list_channel_directory(1, 5, &input).await?;
+        let ids: Vec<_> = channels.iter().map(|c| c.id).collect();
+        assert_eq!(ids, [design.id, random.id]);
+        assert_eq!(channels[0].member_count, 3);
+        assert_eq!(channels[0].last_message_at, None);
+
+        let input = ListChannels {
+            query: Some(" FEEDBACK ".to_string()),
+            limit: None,
+        };
+        let channels = state.list_channel_directory(1, 5, &input).await?;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].name.as_deref(), Some("Design Review"));
+
+        state.set_chat_archived(design.id as _, 1, true).await?;
+        assert!(state.list_channel_directory(1, 5, &input).await?.is_empty());
+        // members don't see the channels they are in
+        state.set_chat_archived(design.id as _, 1, false).await?;
+        assert!(state.list_channel_directory(1, 2, &input).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn message_counts_should_follow_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let sent_count = |members: Vec<ChatMember>, user_id: i64| {
+            members
+                .into_iter()
+                .find(|m| m.user_id == user_id)
+                .map(|m| m.sent_count)
+        };
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert_eq!(chat.message_count, 10);
+        assert_eq!(sent_count(state.list_chat_members(1).await?, 1), Some(4));
+
+        let input = CreateMessage {
+            content: "counted".to_string(),
+            files: vec![],
+        };
+        state.create_message(input, 2, 3).await?;
+        let chat = state.get_chat_by_id(2).await?.expect("chat should exist");
+        assert_eq!(chat.message_count, 1);
+        assert_eq!(sent_count(state.list_chat_members(2).await?, 3), Some(1));
+
+        sqlx::query("DELETE FROM messages WHERE chat_id = 1 AND sender_id = 1")
+            .execute(&state.pool)
+            .await?;
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert_eq!(chat.message_count, 6);
+        assert_eq!(sent_count(state.list_chat_members(1).await?, 1), Some(0));
+        // rejoining members get their count back
+        state.leave_chat(1, 5).await?;
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        state.join_chat(1, &user).await?;
+        assert_eq!(sent_count(state.list_chat_members(1).await?, 5), Some(1));
+
+        sqlx::query("UPDATE chats SET message_count = 0")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE chat_members SET sent_count = 0")
+            .execute(&state.pool)
+            .await?;
+        let (updated_at,): (DateTime<Utc>,) =
+            sqlx::query_as("SELECT updated_at FROM chats WHERE id = 1")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(state.backfill_message_counts(0).await?, Some(4));
+        assert_eq!(state.backfill_message_counts(4).await?, None);
+        let chat = state.get_chat_by_id(1).await?.expect("chat should exist");
+        assert_eq!(chat.message_count, 6);
+        assert_eq!(sent_count(state.list_chat_members(1).await?, 2), Some(2));
+        assert_eq!(sent_count(state.list_chat_members(2).await?, 3), Some(1));
+        // recounting is no change of the chat
+        let (after,): (DateTime<Utc>,) =
+            sqlx::query_as("SELECT updated_at FROM chats WHERE id = 1")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(after, updated_at);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn search_chats_should_match_visible_chat_names() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let review = state
+            .create_chat(ChatDTO::new("Design Review", &[1, 2, 3], true), 1, 1)
+            .await?;
+        let system = state
+            .create_chat(ChatDTO::new("design system", &[1, 2, 3], false), 1, 1)
+            .await?;
+        let search = |q: &str, limit, cursor| SearchChats {
+            q: q.to_string(),
+            limit,
+            cursor,
+        };
+        let ids = |page: &ChatPage| page.chats.iter().map(|c| c.id).collect::<Vec<_>>();
+
+        let page = state
+            .search_chats(1, 1, &search("DESIGN", None, None))
+            .await?;
+        assert_eq!(ids(&page), [review.id, system.id]);
+        // user 5 can't see the private channel
+        let page = state.search_chats(1, 5, &search("des", None, None)).await?;
+        assert_eq!(ids(&page), [review.id]);
+
+        let page = state
+            .search_chats(1, 1, &search("design", Some(1), None))
+            .await?;
+        assert_eq!(ids(&page), [review.id]);
+        let next = search("design", Some(1), page.next_cursor);
+        let page = state.search_chats(1, 1, &next).await?;
+        assert_eq!((ids(&page), page.next_cursor), (vec![system.id], None));
+
+        // words of the name match too, prefix matches come first
+        let channel = state
+            .create_chat(ChatDTO::new("reviews", &[1, 2], true), 1, 1)
+            .await?;
+        let page = state
+            .search_chats(1, 1, &search("review", None, None))
+            .await?;
+        assert_eq!(ids(&page), [channel.id, review.id]);
+
+        for q in ["", "  ", "%", "_"] {
+            let page = state.search_chats(1, 1, &search(q, None, None)).await?;
+            assert!(page.chats.is_empty(), "{q}");
+        }
+        let err = state
+            .search_chats(1, 1, &search("design", None, Some("zz".to_string())))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::InvalidCursor(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn chat_handle_should_slug_the_name() {
+        assert_eq!(chat_handle("General Chat!"), "general-chat");
+        assert_eq!(chat_handle("  Q3 -- Planning "), "q3-planning");
+        assert_eq!(chat_handle("#"), "channel");
+        assert_eq!(chat_handle(&"a".repeat(100)).len(), MAX_HANDLE_LEN);
+    }
+
+    #[tokio::test]
+    async fn chat_handle_should_be_unique_and_follow_renames() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state
+            .create_chat(ChatDTO::new("General", &[1, 2, 3], true), 1, 1)
+            .await?;
+        assert_eq!(chat.handle.as_deref(), Some("general-2"));
+        let found = state.get_chat_by_handle(1, "#General-2", 4).await?;
+        assert_eq!(found.map(|c| c.id), Some(chat.id));
+
+        let input = ChatPatchDTO {
+            name: Some("Town Hall".to_string()),
+            ..Default::default()
+        };
+        let chat = state.update_chat(chat.id as _, 1, input, 1).await?.unwrap();
+        assert_eq!(chat.handle.as_deref(), Some("town-hall"));
+        // the old handle redirects to the renamed chat
+        let found = state.get_chat_by_handle(1, "general-2", 4).await?;
+        assert_eq!(found.map(|c| c.id), Some(chat.id));
+        assert!(state.get_chat_by_handle(2, "town-hall", 4).await?.is_none());
+
+        // user 4 isn't a member of the private channel
+        let found = state.get_chat_by_handle(1, "private", 1).await?;
+        assert_eq!(found.map(|c| c.id), Some(2));
+        assert!(state.get_chat_by_handle(1, "private", 4).await?.is_none());
+
+        // groups have no handle
+        let chat = state.get_chat_by_id(4).await?.unwrap();
+        assert!(chat.handle.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archived_chat_should_be_hidden_and_read_only() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.set_chat_archived(2, 1, true).await?.unwrap();
+        let archived_at = chat.archived_at;
+        assert!(archived_at.is_some());
+        // archiving again keeps the time
+        let chat = state.set_chat_archived(2, 1, true).await?.unwrap();
+        assert_eq!(chat.archived_at, archived_at);
+
+        assert_eq!(state.fetch_chats(1, 1, false).await?.len(), 3);
+        assert_eq!(state.fetch_chats(1, 1, true).await?.len(), 4);
+        let input = crate::CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        let err = state.create_message(input.clone(), 2, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "create message error: Chat is archived");
+
+        let chat = state.set_chat_archived(2, 1, false).await?.unwrap();
+        assert!(chat.archived_at.is_none());
+        state.create_message(input, 2, 1).await?;
+        assert!(state.set_chat_archived(100, 1, true).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leave_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.update_chat_member_role(4, 4, ChatRole::Admin).await?;
+        // the admin takes over from the leaving owner
+        let chat = state.leave_chat(4, 1).await?.expect("chat should exist");
+        assert_eq!(chat.members, vec![3, 4]);
+        assert_eq!(chat.owner_id, Some(4));
+
+        let chat = state.leave_chat(4, 4).await?.expect("chat should exist");
+        assert_eq!(chat.owner_id, Some(3));
+        assert!(state.leave_chat(4, 3).await?.is_none());
+        assert!(state.get_chat_by_id(4).await?.is_none());
+
+        assert!(state.leave_chat(3, 1).await.is_err());
+        assert!(state.leave_chat(2, 5).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify_chat_member_change_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.verify_chat_member_change(2, 4, false).await?;
+        assert_eq!(chat.members, vec![1, 2, 3]);
+        state.verify_chat_member_change(2, 3, true).await?;
+
+        let err = state
+            .verify_chat_member_change(3, 4, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Members of a single chat can't be changed, convert it to a group first"
+        );
+        let err = state
+            .verify_chat_member_change(2, 4, true)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Not found: chat member 4");
+        let err = state
+            .verify_chat_member_change(2, 100, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Not found: user id 100");
+
+        // a channel of 2 can't lose a member
+        let chat = state
+            .create_chat(ChatDTO::new("pair", &[1, 2], false), 1, 1)
+            .await?;
+        let err = state
+            .verify_chat_member_change(chat.id as _, 2, true)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Chat must have at least 2 members"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chat_roles_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chat = state.get_chat_by_id(2).await?.expect("chat should exist");
+        assert_eq!(chat.owner_id, Some(1));
+
+        let err = state
+            .authorize(2, ChatAction::UpdateChat, Resource::chat(2))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: only chat owners and admins can update the chat"
+        );
+        state.update_chat_member_role(2, 2, ChatRole::Admin).await?;
+        state
+            .authorize(2, ChatAction::UpdateChat, Resource::chat(2))
+            .await?;
+
+        // the owner can't be dropped or demoted
+        let input = ChatPatchDTO {
+            members: Some(vec![2, 3]),
+            ..Default::default()
+        };
+        assert!(state.update_chat(2, 1, input, 2).await.is_err());
+        let ret = state.update_chat_member_role(2, 1, ChatRole::Member).await;
+        assert!(ret.is_err());
+
+        // transfer the ownership
+        let member = state.update_chat_member_role(2, 3, ChatRole::Owner).await?;
+        assert_eq!(member.role, ChatRole::Owner);
+        assert_eq!(state.get_chat_role(2, 1).await?, Some(ChatRole::Admin));
+        let chat = state.get_chat_by_id(2).await?.expect("chat should exist");
+        assert_eq!(chat.owner_id, Some(3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn member_limits_should_follow_the_chat_type() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.verify_member_limit(&ChatType::Group, 500)?;
+        let err = state
+            .verify_member_limit(&ChatType::Group, 501)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "member limit reached: group chats can have at most 500 members"
+        );
+        state.verify_member_limit(&ChatType::PublicChannel, 10_000)?;
+        assert!(state
+            .verify_member_limit(&ChatType::PrivateChannel, 10_001)
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn convert_chat_should_make_a_group() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ConvertChat { members: vec![2] };
+        let err = state.convert_chat(3, &input, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: A group needs at least one member besides the two of the single chat"
+        );
+        let err = state.convert_chat(4, &input, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: Only single chats can be converted to a group"
+        );
+
+        let input = ConvertChat {
+            members: vec![3, 3],
+        };
+        let chat = state.convert_chat(3, &input, 1).await?;
+        assert_eq!(chat.r#type, ChatType::Group);
+        assert_eq!(chat.members, [1, 2, 3]);
+        // the pair can start a new single chat
+        let single = state
+            .create_chat(ChatDTO::new("", &[1, 2], false), 1, 1)
+            .await?;
+        assert_eq!(single.r#type, ChatType::Single);
+        assert_ne!(single.id, chat.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transfer_chat_ownership_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // user 4 isn't a member of chat 2
+        let input = TransferChat { user_id: 4 };
+        let err = state
+            .transfer_chat_ownership(2, &input, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "create chat error: The ownership can only be transferred to a member of the chat"
+        );
+
+        let input = TransferChat { user_id: 2 };
+        let chat = state.transfer_chat_ownership(2, &input, 1).await?;
+        assert_eq!(chat.owner_id, Some(2));
+        assert_eq!(state.get_chat_role(2, 1).await?, Some(ChatRole::Admin));
+        assert!(state.transfer_chat_ownership(2, &input, 2).await.is_err());
+
+        let (action, details): (String, serde_json::Value) = sqlx::query_as(
+            "SELECT action, details FROM audit_logs WHERE target_id = 2 ORDER BY id DESC LIMIT 1",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!(action, "chat.transfer");
+        assert_eq!(details, json!({ "from": 1, "to": 2 }));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_user_chats_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let chats = state.fetch_user_chats(3, None).await?;
+        let ids: Vec<_> = chats.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 2, 4]);
 
+        // chats shared by user 3 and user 4
+        let chats = state.fetch_user_chats(3, Some(4)).await?;
+        let ids: Vec<_> = chats.iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 4]);
         Ok(())
     }
 }
@@ -0,0 +1,496 @@
+use super::chat::{get_dm_pair, sync_chat_handle};
+use crate::{AppError, AppState, ChatRole, Job, JobFuture, JobHandler};
+use chat_core::ChatType;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, FromRow};
+use std::collections::HashMap;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// Job kind resetting the demo workspaces, it schedules its next run when done.
+pub const RESET_DEMO_WORKSPACES_JOB: &str = "reset_demo_workspaces";
+
+// latest messages of each chat kept in a snapshot
+const MAX_SNAPSHOT_MESSAGES: i64 = 200;
+
+/// A workspace reset to a snapshot of its users, chats and messages every night.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct DemoWorkspace {
+    pub ws_id: i64,
+    /// users, chats and messages in the snapshot
+    pub users: usize,
+    pub chats: usize,
+    pub messages: usize,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    /// None until the first reset
+    pub reset_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What a demo workspace is reset to. Users are matched by email, accounts created
+/// since are kept but lose their chats.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DemoSnapshot {
+    pub users: Vec<DemoUser>,
+    pub chats: Vec<DemoChat>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct DemoUser {
+    pub fullname: String,
+    pub email: String,
+    pub password_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DemoChat {
+    pub name: Option<String>,
+    pub r#type: ChatType,
+    pub topic: Option<String>,
+    pub settings: serde_json::Value,
+    /// (email, role) of the members
+    pub members: Vec<(String, ChatRole)>,
+    /// oldest first
+    pub messages: Vec<DemoMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DemoMessage {
+    pub sender: String,
+    pub content: String,
+    pub files: Vec<String>,
+    /// seconds before the snapshot the message was sent, replayed as that long before
+    /// the reset so the history looks recent
+    pub age_secs: f64,
+}
+
+/// Runs the `reset_demo_workspaces` job scheduled by `schedule_demo_resets`.
+pub struct ResetDemoWorkspacesJob;
+
+// id, name, type, topic and settings of a chat
+type ChatRow = (
+    i64,
+    Option<String>,
+    ChatType,
+    Option<String>,
+    serde_json::Value,
+);
+
+#[derive(FromRow)]
+struct DemoRow {
+    ws_id: i64,
+    snapshot: Json<DemoSnapshot>,
+    created_by: i64,
+    reset_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Take a snapshot of the workspace as it is now and reset it to the snapshot every
+    /// night. Making a demo workspace again takes a new snapshot.
+    pub async fn create_demo_workspace(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<DemoWorkspace, AppError> {
+        if self.find_workspace_by_id(ws_id).await?.is_none() {
+            return Err(AppError::NotFound(format!("workspace id {ws_id}")));
+        }
+        let snapshot = self.snapshot_workspace(ws_id).await?;
+        let row: DemoRow = sqlx::query_as(
+            r#"
+            INSERT INTO demo_workspaces (ws_id, snapshot, created_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (ws_id) DO UPDATE
+            SET snapshot = EXCLUDED.snapshot, created_by = EXCLUDED.created_by,
+                created_at = NOW()
+            RETURNING ws_id, snapshot, created_by, reset_at, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(Json(&snapshot))
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        self.schedule_demo_resets().await?;
+
+        Ok(row.into())
+    }
+
+    pub async fn get_demo_workspace(&self, ws_id: u64) -> Result<Option<DemoWorkspace>, AppError> {
+        Ok(self.demo_row(ws_id).await?.map(Into::into))
+    }
+
+    /// Stop resetting the workspace, it is left as it is.
+    pub async fn delete_demo_workspace(&self, ws_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM demo_workspaces WHERE ws_id = $1")
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Users, chats and the latest messages of each chat of a workspace. Deleted chats are
+    /// left out.
+    pub async fn snapshot_workspace(&self, ws_id: u64) -> Result<DemoSnapshot, AppError> {
+        let users = sqlx::query_as(
+            "SELECT fullname, email, password_hash FROM users WHERE ws_id = $1 ORDER BY id",
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let chats: Vec<ChatRow> = sqlx::query_as(
+            r#"
+                SELECT id, name, type, topic, settings FROM chats
+                WHERE ws_id = $1 AND deleted_at IS NULL
+                ORDER BY id
+                "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        let ids: Vec<i64> = chats.iter().map(|c| c.0).collect();
+        let members: Vec<(i64, String, ChatRole)> = sqlx::query_as(
+            r#"
+            SELECT m.chat_id, u.email, m.role
+            FROM chat_members m JOIN users u ON u.id = m.user_id
+            WHERE m.chat_id = ANY($1)
+            ORDER BY m.chat_id, m.user_id
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let messages: Vec<(i64, String, String, Vec<String>, f64)> = sqlx::query_as(
+            r#"
+            SELECT chat_id, email, content, files, age_secs FROM (
+                SELECT m.id, m.chat_id, u.email, m.content, m.files,
+                    EXTRACT(EPOCH FROM NOW() - m.created_at)::float8 AS age_secs,
+                    row_number() OVER (PARTITION BY m.chat_id ORDER BY m.id DESC) AS n
+                FROM messages m JOIN users u ON u.id = m.sender_id
+                WHERE m.chat_id = ANY($1)
+            ) m
+            WHERE n <= $2
+            ORDER BY id
+            "#,
+        )
+        .bind(&ids)
+        .bind(MAX_SNAPSHOT_MESSAGES)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut chats: Vec<(i64, DemoChat)> = chats
+            .into_iter()
+            .map(|(id, name, r#type, topic, settings)| {
+                let chat = DemoChat {
+                    name,
+                    r#type,
+                    topic,
+                    settings,
+                    members: vec![],
+                    messages: vec![],
+                };
+                (id, chat)
+            })
+            .collect();
+        let index: HashMap<i64, usize> = chats
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (*id, i))
+            .collect();
+        for (chat_id, email, role) in members {
+            chats[index[&chat_id]].1.members.push((email, role));
+        }
+        for (chat_id, sender, content, files, age_secs) in messages {
+            chats[index[&chat_id]].1.messages.push(DemoMessage {
+                sender,
+                content,
+                files,
+                age_secs,
+            });
+        }
+
+        Ok(DemoSnapshot {
+            users,
+            chats: chats.into_iter().map(|(_, chat)| chat).collect(),
+        })
+    }
+
+    /// Replace the chats and messages of a demo workspace with its snapshot, and restore
+    /// the names and passwords of its users. Returns None if the workspace isn't a demo.
+    pub async fn reset_demo_workspace(
+        &self,
+        ws_id: u64,
+    ) -> Result<Option<DemoWorkspace>, AppError> {
+        let Some(row) = self.demo_row(ws_id).await? else {
+            return Ok(None);
+        };
+        let ws_id = ws_id as i64;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "DELETE FROM messages WHERE chat_id IN (SELECT id FROM chats WHERE ws_id = $1)",
+        )
+        .bind(ws_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM chats WHERE ws_id = $1")
+            .bind(ws_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut users = HashMap::new();
+        for user in &row.snapshot.users {
+            // an email taken in another workspace since isn't restored
+            let id: Option<i64> = sqlx::query_scalar(
+                r#"
+                INSERT INTO users (ws_id, fullname, email, password_hash)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (email) DO UPDATE
+                SET fullname = EXCLUDED.fullname, password_hash = EXCLUDED.password_hash
+                WHERE users.ws_id = EXCLUDED.ws_id
+                RETURNING id
+                "#,
+            )
+            .bind(ws_id)
+            .bind(&user.fullname)
+            .bind(&user.email)
+            .bind(&user.password_hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if let Some(id) = id {
+                users.insert(user.email.as_str(), id);
+            }
+        }
+
+        for chat in &row.snapshot.chats {
+            let (members, roles): (Vec<i64>, Vec<ChatRole>) = chat
+                .members
+                .iter()
+                .filter_map(|(email, role)| Some((*users.get(email.as_str())?, *role)))
+                .unzip();
+            if members.len() < 2 {
+                continue;
+            }
+            let id: i64 = sqlx::query_scalar(
+                r#"
+                INSERT INTO chats (ws_id, name, topic, type, dm_pair, settings)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                RETURNING id
+                "#,
+            )
+            .bind(ws_id)
+            .bind(&chat.name)
+            .bind(&chat.topic)
+            .bind(&chat.r#type)
+            .bind(get_dm_pair(&chat.r#type, &members))
+            .bind(&chat.settings)
+            .fetch_one(&mut *tx)
+            .await?;
+            for (user_id, role) in members.iter().zip(roles) {
+                sqlx::query(
+                    "INSERT INTO chat_members (chat_id, user_id, role) VALUES ($1, $2, $3)",
+                )
+                .bind(id)
+                .bind(user_id)
+                .bind(role)
+                .execute(&mut *tx)
+                .await?;
+            }
+            sync_chat_handle(&mut tx, id).await?;
+            for message in &chat.messages {
+                let Some(sender_id) = users.get(message.sender.as_str()) else {
+                    continue;
+                };
+                sqlx::query(
+                    r#"
+                    INSERT INTO messages (chat_id, sender_id, content, files, created_at)
+                    VALUES ($1, $2, $3, $4, NOW() - make_interval(secs => $5))
+                    "#,
+                )
+                .bind(id)
+                .bind(sender_id)
+                .bind(&message.content)
+                .bind(&message.files)
+                .bind(message.age_secs)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        let row: DemoRow = sqlx::query_as(
+            r#"
+            UPDATE demo_workspaces SET reset_at = NOW() WHERE ws_id = $1
+            RETURNING ws_id, snapshot, created_by, reset_at, created_at
+            "#,
+        )
+        .bind(ws_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(row.into()))
+    }
+
+    /// Reset every demo workspace, returns how many were reset.
+    pub async fn reset_demo_workspaces(&self) -> Result<usize, AppError> {
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT ws_id FROM demo_workspaces ORDER BY ws_id")
+            .fetch_all(&self.pool)
+            .await?;
+        for id in &ids {
+            self.reset_demo_workspace(*id as _).await?;
+        }
+        if !ids.is_empty() {
+            info!("Reset {} demo workspaces", ids.len());
+        }
+        Ok(ids.len())
+    }
+
+    /// Enqueue the `reset_demo_workspaces` job for the next reset hour unless one is
+    /// scheduled already.
+    pub async fn schedule_demo_resets(&self) -> Result<(), AppError> {
+        let scheduled: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM jobs WHERE kind = $1 AND status IN ('pending', 'running'))",
+        )
+        .bind(RESET_DEMO_WORKSPACES_JOB)
+        .fetch_one(&self.pool)
+        .await?;
+        if !scheduled {
+            let run_at = next_reset_at(Utc::now(), self.config.jobs.demo_reset_hour);
+            self.enqueue_job(
+                RESET_DEMO_WORKSPACES_JOB,
+                serde_json::json!({}),
+                Some(run_at),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn demo_row(&self, ws_id: u64) -> Result<Option<DemoRow>, AppError> {
+        let row = sqlx::query_as(
+            r#"
+            SELECT ws_id, snapshot, created_by, reset_at, created_at
+            FROM demo_workspaces
+            WHERE ws_id = $1
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+}
+
+impl JobHandler for ResetDemoWorkspacesJob {
+    fn kind(&self) -> &'static str {
+        RESET_DEMO_WORKSPACES_JOB
+    }
+
+    fn run(&self, state: AppState, _job: Job) -> JobFuture {
+        Box::pin(async move {
+            state.reset_demo_workspaces().await?;
+            let run_at = next_reset_at(Utc::now(), state.config.jobs.demo_reset_hour);
+            state
+                .enqueue_job(
+                    RESET_DEMO_WORKSPACES_JOB,
+                    serde_json::json!({}),
+                    Some(run_at),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+impl From<DemoRow> for DemoWorkspace {
+    fn from(row: DemoRow) -> Self {
+        let snapshot = row.snapshot.0;
+        Self {
+            ws_id: row.ws_id,
+            users: snapshot.users.len(),
+            chats: snapshot.chats.len(),
+            messages: snapshot.chats.iter().map(|c| c.messages.len()).sum(),
+            created_by: row.created_by,
+            reset_at: row.reset_at,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// the next time it is `hour` o'clock in UTC after `now`
+fn next_reset_at(now: DateTime<Utc>, hour: u32) -> DateTime<Utc> {
+    let today = now
+        .date_naive()
+        .and_hms_opt(hour % 24, 0, 0)
+        .expect("hour should be valid")
+        .and_utc();
+    if today > now {
+        today
+    } else {
+        today + Duration::days(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateMessage;
+    use anyhow::Result;
+    use chrono::{TimeZone, Timelike};
+
+    #[test]
+    fn next_reset_at_should_work() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 24, 2, 30, 0).unwrap();
+        assert_eq!(next_reset_at(now, 3).hour(), 3);
+        assert_eq!(next_reset_at(now, 3) - now, Duration::minutes(30));
+        assert_eq!(next_reset_at(now, 2) - now, Duration::minutes(23 * 60 + 30));
+    }
+
+    #[tokio::test]
+    async fn demo_workspace_should_reset_to_its_snapshot() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let demo = state.create_demo_workspace(1, 1).await?;
+        assert_eq!((demo.users, demo.chats, demo.messages), (5, 4, 10));
+        let chats = state.fetch_chats(1, 1, false).await?;
+
+        // a visitor plays around
+        let input = CreateMessage {
+            content: "trying things out".to_string(),
+            files: vec![],
+        };
+        state.create_message(input, 1, 2).await?;
+        state.delete_chat(4, 1).await?;
+        sqlx::query("UPDATE users SET fullname = 'Visitor' WHERE id = 2")
+            .execute(&state.pool)
+            .await?;
+
+        let demo = state
+            .reset_demo_workspace(1)
+            .await?
+            .expect("demo should exist");
+        assert!(demo.reset_at.is_some());
+        let reset = state.fetch_chats(1, 1, false).await?;
+        assert_eq!(reset.len(), chats.len());
+        for (chat, old) in reset.iter().zip(&chats) {
+            assert_eq!((&chat.r#type, &chat.members), (&old.r#type, &old.members));
+        }
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        assert_ne!(user.fullname, "Visitor");
+        let count: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM messages m JOIN chats c ON c.id = m.chat_id WHERE c.ws_id = 1",
+        )
+        .fetch_one(&state.pool)
+        .await?;
+        assert_eq!(count, 10);
+
+        assert!(state.delete_demo_workspace(1).await?);
+        assert!(state.reset_demo_workspace(1).await?.is_none());
+        Ok(())
+    }
+}
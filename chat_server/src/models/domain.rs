@@ -0,0 +1,457 @@
+use crate::{AppError, AppState};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
+    TokioAsyncResolver,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+const TXT_PREFIX: &str = "chat-verification=";
+const EMAIL_CHALLENGE_TTL_MINS: i64 = 60;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceDomain {
+    pub id: i64,
+    pub ws_id: i64,
+    pub domain: String,
+    /// TXT record to add to the domain to prove ownership
+    pub txt_record: String,
+    pub auto_join: bool,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateWorkspaceDomain {
+    pub domain: String,
+    /// add new signups with this domain to the workspace, otherwise it's only suggested
+    #[serde(default)]
+    pub auto_join: bool,
+}
+
+/// Mail a verification code to an address on the domain, instead of adding a TXT record.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct DomainEmailChallenge {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct VerifyDomain {
+    /// code from the email challenge, the TXT record is checked if missing
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct FindSignupWorkspace {
+    pub email: String,
+}
+
+/// Workspace offered to a new user based on the domain of its email.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct SignupWorkspace {
+    pub ws_id: i64,
+    pub name: String,
+    /// the user will be added to this workspace whatever workspace it signs up with
+    pub auto_join: bool,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn add_workspace_domain(
+        &self,
+        ws_id: u64,
+        input: &CreateWorkspaceDomain,
+    ) -> Result<WorkspaceDomain, AppError> {
+        let domain = input.domain.trim().to_lowercase();
+        if !is_valid_domain(&domain) {
+            return Err(AppError::WorkspaceError(format!("Invalid domain {domain}")));
+        }
+        if self.find_verified_domain(&domain).await?.is_some() {
+            return Err(AppError::WorkspaceError(format!(
+                "Domain {domain} is already verified by a workspace"
+            )));
+        }
+
+        let ret = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_domains (ws_id, domain, auto_join)
+            VALUES ($1, $2, $3)
+            RETURNING id, ws_id, domain, $4 || token AS txt_record, auto_join, verified_at, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(&domain)
+        .bind(input.auto_join)
+        .bind(TXT_PREFIX)
+        .fetch_one(&self.pool)
+        .await;
+
+        match ret {
+            Ok(domain) => Ok(domain),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(
+                AppError::WorkspaceError(format!("Domain {domain} is already added")),
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn list_workspace_domains(
+        &self,
+        ws_id: u64,
+    ) -> Result<Vec<WorkspaceDomain>, AppError> {
+        let domains = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, domain, $2 || token AS txt_record, auto_join, verified_at, created_at
+            FROM workspace_domains
+            WHERE ws_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(TXT_PREFIX)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(domains)
+    }
+
+    pub async fn get_workspace_domain(
+        &self,
+        ws_id: u64,
+        id: u64,
+    ) -> Result<Option<WorkspaceDomain>, AppError> {
+        let domain = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, domain, $3 || token AS txt_record, auto_join, verified_at, created_at
+            FROM workspace_domains
+            WHERE id = $1 AND ws_id = $2
+            "#,
+        )
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .bind(TXT_PREFIX)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(domain)
+    }
+
+    pub async fn delete_workspace_domain(&self, ws_id: u64, id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM workspace_domains WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Mark the domain as verified if `txt_records` (the TXT records of the domain)
+    /// contain its verification record.
+    pub async fn verify_workspace_domain(
+        &self,
+        ws_id: u64,
+        id: u64,
+        txt_records: &[String],
+    ) -> Result<WorkspaceDomain, AppError> {
+        let domain = self
+            .get_workspace_domain(ws_id, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("domain id {id}")))?;
+        if domain.verified_at.is_some() {
+            return Ok(domain);
+        }
+        if !txt_records.iter().any(|r| r.trim() == domain.txt_record) {
+            return Err(AppError::WorkspaceError(format!(
+                "TXT record {} not found on {}",
+                domain.txt_record, domain.domain
+            )));
+        }
+        self.mark_domain_verified(&domain).await
+    }
+
+    /// Mail a verification code to `email`, which must be an address on the domain.
+    pub async fn start_domain_email_challenge(
+        &self,
+        ws_id: u64,
+        id: u64,
+        email: &str,
+        requester: &User,
+    ) -> Result<WorkspaceDomain, AppError> {
+        let domain = self
+            .get_workspace_domain(ws_id, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("domain id {id}")))?;
+        let email = email.trim().to_lowercase();
+        if email.rsplit_once('@').map(|(_, d)| d) != Some(domain.domain.as_str()) {
+            return Err(AppError::WorkspaceError(format!(
+                "Email {} is not on domain {}",
+                email, domain.domain
+            )));
+        }
+        let ws = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id {ws_id}")))?;
+
+        let (code,): (String,) = sqlx::query_as(
+            r#"
+            UPDATE workspace_domains
+            SET challenge_email = $1,
+                challenge_code = substr(replace(gen_random_uuid()::text, '-', ''), 1, 8),
+                challenge_expires_at = NOW() + make_interval(mins => $2)
+            WHERE id = $3
+            RETURNING challenge_code
+            "#,
+        )
+        .bind(&email)
+        .bind(EMAIL_CHALLENGE_TTL_MINS as i32)
+        .bind(id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let ctx = json!({
+            "domain": domain.domain,
+            "workspace": ws.name,
+            "requester": requester.fullname,
+            "code": code,
+            "expires_in_mins": EMAIL_CHALLENGE_TTL_MINS,
+        });
+        self.send_email("domain_verification", &email, ctx).await?;
+        Ok(domain)
+    }
+
+    /// Mark the domain as verified if `code` matches the code of its email challenge.
+    pub async fn verify_workspace_domain_by_code(
+        &self,
+        ws_id: u64,
+        id: u64,
+        code: &str,
+    ) -> Result<WorkspaceDomain, AppError> {
+        let domain = self
+            .get_workspace_domain(ws_id, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("domain id {id}")))?;
+        if domain.verified_at.is_some() {
+            return Ok(domain);
+        }
+        let matched: Option<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM workspace_domains
+            WHERE id = $1 AND challenge_code = $2 AND challenge_expires_at > NOW()
+            "#,
+        )
+        .bind(id as i64)
+        .bind(code.trim())
+        .fetch_optional(&self.pool)
+        .await?;
+        if matched.is_none() {
+            return Err(AppError::WorkspaceError(
+                "Invalid or expired verification code".to_string(),
+            ));
+        }
+        self.mark_domain_verified(&domain).await
+    }
+
+    async fn mark_domain_verified(
+        &self,
+        domain: &WorkspaceDomain,
+    ) -> Result<WorkspaceDomain, AppError> {
+        let ret = sqlx::query_as(
+            r#"
+            UPDATE workspace_domains
+            SET verified_at = NOW(), challenge_code = NULL
+            WHERE id = $1
+            RETURNING id, ws_id, domain, $2 || token AS txt_record, auto_join, verified_at, created_at
+            "#,
+        )
+        .bind(domain.id)
+        .bind(TXT_PREFIX)
+        .fetch_one(&self.pool)
+        .await;
+
+        match ret {
+            Ok(domain) => Ok(domain),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                Err(AppError::WorkspaceError(format!(
+                    "Domain {} is already verified by a workspace",
+                    domain.domain
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Find the workspace which verified the domain of `email`.
+    pub async fn find_signup_workspace(
+        &self,
+        email: &str,
+    ) -> Result<Option<SignupWorkspace>, AppError> {
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return Ok(None);
+        };
+        self.find_verified_domain(&domain.to_lowercase()).await
+    }
+
+    async fn find_verified_domain(
+        &self,
+        domain: &str,
+    ) -> Result<Option<SignupWorkspace>, AppError> {
+        let ws = sqlx::query_as(
+            r#"
+            SELECT w.id AS ws_id, w.name, d.auto_join
+            FROM workspace_domains d
+            JOIN workspaces w ON w.id = d.ws_id
+            WHERE d.domain = $1 AND d.verified_at IS NOT NULL
+            "#,
+        )
+        .bind(domain)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(ws)
+    }
+}
+
+/// Fetch the TXT records of a domain, an unknown domain has no records.
+pub(crate) async fn lookup_txt(domain: &str) -> Result<Vec<String>, AppError> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|_| {
+        TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+    });
+    match resolver.txt_lookup(domain).await {
+        Ok(records) => Ok(records
+            .iter()
+            .map(|txt| {
+                txt.txt_data()
+                    .iter()
+                    .map(|data| String::from_utf8_lossy(data))
+                    .collect()
+            })
+            .collect()),
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(vec![]),
+        Err(e) => Err(AppError::AnyError(anyhow::anyhow!(
+            "lookup TXT records of {domain} failed: {e}"
+        ))),
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    let labels: Vec<_> = domain.split('.').collect();
+    domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn input(domain: &str, auto_join: bool) -> CreateWorkspaceDomain {
+        CreateWorkspaceDomain {
+            domain: domain.to_string(),
+            auto_join,
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_workspace_domain_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let domain = state
+            .add_workspace_domain(1, &input("Acme.org", true))
+            .await?;
+        assert_eq!(domain.domain, "acme.org");
+        assert!(domain.txt_record.starts_with(TXT_PREFIX));
+        assert!(state.find_signup_workspace("a@acme.org").await?.is_none());
+
+        let records = vec!["v=spf1 -all".to_string()];
+        let ret = state
+            .verify_workspace_domain(1, domain.id as _, &records)
+            .await;
+        assert!(matches!(ret, Err(AppError::WorkspaceError(_))));
+
+        let records = vec![domain.txt_record.clone()];
+        let domain = state
+            .verify_workspace_domain(1, domain.id as _, &records)
+            .await?;
+        assert!(domain.verified_at.is_some());
+        let ws = state.find_signup_workspace("a@ACME.org").await?.unwrap();
+        assert_eq!(ws.ws_id, 1);
+        assert!(ws.auto_join);
+
+        // other workspaces can't claim a verified domain
+        assert!(state
+            .add_workspace_domain(2, &input("acme.org", false))
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn domain_email_challenge_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        let domain = state
+            .add_workspace_domain(1, &input("acme.org", false))
+            .await?;
+        let id = domain.id as u64;
+        let ret = state
+            .start_domain_email_challenge(1, id, "admin@other.org", &user)
+            .await;
+        assert!(matches!(ret, Err(AppError::WorkspaceError(_))));
+
+        state
+            .start_domain_email_challenge(1, id, "Admin@acme.org", &user)
+            .await?;
+        let (code,): (String,) =
+            sqlx::query_as("SELECT challenge_code FROM workspace_domains WHERE id = $1")
+                .bind(domain.id)
+                .fetch_one(&state.pool)
+                .await?;
+        let (payload,): (serde_json::Value,) =
+            sqlx::query_as("SELECT payload FROM jobs WHERE kind = 'send_email'")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(payload["to"], "admin@acme.org");
+        assert!(payload["body"].as_str().unwrap().contains(&code));
+
+        let ret = state
+            .verify_workspace_domain_by_code(1, id, "00000000")
+            .await;
+        assert!(matches!(ret, Err(AppError::WorkspaceError(_))));
+        let domain = state.verify_workspace_domain_by_code(1, id, &code).await?;
+        assert!(domain.verified_at.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_workspace_domain_should_reject_invalid_domain() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for domain in ["acme", "-acme.org", "acme..org", "acme.org/x"] {
+            assert!(state
+                .add_workspace_domain(1, &input(domain, false))
+                .await
+                .is_err());
+        }
+        state
+            .add_workspace_domain(1, &input("acme.org", false))
+            .await?;
+        assert!(state
+            .add_workspace_domain(1, &input("acme.org", false))
+            .await
+            .is_err());
+        assert_eq!(state.list_workspace_domains(1).await?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+const MAX_DRAFT_LEN: usize = 10_000;
+const MAX_DEVICE_ID_LEN: usize = 64;
+
+/// The message a member is writing in a chat, shared by all their devices.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatDraft {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub content: String,
+    /// device which saved the draft last
+    pub device_id: String,
+    /// number of the save on that device
+    pub seq: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct SaveChatDraft {
+    /// an empty content clears the draft
+    pub content: String,
+    /// any id the device keeps, e.g. a random one made on first start
+    pub device_id: String,
+    /// increases with each save of the device, older saves arriving late are ignored
+    pub seq: i64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// The user's draft in the chat, None if they have none.
+    pub async fn get_chat_draft(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<Option<ChatDraft>, AppError> {
+        let draft = sqlx::query_as(
+            r#"
+            SELECT chat_id, content, device_id, seq, updated_at
+            FROM chat_drafts
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(draft)
+    }
+
+    /// Save the user's draft in the chat and return it. A save with a `seq` not above the
+    /// last one of its device leaves the draft as it is, even if another device saved
+    /// since. The user's other devices get a `DraftUpdated` event when it changed.
+    pub async fn save_chat_draft(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        input: &SaveChatDraft,
+    ) -> Result<ChatDraft, AppError> {
+        if input.content.chars().count() > MAX_DRAFT_LEN {
+            return Err(AppError::DraftError(format!(
+                "Draft must be at most {MAX_DRAFT_LEN} characters"
+            )));
+        }
+        let device_id = input.device_id.trim();
+        if device_id.is_empty() || device_id.len() > MAX_DEVICE_ID_LEN {
+            return Err(AppError::DraftError(format!(
+                "Device id must be 1 to {MAX_DEVICE_ID_LEN} characters"
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let newer: Option<(i64,)> = sqlx::query_as(
+            r#"
+            INSERT INTO chat_draft_devices (user_id, chat_id, device_id, seq)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, chat_id, device_id) DO UPDATE
+            SET seq = EXCLUDED.seq
+            WHERE chat_draft_devices.seq < EXCLUDED.seq
+            RETURNING seq
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(chat_id as i64)
+        .bind(device_id)
+        .bind(input.seq)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let draft = match newer {
+            Some(_) => {
+                let draft: ChatDraft = sqlx::query_as(
+                    r#"
+                    INSERT INTO chat_drafts (user_id, chat_id, content, device_id, seq)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (user_id, chat_id) DO UPDATE
+                    SET content = EXCLUDED.content, device_id = EXCLUDED.device_id,
+                        seq = EXCLUDED.seq, updated_at = NOW()
+                    RETURNING chat_id, content, device_id, seq, updated_at
+                    "#,
+                )
+                .bind(user_id as i64)
+                .bind(chat_id as i64)
+                .bind(&input.content)
+                .bind(device_id)
+                .bind(input.seq)
+                .fetch_one(&mut *tx)
+                .await?;
+                let payload = json!({ "user_id": user_id, "draft": draft });
+                sqlx::query("SELECT pg_notify('chat_draft_updated', $1)")
+                    .bind(payload.to_string())
+                    .execute(&mut *tx)
+                    .await?;
+                draft
+            }
+            None => {
+                sqlx::query_as(
+                    r#"
+                    SELECT chat_id, content, device_id, seq, updated_at
+                    FROM chat_drafts
+                    WHERE chat_id = $1 AND user_id = $2
+                    "#,
+                )
+                .bind(chat_id as i64)
+                .bind(user_id as i64)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+        tx.commit().await?;
+        Ok(draft)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use sqlx::postgres::PgListener;
+
+    fn save(content: &str, device_id: &str, seq: i64) -> SaveChatDraft {
+        SaveChatDraft {
+            content: content.to_string(),
+            device_id: device_id.to_string(),
+            seq,
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_draft_should_follow_device_seqs() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert!(state.get_chat_draft(1, 1).await?.is_none());
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_draft_updated").await?;
+
+        let draft = state
+            .save_chat_draft(1, 1, &save("Hel", "desktop", 1))
+            .await?;
+        assert_eq!(draft.content, "Hel");
+        let notif = listener.recv().await?;
+        let payload: serde_json::Value = serde_json::from_str(notif.payload())?;
+        assert_eq!(payload["user_id"], 1);
+        assert_eq!(payload["draft"]["content"], "Hel");
+
+        // a save of the desktop overtaken by a later one
+        let draft = state
+            .save_chat_draft(1, 1, &save("Hello", "desktop", 3))
+            .await?;
+        assert_eq!(draft.seq, 3);
+        let draft = state
+            .save_chat_draft(1, 1, &save("Hell", "desktop", 2))
+            .await?;
+        assert_eq!(draft.content, "Hello");
+
+        // the phone goes on with the draft, its own seqs start again
+        let draft = state
+            .save_chat_draft(1, 1, &save("Hello there", "phone", 1))
+            .await?;
+        assert_eq!(draft.device_id, "phone");
+        assert_eq!(state.get_chat_draft(1, 1).await?, Some(draft.clone()));
+        // a save of the desktop older than its seq 3, arriving after the phone's
+        let late = state
+            .save_chat_draft(1, 1, &save("Hell", "desktop", 2))
+            .await?;
+        assert_eq!(late, draft);
+        let draft = state
+            .save_chat_draft(1, 1, &save("Hello there!", "desktop", 4))
+            .await?;
+        assert_eq!(draft.content, "Hello there!");
+        // drafts are per user
+        assert!(state.get_chat_draft(1, 2).await?.is_none());
+
+        let ret = state.save_chat_draft(1, 1, &save("hi", " ", 4)).await;
+        assert!(matches!(ret, Err(AppError::DraftError(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,506 @@
+use crate::{AppError, AppState, ChatMember, ChatRole, Locale};
+use chat_core::{Chat, Message, User};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::types::Json;
+use std::fmt;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::{IntoParams, ToSchema};
+
+// lines of a streamed export buffered ahead of a slow client
+const EXPORT_BUFFER: usize = 64;
+
+// members can be held back 6 hours at most
+const MAX_SLOW_MODE_SECS: u32 = 60 * 60 * 6;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportPolicy {
+    /// the owner and admins of the chat
+    #[default]
+    ChatAdmins,
+    /// every member of the chat
+    Members,
+    /// only the owner of the workspace
+    WorkspaceOwner,
+    Disabled,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    pub policy: ExportPolicy,
+    /// stamp the exporting user and time into the exported file
+    pub watermark: bool,
+}
+
+/// Who can send messages to a chat, every member can read it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostPolicy {
+    #[default]
+    Everyone,
+    /// only the owner and admins, e.g. for announcement channels
+    AdminsOnly,
+}
+
+/// Per chat settings, only the workspace owner can change them.
+#[derive(Debug, Clone, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatSettings {
+    pub export: ExportSettings,
+    /// what the chat is about, shown under its name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    /// messages are end-to-end encrypted by the clients, server features which need their
+    /// plaintext are disabled. Can't be turned off once on.
+    pub encrypted: bool,
+    /// translate new messages into this language for members reading another one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translate_to: Option<Locale>,
+    /// seconds members other than the owner and admins wait between their messages, 0
+    /// turns slow mode off
+    pub slow_mode_seconds: u32,
+    pub post_policy: PostPolicy,
+}
+
+/// Server side features which need the plaintext of messages, disabled in encrypted chats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatFeature {
+    Search,
+    Unfurl,
+    Translation,
+}
+
+impl fmt::Display for ChatFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Search => "search",
+            Self::Unfurl => "link unfurling",
+            Self::Translation => "translation",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct Watermark {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub fullname: String,
+    pub email: String,
+    pub exported_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    /// watermark text repeated on every message, so excerpts stay attributable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct ChatExport {
+    pub chat: Chat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<Watermark>,
+    pub members: Vec<ChatMember>,
+    pub messages: Vec<ExportedMessage>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// a single json document
+    #[default]
+    Json,
+    /// one json record per line, streamed as the history is read
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ExportChat {
+    #[serde(default)]
+    #[param(inline)]
+    pub format: ExportFormat,
+}
+
+/// A line of an ndjson export: the chat, then its watermark if any, its members and its
+/// messages oldest first.
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+pub enum ExportRecord {
+    Chat(Chat),
+    Watermark(Watermark),
+    Member(ChatMember),
+    Message(ExportedMessage),
+}
+
+impl Watermark {
+    pub fn text(&self) -> String {
+        format!(
+            "Exported by {} <{}> at {}",
+            self.fullname,
+            self.email,
+            self.exported_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn get_chat_settings(&self, chat_id: u64) -> Result<ChatSettings, AppError> {
+        let settings: Option<(Json<ChatSettings>,)> =
+            sqlx::query_as("SELECT settings FROM chats WHERE id = $1")
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        match settings {
+            Some((settings,)) => Ok(settings.0),
+            None => Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        }
+    }
+
+    pub async fn update_chat_settings(
+        &self,
+        chat_id: u64,
+        settings: &ChatSettings,
+    ) -> Result<ChatSettings, AppError> {
+        // members may hold messages only they can read, decrypting the chat isn't possible
+        if !settings.encrypted && self.get_chat_settings(chat_id).await?.encrypted {
+            return Err(AppError::PermissionDenied(
+                "encryption can't be turned off for a chat".to_string(),
+            ));
+        }
+        if settings.encrypted && settings.translate_to.is_some() {
+            return Err(AppError::PermissionDenied(format!(
+                "{} is disabled in encrypted chats",
+                ChatFeature::Translation
+            )));
+        }
+        if settings.slow_mode_seconds > MAX_SLOW_MODE_SECS {
+            return Err(AppError::ChatDTOError(format!(
+                "Slow mode can be at most {MAX_SLOW_MODE_SECS} seconds"
+            )));
+        }
+        let ret: Option<(Json<ChatSettings>,)> =
+            sqlx::query_as("UPDATE chats SET settings = $1 WHERE id = $2 RETURNING settings")
+                .bind(Json(settings))
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        let settings = match ret {
+            Some((settings,)) => settings.0,
+            None => return Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        };
+        // the search index holds the plaintext
+        if settings.encrypted {
+            sqlx::query("UPDATE messages SET search_vector = NULL WHERE chat_id = $1")
+                .bind(chat_id as i64)
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(settings)
+    }
+
+    /// Fail if `feature` can't be used in the chat. Every server feature reading the content
+    /// of messages must go through it, so encrypted chats never hand it plaintext.
+    pub async fn ensure_chat_feature(
+        &self,
+        chat_id: u64,
+        feature: ChatFeature,
+    ) -> Result<(), AppError> {
+        if self.get_chat_settings(chat_id).await?.encrypted {
+            return Err(AppError::PermissionDenied(format!(
+                "{feature} is disabled in encrypted chats"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Export the full history of a chat if the chat's export policy allows `user` to.
+    /// Every attempt is recorded in the audit log, including rejected ones.
+    pub async fn export_chat(&self, chat_id: u64, user: &User) -> Result<ChatExport, AppError> {
+        let (chat, watermark) = self.authorize_chat_export(chat_id, user).await?;
+        let members = self.list_chat_members(chat_id).await?;
+        let messages: Vec<Message> = sqlx::query_as(EXPORT_MESSAGES)
+            .bind(chat_id as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let text = watermark.as_ref().map(|w| w.text());
+        let messages = messages
+            .into_iter()
+            .map(|message| ExportedMessage {
+                message,
+                watermark: text.clone(),
+            })
+            .collect();
+
+        Ok(ChatExport {
+            chat,
+            watermark,
+            members,
+            messages,
+        })
+    }
+
+    /// Like `export_chat` but as ndjson lines, the messages are read while the lines are
+    /// sent so the history doesn't have to fit in memory. A failure after the first line
+    /// ends the stream with the error.
+    pub async fn stream_chat_export(
+        &self,
+        chat_id: u64,
+        user: &User,
+    ) -> Result<ReceiverStream<Result<String, AppError>>, AppError> {
+        let (chat, watermark) = self.authorize_chat_export(chat_id, user).await?;
+        let members = self.list_chat_members(chat_id).await?;
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER);
+        let state = self.clone();
+        tokio::spawn(async move {
+            let text = watermark.as_ref().map(|w| w.text());
+            let mut records = vec![ExportRecord::Chat(chat)];
+            records.extend(watermark.map(ExportRecord::Watermark));
+            records.extend(members.into_iter().map(ExportRecord::Member));
+            for record in records {
+                if tx.send(Ok(record.to_line())).await.is_err() {
+                    return;
+                }
+            }
+
+            let mut messages = sqlx::query_as::<_, Message>(EXPORT_MESSAGES)
+                .bind(chat_id as i64)
+                .fetch(&state.pool);
+            while let Some(message) = messages.next().await {
+                let line = message.map_err(AppError::from).map(|message| {
+                    ExportRecord::Message(ExportedMessage {
+                        message,
+                        watermark: text.clone(),
+                    })
+                    .to_line()
+                });
+                let failed = line.is_err();
+                if tx.send(line).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    // the chat and the watermark of its export if the policy of the chat allows `user`
+    // to export it, recording the attempt
+    async fn authorize_chat_export(
+        &self,
+        chat_id: u64,
+        user: &User,
+    ) -> Result<(Chat, Option<Watermark>), AppError> {
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        let settings = self.get_chat_settings(chat_id).await?.export;
+        let allowed = match settings.policy {
+            ExportPolicy::ChatAdmins => matches!(
+                self.get_chat_role(chat_id, user.id as _).await?,
+                Some(ChatRole::Owner | ChatRole::Admin)
+            ),
+            ExportPolicy::Members => true,
+            ExportPolicy::WorkspaceOwner => self
+                .find_workspace_by_id(chat.ws_id as _)
+                .await?
+                .is_some_and(|ws| ws.owner_id == user.id),
+            ExportPolicy::Disabled => false,
+        };
+
+        let details = json!({
+            "allowed": allowed,
+            "policy": settings.policy,
+            "watermark": settings.watermark,
+        });
+        self.record_audit(
+            chat.ws_id as _,
+            user.id as _,
+            "chat.export",
+            Some(chat_id),
+            details,
+        )
+        .await?;
+        if !allowed {
+            return Err(AppError::PermissionDenied(format!(
+                "exporting chat {chat_id} is not allowed"
+            )));
+        }
+
+        let watermark = settings.watermark.then(|| Watermark {
+            user_id: user.id,
+            fullname: user.fullname.clone(),
+            email: user.email.clone(),
+            exported_at: Utc::now(),
+        });
+        Ok((chat, watermark))
+    }
+}
+
+const EXPORT_MESSAGES: &str = r#"
+    SELECT id, chat_id, sender_id, content, files, created_at
+    FROM messages
+    WHERE chat_id = $1
+    ORDER BY id
+"#;
+
+impl ExportRecord {
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).expect("export records serialize");
+        line.push('\n');
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListAuditLogs;
+    use anyhow::Result;
+
+    fn settings(policy: ExportPolicy, watermark: bool) -> ChatSettings {
+        ChatSettings {
+            export: ExportSettings { policy, watermark },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn export_chat_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        assert_eq!(state.get_chat_settings(1).await?, ChatSettings::default());
+
+        let export = state.export_chat(1, &user).await?;
+        assert_eq!(export.chat.id, 1);
+        assert_eq!(export.messages.len(), 10);
+        assert!(export.watermark.is_none());
+        assert!(export.messages[0].message.id < export.messages[1].message.id);
+
+        state
+            .update_chat_settings(1, &settings(ExportPolicy::Members, true))
+            .await?;
+        let export = state.export_chat(1, &user).await?;
+        let watermark = export.watermark.unwrap();
+        assert_eq!(watermark.email, "tchen@acme.org");
+        assert_eq!(export.messages[0].watermark, Some(watermark.text()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_chat_should_be_limited_to_chat_admins() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(2).await?.unwrap();
+        let ret = state.export_chat(1, &user).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state.update_chat_member_role(1, 2, ChatRole::Admin).await?;
+        let export = state.export_chat(1, &user).await?;
+        let members: Vec<_> = export.members.iter().map(|m| m.user_id).collect();
+        assert_eq!(members, [1, 2, 3, 4, 5]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_chat_export_should_write_ndjson() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(1).await?.unwrap();
+        state
+            .update_chat_settings(1, &settings(ExportPolicy::ChatAdmins, true))
+            .await?;
+        let lines: Vec<_> = state.stream_chat_export(1, &user).await?.collect().await;
+        let records = lines
+            .into_iter()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<Result<Vec<ExportRecord>>>()?;
+        assert_eq!(records.len(), 1 + 1 + 5 + 10);
+        assert!(matches!(&records[0], ExportRecord::Chat(chat) if chat.id == 1));
+        let ExportRecord::Watermark(watermark) = &records[1] else {
+            panic!("expected the watermark, got {:?}", records[1]);
+        };
+        assert!(matches!(&records[2], ExportRecord::Member(m) if m.user_id == 1));
+        let ExportRecord::Message(message) = &records[16] else {
+            panic!("expected a message, got {:?}", records[16]);
+        };
+        assert_eq!(message.watermark, Some(watermark.text()));
+
+        let user = state.find_user_by_id(2).await?.unwrap();
+        assert!(state.stream_chat_export(1, &user).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_chat_should_respect_policy_and_be_audited() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(2).await?.unwrap();
+        state
+            .update_chat_settings(1, &settings(ExportPolicy::WorkspaceOwner, false))
+            .await?;
+        let ret = state.export_chat(1, &user).await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+
+        state.update_workspace_owner(1, 2).await?;
+        assert!(state.export_chat(1, &user).await.is_ok());
+
+        state
+            .update_chat_settings(1, &settings(ExportPolicy::Disabled, false))
+            .await?;
+        assert!(state.export_chat(1, &user).await.is_err());
+
+        let input = ListAuditLogs {
+            last_id: None,
+            limit: 10,
+            action: Some("chat.export".to_string()),
+        };
+        let logs = state.list_audit_logs(1, &input).await?;
+        let allowed: Vec<_> = logs
+            .iter()
+            .map(|l| l.details["allowed"].as_bool())
+            .collect();
+        // newest first
+        assert_eq!(allowed, vec![Some(false), Some(true), Some(false)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn encrypted_chat_should_disable_plaintext_features() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.ensure_chat_feature(1, ChatFeature::Search).await?;
+
+        let encrypted = ChatSettings {
+            encrypted: true,
+            ..Default::default()
+        };
+        state.update_chat_settings(1, &encrypted).await?;
+        for feature in [
+            ChatFeature::Search,
+            ChatFeature::Unfurl,
+            ChatFeature::Translation,
+        ] {
+            let ret = state.ensure_chat_feature(1, feature).await;
+            assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        }
+        state.ensure_chat_feature(2, ChatFeature::Search).await?;
+
+        let ret = state
+            .update_chat_settings(1, &ChatSettings::default())
+            .await;
+        assert!(matches!(ret, Err(AppError::PermissionDenied(_))));
+        let topic = ChatSettings {
+            topic: Some("secret".to_string()),
+            ..encrypted
+        };
+        state.update_chat_settings(1, &topic).await?;
+        Ok(())
+    }
+}
@@ -3,15 +3,25 @@ use std::{
     str::FromStr,
 };
 
-use crate::{AppError, ChatFile};
+use crate::{AppError, AppState, ChatFile, QuotaResource};
 use sha1::{Digest, Sha1};
+use tokio::fs;
+use tracing::info;
+
+// (magic bytes, extension) of the images accepted as avatars
+const IMAGE_TYPES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+];
 
 impl ChatFile {
     pub fn new(ws_id: u64, filename: &str, data: &[u8]) -> Self {
         let hash = Sha1::digest(data);
         Self {
             ws_id,
-            ext: filename.split('.').last().unwrap_or("txt").to_string(),
+            ext: filename.split('.').next_back().unwrap_or("txt").to_string(),
             hash: hex::encode(hash),
         }
     }
@@ -32,6 +42,41 @@ impl ChatFile {
     }
 }
 
+/// Extension of the image in `data`, None if it isn't a png, jpeg, gif or webp image.
+pub(crate) fn image_ext(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+    IMAGE_TYPES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, ext)| *ext)
+}
+
+impl AppState {
+    /// Store a file of the workspace, files are content addressed so an existing one is
+    /// reused without counting against the storage quota again.
+    pub async fn save_chat_file(
+        &self,
+        ws_id: u64,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<ChatFile, AppError> {
+        let file = ChatFile::new(ws_id, filename, data);
+        let path = file.path(&self.config.server.base_dir);
+        if path.exists() {
+            info!("File {} already exists: {:?}", filename, path);
+        } else {
+            self.check_quota(ws_id, QuotaResource::Storage, data.len() as _)
+                .await?;
+            fs::create_dir_all(path.parent().expect("file path parent should exists")).await?;
+            fs::write(path, data).await?;
+            self.add_storage_usage(ws_id, data.len() as _).await?;
+        }
+        Ok(file)
+    }
+}
+
 impl FromStr for ChatFile {
     type Err = AppError;
 
@@ -86,4 +131,14 @@ mod tests {
         assert_eq!(file.ext, "txt");
         assert_eq!(file.hash, "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
     }
+
+    #[test]
+    fn image_ext_should_detect_images() {
+        assert_eq!(image_ext(b"\x89PNG\r\n\x1a\n0000"), Some("png"));
+        assert_eq!(image_ext(b"\xff\xd8\xff\xe0"), Some("jpg"));
+        assert_eq!(image_ext(b"GIF89a"), Some("gif"));
+        assert_eq!(image_ext(b"RIFF0000WEBPVP8 "), Some("webp"));
+        assert_eq!(image_ext(b"<svg></svg>"), None);
+        assert_eq!(image_ext(b""), None);
+    }
 }
@@ -0,0 +1,336 @@
+use crate::{notify_members_changed, AppError, AppState, USER_JOINED_EVENT};
+use chat_core::{ChatType, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+// guest links can't outlive 30 days
+const MAX_GUEST_LINK_SECS: u64 = 60 * 60 * 24 * 30;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "guest_access", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum GuestAccess {
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct GuestLink {
+    pub id: i64,
+    /// secret part of the link, redeemed with `POST /api/guest-links/{token}/redeem`
+    pub token: String,
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    pub access: GuestAccess,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateGuestLink {
+    #[serde(default)]
+    pub access: GuestAccess,
+    /// how long the link and the guest accounts created from it stay valid
+    pub expires_in_secs: u64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct RedeemGuestLink {
+    /// name shown to the other members of the chat
+    pub fullname: String,
+}
+
+/// A guest account only has access to a single chat until it expires.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Guest {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub link_id: Option<i64>,
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub access: GuestAccess,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Guest {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_guest_link(
+        &self,
+        chat_id: u64,
+        input: &CreateGuestLink,
+        user_id: u64,
+    ) -> Result<GuestLink, AppError> {
+        if !(60..=MAX_GUEST_LINK_SECS).contains(&input.expires_in_secs) {
+            return Err(AppError::GuestError(format!(
+                "Link must expire in 60 to {MAX_GUEST_LINK_SECS} seconds"
+            )));
+        }
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if !matches!(
+            chat.r#type,
+            ChatType::PublicChannel | ChatType::PrivateChannel
+        ) {
+            return Err(AppError::GuestError(
+                "Guest links can only be created for channels".to_string(),
+            ));
+        }
+
+        let link = sqlx::query_as(
+            r#"
+            INSERT INTO guest_links (chat_id, created_by, access, expires_at)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+            RETURNING id, token, chat_id, created_by, access, expires_at, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.access)
+        .bind(input.expires_in_secs as f64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    pub async fn list_guest_links(&self, chat_id: u64) -> Result<Vec<GuestLink>, AppError> {
+        let links = sqlx::query_as(
+            r#"
+            SELECT id, token, chat_id, created_by, access, expires_at, created_at
+            FROM guest_links
+            WHERE chat_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(links)
+    }
+
+    /// Expire a link right away, guests created from it lose their access as well.
+    pub async fn revoke_guest_link(
+        &self,
+        chat_id: u64,
+        link_id: u64,
+    ) -> Result<Option<GuestLink>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let link: Option<GuestLink> = sqlx::query_as(
+            r#"
+            UPDATE guest_links SET expires_at = LEAST(expires_at, NOW())
+            WHERE id = $1 AND chat_id = $2
+            RETURNING id, token, chat_id, created_by, access, expires_at, created_at
+            "#,
+        )
+        .bind(link_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if link.is_some() {
+            sqlx::query(
+                "UPDATE guests SET expires_at = LEAST(expires_at, NOW()) WHERE link_id = $1",
+            )
+            .bind(link_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(link)
+    }
+
+    /// Create a guest account from a link and add it to the link's chat.
+    pub async fn redeem_guest_link(
+        &self,
+        token: &str,
+        input: &RedeemGuestLink,
+    ) -> Result<(User, Guest), AppError> {
+        let fullname = input.fullname.trim();
+        if fullname.is_empty() || fullname.chars().count() > 64 {
+            return Err(AppError::GuestError(
+                "Name must be between 1 and 64 characters".to_string(),
+            ));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let link: Option<GuestLinkRow> = sqlx::query_as(
+            r#"
+            SELECT l.id, l.token, l.chat_id, l.created_by, l.access, l.expires_at, l.created_at,
+                c.ws_id
+            FROM guest_links l
+            JOIN chats c ON c.id = l.chat_id
+            WHERE l.token = $1
+            "#,
+        )
+        .bind(token)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(GuestLinkRow { link, ws_id }) = link else {
+            return Err(AppError::NotFound(format!("guest link {token}")));
+        };
+        if link.expires_at <= Utc::now() {
+            return Err(AppError::GuestError("Link has expired".to_string()));
+        }
+        let chat = self
+            .get_chat_by_id(link.chat_id as _)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("guest link {token}")))?;
+        self.verify_member_limit(&chat.r#type, chat.members.len() + 1)?;
+
+        // guests have no password and can't sign in, the returned token is their only credential
+        let user: User = sqlx::query_as(
+            r#"
+            INSERT INTO users (ws_id, email, fullname, password_hash)
+            VALUES ($1, 'guest-' || replace(gen_random_uuid()::text, '-', '') || '@guest.invalid', $2, '')
+            RETURNING id, ws_id, fullname, email, created_at
+            "#,
+        )
+        .bind(ws_id)
+        .bind(fullname)
+        .fetch_one(&mut *tx)
+        .await?;
+        let guest = sqlx::query_as(
+            r#"
+            INSERT INTO guests (user_id, link_id, chat_id, access, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING user_id, link_id, chat_id, access, expires_at
+            "#,
+        )
+        .bind(user.id)
+        .bind(link.id)
+        .bind(link.chat_id)
+        .bind(link.access)
+        .bind(link.expires_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query("INSERT INTO chat_members (chat_id, user_id, invited_by) VALUES ($1, $2, $3)")
+            .bind(link.chat_id)
+            .bind(user.id)
+            .bind(link.created_by)
+            .execute(&mut *tx)
+            .await?;
+        notify_members_changed(
+            &mut *tx,
+            link.chat_id as _,
+            &[user.id],
+            &[],
+            link.created_by as _,
+        )
+        .await?;
+        tx.commit().await?;
+
+        let extra = json!({ "role": "guest", "chat_id": link.chat_id });
+        self.dispatch_workspace_webhooks(ws_id as _, USER_JOINED_EVENT, &user, extra)
+            .await?;
+        Ok((user, guest))
+    }
+
+    pub async fn find_guest(&self, user_id: u64) -> Result<Option<Guest>, AppError> {
+        let guest = sqlx::query_as(
+            r#"
+            SELECT user_id, link_id, chat_id, access, expires_at
+            FROM guests
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(guest)
+    }
+}
+
+#[derive(FromRow)]
+struct GuestLinkRow {
+    #[sqlx(flatten)]
+    link: GuestLink,
+    ws_id: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn link_input(access: GuestAccess) -> CreateGuestLink {
+        CreateGuestLink {
+            access,
+            expires_in_secs: 3600,
+        }
+    }
+
+    #[tokio::test]
+    async fn redeem_guest_link_should_add_guest_to_chat() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let link = state
+            .create_guest_link(1, &link_input(GuestAccess::ReadWrite), 1)
+            .await?;
+        assert_eq!(link.token.len(), 32);
+
+        let input = RedeemGuestLink {
+            fullname: "Customer".to_string(),
+        };
+        let (user, guest) = state.redeem_guest_link(&link.token, &input).await?;
+        assert_eq!(user.ws_id, 1);
+        assert_eq!(guest.chat_id, 1);
+        assert_eq!(guest.access, GuestAccess::ReadWrite);
+        assert!(!guest.is_expired());
+        assert!(state.is_chat_member(1, user.id as _).await?);
+        assert_eq!(state.find_guest(user.id as _).await?, Some(guest));
+        assert!(state.find_guest(1).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn guest_link_should_only_be_created_for_channels() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ret = state
+            .create_guest_link(3, &link_input(GuestAccess::ReadOnly), 1)
+            .await;
+        assert!(matches!(ret, Err(AppError::GuestError(_))));
+
+        let input = CreateGuestLink {
+            access: GuestAccess::ReadOnly,
+            expires_in_secs: MAX_GUEST_LINK_SECS + 1,
+        };
+        assert!(state.create_guest_link(1, &input, 1).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_guest_link_should_expire_guests() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let link = state
+            .create_guest_link(2, &link_input(GuestAccess::ReadOnly), 1)
+            .await?;
+        let input = RedeemGuestLink {
+            fullname: "Customer".to_string(),
+        };
+        let (user, _) = state.redeem_guest_link(&link.token, &input).await?;
+
+        state.revoke_guest_link(2, link.id as _).await?.unwrap();
+        let guest = state.find_guest(user.id as _).await?.unwrap();
+        assert!(guest.is_expired());
+
+        let ret = state.redeem_guest_link(&link.token, &input).await;
+        assert!(matches!(ret, Err(AppError::GuestError(_))));
+        assert_eq!(state.list_guest_links(2).await?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{AppError, AppState};
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "message_change_op", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MessageChangeOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ChatHistoryQuery {
+    /// reconstruct the chat as it was at this time
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatSnapshot {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub at: DateTime<Utc>,
+    /// messages which existed at `at` with their content at that time, newest first
+    pub messages: Vec<Message>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Rebuild the messages of a chat as of `at` from the message change log, so edited
+    /// and deleted messages show what they were at the time.
+    pub async fn get_chat_snapshot(
+        &self,
+        chat_id: u64,
+        at: DateTime<Utc>,
+    ) -> Result<ChatSnapshot, AppError> {
+        let messages = sqlx::query_as(
+            r#"
+            SELECT message_id AS id, chat_id, sender_id, content, files, message_created_at AS created_at
+            FROM (
+                SELECT DISTINCT ON (message_id) *
+                FROM message_changes
+                WHERE chat_id = $1 AND changed_at <= $2
+                ORDER BY message_id, id DESC
+            ) latest
+            WHERE op <> 'delete'
+            ORDER BY message_id DESC
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(at)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ChatSnapshot {
+            chat_id: chat_id as _,
+            at,
+            messages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateMessage;
+    use anyhow::Result;
+
+    async fn now(state: &AppState) -> Result<DateTime<Utc>> {
+        let (now,): (DateTime<Utc>,) = sqlx::query_as("SELECT clock_timestamp()")
+            .fetch_one(&state.pool)
+            .await?;
+        Ok(now)
+    }
+
+    #[tokio::test]
+    async fn chat_snapshot_should_show_past_versions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let before = now(&state).await?;
+        let input = CreateMessage {
+            content: "original".to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input, 2, 1).await?;
+        let created = now(&state).await?;
+
+        sqlx::query("UPDATE messages SET content = 'edited' WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pool)
+            .await?;
+        let edited = now(&state).await?;
+        sqlx::query("DELETE FROM messages WHERE id = $1")
+            .bind(message.id)
+            .execute(&state.pool)
+            .await?;
+
+        assert!(state
+            .get_chat_snapshot(2, before)
+            .await?
+            .messages
+            .is_empty());
+        let snapshot = state.get_chat_snapshot(2, created).await?;
+        assert_eq!(snapshot.messages, [message]);
+        let snapshot = state.get_chat_snapshot(2, edited).await?;
+        assert_eq!(snapshot.messages[0].content, "edited");
+        assert!(state
+            .get_chat_snapshot(2, now(&state).await?)
+            .await?
+            .messages
+            .is_empty());
+
+        // the log can't be rewritten
+        let ret = sqlx::query("DELETE FROM message_changes")
+            .execute(&state.pool)
+            .await;
+        assert!(ret.is_err());
+        Ok(())
+    }
+}
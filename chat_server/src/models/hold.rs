@@ -0,0 +1,198 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct LegalHold {
+    pub id: i64,
+    pub ws_id: i64,
+    /// the held user, None if the whole workspace is held
+    #[serde(with = "chat_core::id::option")]
+    pub user_id: Option<i64>,
+    pub reason: String,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateLegalHold {
+    /// hold a single user instead of the whole workspace
+    #[serde(default, with = "chat_core::id::option")]
+    pub user_id: Option<i64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Place a legal hold and record it in the audit log.
+    pub async fn create_legal_hold(
+        &self,
+        ws_id: u64,
+        input: &CreateLegalHold,
+        actor_id: u64,
+    ) -> Result<LegalHold, AppError> {
+        if let Some(user_id) = input.user_id {
+            match self.find_user_by_id(user_id as _).await? {
+                Some(user) if user.ws_id == ws_id as i64 => {}
+                _ => return Err(AppError::NotFound(format!("user id {user_id}"))),
+            }
+        }
+        let ret = sqlx::query_as(
+            r#"
+            INSERT INTO legal_holds (ws_id, user_id, reason, created_by)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, ws_id, user_id, reason, created_by, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(input.user_id)
+        .bind(input.reason.trim())
+        .bind(actor_id as i64)
+        .fetch_one(&self.pool)
+        .await;
+        let hold: LegalHold = match ret {
+            Ok(hold) => hold,
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Err(AppError::WorkspaceError(
+                    "Legal hold already exists".to_string(),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let details = json!({ "user_id": hold.user_id, "reason": hold.reason });
+        self.record_audit(
+            ws_id,
+            actor_id,
+            "legal_hold.create",
+            Some(hold.id as _),
+            details,
+        )
+        .await?;
+
+        Ok(hold)
+    }
+
+    pub async fn list_legal_holds(&self, ws_id: u64) -> Result<Vec<LegalHold>, AppError> {
+        let holds = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, user_id, reason, created_by, created_at
+            FROM legal_holds
+            WHERE ws_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(holds)
+    }
+
+    /// Lift a legal hold and record it in the audit log, returns false if there is no
+    /// such hold.
+    pub async fn release_legal_hold(
+        &self,
+        ws_id: u64,
+        id: u64,
+        actor_id: u64,
+    ) -> Result<bool, AppError> {
+        let hold: Option<LegalHold> = sqlx::query_as(
+            r#"
+            DELETE FROM legal_holds
+            WHERE id = $1 AND ws_id = $2
+            RETURNING id, ws_id, user_id, reason, created_by, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(hold) = hold else {
+            return Ok(false);
+        };
+        let details = json!({ "user_id": hold.user_id, "reason": hold.reason });
+        self.record_audit(ws_id, actor_id, "legal_hold.release", Some(id), details)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Whether a chat is held, by a hold of its workspace or of a user who is a member or
+    /// sent messages in it.
+    pub async fn is_chat_on_hold(&self, chat_id: u64) -> Result<bool, AppError> {
+        let (held,): (bool,) = sqlx::query_as(
+            r#"
+            SELECT EXISTS(
+              SELECT 1
+              FROM legal_holds h JOIN chats c ON c.ws_id = h.ws_id
+              WHERE c.id = $1 AND (
+                h.user_id IS NULL
+                OR h.user_id IN (SELECT user_id FROM chat_members WHERE chat_id = $1)
+                OR EXISTS (SELECT 1 FROM messages WHERE chat_id = $1 AND sender_id = h.user_id)
+              )
+            )
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ListAuditLogs;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn legal_hold_should_block_chat_deletion() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateLegalHold {
+            user_id: Some(4),
+            reason: "case 42".to_string(),
+        };
+        let hold = state.create_legal_hold(1, &input, 1).await?;
+        assert_eq!(hold.user_id, Some(4));
+        assert!(state.create_legal_hold(1, &input, 1).await.is_err());
+
+        // user 4 is in chat 1 and 4, not in 2 and 3
+        assert!(state.is_chat_on_hold(1).await?);
+        assert!(state.is_chat_on_hold(4).await?);
+        assert!(!state.is_chat_on_hold(2).await?);
+        let err = state.delete_chat(1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PermissionDenied(_)));
+        assert_eq!(state.delete_chat(2, 1).await?, Some(2));
+
+        let logs = state
+            .list_audit_logs(
+                1,
+                &ListAuditLogs {
+                    last_id: None,
+                    limit: 10,
+                    action: Some("legal_hold.create".to_string()),
+                },
+            )
+            .await?;
+        assert_eq!(logs[0].target_id, Some(hold.id));
+
+        assert!(state.release_legal_hold(1, hold.id as _, 1).await?);
+        assert!(!state.release_legal_hold(1, hold.id as _, 1).await?);
+        assert_eq!(state.delete_chat(1, 1).await?, Some(1));
+
+        // a workspace hold covers every chat
+        state
+            .create_legal_hold(1, &CreateLegalHold::default(), 1)
+            .await?;
+        assert!(state.is_chat_on_hold(3).await?);
+        assert_eq!(state.list_legal_holds(1).await?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,565 @@
+use crate::{notify_members_changed, AppError, AppState};
+use chat_core::{Chat, ChatType, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{FromRow, PgExecutor};
+use utoipa::ToSchema;
+
+// invites can't outlive 30 days
+const MAX_INVITE_SECS: u64 = 60 * 60 * 24 * 30;
+const MAX_JOIN_REQUEST_MESSAGE_LEN: usize = 500;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatInvite {
+    pub id: i64,
+    /// secret part of the link, accepted with `POST /api/invites/{token}/accept`
+    pub token: String,
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    /// None for no limit
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateChatInvite {
+    /// 60 seconds to 30 days, a week by default
+    #[serde(default = "default_expires_in_secs")]
+    pub expires_in_secs: u64,
+    /// members who can join with the invite, no limit if not given
+    pub max_uses: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "join_request_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JoinRequestStatus {
+    Pending,
+    Approved,
+    Denied,
+}
+
+/// A user asking to be added to a private channel, decided on by its owner and admins.
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct JoinRequest {
+    pub id: i64,
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub message: Option<String>,
+    pub status: JoinRequestStatus,
+    /// the owner or admin who approved or denied the request
+    #[serde(with = "chat_core::id::option")]
+    pub decided_by: Option<i64>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateJoinRequest {
+    /// why the user wants to join, up to 500 characters
+    pub message: Option<String>,
+}
+
+fn default_expires_in_secs() -> u64 {
+    60 * 60 * 24 * 7
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_chat_invite(
+        &self,
+        chat_id: u64,
+        input: &CreateChatInvite,
+        user_id: u64,
+    ) -> Result<ChatInvite, AppError> {
+        if !(60..=MAX_INVITE_SECS).contains(&input.expires_in_secs) {
+            return Err(AppError::InviteError(format!(
+                "Invite must expire in 60 to {MAX_INVITE_SECS} seconds"
+            )));
+        }
+        if input.max_uses.is_some_and(|n| n < 1) {
+            return Err(AppError::InviteError(
+                "Max uses must be at least 1".to_string(),
+            ));
+        }
+        let chat = self
+            .get_chat_by_id(chat_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+        if chat.r#type == ChatType::Single {
+            return Err(AppError::InviteError(
+                "Single chats can't have invites".to_string(),
+            ));
+        }
+
+        let invite = sqlx::query_as(
+            r#"
+            INSERT INTO chat_invites (chat_id, created_by, max_uses, expires_at)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4))
+            RETURNING id, token, chat_id, created_by, max_uses, uses, expires_at, revoked_at,
+                created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .bind(input.max_uses)
+        .bind(input.expires_in_secs as f64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(invite)
+    }
+
+    pub async fn list_chat_invites(&self, chat_id: u64) -> Result<Vec<ChatInvite>, AppError> {
+        let invites = sqlx::query_as(
+            r#"
+            SELECT id, token, chat_id, created_by, max_uses, uses, expires_at, revoked_at,
+                created_at
+            FROM chat_invites
+            WHERE chat_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(invites)
+    }
+
+    /// Revoke an invite, members who joined with it stay in the chat.
+    pub async fn revoke_chat_invite(
+        &self,
+        chat_id: u64,
+        invite_id: u64,
+    ) -> Result<Option<ChatInvite>, AppError> {
+        let invite = sqlx::query_as(
+            r#"
+            UPDATE chat_invites SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1 AND chat_id = $2
+            RETURNING id, token, chat_id, created_by, max_uses, uses, expires_at, revoked_at,
+                created_at
+            "#,
+        )
+        .bind(invite_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(invite)
+    }
+
+    /// Add `user` to the chat of the invite. Members of the chat don't use the invite up.
+    pub async fn accept_chat_invite(&self, token: &str, user: &User) -> Result<Chat, AppError> {
+        let not_found = || AppError::NotFound(format!("invite {token}"));
+        let mut tx = self.pool.begin().await?;
+        let invite: Option<InviteRow> = sqlx::query_as(
+            r#"
+            SELECT i.id, i.token, i.chat_id, i.created_by, i.max_uses, i.uses, i.expires_at,
+                i.revoked_at, i.created_at, c.ws_id
+            FROM chat_invites i
+            JOIN chats c ON c.id = i.chat_id AND c.deleted_at IS NULL
+            WHERE i.token = $1
+            FOR UPDATE OF i
+            "#,
+        )
+        .bind(token)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let InviteRow { invite, ws_id } = invite.ok_or_else(not_found)?;
+        if ws_id != user.ws_id {
+            return Err(not_found());
+        }
+        if invite.revoked_at.is_some() {
+            return Err(AppError::InviteError("Invite has been revoked".to_string()));
+        }
+        if invite.expires_at <= Utc::now() {
+            return Err(AppError::InviteError("Invite has expired".to_string()));
+        }
+        let chat = self
+            .get_chat_by_id(invite.chat_id as _)
+            .await?
+            .ok_or_else(not_found)?;
+        if !chat.members.contains(&user.id) {
+            self.verify_not_banned(chat.id as _, user.id as _).await?;
+            self.verify_member_limit(&chat.r#type, chat.members.len() + 1)?;
+        }
+
+        let added: Option<i64> = sqlx::query_scalar(
+            r#"
+            INSERT INTO chat_members (chat_id, user_id, invited_by)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            RETURNING user_id
+            "#,
+        )
+        .bind(invite.chat_id)
+        .bind(user.id)
+        .bind(invite.created_by)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some(added) = added {
+            if invite.max_uses.is_some_and(|n| invite.uses >= n) {
+                return Err(AppError::InviteError("Invite has been used up".to_string()));
+            }
+            sqlx::query("UPDATE chat_invites SET uses = uses + 1 WHERE id = $1")
+                .bind(invite.id)
+                .execute(&mut *tx)
+                .await?;
+            notify_members_changed(
+                &mut *tx,
+                invite.chat_id as _,
+                &[added],
+                &[],
+                invite.created_by as _,
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        self.get_chat_by_id(invite.chat_id as _)
+            .await?
+            .ok_or_else(not_found)
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Ask to be added to a private channel of the user's workspace. Asking again while a
+    /// request is pending returns that request.
+    pub async fn request_to_join_chat(
+        &self,
+        chat_id: u64,
+        input: &CreateJoinRequest,
+        user: &User,
+    ) -> Result<JoinRequest, AppError> {
+        let chat = match self.get_chat_by_id(chat_id).await? {
+            Some(chat) if chat.ws_id == user.ws_id => chat,
+            _ => return Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        };
+        if chat.r#type != ChatType::PrivateChannel {
+            return Err(AppError::JoinRequestError(
+                "Only private channels take join requests".to_string(),
+            ));
+        }
+        if chat.members.contains(&user.id) {
+            return Err(AppError::JoinRequestError(
+                "User is already a member of the chat".to_string(),
+            ));
+        }
+        self.verify_not_banned(chat_id, user.id as _).await?;
+        let message = input
+            .message
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty());
+        if message.is_some_and(|m| m.chars().count() > MAX_JOIN_REQUEST_MESSAGE_LEN) {
+            return Err(AppError::JoinRequestError(format!(
+                "Message must be at most {MAX_JOIN_REQUEST_MESSAGE_LEN} characters"
+            )));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let created: Option<JoinRequest> = sqlx::query_as(
+            r#"
+            INSERT INTO chat_join_requests (chat_id, user_id, message)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (chat_id, user_id) WHERE status = 'pending' DO NOTHING
+            RETURNING id, chat_id, user_id, message, status, decided_by, decided_at, created_at
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user.id)
+        .bind(message)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let request = match created {
+            Some(request) => {
+                notify_join_request(&mut *tx, &request).await?;
+                request
+            }
+            None => {
+                sqlx::query_as(
+                    r#"
+                SELECT id, chat_id, user_id, message, status, decided_by, decided_at, created_at
+                FROM chat_join_requests
+                WHERE chat_id = $1 AND user_id = $2 AND status = 'pending'
+                "#,
+                )
+                .bind(chat_id as i64)
+                .bind(user.id)
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+        tx.commit().await?;
+
+        Ok(request)
+    }
+
+    /// Pending join requests of a chat, oldest first.
+    pub async fn list_join_requests(&self, chat_id: u64) -> Result<Vec<JoinRequest>, AppError> {
+        let requests = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, message, status, decided_by, decided_at, created_at
+            FROM chat_join_requests
+            WHERE chat_id = $1 AND status = 'pending'
+            ORDER BY id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(requests)
+    }
+
+    /// Approve or deny a pending join request, approving adds the user to the chat as if
+    /// `actor_id` added them.
+    pub async fn decide_join_request(
+        &self,
+        chat_id: u64,
+        request_id: u64,
+        approve: bool,
+        actor_id: u64,
+    ) -> Result<Option<JoinRequest>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let request: Option<JoinRequest> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, message, status, decided_by, decided_at, created_at
+            FROM chat_join_requests
+            WHERE id = $1 AND chat_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(request_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(request) = request else {
+            return Ok(None);
+        };
+        if request.status != JoinRequestStatus::Pending {
+            return Err(AppError::JoinRequestError(
+                "Join request has already been decided".to_string(),
+            ));
+        }
+
+        if approve {
+            let chat = self
+                .get_chat_by_id(chat_id)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("chat id {chat_id}")))?;
+            if !chat.members.contains(&request.user_id) {
+                self.verify_not_banned(chat_id, request.user_id as _)
+                    .await?;
+                self.verify_member_limit(&chat.r#type, chat.members.len() + 1)?;
+            }
+            let added: Option<i64> = sqlx::query_scalar(
+                r#"
+                INSERT INTO chat_members (chat_id, user_id, invited_by)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+                RETURNING user_id
+                "#,
+            )
+            .bind(chat_id as i64)
+            .bind(request.user_id)
+            .bind(actor_id as i64)
+            .fetch_optional(&mut *tx)
+            .await?;
+            if let Some(added) = added {
+                notify_members_changed(&mut *tx, chat_id, &[added], &[], actor_id).await?;
+            }
+        }
+        let status = match approve {
+            true => JoinRequestStatus::Approved,
+            false => JoinRequestStatus::Denied,
+        };
+        let request: JoinRequest = sqlx::query_as(
+            r#"
+            UPDATE chat_join_requests
+            SET status = $2, decided_by = $3, decided_at = NOW()
+            WHERE id = $1
+            RETURNING id, chat_id, user_id, message, status, decided_by, decided_at, created_at
+            "#,
+        )
+        .bind(request.id)
+        .bind(status)
+        .bind(actor_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        notify_join_request(&mut *tx, &request).await?;
+        tx.commit().await?;
+
+        Ok(Some(request))
+    }
+}
+
+// delivered by notify_server to the chat owner and admins, and to the requester once the
+// request is decided
+async fn notify_join_request<'e>(
+    executor: impl PgExecutor<'e>,
+    request: &JoinRequest,
+) -> Result<(), AppError> {
+    let requester = match request.status {
+        JoinRequestStatus::Pending => vec![],
+        _ => vec![request.user_id],
+    };
+    sqlx::query(
+        r#"
+        SELECT pg_notify(
+            'chat_join_request',
+            jsonb_build_object(
+                'request', $2::jsonb,
+                'user_ids', ARRAY(
+                    SELECT user_id FROM chat_members
+                    WHERE chat_id = $1 AND role IN ('owner', 'admin')
+                ) || $3::bigint[]
+            )::text
+        )
+        "#,
+    )
+    .bind(request.chat_id)
+    .bind(json!(request))
+    .bind(requester)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+#[derive(FromRow)]
+struct InviteRow {
+    #[sqlx(flatten)]
+    invite: ChatInvite,
+    ws_id: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn chat_invite_should_limit_uses() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateChatInvite {
+            expires_in_secs: 3600,
+            max_uses: Some(1),
+        };
+        let err = state.create_chat_invite(3, &input, 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invite error: Single chats can't have invites"
+        );
+
+        // chat 2 is private with members 1, 2 and 3
+        let invite = state.create_chat_invite(2, &input, 1).await?;
+        let user = state.find_user_by_id(4).await?.expect("user should exist");
+        let chat = state.accept_chat_invite(&invite.token, &user).await?;
+        assert_eq!(chat.members, [1, 2, 3, 4]);
+        // members accepting again don't use it up
+        state.accept_chat_invite(&invite.token, &user).await?;
+
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        let err = state
+            .accept_chat_invite(&invite.token, &user)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invite error: Invite has been used up");
+        let invites = state.list_chat_invites(2).await?;
+        assert_eq!(invites[0].uses, 1);
+
+        let input = CreateChatInvite {
+            expires_in_secs: 3600,
+            max_uses: None,
+        };
+        let invite = state.create_chat_invite(2, &input, 1).await?;
+        let revoked = state.revoke_chat_invite(2, invite.id as _).await?;
+        assert!(revoked.is_some_and(|i| i.revoked_at.is_some()));
+        let err = state
+            .accept_chat_invite(&invite.token, &user)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invite error: Invite has been revoked");
+        assert!(state.revoke_chat_invite(1, invite.id as _).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn join_request_should_add_approved_users() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let user = state.find_user_by_id(4).await?.expect("user should exist");
+        let input = CreateJoinRequest {
+            message: Some(" let me in ".to_string()),
+        };
+        let err = state
+            .request_to_join_chat(1, &input, &user)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "join request error: Only private channels take join requests"
+        );
+
+        // chat 2 is private with members 1, 2 and 3
+        let request = state.request_to_join_chat(2, &input, &user).await?;
+        assert_eq!(request.message.as_deref(), Some("let me in"));
+        assert_eq!(request.status, JoinRequestStatus::Pending);
+        let again = state
+            .request_to_join_chat(2, &CreateJoinRequest::default(), &user)
+            .await?;
+        assert_eq!(again.id, request.id);
+        let pending = state.list_join_requests(2).await?;
+        assert_eq!(
+            pending.iter().map(|r| r.id).collect::<Vec<_>>(),
+            [request.id]
+        );
+
+        let decided = state
+            .decide_join_request(2, request.id as _, true, 1)
+            .await?
+            .expect("request should exist");
+        assert_eq!(
+            (decided.status, decided.decided_by),
+            (JoinRequestStatus::Approved, Some(1))
+        );
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert_eq!(chat.members, [1, 2, 3, 4]);
+        assert!(state.list_join_requests(2).await?.is_empty());
+        let err = state
+            .decide_join_request(2, request.id as _, false, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "join request error: Join request has already been decided"
+        );
+
+        let user = state.find_user_by_id(5).await?.expect("user should exist");
+        let request = state
+            .request_to_join_chat(2, &CreateJoinRequest::default(), &user)
+            .await?;
+        assert!(state
+            .decide_join_request(1, request.id as _, true, 1)
+            .await?
+            .is_none());
+        let decided = state
+            .decide_join_request(2, request.id as _, false, 1)
+            .await?
+            .expect("request should exist");
+        assert_eq!(decided.status, JoinRequestStatus::Denied);
+        let chat = state.get_chat_by_id(2).await?.unwrap();
+        assert!(!chat.members.contains(&5));
+        Ok(())
+    }
+}
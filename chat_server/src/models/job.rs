@@ -0,0 +1,191 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "job_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub payload: Value,
+    pub status: JobStatus,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Enqueue a job of the given kind, to be run at `run_at` (or as soon as possible).
+    pub async fn enqueue_job(
+        &self,
+        kind: &str,
+        payload: impl Serialize,
+        run_at: Option<DateTime<Utc>>,
+    ) -> Result<Job, AppError> {
+        let payload = serde_json::to_value(payload).map_err(anyhow::Error::from)?;
+        let job = sqlx::query_as(
+            r#"
+            INSERT INTO jobs (kind, payload, run_at, max_attempts)
+            VALUES ($1, $2, COALESCE($3, NOW()), $4)
+            RETURNING id, kind, payload, status, attempts, max_attempts, run_at, last_error, created_at
+            "#,
+        )
+        .bind(kind)
+        .bind(payload)
+        .bind(run_at)
+        .bind(self.config.jobs.max_attempts as i32)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Claim the next due job. Jobs stuck in `running` longer than `stale_after_secs`
+    /// (e.g. the worker crashed) are picked up again.
+    pub async fn claim_job(&self) -> Result<Option<Job>, AppError> {
+        let job = sqlx::query_as(
+            r#"
+            UPDATE jobs
+            SET status = 'running', attempts = attempts + 1, updated_at = NOW()
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE (status = 'pending' AND run_at <= NOW())
+                OR (status = 'running' AND updated_at < NOW() - make_interval(secs => $1))
+                ORDER BY run_at, id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, kind, payload, status, attempts, max_attempts, run_at, last_error, created_at
+            "#,
+        )
+        .bind(self.config.jobs.stale_after_secs as f64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn complete_job(&self, id: u64) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = 'done', last_error = NULL, updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(id as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record a failed attempt. The job is retried with exponential backoff until
+    /// `max_attempts` is reached, then it is marked as failed.
+    pub async fn fail_job(&self, id: u64, error: &str) -> Result<Option<Job>, AppError> {
+        let job = sqlx::query_as(
+            r#"
+            UPDATE jobs
+            SET status = CASE WHEN attempts >= max_attempts THEN 'failed'::job_status ELSE 'pending'::job_status END,
+                run_at = NOW() + make_interval(secs => power(2, attempts)),
+                last_error = $2,
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING id, kind, payload, status, attempts, max_attempts, run_at, last_error, created_at
+            "#,
+        )
+        .bind(id as i64)
+        .bind(error)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn get_job_by_id(&self, id: u64) -> Result<Option<Job>, AppError> {
+        let job = sqlx::query_as(
+            r#"
+            SELECT id, kind, payload, status, attempts, max_attempts, run_at, last_error, created_at
+            FROM jobs
+            WHERE id = $1
+            "#,
+        )
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn enqueue_and_claim_job_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let job = state.enqueue_job("test", json!({"a": 1}), None).await?;
+        assert_eq!(job.status, JobStatus::Pending);
+        assert_eq!(job.payload, json!({"a": 1}));
+
+        let claimed = state.claim_job().await?.expect("job should be claimed");
+        assert_eq!(claimed.id, job.id);
+        assert_eq!(claimed.status, JobStatus::Running);
+        assert_eq!(claimed.attempts, 1);
+
+        // nothing left to claim
+        assert!(state.claim_job().await?.is_none());
+
+        state.complete_job(job.id as _).await?;
+        let job = state.get_job_by_id(job.id as _).await?.unwrap();
+        assert_eq!(job.status, JobStatus::Done);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scheduled_job_should_not_be_claimed_early() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let run_at = Utc::now() + chrono::Duration::hours(1);
+        state.enqueue_job("test", json!({}), Some(run_at)).await?;
+        assert!(state.claim_job().await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_job_should_retry_then_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let job = state.enqueue_job("test", json!({}), None).await?;
+        state.claim_job().await?;
+
+        let job = state.fail_job(job.id as _, "boom").await?.unwrap();
+        assert_eq!(job.status, JobStatus::Pending);
+        assert_eq!(job.last_error.as_deref(), Some("boom"));
+        assert!(job.run_at > Utc::now());
+
+        // exhaust all attempts
+        sqlx::query("UPDATE jobs SET attempts = max_attempts WHERE id = $1")
+            .bind(job.id)
+            .execute(&state.pool)
+            .await?;
+        let job = state.fail_job(job.id as _, "boom").await?.unwrap();
+        assert_eq!(job.status, JobStatus::Failed);
+        Ok(())
+    }
+}
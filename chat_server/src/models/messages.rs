@@ -1,9 +1,15 @@
-use crate::{AppError, AppState, ChatFile};
-use chat_core::Message;
+use crate::{
+    translator::primary_lang, AppError, AppState, ChatFeature, Job, JobFuture, JobHandler, Locale,
+    ReactionCount, SendContext, Translation,
+};
+use chat_core::{ChatUser, Message, MessageTranslation};
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::collections::HashMap;
 use utoipa::{IntoParams, ToSchema};
 
+/// Job kind translating a message of a chat with auto translation.
+pub const TRANSLATE_MESSAGE_JOB: &str = "translate_message";
+
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
 pub struct CreateMessage {
     pub content: String,
@@ -12,53 +18,61 @@ pub struct CreateMessage {
 
 #[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
 pub struct ListMessages {
-    pub last_id: Option<u64>,
+    #[serde(default, with = "chat_core::id::option")]
+    pub last_id: Option<i64>,
     pub limit: u64,
+    /// what to embed in the messages, `default` if not given
+    #[serde(default)]
+    #[param(inline)]
+    pub fields: MessageFields,
+}
+
+/// How much of its messages a message list embeds. Clients fetching light payloads
+/// hydrate the rest from the reaction and user endpoints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFields {
+    /// the messages only
+    Minimal,
+    /// the messages and their translations
+    #[default]
+    Default,
+    /// translations, reactions and sender profiles as well
+    Full,
+}
+
+/// A listed message with what its `MessageFields` embed.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct HydratedMessage {
+    #[serde(flatten)]
+    pub message: Message,
+    /// reactions of the message, for `full`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<Vec<ReactionCount>>,
+    /// profile of the sender, for `full`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<ChatUser>,
+}
+
+/// Runs `translate_message` jobs enqueued for new messages of auto translated chats.
+pub struct TranslateMessageJob;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct TranslateMessage {
+    pub(crate) message_id: i64,
 }
 
 #[allow(dead_code)]
 impl AppState {
+    /// Send a message through the message pipeline, see `pipeline` for its stages.
     pub async fn create_message(
         &self,
         input: CreateMessage,
         chat_id: u64,
         user_id: u64,
     ) -> Result<Message, AppError> {
-        let base_dir = &self.config.server.base_dir;
-        // verify content - not empty
-        if input.content.is_empty() {
-            return Err(AppError::CreateMessageError(
-                "Content cannot be empty".to_string(),
-            ));
-        }
-
-        // verify files exist
-        for s in &input.files {
-            let file = ChatFile::from_str(s)?;
-            if !file.path(base_dir).exists() {
-                return Err(AppError::CreateMessageError(format!(
-                    "File {} doesn't exist",
-                    s
-                )));
-            }
-        }
-
-        // create message
-        let message: Message = sqlx::query_as(
-            r#"
-          INSERT INTO messages (chat_id, sender_id, content, files)
-          VALUES ($1, $2, $3, $4)
-          RETURNING id, chat_id, sender_id, content, files, created_at
-          "#,
-        )
-        .bind(chat_id as i64)
-        .bind(user_id as i64)
-        .bind(input.content)
-        .bind(&input.files)
-        .fetch_one(&self.pool)
-        .await?;
-
-        Ok(message)
+        let ctx = SendContext::new(input, chat_id, user_id);
+        self.message_pipeline().run(self, ctx).await
     }
 
     pub async fn list_messages(
@@ -79,19 +93,183 @@ impl AppState {
         "#,
         )
         .bind(chat_id as i64)
-        .bind(last_id as i64)
+        .bind(last_id)
         .bind(input.limit as i64)
         .fetch_all(&self.pool)
         .await?;
 
         Ok(messages)
     }
+
+    /// Translate a message into the language of its chat, returns None if the chat isn't
+    /// auto translated or the message is in that language already.
+    pub async fn translate_message(
+        &self,
+        message_id: u64,
+    ) -> Result<Option<MessageTranslation>, AppError> {
+        let message: Option<(i64, String)> =
+            sqlx::query_as("SELECT chat_id, content FROM messages WHERE id = $1")
+                .bind(message_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((chat_id, content)) = message else {
+            return Ok(None);
+        };
+        let Some(target) = self.get_chat_settings(chat_id as _).await?.translate_to else {
+            return Ok(None);
+        };
+        // the chat may have been encrypted since the message was sent
+        match self
+            .ensure_chat_feature(chat_id as _, ChatFeature::Translation)
+            .await
+        {
+            Err(AppError::PermissionDenied(_)) => return Ok(None),
+            ret => ret?,
+        }
+
+        let Some(translation) = self.translator.translate(&content, target).await? else {
+            return Ok(None);
+        };
+        if primary_lang(&translation.source_lang) == primary_lang(target.as_str()) {
+            return Ok(None);
+        }
+        let translation = self
+            .save_message_translation(message_id, target, &translation)
+            .await?;
+        Ok(Some(translation))
+    }
+
+    pub async fn save_message_translation(
+        &self,
+        message_id: u64,
+        lang: Locale,
+        translation: &Translation,
+    ) -> Result<MessageTranslation, AppError> {
+        let translation = sqlx::query_as(
+            r#"
+            INSERT INTO message_translations (message_id, source_lang, lang, content)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (message_id) DO UPDATE
+            SET source_lang = EXCLUDED.source_lang, lang = EXCLUDED.lang,
+                content = EXCLUDED.content, created_at = NOW()
+            RETURNING source_lang, lang, content
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(&translation.source_lang)
+        .bind(lang.as_str())
+        .bind(&translation.content)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(translation)
+    }
+
+    /// Attach the translations of the messages for a reader in `locale`, messages already
+    /// in the reader's language are left as they are.
+    pub async fn with_translations(
+        &self,
+        mut messages: Vec<Message>,
+        locale: Locale,
+    ) -> Result<Vec<Message>, AppError> {
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let rows: Vec<(i64, String, String, String)> = sqlx::query_as(
+            r#"
+            SELECT message_id, source_lang, lang, content
+            FROM message_translations
+            WHERE message_id = ANY($1)
+            "#,
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(messages);
+        }
+
+        let mut translations: HashMap<_, _> = rows
+            .into_iter()
+            .filter(|(_, source_lang, _, _)| {
+                primary_lang(source_lang) != primary_lang(locale.as_str())
+            })
+            .map(|(id, source_lang, lang, content)| {
+                let translation = MessageTranslation {
+                    source_lang,
+                    lang,
+                    content,
+                };
+                (id, translation)
+            })
+            .collect();
+        for message in messages.iter_mut() {
+            message.translation = translations.remove(&message.id);
+        }
+        Ok(messages)
+    }
+
+    /// Embed the reactions and senders of the messages for `MessageFields::Full`, the
+    /// other fields leave them out.
+    pub async fn hydrate_messages(
+        &self,
+        messages: Vec<Message>,
+        fields: MessageFields,
+    ) -> Result<Vec<HydratedMessage>, AppError> {
+        if fields != MessageFields::Full {
+            let messages = messages
+                .into_iter()
+                .map(|message| HydratedMessage {
+                    message,
+                    reactions: None,
+                    sender: None,
+                })
+                .collect();
+            return Ok(messages);
+        }
+
+        let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+        let mut reactions = self.reactions_of_messages(&ids).await?;
+        let mut sender_ids: Vec<i64> = messages.iter().map(|m| m.sender_id).collect();
+        sender_ids.sort();
+        sender_ids.dedup();
+        let senders: HashMap<_, _> = self
+            .fetch_chat_user_by_ids(&sender_ids)
+            .await?
+            .into_iter()
+            .map(|u| (u.id, u))
+            .collect();
+        let messages = messages
+            .into_iter()
+            .map(|message| HydratedMessage {
+                reactions: Some(reactions.remove(&message.id).unwrap_or_default()),
+                sender: senders.get(&message.sender_id).cloned(),
+                message,
+            })
+            .collect();
+        Ok(messages)
+    }
+}
+
+impl JobHandler for TranslateMessageJob {
+    fn kind(&self) -> &'static str {
+        TRANSLATE_MESSAGE_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: TranslateMessage =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            state.translate_message(job.message_id as _).await?;
+            Ok(())
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ChatFile, ChatSettings};
     use anyhow::Result;
+    use serde_json::json;
 
     #[tokio::test]
     async fn create_message_should_work() -> Result<()> {
@@ -138,6 +316,7 @@ mod tests {
         let input = ListMessages {
             last_id: None,
             limit: 6,
+            fields: Default::default(),
         };
 
         let messages = state.list_messages(input, 1).await?;
@@ -148,6 +327,7 @@ mod tests {
         let input = ListMessages {
             last_id: Some(last_id as _),
             limit: 6,
+            fields: Default::default(),
         };
 
         let messages = state.list_messages(input, 1).await?;
@@ -156,6 +336,90 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn hydrate_messages_should_follow_fields() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = ListMessages {
+            last_id: None,
+            limit: 2,
+            fields: MessageFields::Full,
+        };
+        let messages = state.list_messages(input, 1).await?;
+        state.add_reaction(1, messages[0].id as _, 2, "👍").await?;
+
+        let hydrated = state
+            .hydrate_messages(messages.clone(), MessageFields::Default)
+            .await?;
+        assert!(hydrated[0].reactions.is_none() && hydrated[0].sender.is_none());
+        let hydrated = state
+            .hydrate_messages(messages.clone(), MessageFields::Full)
+            .await?;
+        let reactions = hydrated[0].reactions.as_ref().unwrap();
+        assert_eq!((reactions[0].emoji.as_str(), reactions[0].count), ("👍", 1));
+        assert_eq!(hydrated[1].reactions, Some(vec![]));
+        let sender = hydrated[1].sender.as_ref().unwrap();
+        assert_eq!(sender.id, messages[1].sender_id);
+
+        // embeds are left out of the payload unless asked for
+        let json = serde_json::to_value(
+            &state
+                .hydrate_messages(messages, MessageFields::Minimal)
+                .await?[0],
+        )?;
+        assert!(json.get("reactions").is_none() && json.get("content").is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn translations_should_be_shown_to_other_languages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let settings = ChatSettings {
+            translate_to: Some(Locale::ZhCn),
+            ..Default::default()
+        };
+        state.update_chat_settings(1, &settings).await?;
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input, 1, 1).await?;
+        let (payload,): (serde_json::Value,) =
+            sqlx::query_as("SELECT payload FROM jobs WHERE kind = $1")
+                .bind(TRANSLATE_MESSAGE_JOB)
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(payload, json!({ "message_id": message.id }));
+        // the test config has no translation provider
+        assert_eq!(state.translate_message(message.id as _).await?, None);
+
+        let translation = Translation {
+            source_lang: "en".to_string(),
+            content: "你好".to_string(),
+        };
+        state
+            .save_message_translation(message.id as _, Locale::ZhCn, &translation)
+            .await?;
+        let messages = state
+            .with_translations(vec![message.clone()], Locale::ZhCn)
+            .await?;
+        let translation = messages[0].translation.as_ref().unwrap();
+        assert_eq!(
+            (translation.lang.as_str(), translation.content.as_str()),
+            ("zh-CN", "你好")
+        );
+        let messages = state.with_translations(vec![message], Locale::En).await?;
+        assert_eq!(messages[0].translation, None);
+
+        // encrypted chats can't be translated
+        let settings = ChatSettings {
+            encrypted: true,
+            translate_to: Some(Locale::ZhCn),
+            ..Default::default()
+        };
+        assert!(state.update_chat_settings(2, &settings).await.is_err());
+        Ok(())
+    }
+
     fn upload_dummy_file(state: &AppState) -> Result<String> {
         let file = ChatFile::new(1, "test.txt", b"hello world");
         let path = file.path(&state.config.server.base_dir);
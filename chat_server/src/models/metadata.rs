@@ -0,0 +1,114 @@
+use crate::{AppError, AppState};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use utoipa::ToSchema;
+
+const MAX_METADATA_BYTES: usize = 16 * 1024;
+
+/// Structured data integrations attach to a chat, e.g. the CRM account or ticket it is for.
+/// The server stores it as it is and never reads it.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatMetadata {
+    /// a JSON object, at most 16 KiB
+    #[schema(value_type = Object)]
+    pub metadata: Value,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn get_chat_metadata(&self, chat_id: u64) -> Result<ChatMetadata, AppError> {
+        let metadata: Option<(Json<Value>,)> =
+            sqlx::query_as("SELECT metadata FROM chats WHERE id = $1 AND deleted_at IS NULL")
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        match metadata {
+            Some((metadata,)) => Ok(ChatMetadata {
+                metadata: metadata.0,
+            }),
+            None => Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        }
+    }
+
+    /// Replace the metadata of the chat, an empty object clears it.
+    pub async fn set_chat_metadata(
+        &self,
+        chat_id: u64,
+        input: &ChatMetadata,
+    ) -> Result<ChatMetadata, AppError> {
+        if !input.metadata.is_object() {
+            return Err(AppError::MetadataError(
+                "Metadata must be a JSON object".to_string(),
+            ));
+        }
+        let size = serde_json::to_vec(&input.metadata)
+            .map_err(anyhow::Error::from)?
+            .len();
+        if size > MAX_METADATA_BYTES {
+            return Err(AppError::MetadataError(format!(
+                "Metadata must be at most {MAX_METADATA_BYTES} bytes"
+            )));
+        }
+        let ret: Option<(Json<Value>,)> = sqlx::query_as(
+            r#"
+            UPDATE chats SET metadata = $1
+            WHERE id = $2 AND deleted_at IS NULL
+            RETURNING metadata
+            "#,
+        )
+        .bind(Json(&input.metadata))
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        match ret {
+            Some((metadata,)) => Ok(ChatMetadata {
+                metadata: metadata.0,
+            }),
+            None => Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn chat_metadata_should_be_set_and_replaced() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let metadata = state.get_chat_metadata(2).await?;
+        assert_eq!(metadata.metadata, json!({}));
+
+        let input = ChatMetadata {
+            metadata: json!({ "ticket_id": "SUP-1337", "crm": { "account": "ACME-42" } }),
+        };
+        assert_eq!(state.set_chat_metadata(2, &input).await?, input);
+        assert_eq!(state.get_chat_metadata(2).await?, input);
+        // metadata is per chat
+        assert_eq!(state.get_chat_metadata(1).await?.metadata, json!({}));
+
+        let input = ChatMetadata {
+            metadata: json!({ "ticket_id": "SUP-1338" }),
+        };
+        state.set_chat_metadata(2, &input).await?;
+        assert_eq!(state.get_chat_metadata(2).await?, input);
+
+        let input = ChatMetadata {
+            metadata: json!(["SUP-1337"]),
+        };
+        let ret = state.set_chat_metadata(2, &input).await;
+        assert!(matches!(ret, Err(AppError::MetadataError(_))));
+        let input = ChatMetadata {
+            metadata: json!({ "notes": "x".repeat(MAX_METADATA_BYTES) }),
+        };
+        let ret = state.set_chat_metadata(2, &input).await;
+        assert!(matches!(ret, Err(AppError::MetadataError(_))));
+
+        let ret = state.get_chat_metadata(100).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+        Ok(())
+    }
+}
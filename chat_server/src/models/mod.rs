@@ -1,13 +1,117 @@
+mod archival;
+mod archive;
+mod audit;
+mod ban;
+mod bulk;
+mod capability;
 mod chat;
+mod demo;
+mod domain;
+mod draft;
+mod export;
 mod file;
+mod guest;
+mod history;
+mod hold;
+mod invite;
+mod job;
 mod messages;
+mod metadata;
+mod notification;
+mod onboarding;
+mod orphan;
+mod ownership;
+mod pin;
+mod quota;
+mod reaction;
+mod read;
+mod realtime;
+mod search;
+mod security;
+mod task;
+mod template;
+mod token;
 mod user;
+mod webhook;
 mod workspace;
 
-pub use chat::ChatDTO;
-pub use messages::{CreateMessage, ListMessages};
+pub use archival::{
+    sign_payload, ArchiveMessageJob, ChatArchivalWebhook, SetChatArchivalWebhook,
+    ARCHIVAL_SIGNATURE_HEADER, ARCHIVE_MESSAGE_JOB, MESSAGE_FINALIZED_EVENT,
+};
+pub use archive::{ArchiveStatus, ArchiveWorkspaceJob, UnarchiveWorkspaceJob, WorkspaceArchive};
+pub use audit::{AuditLog, FileAccess, ListAuditLogs};
+pub use ban::{ChatBan, RemoveChatMember};
+pub use bulk::{
+    BulkMessage, BulkMessageJob, BulkMessageReport, BulkMessageTarget, BulkTargetStatus,
+    CreateBulkMessage,
+};
+pub use capability::{
+    Capability, CapabilityStat, ClientCapabilities, ListCapabilityStats, CLIENT_CAPABILITIES_HEADER,
+};
+pub(crate) use chat::notify_members_changed;
+pub use chat::{
+    AddChatMember, ChatDTO, ChatDeletion, ChatMember, ChatPage, ChatPatchDTO, ChatRole, ChatSort,
+    ConvertChat, DeleteScheduledChatJob, DirectoryChannel, ListChannels, ListChats, PurgeChatJob,
+    SearchChats, TransferChat, UpdateChatRole, IDEMPOTENCY_KEY_HEADER,
+};
+pub use demo::{
+    DemoChat, DemoMessage, DemoSnapshot, DemoUser, DemoWorkspace, ResetDemoWorkspacesJob,
+    RESET_DEMO_WORKSPACES_JOB,
+};
+pub(crate) use domain::lookup_txt;
+pub use domain::{
+    CreateWorkspaceDomain, DomainEmailChallenge, FindSignupWorkspace, SignupWorkspace,
+    VerifyDomain, WorkspaceDomain,
+};
+pub use draft::{ChatDraft, SaveChatDraft};
+pub use export::{
+    ChatExport, ChatFeature, ChatSettings, ExportChat, ExportFormat, ExportPolicy, ExportRecord,
+    ExportSettings, ExportedMessage, PostPolicy, Watermark,
+};
+pub(crate) use file::image_ext;
+pub use guest::{CreateGuestLink, Guest, GuestAccess, GuestLink, RedeemGuestLink};
+pub use history::{ChatHistoryQuery, ChatSnapshot, MessageChangeOp};
+pub use hold::{CreateLegalHold, LegalHold};
+pub use invite::{ChatInvite, CreateChatInvite, CreateJoinRequest, JoinRequest, JoinRequestStatus};
+pub use job::{Job, JobStatus};
+pub(crate) use messages::TranslateMessage;
+pub use messages::{
+    CreateMessage, HydratedMessage, ListMessages, MessageFields, TranslateMessageJob,
+    TRANSLATE_MESSAGE_JOB,
+};
+pub use metadata::ChatMetadata;
+pub use notification::ChatNotificationSettings;
+pub use onboarding::{Onboarding, OnboardingProgress, OnboardingStep};
+pub use orphan::{CollectOrphansJob, OrphanReport, COLLECT_ORPHANS_JOB};
+pub use ownership::{TransferWorkspace, WorkspaceAdmin, WorkspaceTransfer};
+pub use pin::{MessagePin, PinLimit, PinList, PinMessage, ReorderPins};
+pub use quota::{
+    CreatePlan, Plan, QuotaResource, QuotaStatus, QuotaUsage, SetWorkspacePlan, WorkspaceUsage,
+};
+pub use reaction::{
+    ChannelReactions, CreateReactionTrigger, DailyEmojiCount, EmojiCount, MessageReactions,
+    ReactionAnalytics, ReactionAnalyticsQuery, ReactionCount, ReactionTrigger, ReactionWebhookJob,
+    TriggerAction, TriggerRun,
+};
+pub use read::{ChatFolder, ChatRead, MarkChatRead, ReadState};
+pub use realtime::{EndpointHealth, ListRealtimeEndpoints, RealtimeEndpoint};
+pub use search::{SearchReindex, SearchReindexJob, SearchReindexStatus};
+pub(crate) use security::networks_contain;
+pub use security::{SecurityPolicy, SessionMethod};
 use serde::{Deserialize, Serialize};
-pub use user::{CreateUser, SigninUser};
+pub use task::{CreateTask, ListTasks, TaskReminderJob, UpdateTask};
+pub use template::{ChannelFromTemplate, ChannelTemplate, CreateChannelTemplate};
+pub use token::{
+    CreatePersonalToken, NewPersonalToken, PersonalToken, PERSONAL_TOKEN_PREFIX,
+    SCOPE_MESSAGES_READ, SCOPE_MESSAGES_WRITE, SCOPE_READ_ONLY,
+};
+pub use user::{CreateUser, NotificationSound, SigninUser, TimeFormat, UserPreferences};
+pub(crate) use webhook::{is_valid_webhook_url, post_webhook};
+pub use webhook::{
+    CreateWebhook, Webhook, WebhookJob, MESSAGE_CREATED_EVENT, REACTION_ADDED_EVENT,
+    USER_JOINED_EVENT, USER_ROLE_CHANGED_EVENT,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
@@ -0,0 +1,123 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// How a member is notified of new messages in a chat. notify_server reads these when it
+/// fans out `NewMessage` events, the messages themselves are still delivered by the api.
+#[derive(Debug, Clone, Default, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatNotificationSettings {
+    /// no `NewMessage` events for the chat
+    #[serde(default)]
+    pub mute: bool,
+    /// the mute ends at this time, without it the chat stays muted until unmuted
+    #[serde(default)]
+    pub mute_until: Option<DateTime<Utc>>,
+    /// only `NewMessage` events of messages mentioning the member
+    #[serde(default)]
+    pub mentions_only: bool,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Settings of the user for the chat, the defaults if they never changed them.
+    pub async fn get_chat_notification_settings(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+    ) -> Result<ChatNotificationSettings, AppError> {
+        let settings = sqlx::query_as(
+            r#"
+            SELECT mute, mute_until, mentions_only
+            FROM chat_notification_settings
+            WHERE chat_id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(settings.unwrap_or_default())
+    }
+
+    pub async fn set_chat_notification_settings(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        input: &ChatNotificationSettings,
+    ) -> Result<ChatNotificationSettings, AppError> {
+        let settings = sqlx::query_as(
+            r#"
+            INSERT INTO chat_notification_settings (user_id, chat_id, mute, mute_until, mentions_only)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, chat_id) DO UPDATE
+            SET mute = EXCLUDED.mute,
+                mute_until = EXCLUDED.mute_until,
+                mentions_only = EXCLUDED.mentions_only,
+                updated_at = NOW()
+            RETURNING mute, mute_until, mentions_only
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(chat_id as i64)
+        .bind(input.mute)
+        // an unmuted chat has no end of its mute
+        .bind(input.mute_until.filter(|_| input.mute))
+        .bind(input.mentions_only)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use chrono::Duration;
+
+    #[tokio::test]
+    async fn chat_notification_settings_should_be_per_member() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert_eq!(
+            state.get_chat_notification_settings(1, 1).await?,
+            ChatNotificationSettings::default()
+        );
+
+        let until = Utc::now() + Duration::hours(1);
+        let input = ChatNotificationSettings {
+            mute: true,
+            mute_until: Some(until),
+            mentions_only: false,
+        };
+        let settings = state.set_chat_notification_settings(1, 1, &input).await?;
+        assert!(settings.mute);
+        assert_eq!(
+            settings.mute_until.map(|t| t.timestamp()),
+            Some(until.timestamp())
+        );
+        assert_eq!(state.get_chat_notification_settings(1, 1).await?, settings);
+        assert_eq!(
+            state.get_chat_notification_settings(1, 2).await?,
+            ChatNotificationSettings::default()
+        );
+
+        // unmuting drops the end of the mute
+        let input = ChatNotificationSettings {
+            mute: false,
+            mute_until: Some(until),
+            mentions_only: true,
+        };
+        let settings = state.set_chat_notification_settings(1, 1, &input).await?;
+        assert_eq!(
+            settings,
+            ChatNotificationSettings {
+                mute: false,
+                mute_until: None,
+                mentions_only: true,
+            }
+        );
+        Ok(())
+    }
+}
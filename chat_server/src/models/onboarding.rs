@@ -0,0 +1,118 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "onboarding_step", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum OnboardingStep {
+    CreatedFirstChannel,
+    /// added someone to a channel or created a guest link
+    InvitedTeammates,
+    SentFirstMessage,
+}
+
+const STEPS: [OnboardingStep; 3] = [
+    OnboardingStep::CreatedFirstChannel,
+    OnboardingStep::InvitedTeammates,
+    OnboardingStep::SentFirstMessage,
+];
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct OnboardingProgress {
+    pub step: OnboardingStep,
+    /// None until the step is done
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Onboarding {
+    /// every step, in the order they are shown
+    pub steps: Vec<OnboardingProgress>,
+    pub completed: bool,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Record that a user did an onboarding step, only the first time counts.
+    pub async fn complete_onboarding_step(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        step: OnboardingStep,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO onboarding_steps (ws_id, user_id, step)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .bind(step)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_onboarding(&self, ws_id: u64, user_id: u64) -> Result<Onboarding, AppError> {
+        let done: Vec<(OnboardingStep, DateTime<Utc>)> = sqlx::query_as(
+            "SELECT step, completed_at FROM onboarding_steps WHERE ws_id = $1 AND user_id = $2",
+        )
+        .bind(ws_id as i64)
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let steps: Vec<_> = STEPS
+            .iter()
+            .map(|step| OnboardingProgress {
+                step: *step,
+                completed_at: done.iter().find(|(s, _)| s == step).map(|(_, t)| *t),
+            })
+            .collect();
+        let completed = steps.iter().all(|s| s.completed_at.is_some());
+        Ok(Onboarding { steps, completed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn onboarding_should_track_steps() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let onboarding = state.get_onboarding(1, 1).await?;
+        assert_eq!(onboarding.steps.len(), 3);
+        assert!(onboarding.steps.iter().all(|s| s.completed_at.is_none()));
+
+        state
+            .complete_onboarding_step(1, 1, OnboardingStep::SentFirstMessage)
+            .await?;
+        let first = state.get_onboarding(1, 1).await?.steps[2].completed_at;
+        assert!(first.is_some());
+        // completing it again keeps the first time
+        state
+            .complete_onboarding_step(1, 1, OnboardingStep::SentFirstMessage)
+            .await?;
+        assert_eq!(
+            state.get_onboarding(1, 1).await?.steps[2].completed_at,
+            first
+        );
+
+        for step in [
+            OnboardingStep::CreatedFirstChannel,
+            OnboardingStep::InvitedTeammates,
+        ] {
+            state.complete_onboarding_step(1, 1, step).await?;
+        }
+        assert!(state.get_onboarding(1, 1).await?.completed);
+        assert!(!state.get_onboarding(1, 2).await?.completed);
+        Ok(())
+    }
+}
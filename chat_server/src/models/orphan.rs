@@ -0,0 +1,294 @@
+use crate::{AppError, AppState, ChatFile, Job, JobFuture, JobHandler};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+use tokio::fs;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// Job kind collecting orphaned data, it schedules its next run when done.
+pub const COLLECT_ORPHANS_JOB: &str = "collect_orphans";
+
+// files uploaded but not sent yet are kept for a day
+const FILE_GRACE_SECS: u64 = 60 * 60 * 24;
+
+// rows of chats which are deleted or the user is no longer a member of
+const NOT_A_MEMBER: &str = r#"
+    NOT EXISTS (
+        SELECT 1 FROM chat_members m JOIN chats c ON c.id = m.chat_id
+        WHERE m.chat_id = t.chat_id AND m.user_id = t.user_id AND c.deleted_at IS NULL
+    )
+"#;
+
+// results of bulk messages sent to users who no longer exist
+const DELETED_RECIPIENT: &str = r#"
+    t.user_id IS NOT NULL AND t.status <> 'pending'
+    AND NOT EXISTS (SELECT 1 FROM users u WHERE u.id = t.user_id)
+"#;
+
+/// Rows and files nothing refers to anymore, found or removed by `collect_orphans`.
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct OrphanReport {
+    /// true if nothing was removed
+    pub dry_run: bool,
+    /// files no message, message version, bulk message or chat avatar refers to
+    pub files: Vec<String>,
+    pub file_bytes: i64,
+    /// read markers of deleted chats or of users who left the chat
+    pub chat_reads: i64,
+    /// notification settings of deleted chats or of users who left the chat
+    pub chat_notification_settings: i64,
+    /// chats pinned by users who left them, or deleted since
+    pub chat_pins: i64,
+    /// bulk message results of users who no longer exist
+    pub bulk_message_targets: i64,
+}
+
+/// Runs the `collect_orphans` job scheduled by `schedule_orphan_collection`.
+pub struct CollectOrphansJob;
+
+#[allow(dead_code)]
+impl AppState {
+    /// Find orphaned data, and remove it unless `dry_run` is set. Files of archived
+    /// workspaces are left alone as their messages are in cold storage.
+    pub async fn collect_orphans(&self, dry_run: bool) -> Result<OrphanReport, AppError> {
+        let mut report = OrphanReport {
+            dry_run,
+            chat_reads: self
+                .collect_rows("chat_reads", NOT_A_MEMBER, dry_run)
+                .await?,
+            chat_notification_settings: self
+                .collect_rows("chat_notification_settings", NOT_A_MEMBER, dry_run)
+                .await?,
+            chat_pins: self
+                .collect_rows("chat_pins", NOT_A_MEMBER, dry_run)
+                .await?,
+            bulk_message_targets: self
+                .collect_rows("bulk_message_targets", DELETED_RECIPIENT, dry_run)
+                .await?,
+            ..Default::default()
+        };
+
+        let base_dir = &self.config.server.base_dir;
+        let grace = SystemTime::now() - std::time::Duration::from_secs(FILE_GRACE_SECS);
+        for ws_id in self.file_workspaces(base_dir).await? {
+            let referenced = self.referenced_files(ws_id).await?;
+            let mut freed = 0;
+            let files = list_chat_files(&base_dir.join(ws_id.to_string())).await?;
+            for (file, path, len, modified) in files {
+                let url = file.url();
+                if file.ws_id != ws_id || modified >= grace || referenced.contains(&url) {
+                    continue;
+                }
+                if !dry_run {
+                    fs::remove_file(&path).await?;
+                    freed += len;
+                }
+                report.file_bytes += len;
+                report.files.push(url);
+            }
+            if freed > 0 {
+                self.add_storage_usage(ws_id, -freed).await?;
+            }
+        }
+        report.files.sort();
+
+        if !dry_run {
+            info!(
+                "Collected {} orphaned files, {} reads, {} notification settings, {} chat pins and {} bulk targets",
+                report.files.len(),
+                report.chat_reads,
+                report.chat_notification_settings,
+                report.chat_pins,
+                report.bulk_message_targets
+            );
+        }
+        Ok(report)
+    }
+
+    /// Enqueue the `collect_orphans` job unless one is scheduled already.
+    pub async fn schedule_orphan_collection(&self) -> Result<(), AppError> {
+        let scheduled: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM jobs WHERE kind = $1 AND status IN ('pending', 'running'))",
+        )
+        .bind(COLLECT_ORPHANS_JOB)
+        .fetch_one(&self.pool)
+        .await?;
+        if !scheduled {
+            self.enqueue_job(COLLECT_ORPHANS_JOB, serde_json::json!({}), None)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // count, or delete, the rows of `table` (aliased `t`) matching `orphaned`
+    async fn collect_rows(
+        &self,
+        table: &str,
+        orphaned: &str,
+        dry_run: bool,
+    ) -> Result<i64, AppError> {
+        let sql = if dry_run {
+            format!("SELECT COUNT(*) FROM {table} t WHERE {orphaned}")
+        } else {
+            format!("WITH d AS (DELETE FROM {table} t WHERE {orphaned} RETURNING 1) SELECT COUNT(*) FROM d")
+        };
+        let count = sqlx::query_scalar(&sql).fetch_one(&self.pool).await?;
+        Ok(count)
+    }
+
+    // workspaces with a file directory which aren't archived or being archived
+    async fn file_workspaces(&self, base_dir: &Path) -> Result<Vec<u64>, AppError> {
+        let mut ids = vec![];
+        let Ok(mut entries) = fs::read_dir(base_dir).await else {
+            return Ok(ids);
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(id) = entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        let active: Vec<i64> = sqlx::query_scalar(
+            "SELECT id FROM workspaces WHERE id = ANY($1) AND archive_status IS NULL ORDER BY id",
+        )
+        .bind(ids.iter().map(|id| *id as i64).collect::<Vec<_>>())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(active.into_iter().map(|id| id as u64).collect())
+    }
+
+    async fn referenced_files(&self, ws_id: u64) -> Result<HashSet<String>, AppError> {
+        let urls: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT url FROM (
+                SELECT unnest(files) AS url FROM messages
+                UNION SELECT unnest(files) FROM message_changes
+                UNION SELECT unnest(files) FROM bulk_messages
+                UNION SELECT avatar_url FROM chats WHERE avatar_url IS NOT NULL
+            ) f
+            WHERE url LIKE $1
+            "#,
+        )
+        .bind(format!("/files/{ws_id}/%"))
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(urls.into_iter().collect())
+    }
+}
+
+impl JobHandler for CollectOrphansJob {
+    fn kind(&self) -> &'static str {
+        COLLECT_ORPHANS_JOB
+    }
+
+    fn run(&self, state: AppState, _job: Job) -> JobFuture {
+        Box::pin(async move {
+            state.collect_orphans(false).await?;
+            let hours = state.config.jobs.orphan_interval_hours as i64;
+            let run_at = Utc::now() + Duration::hours(hours);
+            state
+                .enqueue_job(COLLECT_ORPHANS_JOB, serde_json::json!({}), Some(run_at))
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+// (file, path, size, modified) of the files stored under a workspace directory
+async fn list_chat_files(
+    ws_dir: &Path,
+) -> Result<Vec<(ChatFile, PathBuf, i64, SystemTime)>, AppError> {
+    let mut files = vec![];
+    let mut dirs = vec![ws_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = entry.metadata().await?;
+            let path = entry.path();
+            if meta.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let Some(rel) = ws_dir
+                .parent()
+                .and_then(|base| path.strip_prefix(base).ok())
+            else {
+                continue;
+            };
+            let url = format!("/files/{}", rel.to_string_lossy());
+            // anything not named like a chat file isn't ours to remove
+            if let Ok(file) = ChatFile::from_str(&url) {
+                files.push((file, path, meta.len() as i64, meta.modified()?));
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn collect_orphans_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let base_dir = &state.config.server.base_dir;
+        let write = |file: &ChatFile, age: u64| -> Result<()> {
+            let path = file.path(base_dir);
+            std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+            std::fs::write(&path, b"orphan")?;
+            let modified = SystemTime::now() - Duration::from_secs(age);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(modified)?;
+            Ok(())
+        };
+        let orphan = ChatFile::new(1, "orphan.txt", b"orphan gc test");
+        write(&orphan, FILE_GRACE_SECS * 2)?;
+        let fresh = ChatFile::new(1, "fresh.txt", b"orphan gc fresh");
+        write(&fresh, 0)?;
+        let sent = ChatFile::new(1, "sent.txt", b"orphan gc sent");
+        write(&sent, FILE_GRACE_SECS * 2)?;
+        sqlx::query(
+            "INSERT INTO messages (chat_id, sender_id, content, files) VALUES (1, 1, 'f', $1)",
+        )
+        .bind(vec![sent.url()])
+        .execute(&state.pool)
+        .await?;
+
+        // user 5 left chat 1, chat 3 is deleted
+        sqlx::query("INSERT INTO chat_reads (user_id, chat_id, last_read_id) VALUES (5, 1, 1), (1, 3, 1), (1, 1, 1)")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("DELETE FROM chat_members WHERE chat_id = 1 AND user_id = 5")
+            .execute(&state.pool)
+            .await?;
+        sqlx::query("UPDATE chats SET deleted_at = NOW() WHERE id = 3")
+            .execute(&state.pool)
+            .await?;
+
+        let report = state.collect_orphans(true).await?;
+        assert!(report.files.contains(&orphan.url()));
+        assert!(!report.files.contains(&fresh.url()));
+        assert!(!report.files.contains(&sent.url()));
+        assert_eq!(report.chat_reads, 2);
+        assert!(orphan.path(base_dir).exists());
+
+        let report = state.collect_orphans(false).await?;
+        assert_eq!(report.chat_reads, 2);
+        assert!(!orphan.path(base_dir).exists());
+        assert!(sent.path(base_dir).exists());
+        let report = state.collect_orphans(true).await?;
+        assert_eq!(report.chat_reads, 0);
+        assert!(!report.files.contains(&orphan.url()));
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use crate::{AppError, AppState};
+use chat_core::{User, Workspace};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceAdmin {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    /// the oldest admin becomes the owner if the owner is deleted
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceTransfer {
+    pub ws_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub from_id: i64,
+    /// the new owner, who has to accept the transfer
+    #[serde(with = "chat_core::id")]
+    pub to_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct TransferWorkspace {
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn list_workspace_admins(&self, ws_id: u64) -> Result<Vec<WorkspaceAdmin>, AppError> {
+        let admins = sqlx::query_as(
+            "SELECT user_id, created_at FROM workspace_admins WHERE ws_id = $1 ORDER BY created_at, user_id",
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(admins)
+    }
+
+    /// Find workspace `ws_id`, failing unless the user owns or administers it. Other
+    /// workspaces are not found. `action` is used in the error message.
+    pub async fn verify_workspace_admin(
+        &self,
+        user: &User,
+        ws_id: u64,
+        action: &str,
+    ) -> Result<Workspace, AppError> {
+        let ws = match self.find_workspace_by_id(ws_id).await? {
+            Some(ws) if ws.id == user.ws_id => ws,
+            _ => return Err(AppError::NotFound(format!("workspace id {ws_id}"))),
+        };
+        if ws.owner_id == user.id {
+            return Ok(ws);
+        }
+        let admins = self.list_workspace_admins(ws_id).await?;
+        if !admins.iter().any(|a| a.user_id == user.id) {
+            return Err(AppError::PermissionDenied(format!(
+                "only workspace owners and admins can {action}"
+            )));
+        }
+        Ok(ws)
+    }
+
+    pub async fn add_workspace_admin(
+        &self,
+        ws: &Workspace,
+        user_id: u64,
+    ) -> Result<WorkspaceAdmin, AppError> {
+        self.verify_workspace_member(ws, user_id).await?;
+        if ws.owner_id == user_id as i64 {
+            return Err(AppError::WorkspaceError(
+                "The owner can't be an admin".to_string(),
+            ));
+        }
+        let admins = self.list_workspace_admins(ws.id as _).await?;
+        let promoted = !admins.iter().any(|a| a.user_id == user_id as i64);
+        let admin = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_admins (ws_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT (ws_id, user_id) DO UPDATE SET ws_id = EXCLUDED.ws_id
+            RETURNING user_id, created_at
+            "#,
+        )
+        .bind(ws.id)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        if promoted {
+            self.dispatch_role_changed(ws.id as _, user_id, "member", "admin")
+                .await?;
+        }
+        Ok(admin)
+    }
+
+    /// Returns false if the user is not an admin.
+    pub async fn remove_workspace_admin(&self, ws_id: u64, user_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM workspace_admins WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws_id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+        let removed = ret.rows_affected() > 0;
+        if removed {
+            self.dispatch_role_changed(ws_id, user_id, "admin", "member")
+                .await?;
+        }
+        Ok(removed)
+    }
+
+    pub async fn get_workspace_transfer(
+        &self,
+        ws_id: u64,
+    ) -> Result<Option<WorkspaceTransfer>, AppError> {
+        let transfer = sqlx::query_as(
+            "SELECT ws_id, from_id, to_id, created_at FROM workspace_transfers WHERE ws_id = $1",
+        )
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(transfer)
+    }
+
+    /// Offer the workspace to another member, replacing a pending transfer.
+    pub async fn create_workspace_transfer(
+        &self,
+        ws: &Workspace,
+        to_id: u64,
+    ) -> Result<WorkspaceTransfer, AppError> {
+        self.verify_workspace_member(ws, to_id).await?;
+        if ws.owner_id == to_id as i64 {
+            return Err(AppError::WorkspaceError(
+                "The workspace is already owned by this user".to_string(),
+            ));
+        }
+        let transfer = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_transfers (ws_id, from_id, to_id)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (ws_id) DO UPDATE
+            SET from_id = EXCLUDED.from_id, to_id = EXCLUDED.to_id, created_at = NOW()
+            RETURNING ws_id, from_id, to_id, created_at
+            "#,
+        )
+        .bind(ws.id)
+        .bind(ws.owner_id)
+        .bind(to_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(transfer)
+    }
+
+    /// Returns false if there is no pending transfer.
+    pub async fn cancel_workspace_transfer(&self, ws_id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM workspace_transfers WHERE ws_id = $1")
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Make `user_id` the owner if the pending transfer is offered to them, the old owner
+    /// stays on as an admin.
+    pub async fn accept_workspace_transfer(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+    ) -> Result<Workspace, AppError> {
+        let transfer = match self.get_workspace_transfer(ws_id).await? {
+            Some(t) if t.to_id == user_id as i64 => t,
+            _ => return Err(AppError::NotFound("workspace transfer".to_string())),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let ws: Workspace = sqlx::query_as(
+            r#"
+            UPDATE workspaces
+            SET owner_id = $1
+            WHERE id = $2
+            RETURNING id, name, owner_id, settings, created_at
+            "#,
+        )
+        .bind(transfer.to_id)
+        .bind(ws_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM workspace_admins WHERE ws_id = $1 AND user_id = $2")
+            .bind(ws_id as i64)
+            .bind(transfer.to_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO workspace_admins (ws_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        )
+        .bind(ws_id as i64)
+        .bind(transfer.from_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM workspace_transfers WHERE ws_id = $1")
+            .bind(ws_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.dispatch_role_changed(ws_id, transfer.to_id as _, "admin", "owner")
+            .await?;
+        self.dispatch_role_changed(ws_id, transfer.from_id as _, "owner", "admin")
+            .await?;
+        Ok(ws)
+    }
+
+    // guests can't run a workspace
+    async fn verify_workspace_member(&self, ws: &Workspace, user_id: u64) -> Result<(), AppError> {
+        match self.find_user_by_id(user_id as _).await? {
+            Some(user) if user.ws_id == ws.id => {}
+            _ => return Err(AppError::NotFound(format!("user id {user_id}"))),
+        }
+        if self.find_guest(user_id).await?.is_some() {
+            return Err(AppError::WorkspaceError(
+                "Guests can't manage the workspace".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateUser;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn workspace_transfer_should_need_acceptance() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ws = state.update_workspace_owner(1, 1).await?;
+
+        let transfer = state.create_workspace_transfer(&ws, 2).await?;
+        assert_eq!((transfer.from_id, transfer.to_id), (1, 2));
+        // only the new owner can accept it
+        let ret = state.accept_workspace_transfer(1, 3).await;
+        assert!(matches!(ret, Err(AppError::NotFound(_))));
+
+        let ws = state.accept_workspace_transfer(1, 2).await?;
+        assert_eq!(ws.owner_id, 2);
+        assert!(state.get_workspace_transfer(1).await?.is_none());
+        let admins = state.list_workspace_admins(1).await?;
+        assert_eq!(admins.len(), 1);
+        assert_eq!(admins[0].user_id, 1);
+
+        let ret = state.create_workspace_transfer(&ws, 2).await;
+        assert!(ret.is_err());
+        state.create_workspace_transfer(&ws, 3).await?;
+        assert!(state.cancel_workspace_transfer(1).await?);
+        assert!(!state.cancel_workspace_transfer(1).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deleted_owner_should_be_succeeded() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let owner = state
+            .create_user(&CreateUser::new(
+                "home",
+                "Owner",
+                "owner@home.org",
+                "123456",
+            ))
+            .await?;
+        let member = state
+            .create_user(&CreateUser::new(
+                "home",
+                "Member",
+                "member@home.org",
+                "123456",
+            ))
+            .await?;
+        let admin = state
+            .create_user(&CreateUser::new(
+                "home",
+                "Admin",
+                "admin@home.org",
+                "123456",
+            ))
+            .await?;
+        let ws = state
+            .find_workspace_by_id(owner.ws_id as _)
+            .await?
+            .expect("ws should exist");
+        assert_eq!(ws.owner_id, owner.id);
+        state.add_workspace_admin(&ws, admin.id as _).await?;
+
+        let delete = |id: i64| {
+            sqlx::query("DELETE FROM users WHERE id = $1")
+                .bind(id)
+                .execute(&state.pool)
+        };
+        // the admin takes over before the older member
+        delete(owner.id).await?;
+        let ws = state.find_workspace_by_id(ws.id as _).await?.unwrap();
+        assert_eq!(ws.owner_id, admin.id);
+        assert!(state.list_workspace_admins(ws.id as _).await?.is_empty());
+
+        delete(admin.id).await?;
+        let ws = state.find_workspace_by_id(ws.id as _).await?.unwrap();
+        assert_eq!(ws.owner_id, member.id);
+
+        delete(member.id).await?;
+        let ws = state.find_workspace_by_id(ws.id as _).await?.unwrap();
+        assert_eq!(ws.owner_id, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{AppError, AppState};
+use chat_core::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, Transaction};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct MessagePin {
+    #[sqlx(flatten)]
+    pub message: Message,
+    /// 0 is the top of the pinned list
+    pub position: i32,
+    #[serde(with = "chat_core::id")]
+    pub pinned_by: i64,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct PinList {
+    pub limit: i32,
+    pub pins: Vec<MessagePin>,
+    /// Once the limit is reached, the message that should be replaced by a new pin
+    /// (the bottom one). Pass it as `replace` when pinning.
+    #[serde(with = "chat_core::id::option")]
+    pub replace_candidate: Option<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct PinMessage {
+    #[serde(with = "chat_core::id")]
+    pub message_id: i64,
+    /// pinned message to unpin if the limit is reached
+    #[serde(default, with = "chat_core::id::option")]
+    pub replace: Option<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct ReorderPins {
+    /// all pinned message ids of the chat, in the new order
+    #[serde(with = "chat_core::id::list")]
+    pub message_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct PinLimit {
+    pub limit: i32,
+}
+
+impl AppState {
+    pub async fn list_pins(&self, chat_id: u64) -> Result<PinList, AppError> {
+        let limit = self.get_pin_limit(chat_id).await?;
+        let pins: Vec<MessagePin> = sqlx::query_as(
+            r#"
+            SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.created_at,
+                p.position, p.pinned_by, p.created_at AS pinned_at
+            FROM message_pins p
+            JOIN messages m ON m.id = p.message_id
+            WHERE p.chat_id = $1
+            ORDER BY p.position
+            "#,
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let replace_candidate = if pins.len() >= limit as usize {
+            pins.last().map(|p| p.message.id)
+        } else {
+            None
+        };
+        Ok(PinList {
+            limit,
+            pins,
+            replace_candidate,
+        })
+    }
+
+    /// Pin a message to the top of the chat's pinned list.
+    pub async fn pin_message(
+        &self,
+        chat_id: u64,
+        input: &PinMessage,
+        user_id: u64,
+    ) -> Result<PinList, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // lock the chat so concurrent pins can't go over the limit
+        let limit = self.lock_pin_limit(&mut tx, chat_id).await?;
+
+        let message: Option<(i64,)> =
+            sqlx::query_as("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(input.message_id)
+                .bind(chat_id as i64)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if message.is_none() {
+            return Err(AppError::NotFound(format!(
+                "message id {} in chat {chat_id}",
+                input.message_id
+            )));
+        }
+
+        // (message_id, position) of current pins
+        let pins: Vec<(i64, i32)> = sqlx::query_as(
+            "SELECT message_id, position FROM message_pins WHERE chat_id = $1 ORDER BY position",
+        )
+        .bind(chat_id as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+        if pins.iter().any(|(id, _)| *id == input.message_id) {
+            return Err(AppError::PinError(format!(
+                "message {} is already pinned",
+                input.message_id
+            )));
+        }
+
+        if pins.len() >= limit as usize {
+            let candidate = pins.last().map(|(id, _)| *id).unwrap_or_default();
+            let Some(replace) = input.replace else {
+                return Err(AppError::PinLimitReached(format!(
+                    "chat {chat_id} already has {limit} pins, unpin one or replace message {candidate}"
+                )));
+            };
+            if !pins.iter().any(|(id, _)| *id == replace) {
+                return Err(AppError::PinError(format!(
+                    "message {replace} to replace is not pinned"
+                )));
+            }
+            sqlx::query("DELETE FROM message_pins WHERE chat_id = $1 AND message_id = $2")
+                .bind(chat_id as i64)
+                .bind(replace)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query("UPDATE message_pins SET position = position + 1 WHERE chat_id = $1")
+            .bind(chat_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO message_pins (chat_id, message_id, position, pinned_by)
+            VALUES ($1, $2, 0, $3)
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(input.message_id)
+        .bind(user_id as i64)
+        .execute(&mut *tx)
+        .await?;
+        compact_pins(&mut tx, chat_id).await?;
+        tx.commit().await?;
+
+        self.list_pins(chat_id).await
+    }
+
+    pub async fn unpin_message(&self, chat_id: u64, message_id: u64) -> Result<PinList, AppError> {
+        let mut tx = self.pool.begin().await?;
+        self.lock_pin_limit(&mut tx, chat_id).await?;
+        let ret = sqlx::query("DELETE FROM message_pins WHERE chat_id = $1 AND message_id = $2")
+            .bind(chat_id as i64)
+            .bind(message_id as i64)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!(
+                "pinned message id {message_id} in chat {chat_id}"
+            )));
+        }
+        compact_pins(&mut tx, chat_id).await?;
+        tx.commit().await?;
+
+        self.list_pins(chat_id).await
+    }
+
+    /// Reorder the pins, `message_ids` must contain every pinned message exactly once.
+    pub async fn reorder_pins(
+        &self,
+        chat_id: u64,
+        input: &ReorderPins,
+    ) -> Result<PinList, AppError> {
+        let mut tx = self.pool.begin().await?;
+        self.lock_pin_limit(&mut tx, chat_id).await?;
+        let mut pinned: Vec<(i64,)> =
+            sqlx::query_as("SELECT message_id FROM message_pins WHERE chat_id = $1")
+                .bind(chat_id as i64)
+                .fetch_all(&mut *tx)
+                .await?;
+        let mut ids = input.message_ids.clone();
+        pinned.sort_unstable();
+        ids.sort_unstable();
+        if pinned.into_iter().map(|(id,)| id).ne(ids) {
+            return Err(AppError::PinError(
+                "message ids must contain every pinned message exactly once".to_string(),
+            ));
+        }
+
+        sqlx::query(
+            r#"
+            UPDATE message_pins p
+            SET position = a.ord - 1
+            FROM unnest($2::bigint[]) WITH ORDINALITY AS a(message_id, ord)
+            WHERE p.chat_id = $1 AND p.message_id = a.message_id
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(&input.message_ids)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.list_pins(chat_id).await
+    }
+
+    /// Set the pin limit of a chat. Existing pins over the limit are kept, but no new
+    /// message can be pinned until the chat is back under the limit.
+    pub async fn set_pin_limit(&self, chat_id: u64, limit: i32) -> Result<PinList, AppError> {
+        let max = self.config.chat.max_pins as i32;
+        if !(1..=max).contains(&limit) {
+            return Err(AppError::PinError(format!(
+                "pin limit must be between 1 and {max}"
+            )));
+        }
+        let ret = sqlx::query("UPDATE chats SET pin_limit = $1 WHERE id = $2")
+            .bind(limit)
+            .bind(chat_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("chat id {chat_id}")));
+        }
+
+        self.list_pins(chat_id).await
+    }
+
+    async fn get_pin_limit(&self, chat_id: u64) -> Result<i32, AppError> {
+        let limit: Option<(Option<i32>,)> =
+            sqlx::query_as("SELECT pin_limit FROM chats WHERE id = $1")
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        match limit {
+            Some((limit,)) => Ok(limit.unwrap_or(self.config.chat.max_pins as i32)),
+            None => Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        }
+    }
+
+    async fn lock_pin_limit(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        chat_id: u64,
+    ) -> Result<i32, AppError> {
+        let limit: Option<(Option<i32>,)> =
+            sqlx::query_as("SELECT pin_limit FROM chats WHERE id = $1 FOR UPDATE")
+                .bind(chat_id as i64)
+                .fetch_optional(&mut **tx)
+                .await?;
+        match limit {
+            Some((limit,)) => Ok(limit.unwrap_or(self.config.chat.max_pins as i32)),
+            None => Err(AppError::NotFound(format!("chat id {chat_id}"))),
+        }
+    }
+}
+
+// renumber positions to 0..n after pins are removed
+async fn compact_pins(tx: &mut Transaction<'_, Postgres>, chat_id: u64) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        UPDATE message_pins p
+        SET position = r.rn - 1
+        FROM (
+            SELECT message_id, row_number() OVER (ORDER BY position) AS rn
+            FROM message_pins
+            WHERE chat_id = $1
+        ) r
+        WHERE p.chat_id = $1 AND p.message_id = r.message_id
+        "#,
+    )
+    .bind(chat_id as i64)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn pin(message_id: i64, replace: Option<i64>) -> PinMessage {
+        PinMessage {
+            message_id,
+            replace,
+        }
+    }
+
+    fn pinned_ids(list: &PinList) -> Vec<i64> {
+        list.pins.iter().map(|p| p.message.id).collect()
+    }
+
+    #[tokio::test]
+    async fn pin_and_unpin_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.pin_message(1, &pin(1, None), 1).await?;
+        let list = state.pin_message(1, &pin(2, None), 1).await?;
+        // newest pin goes to the top
+        assert_eq!(pinned_ids(&list), vec![2, 1]);
+        assert_eq!(list.limit, 50);
+        assert!(list.replace_candidate.is_none());
+
+        let list = state.unpin_message(1, 2).await?;
+        assert_eq!(pinned_ids(&list), vec![1]);
+        assert_eq!(list.pins[0].position, 0);
+
+        // message from another chat can't be pinned
+        let err = state.pin_message(2, &pin(1, None), 1).await.unwrap_err();
+        assert!(matches!(err, AppError::NotFound(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_limit_should_prompt_replacement() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.set_pin_limit(1, 2).await?;
+        state.pin_message(1, &pin(1, None), 1).await?;
+        let list = state.pin_message(1, &pin(2, None), 1).await?;
+        assert_eq!(list.replace_candidate, Some(1));
+
+        let err = state.pin_message(1, &pin(3, None), 1).await.unwrap_err();
+        assert!(matches!(err, AppError::PinLimitReached(_)));
+
+        let list = state.pin_message(1, &pin(3, Some(1)), 1).await?;
+        assert_eq!(pinned_ids(&list), vec![3, 2]);
+        assert_eq!(list.replace_candidate, Some(2));
+
+        assert!(state.set_pin_limit(1, 0).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reorder_pins_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for id in 1..=3 {
+            state.pin_message(1, &pin(id, None), 1).await?;
+        }
+        let input = ReorderPins {
+            message_ids: vec![1, 3, 2],
+        };
+        let list = state.reorder_pins(1, &input).await?;
+        assert_eq!(pinned_ids(&list), vec![1, 3, 2]);
+
+        let input = ReorderPins {
+            message_ids: vec![1, 3],
+        };
+        assert!(state.reorder_pins(1, &input).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,475 @@
+use crate::{AppError, AppState};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use tracing::warn;
+use utoipa::ToSchema;
+
+const DEFAULT_GRACE_PERCENT: i32 = 10;
+// share of a limit from which workspace admins are warned
+const WARNING_PERCENT: i64 = 80;
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq, sqlx::FromRow)]
+pub struct Plan {
+    pub id: i64,
+    pub name: String,
+    /// None is unlimited
+    pub max_messages: Option<i64>,
+    pub max_storage_bytes: Option<i64>,
+    pub max_members: Option<i64>,
+    /// how far above a limit, in percent, creates and uploads are still accepted
+    pub grace_percent: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreatePlan {
+    pub name: String,
+    pub max_messages: Option<i64>,
+    pub max_storage_bytes: Option<i64>,
+    pub max_members: Option<i64>,
+    pub grace_percent: Option<i32>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct SetWorkspacePlan {
+    /// None removes the plan and its limits
+    pub plan_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaResource {
+    Messages,
+    Storage,
+    /// guests are not counted
+    Members,
+}
+
+/// Ordered by severity.
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaStatus {
+    Ok,
+    /// 80% of the limit or more is used
+    Warning,
+    /// above the limit but within the grace threshold
+    Grace,
+    Exceeded,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct QuotaUsage {
+    pub used: i64,
+    /// None is unlimited
+    pub limit: Option<i64>,
+    /// the usage from which admins are warned
+    pub warning_threshold: Option<i64>,
+    /// the limit plus its grace threshold
+    pub hard_limit: Option<i64>,
+    pub status: QuotaStatus,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct WorkspaceUsage {
+    pub ws_id: i64,
+    pub plan: Option<Plan>,
+    pub messages: QuotaUsage,
+    pub storage: QuotaUsage,
+    pub members: QuotaUsage,
+}
+
+impl fmt::Display for QuotaResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Messages => write!(f, "messages"),
+            Self::Storage => write!(f, "storage"),
+            Self::Members => write!(f, "members"),
+        }
+    }
+}
+
+impl Plan {
+    fn limit(&self, resource: QuotaResource) -> Option<i64> {
+        match resource {
+            QuotaResource::Messages => self.max_messages,
+            QuotaResource::Storage => self.max_storage_bytes,
+            QuotaResource::Members => self.max_members,
+        }
+    }
+}
+
+impl QuotaUsage {
+    fn new(used: i64, limit: Option<i64>, grace_percent: i32) -> Self {
+        let hard_limit = limit.map(|l| l + l * grace_percent as i64 / 100);
+        let warning_threshold = limit.map(|l| l * WARNING_PERCENT / 100);
+        let status = match (limit, hard_limit, warning_threshold) {
+            (Some(_), Some(hard), _) if used > hard => QuotaStatus::Exceeded,
+            (Some(limit), _, _) if used > limit => QuotaStatus::Grace,
+            (_, _, Some(warning)) if used >= warning => QuotaStatus::Warning,
+            _ => QuotaStatus::Ok,
+        };
+        Self {
+            used,
+            limit,
+            warning_threshold,
+            hard_limit,
+            status,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_plan(&self, input: &CreatePlan) -> Result<Plan, AppError> {
+        if input.name.is_empty() {
+            return Err(AppError::WorkspaceError(
+                "Plan name cannot be empty".to_string(),
+            ));
+        }
+        let limits = [
+            input.max_messages,
+            input.max_storage_bytes,
+            input.max_members,
+        ];
+        let grace_percent = input.grace_percent.unwrap_or(DEFAULT_GRACE_PERCENT);
+        if limits.iter().flatten().any(|l| *l < 0) || grace_percent < 0 {
+            return Err(AppError::WorkspaceError(
+                "Plan limits cannot be negative".to_string(),
+            ));
+        }
+
+        let plan = sqlx::query_as(
+            r#"
+            INSERT INTO plans (name, max_messages, max_storage_bytes, max_members, grace_percent)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, max_messages, max_storage_bytes, max_members, grace_percent, created_at
+            "#,
+        )
+        .bind(&input.name)
+        .bind(input.max_messages)
+        .bind(input.max_storage_bytes)
+        .bind(input.max_members)
+        .bind(grace_percent)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(plan)
+    }
+
+    pub async fn list_plans(&self) -> Result<Vec<Plan>, AppError> {
+        let plans = sqlx::query_as(
+            r#"
+            SELECT id, name, max_messages, max_storage_bytes, max_members, grace_percent, created_at
+            FROM plans
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(plans)
+    }
+
+    pub async fn set_workspace_plan(
+        &self,
+        ws_id: u64,
+        plan_id: Option<i64>,
+    ) -> Result<WorkspaceUsage, AppError> {
+        if let Some(id) = plan_id {
+            let exists: Option<(i64,)> = sqlx::query_as("SELECT id FROM plans WHERE id = $1")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+            if exists.is_none() {
+                return Err(AppError::NotFound(format!("plan id {id}")));
+            }
+        }
+        let updated = sqlx::query("UPDATE workspaces SET plan_id = $1 WHERE id = $2")
+            .bind(plan_id)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+        if updated.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("workspace id {ws_id}")));
+        }
+        self.get_workspace_usage(ws_id).await
+    }
+
+    pub async fn get_workspace_usage(&self, ws_id: u64) -> Result<WorkspaceUsage, AppError> {
+        let row: Option<(i64, i64, i64, Option<i64>)> = sqlx::query_as(
+            r#"
+            SELECT
+              (SELECT COALESCE(SUM(c.message_count), 0)::bigint FROM chats c WHERE c.ws_id = w.id),
+              w.storage_bytes,
+              (SELECT COUNT(*) FROM users u WHERE u.ws_id = w.id
+                AND NOT EXISTS (SELECT 1 FROM guests g WHERE g.user_id = u.id)),
+              w.plan_id
+            FROM workspaces w
+            WHERE w.id = $1
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((messages, storage, members, plan_id)) = row else {
+            return Err(AppError::NotFound(format!("workspace id {ws_id}")));
+        };
+
+        let plan: Option<Plan> = match plan_id {
+            Some(id) => {
+                sqlx::query_as(
+                    r#"
+                    SELECT id, name, max_messages, max_storage_bytes, max_members, grace_percent, created_at
+                    FROM plans
+                    WHERE id = $1
+                    "#,
+                )
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?
+            }
+            None => None,
+        };
+        let usage = |used, resource| {
+            let limit = plan.as_ref().and_then(|p| p.limit(resource));
+            let grace = plan.as_ref().map_or(0, |p| p.grace_percent);
+            QuotaUsage::new(used, limit, grace)
+        };
+        Ok(WorkspaceUsage {
+            ws_id: ws_id as _,
+            messages: usage(messages, QuotaResource::Messages),
+            storage: usage(storage, QuotaResource::Storage),
+            members: usage(members, QuotaResource::Members),
+            plan,
+        })
+    }
+
+    /// Check that adding `extra` of a resource keeps the workspace within its plan. Going
+    /// over the limit is allowed up to the grace threshold. The workspace owner and admins
+    /// get a `QuotaWarning` event when the usage first reaches the warning threshold and
+    /// again when it goes over the limit.
+    pub async fn check_quota(
+        &self,
+        ws_id: u64,
+        resource: QuotaResource,
+        extra: i64,
+    ) -> Result<(), AppError> {
+        let usage = self.get_workspace_usage(ws_id).await?;
+        let quota = match resource {
+            QuotaResource::Messages => usage.messages,
+            QuotaResource::Storage => usage.storage,
+            QuotaResource::Members => usage.members,
+        };
+        let grace = usage.plan.as_ref().map_or(0, |p| p.grace_percent);
+        let after = QuotaUsage::new(quota.used + extra, quota.limit, grace);
+        if after.status > quota.status && after.status != QuotaStatus::Exceeded {
+            self.send_quota_warning(ws_id, resource, &after).await?;
+        }
+        match after.status {
+            QuotaStatus::Ok | QuotaStatus::Warning => Ok(()),
+            QuotaStatus::Grace => {
+                warn!(
+                    "workspace {} is over its {} quota: {} of {:?}",
+                    ws_id, resource, after.used, after.limit
+                );
+                Ok(())
+            }
+            QuotaStatus::Exceeded => Err(AppError::QuotaExceeded(format!(
+                "workspace has reached its {resource} limit"
+            ))),
+        }
+    }
+
+    // delivered by notify_server to the workspace owner and admins
+    async fn send_quota_warning(
+        &self,
+        ws_id: u64,
+        resource: QuotaResource,
+        usage: &QuotaUsage,
+    ) -> Result<(), AppError> {
+        let Some(ws) = self.find_workspace_by_id(ws_id).await? else {
+            return Ok(());
+        };
+        let mut user_ids: Vec<_> = self
+            .list_workspace_admins(ws_id)
+            .await?
+            .into_iter()
+            .map(|a| a.user_id)
+            .collect();
+        if !user_ids.contains(&ws.owner_id) {
+            user_ids.push(ws.owner_id);
+        }
+
+        let mut warning = json!(usage);
+        warning["ws_id"] = json!(ws_id);
+        warning["resource"] = json!(resource);
+        let payload = json!({ "warning": warning, "user_ids": user_ids });
+        sqlx::query("SELECT pg_notify('quota_warning', $1)")
+            .bind(payload.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn add_storage_usage(&self, ws_id: u64, bytes: i64) -> Result<(), AppError> {
+        sqlx::query("UPDATE workspaces SET storage_bytes = storage_bytes + $1 WHERE id = $2")
+            .bind(bytes)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CreateMessage, CreateUser};
+    use anyhow::Result;
+    use sqlx::postgres::PgListener;
+    use std::time::Duration;
+
+    fn plan(name: &str) -> CreatePlan {
+        CreatePlan {
+            name: name.to_string(),
+            max_messages: Some(10),
+            max_storage_bytes: Some(100),
+            max_members: Some(5),
+            grace_percent: Some(20),
+        }
+    }
+
+    #[test]
+    fn quota_usage_should_apply_grace() {
+        assert_eq!(QuotaUsage::new(7, Some(10), 20).status, QuotaStatus::Ok);
+        let usage = QuotaUsage::new(10, Some(10), 20);
+        assert_eq!(usage.status, QuotaStatus::Warning);
+        assert_eq!(usage.warning_threshold, Some(8));
+        assert_eq!(usage.hard_limit, Some(12));
+        assert_eq!(QuotaUsage::new(12, Some(10), 20).status, QuotaStatus::Grace);
+        assert_eq!(
+            QuotaUsage::new(13, Some(10), 20).status,
+            QuotaStatus::Exceeded
+        );
+        assert_eq!(QuotaUsage::new(1000, None, 20).status, QuotaStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn workspace_usage_should_report_plan_limits() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let usage = state.get_workspace_usage(1).await?;
+        assert!(usage.plan.is_none());
+        assert_eq!(usage.messages.used, 10);
+        assert_eq!(usage.members.used, 5);
+        assert_eq!(usage.members.limit, None);
+
+        let plan = state.create_plan(&plan("starter")).await?;
+        let usage = state.set_workspace_plan(1, Some(plan.id)).await?;
+        assert_eq!(usage.plan, Some(plan));
+        // the fixtures have 10 messages, which is the limit
+        assert_eq!(usage.messages.status, QuotaStatus::Warning);
+        assert_eq!(usage.storage.status, QuotaStatus::Ok);
+        assert_eq!(usage.storage.limit, Some(100));
+
+        let err = state.set_workspace_plan(1, Some(1000)).await.unwrap_err();
+        assert_eq!(err.to_string(), "Not found: plan id 1000");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_message_should_respect_quota() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let plan = state.create_plan(&plan("starter")).await?;
+        state.set_workspace_plan(1, Some(plan.id)).await?;
+
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        // 10 messages in the fixtures, the grace allows 2 more
+        for _ in 0..2 {
+            state.create_message(input.clone(), 1, 1).await?;
+        }
+        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        assert!(matches!(err, AppError::QuotaExceeded(_)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_user_should_respect_member_quota() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut input = plan("starter");
+        input.max_members = Some(5);
+        input.grace_percent = Some(0);
+        let plan = state.create_plan(&input).await?;
+        state.set_workspace_plan(1, Some(plan.id)).await?;
+
+        let ws = state
+            .find_workspace_by_id(1)
+            .await?
+            .expect("ws should exist");
+        let input = CreateUser::new(&ws.name, "Eve Chen", "eve@acme.org", "123456");
+        let err = state.create_user(&input).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "quota exceeded: workspace has reached its members limit"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn storage_usage_should_add_up() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let plan = state.create_plan(&plan("starter")).await?;
+        state.set_workspace_plan(1, Some(plan.id)).await?;
+
+        state.check_quota(1, QuotaResource::Storage, 100).await?;
+        state.add_storage_usage(1, 100).await?;
+        assert_eq!(state.get_workspace_usage(1).await?.storage.used, 100);
+        // within the 20% grace
+        state.check_quota(1, QuotaResource::Storage, 20).await?;
+        assert!(state
+            .check_quota(1, QuotaResource::Storage, 21)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    async fn next_warning(listener: &mut PgListener) -> Result<serde_json::Value> {
+        let notif = tokio::time::timeout(Duration::from_secs(5), listener.recv()).await??;
+        Ok(serde_json::from_str(notif.payload())?)
+    }
+
+    #[tokio::test]
+    async fn quota_warning_should_be_sent_once_per_tier() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let plan = state.create_plan(&plan("starter")).await?;
+        state.set_workspace_plan(1, Some(plan.id)).await?;
+        let ws = state
+            .find_workspace_by_id(1)
+            .await?
+            .expect("ws should exist");
+        state.add_workspace_admin(&ws, 2).await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("quota_warning").await?;
+
+        // 50 of 100 bytes is fine, 80 reaches the warning threshold
+        state.check_quota(1, QuotaResource::Storage, 50).await?;
+        state.check_quota(1, QuotaResource::Storage, 80).await?;
+        let payload = next_warning(&mut listener).await?;
+        assert_eq!(payload["warning"]["resource"], "storage");
+        assert_eq!(payload["warning"]["status"], "warning");
+        assert_eq!(payload["warning"]["used"], 80);
+        // the admins and the owner
+        assert_eq!(payload["user_ids"], json!([2, ws.owner_id]));
+
+        // still a warning, then over the limit
+        state.add_storage_usage(1, 80).await?;
+        state.check_quota(1, QuotaResource::Storage, 10).await?;
+        state.check_quota(1, QuotaResource::Storage, 30).await?;
+        let payload = next_warning(&mut listener).await?;
+        assert_eq!(payload["warning"]["status"], "grace");
+        assert_eq!(payload["warning"]["used"], 110);
+        Ok(())
+    }
+}
@@ -0,0 +1,730 @@
+use crate::{
+    is_valid_webhook_url, post_webhook, AppError, AppState, Job, JobFuture, JobHandler, PinMessage,
+    REACTION_ADDED_EVENT,
+};
+use chat_core::Message;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{types::Json, FromRow};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+// daily counts of the workspace's ($1) channels since $2
+const CHANNEL_COUNTS: &str = r#"
+    FROM reaction_daily_counts d
+    JOIN chats c ON c.id = d.chat_id
+    WHERE d.ws_id = $1 AND d.day >= $2 AND c.deleted_at IS NULL
+        AND c.type IN ('public_channel', 'private_channel')"#;
+
+/// Job kind used to forward reacted messages to a webhook.
+pub const REACTION_WEBHOOK_JOB: &str = "reaction_webhook";
+
+/// emoji in the workspace summary and its timeline
+const TOP_EMOJI: i64 = 10;
+/// emoji in the summary of each channel
+const TOP_CHANNEL_EMOJI: usize = 5;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    #[serde(with = "chat_core::id::list")]
+    pub user_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct MessageReactions {
+    #[serde(with = "chat_core::id")]
+    pub message_id: i64,
+    pub reactions: Vec<ReactionCount>,
+    /// triggers run by this reaction
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub triggered: Vec<TriggerRun>,
+}
+
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TriggerAction {
+    /// pin the message in its chat
+    Pin,
+    /// post the message to `url`
+    Webhook { url: String },
+    /// mark the task created from the message as done
+    CompleteTask,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ReactionTrigger {
+    pub id: i64,
+    pub ws_id: i64,
+    pub emoji: String,
+    #[schema(value_type = TriggerAction)]
+    pub action: Json<TriggerAction>,
+    pub rate_limit_per_min: i32,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateReactionTrigger {
+    pub emoji: String,
+    pub action: TriggerAction,
+    #[serde(default = "default_rate_limit")]
+    pub rate_limit_per_min: i32,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct TriggerRun {
+    pub id: i64,
+    pub trigger_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub message_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    /// fired, rate_limited or failed
+    pub status: String,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ReactionAnalyticsQuery {
+    /// days to summarize, up to today, at most 365
+    #[serde(default = "default_analytics_days")]
+    pub days: u32,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct EmojiCount {
+    pub emoji: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct DailyEmojiCount {
+    pub day: NaiveDate,
+    pub emoji: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChannelReactions {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub name: Option<String>,
+    pub total: i64,
+    pub top: Vec<EmojiCount>,
+}
+
+/// Reactions in the channels of a workspace, direct messages and groups are left out.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ReactionAnalytics {
+    pub ws_id: i64,
+    /// first day of the summary, in UTC
+    pub since: NaiveDate,
+    /// most used emoji of the workspace
+    pub top: Vec<EmojiCount>,
+    /// daily counts of the most used emoji, days without reactions are left out
+    pub timeline: Vec<DailyEmojiCount>,
+    /// channels with reactions, most reacted first
+    pub channels: Vec<ChannelReactions>,
+}
+
+/// Runs `reaction_webhook` jobs enqueued by webhook triggers.
+pub struct ReactionWebhookJob;
+
+fn default_rate_limit() -> i32 {
+    10
+}
+
+fn default_analytics_days() -> u32 {
+    30
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn list_reactions(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<MessageReactions, AppError> {
+        self.verify_chat_message(chat_id, message_id).await?;
+        self.message_reactions(message_id, vec![]).await
+    }
+
+    /// Add a reaction of `user_id`. The first reaction with an emoji on a message runs
+    /// the workspace's triggers for that emoji.
+    pub async fn add_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<MessageReactions, AppError> {
+        if !is_valid_emoji(emoji) {
+            return Err(AppError::ReactionError(format!("Invalid emoji {emoji}")));
+        }
+        let message = self.verify_chat_message(chat_id, message_id).await?;
+
+        let ret = sqlx::query(
+            r#"
+            INSERT INTO message_reactions (message_id, user_id, emoji)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM message_reactions WHERE message_id = $1 AND emoji = $2",
+        )
+        .bind(message_id as i64)
+        .bind(emoji)
+        .fetch_one(&self.pool)
+        .await?;
+        let triggered = if ret.rows_affected() > 0 && count == 1 {
+            self.run_reaction_triggers(&message, user_id, emoji).await?
+        } else {
+            vec![]
+        };
+        if ret.rows_affected() > 0 {
+            let extra = json!({ "emoji": emoji });
+            self.dispatch_webhooks(REACTION_ADDED_EVENT, &message, user_id, extra)
+                .await?;
+        }
+
+        self.message_reactions(message_id, triggered).await
+    }
+
+    pub async fn remove_reaction(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<MessageReactions, AppError> {
+        self.verify_chat_message(chat_id, message_id).await?;
+        sqlx::query(
+            "DELETE FROM message_reactions WHERE message_id = $1 AND user_id = $2 AND emoji = $3",
+        )
+        .bind(message_id as i64)
+        .bind(user_id as i64)
+        .bind(emoji)
+        .execute(&self.pool)
+        .await?;
+
+        self.message_reactions(message_id, vec![]).await
+    }
+
+    /// Summarize the reactions of the last `days` days from the daily counts kept by
+    /// `reaction_counts_trigger`.
+    pub async fn reaction_analytics(
+        &self,
+        ws_id: u64,
+        days: u32,
+    ) -> Result<ReactionAnalytics, AppError> {
+        if !(1..=365).contains(&days) {
+            return Err(AppError::ReactionError(
+                "Days must be between 1 and 365".to_string(),
+            ));
+        }
+        let since = Utc::now().date_naive() - Duration::days(days as i64 - 1);
+        let top: Vec<EmojiCount> = sqlx::query_as(&format!(
+            r#"
+            SELECT d.emoji, SUM(d.count)::bigint AS count
+            {CHANNEL_COUNTS}
+            GROUP BY d.emoji
+            HAVING SUM(d.count) > 0
+            ORDER BY count DESC, d.emoji
+            LIMIT $3
+            "#
+        ))
+        .bind(ws_id as i64)
+        .bind(since)
+        .bind(TOP_EMOJI)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let emoji: Vec<_> = top.iter().map(|e| e.emoji.clone()).collect();
+        let timeline = sqlx::query_as(&format!(
+            r#"
+            SELECT d.day, d.emoji, SUM(d.count)::bigint AS count
+            {CHANNEL_COUNTS} AND d.emoji = ANY($3)
+            GROUP BY d.day, d.emoji
+            HAVING SUM(d.count) > 0
+            ORDER BY d.day, count DESC, d.emoji
+            "#
+        ))
+        .bind(ws_id as i64)
+        .bind(since)
+        .bind(&emoji)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let rows: Vec<(i64, Option<String>, String, i64)> = sqlx::query_as(&format!(
+            r#"
+            SELECT c.id, c.name, d.emoji, SUM(d.count)::bigint AS count
+            {CHANNEL_COUNTS}
+            GROUP BY c.id, c.name, d.emoji
+            HAVING SUM(d.count) > 0
+            ORDER BY c.id, count DESC, d.emoji
+            "#
+        ))
+        .bind(ws_id as i64)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut channels: Vec<ChannelReactions> = vec![];
+        for (chat_id, name, emoji, count) in rows {
+            let channel = match channels.last_mut() {
+                Some(c) if c.chat_id == chat_id => c,
+                _ => {
+                    channels.push(ChannelReactions {
+                        chat_id,
+                        name,
+                        total: 0,
+                        top: vec![],
+                    });
+                    channels.last_mut().expect("channel was just added")
+                }
+            };
+            channel.total += count;
+            if channel.top.len() < TOP_CHANNEL_EMOJI {
+                channel.top.push(EmojiCount { emoji, count });
+            }
+        }
+        channels.sort_by(|a, b| b.total.cmp(&a.total).then(a.chat_id.cmp(&b.chat_id)));
+
+        Ok(ReactionAnalytics {
+            ws_id: ws_id as i64,
+            since,
+            top,
+            timeline,
+            channels,
+        })
+    }
+
+    pub async fn create_reaction_trigger(
+        &self,
+        ws_id: u64,
+        input: &CreateReactionTrigger,
+        user_id: u64,
+    ) -> Result<ReactionTrigger, AppError> {
+        if !is_valid_emoji(&input.emoji) {
+            return Err(AppError::ReactionError(format!(
+                "Invalid emoji {}",
+                input.emoji
+            )));
+        }
+        if !(1..=600).contains(&input.rate_limit_per_min) {
+            return Err(AppError::ReactionError(
+                "Rate limit must be between 1 and 600 per minute".to_string(),
+            ));
+        }
+        if let TriggerAction::Webhook { url } = &input.action {
+            if !is_valid_webhook_url(url) {
+                return Err(AppError::ReactionError(
+                    "Webhook url must be a http(s) url".to_string(),
+                ));
+            }
+        }
+
+        let trigger = sqlx::query_as(
+            r#"
+            INSERT INTO reaction_triggers (ws_id, emoji, action, rate_limit_per_min, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, ws_id, emoji, action, rate_limit_per_min, created_by, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(&input.emoji)
+        .bind(Json(&input.action))
+        .bind(input.rate_limit_per_min)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(trigger)
+    }
+
+    pub async fn list_reaction_triggers(
+        &self,
+        ws_id: u64,
+    ) -> Result<Vec<ReactionTrigger>, AppError> {
+        let triggers = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, emoji, action, rate_limit_per_min, created_by, created_at
+            FROM reaction_triggers
+            WHERE ws_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(triggers)
+    }
+
+    pub async fn delete_reaction_trigger(&self, ws_id: u64, id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM reaction_triggers WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    async fn run_reaction_triggers(
+        &self,
+        message: &Message,
+        user_id: u64,
+        emoji: &str,
+    ) -> Result<Vec<TriggerRun>, AppError> {
+        let triggers: Vec<ReactionTrigger> = sqlx::query_as(
+            r#"
+            SELECT t.id, t.ws_id, t.emoji, t.action, t.rate_limit_per_min, t.created_by, t.created_at
+            FROM reaction_triggers t
+            JOIN chats c ON c.ws_id = t.ws_id
+            WHERE c.id = $1 AND t.emoji = $2
+            ORDER BY t.id
+            "#,
+        )
+        .bind(message.chat_id)
+        .bind(emoji)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut runs = Vec::with_capacity(triggers.len());
+        for trigger in triggers {
+            let mut run = self.start_trigger_run(&trigger, message, user_id).await?;
+            if run.status != "fired" {
+                runs.push(run);
+                continue;
+            }
+            if let Err(e) = self.run_trigger_action(&trigger, message, user_id).await {
+                run = sqlx::query_as(
+                    r#"
+                    UPDATE reaction_trigger_runs SET status = 'failed', error = $1
+                    WHERE id = $2
+                    RETURNING id, trigger_id, message_id, user_id, status, error, created_at
+                    "#,
+                )
+                .bind(e.to_string())
+                .bind(run.id)
+                .fetch_one(&self.pool)
+                .await?;
+            }
+            runs.push(run);
+        }
+        Ok(runs)
+    }
+
+    /// Record a run of the trigger, as rate limited if it already fired too often
+    /// in the last minute.
+    async fn start_trigger_run(
+        &self,
+        trigger: &ReactionTrigger,
+        message: &Message,
+        user_id: u64,
+    ) -> Result<TriggerRun, AppError> {
+        let mut tx = self.pool.begin().await?;
+        // lock the trigger so concurrent reactions can't go over the limit
+        sqlx::query("SELECT id FROM reaction_triggers WHERE id = $1 FOR UPDATE")
+            .bind(trigger.id)
+            .execute(&mut *tx)
+            .await?;
+        let (fired,): (i64,) = sqlx::query_as(
+            r#"
+            SELECT COUNT(*) FROM reaction_trigger_runs
+            WHERE trigger_id = $1 AND status <> 'rate_limited'
+              AND created_at > NOW() - interval '1 minute'
+            "#,
+        )
+        .bind(trigger.id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let status = if fired < trigger.rate_limit_per_min as i64 {
+            "fired"
+        } else {
+            "rate_limited"
+        };
+
+        let run = sqlx::query_as(
+            r#"
+            INSERT INTO reaction_trigger_runs (trigger_id, message_id, user_id, status)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, trigger_id, message_id, user_id, status, error, created_at
+            "#,
+        )
+        .bind(trigger.id)
+        .bind(message.id)
+        .bind(user_id as i64)
+        .bind(status)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(run)
+    }
+
+    async fn run_trigger_action(
+        &self,
+        trigger: &ReactionTrigger,
+        message: &Message,
+        user_id: u64,
+    ) -> Result<(), AppError> {
+        match &trigger.action.0 {
+            TriggerAction::Pin => {
+                let input = PinMessage {
+                    message_id: message.id,
+                    replace: None,
+                };
+                self.pin_message(message.chat_id as _, &input, user_id)
+                    .await?;
+            }
+            TriggerAction::CompleteTask => {
+                self.complete_message_task(message.id as _).await?;
+            }
+            TriggerAction::Webhook { url } => {
+                let payload = json!({
+                    "url": url,
+                    "trigger_id": trigger.id,
+                    "emoji": trigger.emoji,
+                    "user_id": user_id,
+                    "message": message,
+                });
+                self.enqueue_job(REACTION_WEBHOOK_JOB, payload, None)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn verify_chat_message(
+        &self,
+        chat_id: u64,
+        message_id: u64,
+    ) -> Result<Message, AppError> {
+        let message: Option<Message> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, sender_id, content, files, created_at
+            FROM messages
+            WHERE id = $1 AND chat_id = $2
+            "#,
+        )
+        .bind(message_id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        message
+            .ok_or_else(|| AppError::NotFound(format!("message id {message_id} in chat {chat_id}")))
+    }
+
+    /// Reactions of each of the messages, messages without any are left out.
+    pub(crate) async fn reactions_of_messages(
+        &self,
+        message_ids: &[i64],
+    ) -> Result<HashMap<i64, Vec<ReactionCount>>, AppError> {
+        let rows: Vec<(i64, String, i64, Vec<i64>)> = sqlx::query_as(
+            r#"
+            SELECT message_id, emoji, COUNT(*), array_agg(user_id ORDER BY created_at)
+            FROM message_reactions
+            WHERE message_id = ANY($1)
+            GROUP BY message_id, emoji
+            ORDER BY message_id, MIN(created_at)
+            "#,
+        )
+        .bind(message_ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut reactions: HashMap<i64, Vec<ReactionCount>> = HashMap::new();
+        for (message_id, emoji, count, user_ids) in rows {
+            reactions
+                .entry(message_id)
+                .or_default()
+                .push(ReactionCount {
+                    emoji,
+                    count,
+                    user_ids,
+                });
+        }
+        Ok(reactions)
+    }
+
+    async fn message_reactions(
+        &self,
+        message_id: u64,
+        triggered: Vec<TriggerRun>,
+    ) -> Result<MessageReactions, AppError> {
+        let reactions = sqlx::query_as(
+            r#"
+            SELECT emoji, COUNT(*) AS count, array_agg(user_id ORDER BY created_at) AS user_ids
+            FROM message_reactions
+            WHERE message_id = $1
+            GROUP BY emoji
+            ORDER BY MIN(created_at)
+            "#,
+        )
+        .bind(message_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(MessageReactions {
+            message_id: message_id as _,
+            reactions,
+            triggered,
+        })
+    }
+}
+
+impl JobHandler for ReactionWebhookJob {
+    fn kind(&self) -> &'static str {
+        REACTION_WEBHOOK_JOB
+    }
+
+    fn run(&self, _state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move { post_webhook(&job.payload).await })
+    }
+}
+
+fn is_valid_emoji(emoji: &str) -> bool {
+    !emoji.is_empty() && emoji.chars().count() <= 32 && !emoji.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn trigger(
+        emoji: &str,
+        action: TriggerAction,
+        rate_limit_per_min: i32,
+    ) -> CreateReactionTrigger {
+        CreateReactionTrigger {
+            emoji: emoji.to_string(),
+            action,
+            rate_limit_per_min,
+        }
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_reaction_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.add_reaction(1, 1, 1, "👍").await?;
+        state.add_reaction(1, 1, 2, "👍").await?;
+        let ret = state.add_reaction(1, 1, 1, "🎉").await?;
+        assert_eq!(ret.reactions.len(), 2);
+        assert_eq!(ret.reactions[0].count, 2);
+        assert_eq!(ret.reactions[0].user_ids, vec![1, 2]);
+
+        let ret = state.remove_reaction(1, 1, 1, "🎉").await?;
+        assert_eq!(ret.reactions.len(), 1);
+
+        // message 1 is not in chat 2
+        assert!(state.add_reaction(2, 1, 1, "👍").await.is_err());
+        assert!(state.add_reaction(1, 1, 1, "").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reaction_analytics_should_summarize_channels() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for user_id in [1, 2, 3] {
+            state.add_reaction(1, 1, user_id, "👍").await?;
+        }
+        state.add_reaction(1, 2, 1, "🎉").await?;
+        state.add_reaction(1, 2, 2, "🎉").await?;
+        state.remove_reaction(1, 2, 2, "🎉").await?;
+        let input = |content: &str| crate::CreateMessage {
+            content: content.to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input("report"), 2, 1).await?;
+        state.add_reaction(2, message.id as _, 3, "🎉").await?;
+        // direct messages are left out
+        let message = state.create_message(input("hi"), 3, 1).await?;
+        state.add_reaction(3, message.id as _, 2, "👍").await?;
+
+        let count = |emoji: &str, count: i64| EmojiCount {
+            emoji: emoji.to_string(),
+            count,
+        };
+        let analytics = state.reaction_analytics(1, 30).await?;
+        assert_eq!(analytics.top, vec![count("👍", 3), count("🎉", 2)]);
+        assert_eq!(analytics.timeline.len(), 2);
+        assert_eq!(analytics.timeline[0].day, Utc::now().date_naive());
+        assert_eq!(analytics.timeline[0].count, 3);
+        let channels: Vec<_> = analytics
+            .channels
+            .iter()
+            .map(|c| (c.chat_id, c.total))
+            .collect();
+        assert_eq!(channels, vec![(1, 4), (2, 1)]);
+        assert_eq!(
+            analytics.channels[0].top,
+            vec![count("👍", 3), count("🎉", 1)]
+        );
+
+        assert!(state.reaction_analytics(2, 30).await?.top.is_empty());
+        assert!(state.reaction_analytics(1, 0).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pin_trigger_should_pin_message_with_rate_limit() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state
+            .create_reaction_trigger(1, &trigger("📌", TriggerAction::Pin, 1), 1)
+            .await?;
+
+        let ret = state.add_reaction(1, 1, 2, "📌").await?;
+        assert_eq!(ret.triggered.len(), 1);
+        assert_eq!(ret.triggered[0].status, "fired");
+        assert_eq!(state.list_pins(1).await?.pins[0].message.id, 1);
+
+        // more reactions with the same emoji don't run it again
+        let ret = state.add_reaction(1, 1, 3, "📌").await?;
+        assert!(ret.triggered.is_empty());
+
+        let ret = state.add_reaction(1, 2, 2, "📌").await?;
+        assert_eq!(ret.triggered[0].status, "rate_limited");
+        assert_eq!(state.list_pins(1).await?.pins.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn webhook_trigger_should_enqueue_job() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let action = TriggerAction::Webhook {
+            url: "https://example.com/hook".to_string(),
+        };
+        state
+            .create_reaction_trigger(1, &trigger("🎫", action, 10), 1)
+            .await?;
+        let ret = state.add_reaction(1, 3, 1, "🎫").await?;
+        assert_eq!(ret.triggered[0].status, "fired");
+
+        let job = state.claim_job().await?.unwrap();
+        assert_eq!(job.kind, REACTION_WEBHOOK_JOB);
+        assert_eq!(job.payload["message"]["id"], 3);
+
+        let action = TriggerAction::Webhook {
+            url: "ftp://example.com".to_string(),
+        };
+        assert!(state
+            .create_reaction_trigger(1, &trigger("🎫", action, 10), 1)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{AppError, AppState};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Views of a member's chat list which can be marked as read at once. Apart from
+/// `archived`, folders leave out archived chats like the chat list does.
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatFolder {
+    Pinned,
+    /// single and group chats
+    Direct,
+    /// public and private channels
+    Channels,
+    Archived,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChatRead {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub last_read_id: i64,
+    /// unread messages left in the chat
+    pub unread: i64,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct MarkChatRead {
+    /// message to mark as read with everything before it, the latest one if not given
+    #[serde(default, with = "chat_core::id::option")]
+    pub last_read_id: Option<i64>,
+}
+
+/// Chats whose read state changed, also sent to the member's other clients as a single
+/// `UnreadCountChanged` event.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ReadState {
+    pub chats: Vec<ChatRead>,
+}
+
+impl ChatFolder {
+    // matches chats `c` with the member's pin `p`
+    fn condition(&self) -> &'static str {
+        match self {
+            Self::Pinned => "p.chat_id IS NOT NULL AND c.archived_at IS NULL",
+            Self::Direct => "c.type IN ('single', 'group') AND c.archived_at IS NULL",
+            Self::Channels => {
+                "c.type IN ('public_channel', 'private_channel') AND c.archived_at IS NULL"
+            }
+            Self::Archived => "c.archived_at IS NOT NULL",
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Mark every message in the user's chats of `folder`, or all their chats if None, as
+    /// read in one transaction. Chats the user had read already are left out.
+    pub async fn mark_chats_read(
+        &self,
+        user_id: u64,
+        folder: Option<ChatFolder>,
+    ) -> Result<ReadState, AppError> {
+        let condition = folder.map_or("TRUE", |f| f.condition());
+        let mut tx = self.pool.begin().await?;
+        let mut chats: Vec<ChatRead> = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO chat_reads (user_id, chat_id, last_read_id)
+            SELECT $1, c.id, MAX(m.id)
+            FROM chats c
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $1
+            JOIN messages m ON m.chat_id = c.id
+            LEFT JOIN chat_pins p ON p.chat_id = c.id AND p.user_id = $1
+            WHERE c.deleted_at IS NULL AND {condition}
+            GROUP BY c.id
+            ON CONFLICT (user_id, chat_id) DO UPDATE
+            SET last_read_id = EXCLUDED.last_read_id, updated_at = NOW()
+            WHERE chat_reads.last_read_id < EXCLUDED.last_read_id
+            RETURNING chat_id, last_read_id, 0::bigint AS unread
+            "#
+        ))
+        .bind(user_id as i64)
+        .fetch_all(&mut *tx)
+        .await?;
+        chats.sort_by_key(|c| c.chat_id);
+
+        if !chats.is_empty() {
+            let payload = json!({ "user_id": user_id, "chats": chats });
+            sqlx::query("SELECT pg_notify('unread_count_changed', $1)")
+                .bind(payload.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(ReadState { chats })
+    }
+
+    /// Move the user's read marker of the chat forward, markers never move back. The user's
+    /// other clients get a `ChatRead` event if the marker moved.
+    pub async fn mark_chat_read(
+        &self,
+        chat_id: u64,
+        user_id: u64,
+        input: &MarkChatRead,
+    ) -> Result<ChatRead, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let (last_id,): (Option<i64>,) = match input.last_read_id {
+            Some(id) => sqlx::query_as("SELECT id FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(id)
+                .bind(chat_id as i64)
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or_else(|| AppError::NotFound(format!("message id {id}")))?,
+            None => {
+                sqlx::query_as("SELECT MAX(id) FROM messages WHERE chat_id = $1")
+                    .bind(chat_id as i64)
+                    .fetch_one(&mut *tx)
+                    .await?
+            }
+        };
+        let moved = match last_id {
+            Some(last_id) => {
+                sqlx::query(
+                    r#"
+                    INSERT INTO chat_reads (user_id, chat_id, last_read_id)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (user_id, chat_id) DO UPDATE
+                    SET last_read_id = EXCLUDED.last_read_id, updated_at = NOW()
+                    WHERE chat_reads.last_read_id < EXCLUDED.last_read_id
+                    "#,
+                )
+                .bind(user_id as i64)
+                .bind(chat_id as i64)
+                .bind(last_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                    > 0
+            }
+            None => false,
+        };
+        let read: ChatRead = sqlx::query_as(
+            r#"
+            SELECT $1::bigint AS chat_id, COALESCE(r.last_read_id, 0) AS last_read_id,
+                chat_unread_count($1, $2) AS unread
+            FROM (SELECT 1) _
+            LEFT JOIN chat_reads r ON r.chat_id = $1 AND r.user_id = $2
+            "#,
+        )
+        .bind(chat_id as i64)
+        .bind(user_id as i64)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if moved {
+            let payload = json!({ "user_id": user_id, "read": read });
+            sqlx::query("SELECT pg_notify('chat_read', $1)")
+                .bind(payload.to_string())
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(read)
+    }
+
+    /// Unread messages of others in the user's chats, chats without any are left out.
+    pub async fn list_unread_counts(&self, user_id: u64) -> Result<Vec<ChatRead>, AppError> {
+        let counts = sqlx::query_as(
+            r#"
+            SELECT c.id AS chat_id, COALESCE(r.last_read_id, 0) AS last_read_id,
+                COUNT(m.id) AS unread
+            FROM chats c
+            JOIN chat_members cm ON cm.chat_id = c.id AND cm.user_id = $1
+            LEFT JOIN chat_reads r ON r.chat_id = c.id AND r.user_id = $1
+            JOIN messages m ON m.chat_id = c.id
+                AND m.id > COALESCE(r.last_read_id, 0)
+                AND m.sender_id <> $1
+            WHERE c.deleted_at IS NULL
+            GROUP BY c.id, r.last_read_id
+            ORDER BY c.id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CreateMessage;
+    use anyhow::Result;
+    use sqlx::postgres::PgListener;
+
+    #[tokio::test]
+    async fn mark_chats_read_should_cover_folders() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let unread = state.list_unread_counts(2).await?;
+        assert_eq!(unread.len(), 1);
+        assert_eq!((unread[0].chat_id, unread[0].unread), (1, 8));
+
+        // chat 1 is a channel, the direct chats have no messages yet
+        assert!(state
+            .mark_chats_read(2, Some(ChatFolder::Direct))
+            .await?
+            .chats
+            .is_empty());
+        let read = state.mark_chats_read(2, Some(ChatFolder::Channels)).await?;
+        let expected = ChatRead {
+            chat_id: 1,
+            last_read_id: 10,
+            unread: 0,
+        };
+        assert_eq!(read.chats, [expected]);
+        assert!(state.list_unread_counts(2).await?.is_empty());
+        assert!(state.mark_chats_read(2, None).await?.chats.is_empty());
+
+        let input = CreateMessage {
+            content: "hello".to_string(),
+            files: vec![],
+        };
+        let message = state.create_message(input, 3, 1).await?;
+        assert!(state
+            .mark_chats_read(2, Some(ChatFolder::Pinned))
+            .await?
+            .chats
+            .is_empty());
+        state.pin_chat(3, 2).await?;
+        let read = state.mark_chats_read(2, Some(ChatFolder::Pinned)).await?;
+        assert_eq!(read.chats.len(), 1);
+        assert_eq!(read.chats[0].last_read_id, message.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mark_chat_read_should_only_move_forward() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let mut listener = PgListener::connect_with(&state.pool).await?;
+        listener.listen("chat_read").await?;
+
+        let input = MarkChatRead {
+            last_read_id: Some(5),
+        };
+        let read = state.mark_chat_read(1, 2, &input).await?;
+        // one of the 5 later messages is user 2's own
+        assert_eq!((read.last_read_id, read.unread), (5, 4));
+        let notif = listener.recv().await?;
+        let payload: serde_json::Value = serde_json::from_str(notif.payload())?;
+        assert_eq!(payload["user_id"], 2);
+        assert_eq!(payload["read"]["unread"], 4);
+
+        let input = MarkChatRead {
+            last_read_id: Some(3),
+        };
+        let read = state.mark_chat_read(1, 2, &input).await?;
+        assert_eq!(read.last_read_id, 5);
+        let read = state.mark_chat_read(1, 2, &MarkChatRead::default()).await?;
+        assert_eq!((read.last_read_id, read.unread), (10, 0));
+
+        // chat 2 has no messages
+        let read = state.mark_chat_read(2, 2, &MarkChatRead::default()).await?;
+        assert_eq!((read.last_read_id, read.unread), (0, 0));
+        let input = MarkChatRead {
+            last_read_id: Some(1),
+        };
+        let err = state.mark_chat_read(2, 2, &input).await.unwrap_err();
+        assert_eq!(err.to_string(), "Not found: message id 1");
+        Ok(())
+    }
+}
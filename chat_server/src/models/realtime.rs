@@ -0,0 +1,172 @@
+use crate::{config::RealtimeEndpointConfig, AppState};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tokio::task::JoinSet;
+use utoipa::{IntoParams, ToSchema};
+
+// a node not answering in time is considered down
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Health of a node as reported by its `/status`, healthier first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointHealth {
+    Operational,
+    Degraded,
+    /// not reachable, or reporting itself down
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct RealtimeEndpoint {
+    pub url: String,
+    pub region: String,
+    pub health: EndpointHealth,
+    /// round trip of the last probe from the api server, None if the probe failed.
+    /// Clients can ping the candidates themselves for their own latency
+    pub latency_ms: Option<u64>,
+    pub checked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListRealtimeEndpoints {
+    /// region of the client, its endpoints come first among equally healthy ones
+    pub region: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StatusReport {
+    status: EndpointHealth,
+}
+
+impl AppState {
+    /// The configured notify_server endpoints, best first: healthier ones, then the ones
+    /// in `region`, then the ones answering probes faster. The health is probed when the
+    /// cached one is older than `realtime.health_ttl_secs`.
+    pub async fn list_realtime_endpoints(&self, region: Option<&str>) -> Vec<RealtimeEndpoint> {
+        let ttl = Duration::seconds(self.config.realtime.health_ttl_secs as i64);
+        let now = Utc::now();
+        let mut endpoints = vec![];
+        let mut probes = JoinSet::new();
+        {
+            let cache = self.realtime_health.read().unwrap();
+            for config in &self.config.realtime.endpoints {
+                match cache.get(&config.url) {
+                    Some(e) if now - e.checked_at < ttl && e.region == config.region => {
+                        endpoints.push(e.clone())
+                    }
+                    _ => {
+                        probes.spawn(probe_endpoint(config.clone()));
+                    }
+                }
+            }
+        }
+        while let Some(ret) = probes.join_next().await {
+            let endpoint = ret.expect("probe should not panic");
+            self.realtime_health
+                .write()
+                .unwrap()
+                .insert(endpoint.url.clone(), endpoint.clone());
+            endpoints.push(endpoint);
+        }
+
+        rank_endpoints(&mut endpoints, region);
+        endpoints
+    }
+}
+
+async fn probe_endpoint(config: RealtimeEndpointConfig) -> RealtimeEndpoint {
+    let url = format!("{}/status", config.url.trim_end_matches('/'));
+    let start = Instant::now();
+    let report = match reqwest::Client::new()
+        .get(&url)
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+    {
+        Ok(res) if res.status().is_success() => res.json::<StatusReport>().await.ok(),
+        _ => None,
+    };
+
+    RealtimeEndpoint {
+        url: config.url,
+        region: config.region,
+        health: report.as_ref().map_or(EndpointHealth::Down, |r| r.status),
+        latency_ms: report.map(|_| start.elapsed().as_millis() as u64),
+        checked_at: Utc::now(),
+    }
+}
+
+fn rank_endpoints(endpoints: &mut [RealtimeEndpoint], region: Option<&str>) {
+    endpoints.sort_by_key(|e| {
+        let nearby = region.is_some_and(|r| r.eq_ignore_ascii_case(&e.region));
+        (e.health, !nearby, e.latency_ms.unwrap_or(u64::MAX))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{routing::get, Json, Router};
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    fn endpoint(region: &str, health: EndpointHealth, latency_ms: u64) -> RealtimeEndpoint {
+        RealtimeEndpoint {
+            url: format!("https://{region}.example.com"),
+            region: region.to_string(),
+            health,
+            latency_ms: Some(latency_ms),
+            checked_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn rank_endpoints_should_prefer_healthy_nearby_nodes() {
+        let mut endpoints = vec![
+            endpoint("us-east", EndpointHealth::Degraded, 5),
+            endpoint("eu-west", EndpointHealth::Operational, 80),
+            endpoint("ap-south", EndpointHealth::Operational, 40),
+            endpoint("eu-central", EndpointHealth::Down, 1),
+        ];
+        rank_endpoints(&mut endpoints, Some("EU-WEST"));
+        let regions: Vec<_> = endpoints.iter().map(|e| e.region.as_str()).collect();
+        assert_eq!(regions, ["eu-west", "ap-south", "us-east", "eu-central"]);
+
+        rank_endpoints(&mut endpoints, None);
+        assert_eq!(endpoints[0].region, "ap-south");
+    }
+
+    #[tokio::test]
+    async fn probe_endpoint_should_read_node_status() -> Result<()> {
+        let app = Router::new().route(
+            "/status",
+            get(|| async { Json(json!({ "status": "degraded" })) }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = RealtimeEndpointConfig {
+            url: format!("http://{addr}/"),
+            region: "local".to_string(),
+        };
+        let endpoint = probe_endpoint(config).await;
+        assert_eq!(endpoint.health, EndpointHealth::Degraded);
+        assert!(endpoint.latency_ms.is_some());
+
+        // nothing listens on port 1
+        let config = RealtimeEndpointConfig {
+            url: "http://127.0.0.1:1".to_string(),
+            region: "local".to_string(),
+        };
+        let endpoint = probe_endpoint(config).await;
+        assert_eq!(
+            (endpoint.health, endpoint.latency_ms),
+            (EndpointHealth::Down, None)
+        );
+        Ok(())
+    }
+}
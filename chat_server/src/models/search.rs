@@ -0,0 +1,228 @@
+use crate::{AppError, AppState, Job, JobFuture, JobHandler};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Job kind indexing the next batch of messages of a running reindex.
+pub const SEARCH_REINDEX_JOB: &str = "search_reindex";
+
+/// messages indexed by a single batch, the rest is left to a follow up job so none runs for long
+const MESSAGES_PER_BATCH: i64 = 1000;
+
+const REINDEX_COLUMNS: &str = "id, status, total, indexed, last_id, started_at, finished_at";
+
+#[derive(Debug, Clone, Copy, ToSchema, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "search_reindex_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SearchReindexStatus {
+    Running,
+    Finished,
+}
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct SearchReindex {
+    pub id: i64,
+    pub status: SearchReindexStatus,
+    /// messages when the reindex started, new ones are indexed as they are sent
+    pub total: i64,
+    pub indexed: i64,
+    /// id of the last indexed message
+    #[serde(with = "chat_core::id")]
+    pub last_id: i64,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Runs `search_reindex` jobs enqueued for new reindexes.
+pub struct SearchReindexJob;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReindexJob {
+    reindex_id: i64,
+}
+
+#[allow(dead_code)]
+impl AppState {
+    /// Start rebuilding the search index of all messages, or return the reindex which is
+    /// already running. Pass `enqueue` to index it in the background.
+    pub async fn create_search_reindex(&self, enqueue: bool) -> Result<SearchReindex, AppError> {
+        let running: Option<SearchReindex> = sqlx::query_as(&format!(
+            "SELECT {REINDEX_COLUMNS} FROM search_reindexes WHERE status = 'running' ORDER BY id LIMIT 1"
+        ))
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(reindex) = running {
+            return Ok(reindex);
+        }
+
+        let reindex: SearchReindex = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO search_reindexes (total)
+            SELECT COUNT(*) FROM messages
+            RETURNING {REINDEX_COLUMNS}
+            "#
+        ))
+        .fetch_one(&self.pool)
+        .await?;
+        if enqueue {
+            let job = ReindexJob {
+                reindex_id: reindex.id,
+            };
+            self.enqueue_job(SEARCH_REINDEX_JOB, job, None).await?;
+        }
+        Ok(reindex)
+    }
+
+    pub async fn get_latest_search_reindex(&self) -> Result<Option<SearchReindex>, AppError> {
+        let reindex = sqlx::query_as(&format!(
+            "SELECT {REINDEX_COLUMNS} FROM search_reindexes ORDER BY id DESC LIMIT 1"
+        ))
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(reindex)
+    }
+
+    /// Index the next batch of messages and record the progress, the reindex is finished
+    /// once a batch comes up short. Messages of encrypted chats are left out of the index.
+    pub async fn run_search_reindex_batch(&self, id: u64) -> Result<SearchReindex, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let reindex: Option<SearchReindex> = sqlx::query_as(&format!(
+            "SELECT {REINDEX_COLUMNS} FROM search_reindexes WHERE id = $1 FOR UPDATE"
+        ))
+        .bind(id as i64)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let reindex = match reindex {
+            Some(r) if r.status == SearchReindexStatus::Running => r,
+            Some(r) => return Ok(r),
+            None => return Err(AppError::NotFound(format!("search reindex id {id}"))),
+        };
+
+        let (count, last_id): (i64, Option<i64>) = sqlx::query_as(
+            r#"
+            WITH batch AS (
+                UPDATE messages m
+                SET search_vector = message_search_vector(m.chat_id, m.content)
+                WHERE m.id IN (
+                    SELECT id FROM messages WHERE id > $1 ORDER BY id LIMIT $2
+                )
+                RETURNING m.id
+            )
+            SELECT COUNT(*), MAX(id) FROM batch
+            "#,
+        )
+        .bind(reindex.last_id)
+        .bind(MESSAGES_PER_BATCH)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let reindex = sqlx::query_as(&format!(
+            r#"
+            UPDATE search_reindexes
+            SET indexed = indexed + $2,
+                last_id = $3,
+                status = CASE WHEN $4 THEN 'finished'::search_reindex_status ELSE status END,
+                finished_at = CASE WHEN $4 THEN NOW() ELSE finished_at END
+            WHERE id = $1
+            RETURNING {REINDEX_COLUMNS}
+            "#
+        ))
+        .bind(id as i64)
+        .bind(count)
+        .bind(last_id.unwrap_or(reindex.last_id))
+        .bind(count < MESSAGES_PER_BATCH)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(reindex)
+    }
+}
+
+impl JobHandler for SearchReindexJob {
+    fn kind(&self) -> &'static str {
+        SEARCH_REINDEX_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let job: ReindexJob =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            let reindex = state.run_search_reindex_batch(job.reindex_id as _).await?;
+            if reindex.status == SearchReindexStatus::Running {
+                state.enqueue_job(SEARCH_REINDEX_JOB, job, None).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ChatSettings, JobRunner};
+    use anyhow::Result;
+
+    async fn indexed(state: &AppState, chat_id: i64) -> Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM messages WHERE chat_id = $1 AND search_vector IS NOT NULL",
+        )
+        .bind(chat_id)
+        .fetch_one(&state.pool)
+        .await?;
+        Ok(count)
+    }
+
+    #[tokio::test]
+    async fn search_reindex_should_index_existing_messages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        // the fixtures were loaded before indexing existed
+        sqlx::query("UPDATE messages SET search_vector = NULL")
+            .execute(&state.pool)
+            .await?;
+        let encrypted = ChatSettings {
+            encrypted: true,
+            ..Default::default()
+        };
+        state.update_chat_settings(1, &encrypted).await?;
+
+        let reindex = state.create_search_reindex(true).await?;
+        assert_eq!((reindex.total, reindex.indexed), (10, 0));
+        // a second request joins the running reindex
+        assert_eq!(state.create_search_reindex(true).await?.id, reindex.id);
+
+        let runner = JobRunner::new(state.clone()).register(SearchReindexJob);
+        assert!(runner.run_once().await?);
+        let reindex = state
+            .get_latest_search_reindex()
+            .await?
+            .expect("reindex should exist");
+        assert_eq!(reindex.status, SearchReindexStatus::Finished);
+        assert_eq!(reindex.indexed, 10);
+        assert!(reindex.finished_at.is_some());
+        assert!(!runner.run_once().await?);
+        // encrypted chats stay out of the index
+        assert_eq!(indexed(&state, 1).await?, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn new_messages_should_be_indexed() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = crate::CreateMessage {
+            content: "quarterly report".to_string(),
+            files: vec![],
+        };
+        state.create_message(input, 2, 1).await?;
+        assert_eq!(indexed(&state, 2).await?, 1);
+
+        let encrypted = ChatSettings {
+            encrypted: true,
+            ..Default::default()
+        };
+        state.update_chat_settings(2, &encrypted).await?;
+        assert_eq!(indexed(&state, 2).await?, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+use super::token::hash_token;
+use crate::{AppError, AppState};
+use chat_core::{User, JWT_DURATION};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::FromRow;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+
+const MIN_SESSION_SECS: i32 = 5 * 60;
+const MAX_ALLOWLIST_LEN: usize = 100;
+// last_seen_at is only written once a minute, idle timeouts are that much less precise
+const SESSION_TOUCH_SECS: i64 = 60;
+
+/// Restrictions on how members of a workspace sign in and how long they stay signed in.
+/// The workspace owner is exempt from `require_sso` so a workspace can't lock itself out.
+#[derive(Debug, Clone, Default, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SecurityPolicy {
+    /// reject password and guest link sessions
+    pub require_sso: bool,
+    /// seconds a session lasts after signing in, at least 300
+    pub max_session_secs: Option<i32>,
+    /// seconds without requests after which a session ends, at least 300
+    pub idle_timeout_secs: Option<i32>,
+    /// addresses or networks in CIDR notation requests may come from, empty allows all
+    pub ip_allowlist: Vec<String>,
+}
+
+/// How a session was signed in.
+#[derive(Debug, Clone, Copy, PartialEq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "session_method", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SessionMethod {
+    Password,
+    GuestLink,
+    /// through the workspace's identity provider
+    Sso,
+}
+
+#[derive(Debug, FromRow)]
+struct Session {
+    method: SessionMethod,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    is_owner: bool,
+}
+
+impl SecurityPolicy {
+    /// Whether anything about sessions is restricted, the allowlist aside.
+    pub fn restricts_sessions(&self) -> bool {
+        self.require_sso || self.max_session_secs.is_some() || self.idle_timeout_secs.is_some()
+    }
+
+    pub fn allows_ip(&self, ip: &str) -> bool {
+        let Ok(ip) = ip.parse::<IpAddr>() else {
+            return false;
+        };
+        self.ip_allowlist.is_empty() || networks_contain(&self.ip_allowlist, ip)
+    }
+
+    fn validate(&mut self) -> Result<(), AppError> {
+        for secs in [self.max_session_secs, self.idle_timeout_secs]
+            .into_iter()
+            .flatten()
+        {
+            if secs < MIN_SESSION_SECS {
+                return Err(AppError::WorkspaceError(format!(
+                    "Session timeouts must be at least {MIN_SESSION_SECS} seconds"
+                )));
+            }
+        }
+        if self.ip_allowlist.len() > MAX_ALLOWLIST_LEN {
+            return Err(AppError::WorkspaceError(format!(
+                "IP allowlist can have at most {MAX_ALLOWLIST_LEN} entries"
+            )));
+        }
+        for net in self.ip_allowlist.iter_mut() {
+            let (ip, len) = parse_network(net.trim()).ok_or_else(|| {
+                AppError::WorkspaceError(format!("Invalid IP address or network {net}"))
+            })?;
+            *net = format!("{ip}/{len}");
+        }
+        self.ip_allowlist.sort();
+        self.ip_allowlist.dedup();
+        Ok(())
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn get_security_policy(&self, ws_id: u64) -> Result<SecurityPolicy, AppError> {
+        let policy = sqlx::query_as(
+            r#"
+            SELECT require_sso, max_session_secs, idle_timeout_secs, ip_allowlist
+            FROM workspace_security_policies
+            WHERE ws_id = $1
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(policy.unwrap_or_default())
+    }
+
+    /// Replace the security policy of the workspace. `client_ip` is the address of the
+    /// request and has to stay allowed, so the caller can't lock themselves out.
+    pub async fn update_security_policy(
+        &self,
+        ws_id: u64,
+        mut input: SecurityPolicy,
+        actor_id: u64,
+        client_ip: Option<&str>,
+    ) -> Result<SecurityPolicy, AppError> {
+        input.validate()?;
+        if !input.ip_allowlist.is_empty() {
+            match client_ip {
+                Some(ip) if input.allows_ip(ip) => {}
+                ip => {
+                    return Err(AppError::WorkspaceError(format!(
+                        "IP allowlist must include your address {}",
+                        ip.unwrap_or("unknown")
+                    )))
+                }
+            }
+        }
+
+        let policy = sqlx::query_as(
+            r#"
+            INSERT INTO workspace_security_policies
+                (ws_id, require_sso, max_session_secs, idle_timeout_secs, ip_allowlist)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (ws_id) DO UPDATE
+            SET require_sso = EXCLUDED.require_sso,
+                max_session_secs = EXCLUDED.max_session_secs,
+                idle_timeout_secs = EXCLUDED.idle_timeout_secs,
+                ip_allowlist = EXCLUDED.ip_allowlist,
+                updated_at = NOW()
+            RETURNING require_sso, max_session_secs, idle_timeout_secs, ip_allowlist
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(input.require_sso)
+        .bind(input.max_session_secs)
+        .bind(input.idle_timeout_secs)
+        .bind(&input.ip_allowlist)
+        .fetch_one(&self.pool)
+        .await?;
+        self.record_audit(ws_id, actor_id, "security_policy.update", None, &policy)
+            .await?;
+
+        Ok(policy)
+    }
+
+    /// Sign a session token for the user and record the session, `secs` defaults to the
+    /// lifetime of session tokens.
+    pub async fn create_session(
+        &self,
+        user: User,
+        method: SessionMethod,
+        secs: Option<u64>,
+    ) -> Result<String, AppError> {
+        let secs = secs.unwrap_or(JWT_DURATION);
+        let user_id = user.id;
+        let token = self.ek.sign_with_duration(user, secs)?;
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND expires_at < NOW()")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            r#"
+            INSERT INTO sessions (token_hash, user_id, method, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(hash_token(&token))
+        .bind(user_id)
+        .bind(method)
+        .bind(Utc::now() + Duration::seconds(secs as _))
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(token)
+    }
+
+    /// Check the session of `token` against the policy of the user's workspace and record
+    /// its use. Tokens without a session are only accepted while sessions aren't restricted.
+    pub async fn verify_session(
+        &self,
+        user: &User,
+        token: &str,
+        policy: &SecurityPolicy,
+    ) -> Result<(), AppError> {
+        if !policy.restricts_sessions() {
+            return Ok(());
+        }
+        let session: Option<Session> = sqlx::query_as(
+            r#"
+            SELECT s.method, s.created_at, s.last_seen_at, w.owner_id = s.user_id AS is_owner
+            FROM sessions s
+            JOIN users u ON u.id = s.user_id
+            JOIN workspaces w ON w.id = u.ws_id
+            WHERE s.token_hash = $1 AND s.user_id = $2 AND s.expires_at > NOW()
+            "#,
+        )
+        .bind(hash_token(token))
+        .bind(user.id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let expired = || AppError::Unauthorized("session has expired".to_string());
+        let session = session.ok_or_else(expired)?;
+
+        let now = Utc::now();
+        if policy.require_sso && session.method != SessionMethod::Sso && !session.is_owner {
+            return Err(AppError::PermissionDenied(
+                "workspace requires single sign-on".to_string(),
+            ));
+        }
+        if policy
+            .max_session_secs
+            .is_some_and(|secs| session.created_at + Duration::seconds(secs as _) < now)
+        {
+            return Err(expired());
+        }
+        if policy
+            .idle_timeout_secs
+            .is_some_and(|secs| session.last_seen_at + Duration::seconds(secs as _) < now)
+        {
+            return Err(expired());
+        }
+
+        if session.last_seen_at + Duration::seconds(SESSION_TOUCH_SECS) < now {
+            sqlx::query("UPDATE sessions SET last_seen_at = NOW() WHERE token_hash = $1")
+                .bind(hash_token(token))
+                .execute(&self.pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reject password sign ins of workspaces requiring single sign-on, except for the owner.
+    pub async fn verify_password_signin(&self, user: &User) -> Result<(), AppError> {
+        let policy = self.get_security_policy(user.ws_id as _).await?;
+        if !policy.require_sso {
+            return Ok(());
+        }
+        let ws = self.find_workspace_by_id(user.ws_id as _).await?;
+        if ws.is_some_and(|ws| ws.owner_id == user.id) {
+            return Ok(());
+        }
+        let details = json!({ "user_id": user.id });
+        self.record_audit(
+            user.ws_id as _,
+            user.id as _,
+            "security_policy.signin_rejected",
+            None,
+            details,
+        )
+        .await?;
+        Err(AppError::PermissionDenied(
+            "workspace requires single sign-on".to_string(),
+        ))
+    }
+}
+
+// an address, or a network as `address/prefix length`
+fn parse_network(s: &str) -> Option<(IpAddr, u8)> {
+    let (ip, len) = match s.split_once('/') {
+        Some((ip, len)) => (ip.parse::<IpAddr>().ok()?, Some(len.parse::<u8>().ok()?)),
+        None => (s.parse::<IpAddr>().ok()?, None),
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let len = len.unwrap_or(max);
+    if len > max {
+        return None;
+    }
+    // normalize to the network address
+    let ip = match ip {
+        IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & mask_v4(len)).into()),
+        IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & mask_v6(len)).into()),
+    };
+    Some((ip, len))
+}
+
+/// Whether `ip` is in any of the addresses or CIDR networks, invalid ones are skipped.
+pub(crate) fn networks_contain(networks: &[String], ip: IpAddr) -> bool {
+    networks
+        .iter()
+        .filter_map(|net| parse_network(net))
+        .any(|net| network_contains(net, ip))
+}
+
+fn network_contains((net, len): (IpAddr, u8), ip: IpAddr) -> bool {
+    match (net, ip) {
+        (IpAddr::V4(net), IpAddr::V4(ip)) => u32::from(ip) & mask_v4(len) == u32::from(net),
+        (IpAddr::V6(net), IpAddr::V6(ip)) => u128::from(ip) & mask_v6(len) == u128::from(net),
+        _ => false,
+    }
+}
+
+fn mask_v4(len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - len as u32).unwrap_or(0)
+}
+
+fn mask_v6(len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - len as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn policy_should_match_ip_allowlist() {
+        let policy = SecurityPolicy {
+            ip_allowlist: vec!["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.allows_ip("10.1.2.3"));
+        assert!(policy.allows_ip("2001:db8::1"));
+        assert!(!policy.allows_ip("11.0.0.1"));
+        assert!(!policy.allows_ip("not an ip"));
+        assert!(SecurityPolicy::default().allows_ip("11.0.0.1"));
+        assert_eq!(
+            parse_network("192.168.1.7/24"),
+            Some(("192.168.1.0".parse().unwrap(), 24))
+        );
+        assert_eq!(parse_network("0.0.0.0/0").map(|n| n.1), Some(0));
+        assert_eq!(parse_network("10.0.0.0/33"), None);
+    }
+
+    #[tokio::test]
+    async fn security_policy_should_be_enforced_on_sessions() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = SecurityPolicy {
+            ip_allowlist: vec!["10.0.0.0/8".to_string()],
+            ..Default::default()
+        };
+        let err = state
+            .update_security_policy(1, input.clone(), 1, Some("192.168.0.1"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "workspace error: IP allowlist must include your address 192.168.0.1"
+        );
+        let input = SecurityPolicy {
+            max_session_secs: Some(60),
+            ..input
+        };
+        let err = state
+            .update_security_policy(1, input.clone(), 1, Some("10.0.0.1"))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "workspace error: Session timeouts must be at least 300 seconds"
+        );
+
+        let user = state.find_user_by_id(2).await?.expect("user should exist");
+        let session = state
+            .create_session(user.clone(), SessionMethod::Password, None)
+            .await?;
+        let input = SecurityPolicy {
+            max_session_secs: Some(3600),
+            idle_timeout_secs: Some(600),
+            ..input
+        };
+        let policy = state
+            .update_security_policy(1, input, 1, Some("10.0.0.1"))
+            .await?;
+        assert_eq!(policy.ip_allowlist, ["10.0.0.0/8"]);
+        state.verify_session(&user, &session, &policy).await?;
+        // tokens signed without a session are rejected once sessions are restricted
+        let token = state.ek.sign(user.clone())?;
+        let err = state
+            .verify_session(&user, &token, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unauthorized: session has expired");
+
+        sqlx::query("UPDATE sessions SET last_seen_at = NOW() - interval '11 minutes'")
+            .execute(&state.pool)
+            .await?;
+        let err = state
+            .verify_session(&user, &session, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unauthorized: session has expired");
+
+        let policy = SecurityPolicy {
+            require_sso: true,
+            ..Default::default()
+        };
+        let policy = state.update_security_policy(1, policy, 1, None).await?;
+        let session = state
+            .create_session(user.clone(), SessionMethod::Password, None)
+            .await?;
+        let err = state
+            .verify_session(&user, &session, &policy)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: workspace requires single sign-on"
+        );
+        assert!(state.verify_password_signin(&user).await.is_err());
+        Ok(())
+    }
+}
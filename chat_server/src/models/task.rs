@@ -0,0 +1,376 @@
+use crate::{AppError, AppState, Job, JobFuture, JobHandler};
+use chat_core::{Task, TaskStatus};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, ToSchema};
+
+/// Job kind used to remind the assignee when a task is due.
+pub const TASK_REMINDER_JOB: &str = "task_reminder";
+
+const TASK_COLUMNS: &str = "id, chat_id, message_id, title, assignee_id, due_at, status, created_by, created_at, completed_at";
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateTask {
+    #[serde(with = "chat_core::id")]
+    pub message_id: i64,
+    /// defaults to the beginning of the message
+    pub title: Option<String>,
+    /// must be a member of the chat
+    #[serde(default, with = "chat_core::id::option")]
+    pub assignee_id: Option<i64>,
+    pub due_at: Option<DateTime<Utc>>,
+}
+
+/// Fields to change, missing ones are kept.
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct UpdateTask {
+    pub title: Option<String>,
+    #[serde(default, with = "chat_core::id::option")]
+    pub assignee_id: Option<i64>,
+    pub due_at: Option<DateTime<Utc>>,
+    pub status: Option<TaskStatus>,
+}
+
+#[derive(Debug, Clone, Default, IntoParams, ToSchema, Serialize, Deserialize)]
+pub struct ListTasks {
+    pub status: Option<TaskStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TaskReminder {
+    task_id: i64,
+    due_at: DateTime<Utc>,
+}
+
+/// Runs `task_reminder` jobs scheduled at the due date of tasks.
+pub struct TaskReminderJob;
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_task(
+        &self,
+        chat_id: u64,
+        input: &CreateTask,
+        user_id: u64,
+    ) -> Result<Task, AppError> {
+        let message: Option<(String,)> =
+            sqlx::query_as("SELECT content FROM messages WHERE id = $1 AND chat_id = $2")
+                .bind(input.message_id)
+                .bind(chat_id as i64)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((content,)) = message else {
+            return Err(AppError::NotFound(format!(
+                "message id {} in chat {chat_id}",
+                input.message_id
+            )));
+        };
+        let title = match &input.title {
+            Some(title) => title.trim().to_string(),
+            None => content.chars().take(100).collect(),
+        };
+        self.verify_task_input(chat_id, &title, input.assignee_id)
+            .await?;
+
+        let ret = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO tasks (chat_id, message_id, title, assignee_id, due_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING {TASK_COLUMNS}
+            "#
+        ))
+        .bind(chat_id as i64)
+        .bind(input.message_id)
+        .bind(&title)
+        .bind(input.assignee_id)
+        .bind(input.due_at)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await;
+        let task: Task = match ret {
+            Ok(task) => task,
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                return Err(AppError::TaskError(format!(
+                    "message {} is already a task",
+                    input.message_id
+                )))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        self.schedule_task_reminder(&task).await?;
+        Ok(task)
+    }
+
+    pub async fn update_task(
+        &self,
+        chat_id: u64,
+        id: u64,
+        input: &UpdateTask,
+    ) -> Result<Task, AppError> {
+        let task = self
+            .get_task(chat_id, id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("task id {id}")))?;
+        let title = input
+            .title
+            .as_ref()
+            .map(|t| t.trim().to_string())
+            .unwrap_or(task.title);
+        let assignee_id = input.assignee_id.or(task.assignee_id);
+        self.verify_task_input(chat_id, &title, assignee_id).await?;
+
+        let updated: Task = sqlx::query_as(&format!(
+            r#"
+            UPDATE tasks
+            SET title = $1,
+                assignee_id = $2,
+                due_at = COALESCE($3, due_at),
+                status = COALESCE($4, status),
+                completed_at = CASE
+                    WHEN COALESCE($4, status) = 'done' THEN COALESCE(completed_at, NOW())
+                    ELSE NULL
+                END
+            WHERE id = $5
+            RETURNING {TASK_COLUMNS}
+            "#
+        ))
+        .bind(&title)
+        .bind(assignee_id)
+        .bind(input.due_at)
+        .bind(input.status)
+        .bind(id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+
+        if updated.due_at != task.due_at {
+            self.schedule_task_reminder(&updated).await?;
+        }
+        Ok(updated)
+    }
+
+    /// Mark the task created from `message_id` as done.
+    pub async fn complete_message_task(&self, message_id: u64) -> Result<Task, AppError> {
+        let task: Option<Task> = sqlx::query_as(&format!(
+            r#"
+            UPDATE tasks SET status = 'done', completed_at = COALESCE(completed_at, NOW())
+            WHERE message_id = $1
+            RETURNING {TASK_COLUMNS}
+            "#
+        ))
+        .bind(message_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        task.ok_or_else(|| AppError::TaskError(format!("message {message_id} is not a task")))
+    }
+
+    pub async fn get_task(&self, chat_id: u64, id: u64) -> Result<Option<Task>, AppError> {
+        let task = sqlx::query_as(&format!(
+            "SELECT {TASK_COLUMNS} FROM tasks WHERE id = $1 AND chat_id = $2"
+        ))
+        .bind(id as i64)
+        .bind(chat_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(task)
+    }
+
+    /// Tasks of a chat, open ones with the nearest due date first.
+    pub async fn list_chat_tasks(
+        &self,
+        chat_id: u64,
+        input: &ListTasks,
+    ) -> Result<Vec<Task>, AppError> {
+        let tasks = sqlx::query_as(&format!(
+            r#"
+            SELECT {TASK_COLUMNS} FROM tasks
+            WHERE chat_id = $1 AND ($2::task_status IS NULL OR status = $2)
+            ORDER BY status, due_at NULLS LAST, id
+            "#
+        ))
+        .bind(chat_id as i64)
+        .bind(input.status)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tasks)
+    }
+
+    /// Tasks assigned to a user across all chats.
+    pub async fn list_user_tasks(
+        &self,
+        user_id: u64,
+        input: &ListTasks,
+    ) -> Result<Vec<Task>, AppError> {
+        let tasks = sqlx::query_as(&format!(
+            r#"
+            SELECT {TASK_COLUMNS} FROM tasks
+            WHERE assignee_id = $1 AND ($2::task_status IS NULL OR status = $2)
+            ORDER BY status, due_at NULLS LAST, id
+            "#
+        ))
+        .bind(user_id as i64)
+        .bind(input.status)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tasks)
+    }
+
+    async fn verify_task_input(
+        &self,
+        chat_id: u64,
+        title: &str,
+        assignee_id: Option<i64>,
+    ) -> Result<(), AppError> {
+        if title.is_empty() || title.chars().count() > 256 {
+            return Err(AppError::TaskError(
+                "Title must be between 1 and 256 characters".to_string(),
+            ));
+        }
+        if let Some(assignee_id) = assignee_id {
+            if !self.is_chat_member(chat_id, assignee_id as _).await? {
+                return Err(AppError::TaskError(format!(
+                    "Assignee {assignee_id} is not a member of chat {chat_id}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    async fn schedule_task_reminder(&self, task: &Task) -> Result<(), AppError> {
+        if let Some(due_at) = task.due_at {
+            let reminder = TaskReminder {
+                task_id: task.id,
+                due_at,
+            };
+            self.enqueue_job(TASK_REMINDER_JOB, reminder, Some(due_at))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Notify the assignee (or the creator) that the task is due, unless it was done
+    /// or rescheduled since the reminder was scheduled.
+    async fn send_task_reminder(&self, reminder: &TaskReminder) -> Result<(), AppError> {
+        let task: Option<Task> =
+            sqlx::query_as(&format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = $1"))
+                .bind(reminder.task_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some(task) = task else {
+            return Ok(());
+        };
+        if task.status == TaskStatus::Done || task.due_at != Some(reminder.due_at) {
+            return Ok(());
+        }
+
+        let user_id = task.assignee_id.unwrap_or(task.created_by);
+        let payload = json!({ "task": task, "user_ids": [user_id] });
+        sqlx::query("SELECT pg_notify('task_reminder', $1)")
+            .bind(payload.to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+impl JobHandler for TaskReminderJob {
+    fn kind(&self) -> &'static str {
+        TASK_REMINDER_JOB
+    }
+
+    fn run(&self, state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move {
+            let reminder: TaskReminder =
+                serde_json::from_value(job.payload).map_err(anyhow::Error::from)?;
+            state.send_task_reminder(&reminder).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{JobRunner, JobStatus};
+    use anyhow::Result;
+    use chrono::Duration;
+
+    fn input(message_id: i64, assignee_id: Option<i64>) -> CreateTask {
+        CreateTask {
+            message_id,
+            title: None,
+            assignee_id,
+            due_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn create_and_list_tasks_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let task = state.create_task(1, &input(1, Some(2)), 1).await?;
+        assert_eq!(task.status, TaskStatus::Open);
+        assert!(!task.title.is_empty());
+        state.create_task(1, &input(2, None), 1).await?;
+
+        assert!(state.create_task(1, &input(1, None), 1).await.is_err());
+        // message 1 is not in chat 2
+        assert!(state.create_task(2, &input(1, None), 1).await.is_err());
+
+        assert_eq!(
+            state.list_chat_tasks(1, &ListTasks::default()).await?.len(),
+            2
+        );
+        let tasks = state.list_user_tasks(2, &ListTasks::default()).await?;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].id, task.id);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_task_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let task = state.create_task(1, &input(1, None), 1).await?;
+        let update = UpdateTask {
+            status: Some(TaskStatus::Done),
+            ..Default::default()
+        };
+        let task = state.update_task(1, task.id as _, &update).await?;
+        assert_eq!(task.status, TaskStatus::Done);
+        assert!(task.completed_at.is_some());
+
+        let input = ListTasks {
+            status: Some(TaskStatus::Open),
+        };
+        assert!(state.list_chat_tasks(1, &input).await?.is_empty());
+
+        let update = UpdateTask {
+            assignee_id: Some(10),
+            ..Default::default()
+        };
+        assert!(state.update_task(1, task.id as _, &update).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn task_reminder_should_be_scheduled_at_due_date() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let due_at = Utc::now() - Duration::seconds(1);
+        let input = CreateTask {
+            due_at: Some(due_at),
+            ..input(1, Some(2))
+        };
+        state.create_task(1, &input, 1).await?;
+
+        let runner = JobRunner::new(state.clone()).register(TaskReminderJob);
+        assert!(runner.run_once().await?);
+        let (status,): (JobStatus,) =
+            sqlx::query_as("SELECT status FROM jobs WHERE kind = 'task_reminder'")
+                .fetch_one(&state.pool)
+                .await?;
+        assert_eq!(status, JobStatus::Done);
+        Ok(())
+    }
+}
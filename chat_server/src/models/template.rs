@@ -0,0 +1,310 @@
+use crate::{AppError, AppState, ChatDTO, ChatSettings, CreateMessage, PinMessage};
+use chat_core::{Chat, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+const TEMPLATE_COLUMNS: &str = "id, ws_id, name, name_pattern, topic, public, members, groups, welcome_message, created_by, created_at";
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct ChannelTemplate {
+    pub id: i64,
+    pub ws_id: i64,
+    pub name: String,
+    /// name of created channels, `{name}` is replaced by the name given on creation
+    pub name_pattern: String,
+    pub topic: Option<String>,
+    pub public: bool,
+    #[serde(with = "chat_core::id::list")]
+    pub members: Vec<i64>,
+    /// chats whose members are added as well
+    #[serde(with = "chat_core::id::list")]
+    pub groups: Vec<i64>,
+    /// posted by the creator and pinned in created channels
+    pub welcome_message: Option<String>,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct CreateChannelTemplate {
+    pub name: String,
+    pub name_pattern: String,
+    pub topic: Option<String>,
+    #[serde(default = "default_public")]
+    pub public: bool,
+    #[serde(default, with = "chat_core::id::list")]
+    pub members: Vec<i64>,
+    #[serde(default, with = "chat_core::id::list")]
+    pub groups: Vec<i64>,
+    pub welcome_message: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, ToSchema, Serialize, Deserialize)]
+pub struct ChannelFromTemplate {
+    /// replaces `{name}` in the name pattern of the template
+    pub name: String,
+    /// added on top of the members of the template
+    #[serde(default, with = "chat_core::id::list")]
+    pub members: Vec<i64>,
+}
+
+fn default_public() -> bool {
+    true
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_channel_template(
+        &self,
+        ws_id: u64,
+        input: &CreateChannelTemplate,
+        user_id: u64,
+    ) -> Result<ChannelTemplate, AppError> {
+        let name = input.name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err(AppError::WorkspaceError(
+                "Template name must be between 1 and 64 characters".to_string(),
+            ));
+        }
+        if input.name_pattern.trim().is_empty() || input.name_pattern.chars().count() > 64 {
+            return Err(AppError::WorkspaceError(
+                "Name pattern must be between 1 and 64 characters".to_string(),
+            ));
+        }
+        let members = self.workspace_members(ws_id, &input.members).await?;
+        if members.len() != input.members.len() {
+            return Err(AppError::WorkspaceError(
+                "Some members are not in the workspace".to_string(),
+            ));
+        }
+        let groups: Vec<(i64,)> =
+            sqlx::query_as("SELECT id FROM chats WHERE ws_id = $1 AND id = ANY($2)")
+                .bind(ws_id as i64)
+                .bind(&input.groups)
+                .fetch_all(&self.pool)
+                .await?;
+        if groups.len() != input.groups.len() {
+            return Err(AppError::WorkspaceError(
+                "Some groups are not in the workspace".to_string(),
+            ));
+        }
+
+        let ret = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO channel_templates (ws_id, name, name_pattern, topic, public, members, groups, welcome_message, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING {TEMPLATE_COLUMNS}
+            "#
+        ))
+        .bind(ws_id as i64)
+        .bind(name)
+        .bind(input.name_pattern.trim())
+        .bind(&input.topic)
+        .bind(input.public)
+        .bind(&input.members)
+        .bind(&input.groups)
+        .bind(&input.welcome_message)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await;
+        match ret {
+            Ok(template) => Ok(template),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() => Err(
+                AppError::WorkspaceError(format!("Template {name} already exists")),
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn list_channel_templates(
+        &self,
+        ws_id: u64,
+    ) -> Result<Vec<ChannelTemplate>, AppError> {
+        let templates = sqlx::query_as(&format!(
+            "SELECT {TEMPLATE_COLUMNS} FROM channel_templates WHERE ws_id = $1 ORDER BY name"
+        ))
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(templates)
+    }
+
+    pub async fn get_channel_template(
+        &self,
+        ws_id: u64,
+        name: &str,
+    ) -> Result<Option<ChannelTemplate>, AppError> {
+        let template = sqlx::query_as(&format!(
+            "SELECT {TEMPLATE_COLUMNS} FROM channel_templates WHERE ws_id = $1 AND name = $2"
+        ))
+        .bind(ws_id as i64)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(template)
+    }
+
+    pub async fn delete_channel_template(&self, ws_id: u64, id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM channel_templates WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Create a channel from the workspace template `name` with `user` as its owner. The
+    /// topic goes to the chat settings and the welcome message is posted and pinned.
+    pub async fn create_channel_from_template(
+        &self,
+        ws_id: u64,
+        name: &str,
+        input: &ChannelFromTemplate,
+        user: &User,
+    ) -> Result<Chat, AppError> {
+        let template = self
+            .get_channel_template(ws_id, name)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("channel template {name}")))?;
+        let channel_name = template.name_pattern.replace("{name}", input.name.trim());
+
+        let (group_members,): (Vec<i64>,) = sqlx::query_as(
+            "SELECT COALESCE(array_agg(DISTINCT user_id), '{}') FROM chat_members WHERE chat_id = ANY($1)",
+        )
+        .bind(&template.groups)
+        .fetch_one(&self.pool)
+        .await?;
+        let mut ids = vec![user.id];
+        ids.extend(&template.members);
+        ids.extend(group_members);
+        ids.extend(&input.members);
+        let ids: Vec<i64> = ids.into_iter().fold(Vec::new(), |mut acc, id| {
+            if !acc.contains(&id) {
+                acc.push(id);
+            }
+            acc
+        });
+        // members who left the workspace since the template was made are skipped
+        let members = self.workspace_members(ws_id, &ids).await?;
+        let members = ids.into_iter().filter(|id| members.contains(id)).collect();
+
+        let input = ChatDTO {
+            name: Some(channel_name),
+            topic: None,
+            members,
+            public: template.public,
+        };
+        let chat = self.create_chat(input, ws_id, user.id as _).await?;
+
+        if template.topic.is_some() {
+            let settings = ChatSettings {
+                topic: template.topic,
+                ..Default::default()
+            };
+            self.update_chat_settings(chat.id as _, &settings).await?;
+        }
+        if let Some(content) = template.welcome_message {
+            let input = CreateMessage {
+                content,
+                files: vec![],
+            };
+            let message = self
+                .create_message(input, chat.id as _, user.id as _)
+                .await?;
+            let input = PinMessage {
+                message_id: message.id,
+                replace: None,
+            };
+            self.pin_message(chat.id as _, &input, user.id as _).await?;
+        }
+
+        Ok(chat)
+    }
+
+    async fn workspace_members(&self, ws_id: u64, ids: &[i64]) -> Result<Vec<i64>, AppError> {
+        let members: Vec<(i64,)> =
+            sqlx::query_as("SELECT id FROM users WHERE ws_id = $1 AND id = ANY($2)")
+                .bind(ws_id as i64)
+                .bind(ids)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(members.into_iter().map(|(id,)| id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn template() -> CreateChannelTemplate {
+        CreateChannelTemplate {
+            name: "team".to_string(),
+            name_pattern: "team-{name}".to_string(),
+            topic: Some("Everything about the team".to_string()),
+            public: false,
+            members: vec![2],
+            groups: vec![4],
+            welcome_message: Some("Welcome to the team!".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_channel_template_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let template = state.create_channel_template(1, &template(), 1).await?;
+        assert_eq!(template.name_pattern, "team-{name}");
+        assert!(state
+            .create_channel_template(1, &self::template(), 1)
+            .await
+            .is_err());
+
+        let input = CreateChannelTemplate {
+            name: "other".to_string(),
+            members: vec![100],
+            ..self::template()
+        };
+        assert!(state.create_channel_template(1, &input, 1).await.is_err());
+
+        assert_eq!(state.list_channel_templates(1).await?.len(), 1);
+        assert!(state.delete_channel_template(1, template.id as _).await?);
+        assert!(state.list_channel_templates(1).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_channel_from_template_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        state.create_channel_template(1, &template(), 1).await?;
+        let user = state.find_user_by_id(1).await?.expect("user should exist");
+        let input = ChannelFromTemplate {
+            name: "rust".to_string(),
+            members: vec![5],
+        };
+        let chat = state
+            .create_channel_from_template(1, "team", &input, &user)
+            .await?;
+        assert_eq!(chat.name.as_deref(), Some("team-rust"));
+        assert_eq!(chat.r#type, chat_core::ChatType::PrivateChannel);
+        let mut members = chat.members.clone();
+        members.sort();
+        assert_eq!(members, vec![1, 2, 3, 4, 5]);
+
+        let settings = state.get_chat_settings(chat.id as _).await?;
+        assert_eq!(settings.topic.as_deref(), Some("Everything about the team"));
+        let pins = state.list_pins(chat.id as _).await?;
+        assert_eq!(pins.pins.len(), 1);
+
+        assert!(state
+            .create_channel_from_template(1, "unknown", &input, &user)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{AppError, AppState};
+use chat_core::User;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Prefix telling personal access tokens apart from session jwts.
+pub const PERSONAL_TOKEN_PREFIX: &str = "pat_";
+/// read chats, messages and files
+pub const SCOPE_MESSAGES_READ: &str = "messages:read";
+/// send messages and upload files as the token's user
+pub const SCOPE_MESSAGES_WRITE: &str = "messages:write";
+/// every GET endpoint including exports, for analytics and export tooling
+pub const SCOPE_READ_ONLY: &str = "read_only";
+
+const SCOPES: &[&str] = &[SCOPE_MESSAGES_READ, SCOPE_MESSAGES_WRITE, SCOPE_READ_ONLY];
+const MAX_EXPIRES_IN_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct PersonalToken {
+    pub id: i64,
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub name: String,
+    /// first characters of the token
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreatePersonalToken {
+    pub name: String,
+    /// `messages:read` and/or `messages:write`, or `read_only` alone
+    pub scopes: Vec<String>,
+    /// 1 to 365 days
+    #[serde(default = "default_expires_in_days")]
+    pub expires_in_days: i64,
+}
+
+/// A newly minted token, the secret is only returned once.
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct NewPersonalToken {
+    #[serde(flatten)]
+    pub token: PersonalToken,
+    pub secret: String,
+}
+
+fn default_expires_in_days() -> i64 {
+    90
+}
+
+impl PersonalToken {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+}
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_personal_token(
+        &self,
+        user_id: u64,
+        input: &CreatePersonalToken,
+    ) -> Result<NewPersonalToken, AppError> {
+        let name = input.name.trim();
+        if name.is_empty() || name.chars().count() > 64 {
+            return Err(AppError::TokenError(
+                "Name must be between 1 and 64 characters".to_string(),
+            ));
+        }
+        if input.scopes.is_empty() {
+            return Err(AppError::TokenError(
+                "At least one scope is required".to_string(),
+            ));
+        }
+        if let Some(scope) = input.scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+            return Err(AppError::TokenError(format!("Unknown scope {scope}")));
+        }
+        if input.scopes.iter().any(|s| s == SCOPE_READ_ONLY)
+            && input.scopes.iter().any(|s| s != SCOPE_READ_ONLY)
+        {
+            return Err(AppError::TokenError(format!(
+                "Scope {SCOPE_READ_ONLY} can't be combined with other scopes"
+            )));
+        }
+        if !(1..=MAX_EXPIRES_IN_DAYS).contains(&input.expires_in_days) {
+            return Err(AppError::TokenError(format!(
+                "Expiry must be between 1 and {MAX_EXPIRES_IN_DAYS} days"
+            )));
+        }
+
+        let (random,): (String,) =
+            sqlx::query_as("SELECT replace(gen_random_uuid()::text, '-', '')")
+                .fetch_one(&self.pool)
+                .await?;
+        let secret = format!("{PERSONAL_TOKEN_PREFIX}{random}");
+        let mut scopes = input.scopes.clone();
+        scopes.sort();
+        scopes.dedup();
+        let token = sqlx::query_as(
+            r#"
+            INSERT INTO personal_tokens (user_id, name, token_hash, prefix, scopes, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at
+            "#,
+        )
+        .bind(user_id as i64)
+        .bind(name)
+        .bind(hash_token(&secret))
+        .bind(&secret[..PERSONAL_TOKEN_PREFIX.len() + 8])
+        .bind(&scopes)
+        .bind(Utc::now() + Duration::days(input.expires_in_days))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(NewPersonalToken { token, secret })
+    }
+
+    pub async fn list_personal_tokens(&self, user_id: u64) -> Result<Vec<PersonalToken>, AppError> {
+        let tokens = sqlx::query_as(
+            r#"
+            SELECT id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at
+            FROM personal_tokens
+            WHERE user_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(user_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn revoke_personal_token(&self, user_id: u64, id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM personal_tokens WHERE id = $1 AND user_id = $2")
+            .bind(id as i64)
+            .bind(user_id as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// Find the user of an unexpired token and record its use.
+    pub async fn verify_personal_token(
+        &self,
+        secret: &str,
+    ) -> Result<(User, PersonalToken), AppError> {
+        let token: Option<PersonalToken> = sqlx::query_as(
+            r#"
+            UPDATE personal_tokens SET last_used_at = NOW()
+            WHERE token_hash = $1 AND expires_at > NOW()
+            RETURNING id, user_id, name, prefix, scopes, expires_at, last_used_at, created_at
+            "#,
+        )
+        .bind(hash_token(secret))
+        .fetch_optional(&self.pool)
+        .await?;
+        let token =
+            token.ok_or_else(|| AppError::Unauthorized("invalid or expired token".to_string()))?;
+        let user = self
+            .find_user_by_id(token.user_id)
+            .await?
+            .ok_or_else(|| AppError::Unauthorized("invalid or expired token".to_string()))?;
+
+        Ok((user, token))
+    }
+}
+
+pub(crate) fn hash_token(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn input(scopes: &[&str]) -> CreatePersonalToken {
+        CreatePersonalToken {
+            name: "cli".to_string(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            expires_in_days: 30,
+        }
+    }
+
+    #[tokio::test]
+    async fn personal_token_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let new = state
+            .create_personal_token(1, &input(&[SCOPE_MESSAGES_READ]))
+            .await?;
+        assert!(new.secret.starts_with(PERSONAL_TOKEN_PREFIX));
+        assert!(new.secret.starts_with(&new.token.prefix));
+
+        let (user, token) = state.verify_personal_token(&new.secret).await?;
+        assert_eq!(user.id, 1);
+        assert!(token.has_scope(SCOPE_MESSAGES_READ));
+        assert!(!token.has_scope(SCOPE_MESSAGES_WRITE));
+        assert!(token.last_used_at.is_some());
+        assert!(state.verify_personal_token("pat_unknown").await.is_err());
+
+        assert_eq!(state.list_personal_tokens(1).await?.len(), 1);
+        assert!(state.revoke_personal_token(1, token.id as _).await?);
+        assert!(state.verify_personal_token(&new.secret).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_personal_token_should_fail() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert!(state.create_personal_token(1, &input(&[])).await.is_err());
+        assert!(state
+            .create_personal_token(1, &input(&["admin"]))
+            .await
+            .is_err());
+        assert!(state
+            .create_personal_token(1, &input(&[SCOPE_READ_ONLY, SCOPE_MESSAGES_WRITE]))
+            .await
+            .is_err());
+        let input = CreatePersonalToken {
+            expires_in_days: 1000,
+            ..input(&[SCOPE_MESSAGES_READ])
+        };
+        assert!(state.create_personal_token(1, &input).await.is_err());
+        Ok(())
+    }
+}
@@ -1,11 +1,13 @@
-use crate::{AppError, AppState};
+use crate::{config::SignupThrottle, AppError, AppState, Locale, QuotaResource, USER_JOINED_EVENT};
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
 use chat_core::{ChatUser, User};
 use serde::{Deserialize, Serialize};
-use std::mem;
+use serde_json::json;
+use sqlx::FromRow;
+use std::{mem, net::IpAddr};
 use utoipa::ToSchema;
 
 /// create a user with email and password
@@ -19,6 +21,9 @@ pub struct CreateUser {
     pub workspace: String,
     /// Password of the user
     pub password: String,
+    /// Solved captcha, required when the server has `signup.captcha` configured
+    #[serde(default)]
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
@@ -27,6 +32,41 @@ pub struct SigninUser {
     pub password: String,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "time_format")]
+pub enum TimeFormat {
+    #[sqlx(rename = "12h")]
+    #[serde(rename = "12h")]
+    H12,
+    #[default]
+    #[sqlx(rename = "24h")]
+    #[serde(rename = "24h")]
+    H24,
+}
+
+/// Sound clients play for notifications, sent to them in the hints of message events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ToSchema, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "notification_sound", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationSound {
+    #[default]
+    Default,
+    Chime,
+    Ping,
+    /// notify silently
+    None,
+}
+
+/// Locale and time format of a user, used to localize messages and render timestamps.
+#[derive(Debug, Clone, Default, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct UserPreferences {
+    #[sqlx(try_from = "String")]
+    pub locale: Locale,
+    pub time_format: TimeFormat,
+    #[serde(default)]
+    pub notification_sound: NotificationSound,
+}
+
 #[allow(dead_code)]
 impl AppState {
     /// Find a user by email
@@ -60,12 +100,23 @@ impl AppState {
             return Err(AppError::EmailAlreadyExists(input.email.clone()));
         }
 
-        // check if workspace exists, if not create one
-        let ws = match self.find_workspace_by_name(&input.workspace).await? {
+        // a workspace which verified the email domain with auto join takes precedence,
+        // otherwise check if workspace exists, if not create one
+        let auto_join = match self.find_signup_workspace(&input.email).await? {
+            Some(ws) if ws.auto_join => self.find_workspace_by_id(ws.ws_id as _).await?,
+            _ => None,
+        };
+        let ws = match auto_join {
             Some(ws) => ws,
-            None => self.create_workspace(&input.workspace, 0).await?,
+            None => match self.find_workspace_by_name(&input.workspace).await? {
+                Some(ws) => ws,
+                None => self.create_workspace(&input.workspace, 0).await?,
+            },
         };
 
+        self.check_quota(ws.id as _, QuotaResource::Members, 1)
+            .await?;
+
         let password_hash = hash_password(&input.password)?;
         let user: User = sqlx::query_as(
             r#"
@@ -81,14 +132,98 @@ impl AppState {
         .fetch_one(&self.pool)
         .await?;
 
-        if ws.owner_id == 0 {
+        let role = if ws.owner_id == 0 {
             self.update_workspace_owner(ws.id as _, user.id as _)
                 .await?;
-        }
+            "owner"
+        } else {
+            "member"
+        };
+        self.dispatch_workspace_webhooks(
+            ws.id as _,
+            USER_JOINED_EVENT,
+            &user,
+            json!({ "role": role }),
+        )
+        .await?;
 
         Ok(user)
     }
 
+    /// Count a signup from `ip` with `email` against the limits of `throttle`, failing with
+    /// the seconds until the next one is allowed if either is reached.
+    pub async fn throttle_signup(
+        &self,
+        ip: Option<IpAddr>,
+        email: &str,
+        throttle: &SignupThrottle,
+    ) -> Result<(), AppError> {
+        let ip = ip.map(|ip| ip.to_string());
+        let domain = email
+            .rsplit_once('@')
+            .map(|(_, d)| d.to_lowercase())
+            .unwrap_or_default();
+        let window = throttle.window_secs as f64;
+        let mut tx = self.pool.begin().await?;
+        // concurrent signups from the address or of the domain count one after another,
+        // the address is always locked first
+        if let Some(ip) = &ip {
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                .bind(format!("signup_ip:{ip}"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(format!("signup_domain:{domain}"))
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "DELETE FROM signup_attempts WHERE created_at < NOW() - make_interval(secs => $1)",
+        )
+        .bind(window)
+        .execute(&mut *tx)
+        .await?;
+        // seconds until the oldest attempt of each limit leaves the window
+        let (ip_count, ip_wait, domain_count, domain_wait): (i64, Option<f64>, i64, Option<f64>) =
+            sqlx::query_as(
+                r#"
+                SELECT
+                    COUNT(*) FILTER (WHERE ip = $1),
+                    EXTRACT(EPOCH FROM MIN(created_at) FILTER (WHERE ip = $1)
+                        + make_interval(secs => $3) - NOW())::float8,
+                    COUNT(*) FILTER (WHERE email_domain = $2),
+                    EXTRACT(EPOCH FROM MIN(created_at) FILTER (WHERE email_domain = $2)
+                        + make_interval(secs => $3) - NOW())::float8
+                FROM signup_attempts
+                "#,
+            )
+            .bind(&ip)
+            .bind(&domain)
+            .bind(window)
+            .fetch_one(&mut *tx)
+            .await?;
+        let over = |count: i64, max: Option<u32>| max.is_some_and(|max| count >= max as i64);
+        let wait = [
+            (over(ip_count, throttle.max_per_ip), ip_wait),
+            (over(domain_count, throttle.max_per_domain), domain_wait),
+        ]
+        .into_iter()
+        .filter(|(over, _)| *over)
+        .filter_map(|(_, wait)| wait)
+        .reduce(f64::max);
+        if let Some(wait) = wait {
+            return Err(AppError::SignupThrottled(wait.ceil().max(1.0) as u64));
+        }
+
+        sqlx::query("INSERT INTO signup_attempts (ip, email_domain) VALUES ($1, $2)")
+            .bind(&ip)
+            .bind(&domain)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     /// Verify email and password
     pub async fn verify_user(&self, input: &SigninUser) -> Result<Option<User>, AppError> {
         let user: Option<User> = sqlx::query_as(
@@ -112,6 +247,38 @@ impl AppState {
         }
     }
 
+    pub async fn get_user_preferences(&self, user_id: u64) -> Result<UserPreferences, AppError> {
+        let prefs: Option<UserPreferences> = sqlx::query_as(
+            "SELECT locale, time_format, notification_sound FROM users WHERE id = $1",
+        )
+        .bind(user_id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(prefs.unwrap_or_default())
+    }
+
+    pub async fn update_user_preferences(
+        &self,
+        user_id: u64,
+        prefs: &UserPreferences,
+    ) -> Result<UserPreferences, AppError> {
+        let prefs = sqlx::query_as(
+            r#"
+        UPDATE users
+        SET locale = $1, time_format = $2, notification_sound = $3
+        WHERE id = $4
+        RETURNING locale, time_format, notification_sound
+        "#,
+        )
+        .bind(prefs.locale.as_str())
+        .bind(prefs.time_format)
+        .bind(prefs.notification_sound)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(prefs)
+    }
+
     pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
         let users = sqlx::query_as(
             r#"
@@ -175,6 +342,7 @@ impl CreateUser {
             workspace: ws.to_string(),
             email: email.to_string(),
             password: password.to_string(),
+            captcha_token: None,
         }
     }
 }
@@ -241,6 +409,46 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn create_user_should_auto_join_verified_domain() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = crate::CreateWorkspaceDomain {
+            domain: "acme.org".to_string(),
+            auto_join: true,
+        };
+        let domain = state.add_workspace_domain(1, &input).await?;
+        state
+            .verify_workspace_domain(1, domain.id as _, &[domain.txt_record])
+            .await?;
+
+        let input = CreateUser::new("new-ws", "Tian Chen", "tyr@acme.org", "hunter42");
+        let user = state.create_user(&input).await?;
+        assert_eq!(user.ws_id, 1);
+
+        let input = CreateUser::new("new-ws", "Alice", "alice@other.org", "hunter42");
+        let user = state.create_user(&input).await?;
+        assert_ne!(user.ws_id, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn user_preferences_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+
+        let prefs = state.get_user_preferences(1).await?;
+        assert_eq!(prefs, UserPreferences::default());
+
+        let input = UserPreferences {
+            locale: Locale::ZhCn,
+            time_format: TimeFormat::H12,
+            notification_sound: NotificationSound::None,
+        };
+        let prefs = state.update_user_preferences(1, &input).await?;
+        assert_eq!(prefs, input);
+        assert_eq!(state.get_user_preferences(1).await?, input);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn find_user_by_id_should_work() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
@@ -251,4 +459,70 @@ mod tests {
         assert_eq!(user.id, 1);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn signups_should_be_throttled_per_ip_and_domain() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let throttle = SignupThrottle {
+            max_per_ip: Some(2),
+            max_per_domain: Some(3),
+            window_secs: 600,
+        };
+        let ip = "10.0.0.1".parse().ok();
+        state.throttle_signup(ip, "a@spam.io", &throttle).await?;
+        state.throttle_signup(ip, "b@other.io", &throttle).await?;
+        let err = state
+            .throttle_signup(ip, "c@other.io", &throttle)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::SignupThrottled(secs) if (599..=600).contains(&secs)));
+
+        // the limit of the domain counts every address
+        state
+            .throttle_signup("10.0.0.2".parse().ok(), "d@SPAM.io", &throttle)
+            .await?;
+        state.throttle_signup(None, "e@spam.io", &throttle).await?;
+        let err = state
+            .throttle_signup("10.0.0.3".parse().ok(), "f@spam.io", &throttle)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AppError::SignupThrottled(_)));
+
+        let unlimited = SignupThrottle {
+            max_per_ip: None,
+            ..throttle
+        };
+        state.throttle_signup(ip, "g@other.io", &unlimited).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_signups_should_be_throttled() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let throttle = SignupThrottle {
+            max_per_ip: Some(2),
+            max_per_domain: None,
+            window_secs: 600,
+        };
+        let tasks = (0..8)
+            .map(|i| {
+                let state = state.clone();
+                let throttle = throttle.clone();
+                tokio::spawn(async move {
+                    let email = format!("u{i}@other.io");
+                    state
+                        .throttle_signup("10.0.0.1".parse().ok(), &email, &throttle)
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut allowed = 0;
+        for task in tasks {
+            if task.await?.is_ok() {
+                allowed += 1;
+            }
+        }
+        assert_eq!(allowed, 2);
+        Ok(())
+    }
 }
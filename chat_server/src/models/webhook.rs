@@ -0,0 +1,397 @@
+use crate::{AppError, AppState, EventContext, Filter, Job, JobFuture, JobHandler};
+use chat_core::{Message, User};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::FromRow;
+use std::{sync::Arc, time::Duration};
+use utoipa::ToSchema;
+
+/// Job kind used to deliver events to webhooks.
+pub const WEBHOOK_JOB: &str = "webhook";
+
+pub const MESSAGE_CREATED_EVENT: &str = "message.created";
+pub const REACTION_ADDED_EVENT: &str = "reaction.added";
+pub const USER_JOINED_EVENT: &str = "user.joined";
+pub const USER_ROLE_CHANGED_EVENT: &str = "user.role_changed";
+
+const MAX_FILTER_LEN: usize = 2048;
+
+#[derive(Debug, Clone, FromRow, ToSchema, Serialize, Deserialize, PartialEq)]
+pub struct Webhook {
+    pub id: i64,
+    pub ws_id: i64,
+    pub url: String,
+    /// only events matching it are delivered, empty for all events
+    pub filter: String,
+    #[serde(with = "chat_core::id")]
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, ToSchema, Serialize, Deserialize)]
+pub struct CreateWebhook {
+    pub url: String,
+    /// at most 2048 characters, e.g.
+    /// `event = message.created and chat in (1, 2) and content contains "deploy"`
+    #[serde(default)]
+    pub filter: String,
+}
+
+/// Runs `webhook` jobs enqueued for the events matching a webhook's filter.
+pub struct WebhookJob;
+
+#[allow(dead_code)]
+impl AppState {
+    pub async fn create_webhook(
+        &self,
+        ws_id: u64,
+        input: &CreateWebhook,
+        user_id: u64,
+    ) -> Result<Webhook, AppError> {
+        let url = input.url.trim();
+        if !is_valid_webhook_url(url) {
+            return Err(AppError::WebhookError(
+                "Webhook url must be a http(s) url".to_string(),
+            ));
+        }
+        let filter = input.filter.trim();
+        if filter.chars().count() > MAX_FILTER_LEN {
+            return Err(AppError::WebhookError(format!(
+                "Webhook filter must be at most {MAX_FILTER_LEN} characters"
+            )));
+        }
+        let parsed = Arc::new(Filter::parse(filter)?);
+
+        let webhook: Webhook = sqlx::query_as(
+            r#"
+            INSERT INTO webhooks (ws_id, url, filter, created_by)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, ws_id, url, filter, created_by, created_at
+            "#,
+        )
+        .bind(ws_id as i64)
+        .bind(url)
+        .bind(filter)
+        .bind(user_id as i64)
+        .fetch_one(&self.pool)
+        .await?;
+        self.webhook_filters
+            .write()
+            .unwrap()
+            .insert(webhook.id, parsed);
+
+        Ok(webhook)
+    }
+
+    pub async fn list_webhooks(&self, ws_id: u64) -> Result<Vec<Webhook>, AppError> {
+        let webhooks = sqlx::query_as(
+            r#"
+            SELECT id, ws_id, url, filter, created_by, created_at
+            FROM webhooks
+            WHERE ws_id = $1
+            ORDER BY id
+            "#,
+        )
+        .bind(ws_id as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(webhooks)
+    }
+
+    pub async fn delete_webhook(&self, ws_id: u64, id: u64) -> Result<bool, AppError> {
+        let ret = sqlx::query("DELETE FROM webhooks WHERE id = $1 AND ws_id = $2")
+            .bind(id as i64)
+            .bind(ws_id as i64)
+            .execute(&self.pool)
+            .await?;
+        self.webhook_filters.write().unwrap().remove(&(id as i64));
+
+        Ok(ret.rows_affected() > 0)
+    }
+
+    /// The parsed filter of the webhook, parsed once per webhook and server.
+    fn webhook_filter(&self, webhook: &Webhook) -> Result<Arc<Filter>, AppError> {
+        if let Some(filter) = self.webhook_filters.read().unwrap().get(&webhook.id) {
+            return Ok(filter.clone());
+        }
+        let filter = Arc::new(Filter::parse(&webhook.filter)?);
+        self.webhook_filters
+            .write()
+            .unwrap()
+            .insert(webhook.id, filter.clone());
+        Ok(filter)
+    }
+
+    /// Enqueue a delivery of `event` to each webhook of the chat's workspace whose filter
+    /// matches. `user_id` is the sender of the event, `extra` is merged into the payload.
+    /// Returns the number of deliveries enqueued.
+    pub async fn dispatch_webhooks(
+        &self,
+        event: &str,
+        message: &Message,
+        user_id: u64,
+        extra: Value,
+    ) -> Result<usize, AppError> {
+        let webhooks: Vec<Webhook> = sqlx::query_as(
+            r#"
+            SELECT w.id, w.ws_id, w.url, w.filter, w.created_by, w.created_at
+            FROM webhooks w JOIN chats c ON c.ws_id = w.ws_id
+            WHERE c.id = $1
+            ORDER BY w.id
+            "#,
+        )
+        .bind(message.chat_id)
+        .fetch_all(&self.pool)
+        .await?;
+        if webhooks.is_empty() {
+            return Ok(0);
+        }
+
+        let (email,): (String,) = sqlx::query_as("SELECT email FROM users WHERE id = $1")
+            .bind(user_id as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        let ctx = EventContext {
+            event,
+            chat_id: message.chat_id,
+            sender_id: user_id as _,
+            sender_email: &email,
+            content: &message.content,
+        };
+
+        let mut count = 0;
+        for webhook in webhooks {
+            // a filter can't become invalid, but skip rather than fail the event if it does
+            let matched = self.webhook_filter(&webhook).is_ok_and(|f| f.matches(&ctx));
+            if !matched {
+                continue;
+            }
+            let mut payload = json!({
+                "url": webhook.url,
+                "webhook_id": webhook.id,
+                "event": event,
+                "user_id": user_id,
+                "message": message,
+            });
+            if let (Some(payload), Value::Object(extra)) = (payload.as_object_mut(), &extra) {
+                payload.extend(extra.clone());
+            }
+            self.enqueue_job(WEBHOOK_JOB, payload, None).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Enqueue a delivery of a membership `event` about `user` to each webhook of the
+    /// workspace whose filter matches, `sender` in a filter is the user the event is about.
+    /// `extra` is merged into the payload. Returns the number of deliveries enqueued.
+    pub async fn dispatch_workspace_webhooks(
+        &self,
+        ws_id: u64,
+        event: &str,
+        user: &User,
+        extra: Value,
+    ) -> Result<usize, AppError> {
+        let webhooks = self.list_webhooks(ws_id).await?;
+        let ctx = EventContext {
+            event,
+            chat_id: 0,
+            sender_id: user.id,
+            sender_email: &user.email,
+            content: "",
+        };
+
+        let mut count = 0;
+        for webhook in webhooks {
+            let matched = self.webhook_filter(&webhook).is_ok_and(|f| f.matches(&ctx));
+            if !matched {
+                continue;
+            }
+            let mut payload = json!({
+                "url": webhook.url,
+                "webhook_id": webhook.id,
+                "event": event,
+                "user_id": user.id,
+                "ws_id": ws_id,
+                "user": user,
+            });
+            if let (Some(payload), Value::Object(extra)) = (payload.as_object_mut(), &extra) {
+                payload.extend(extra.clone());
+            }
+            self.enqueue_job(WEBHOOK_JOB, payload, None).await?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Tell the workspace webhooks `user` went from `previous` to `role`, one of `owner`,
+    /// `admin` or `member`.
+    pub(crate) async fn dispatch_role_changed(
+        &self,
+        ws_id: u64,
+        user_id: u64,
+        previous: &str,
+        role: &str,
+    ) -> Result<(), AppError> {
+        let Some(user) = self.find_user_by_id(user_id as _).await? else {
+            return Ok(());
+        };
+        let extra = json!({ "role": role, "previous_role": previous });
+        self.dispatch_workspace_webhooks(ws_id, USER_ROLE_CHANGED_EVENT, &user, extra)
+            .await?;
+        Ok(())
+    }
+}
+
+impl JobHandler for WebhookJob {
+    fn kind(&self) -> &'static str {
+        WEBHOOK_JOB
+    }
+
+    fn run(&self, _state: AppState, job: Job) -> JobFuture {
+        Box::pin(async move { post_webhook(&job.payload).await })
+    }
+}
+
+/// Post `payload` as json to its `url`, failing on non 2xx responses so the job retries.
+pub(crate) async fn post_webhook(payload: &Value) -> Result<(), AppError> {
+    let url = payload["url"].as_str().unwrap_or_default();
+    let res = reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .json(payload)
+        .send()
+        .await
+        .map_err(anyhow::Error::from)?;
+    if !res.status().is_success() {
+        return Err(AppError::AnyError(anyhow::anyhow!(
+            "webhook {url} responded {}",
+            res.status()
+        )));
+    }
+    Ok(())
+}
+
+pub(crate) fn is_valid_webhook_url(url: &str) -> bool {
+    (url.starts_with("http://") || url.starts_with("https://")) && url.len() <= 2048
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn create_webhook_should_work() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateWebhook {
+            url: "https://hooks.acme.org/chat".to_string(),
+            filter: "event = message.created and chat = 1".to_string(),
+        };
+        let webhook = state.create_webhook(1, &input, 1).await?;
+        assert_eq!(state.list_webhooks(1).await?, vec![webhook.clone()]);
+
+        let invalid = CreateWebhook {
+            filter: "chat in (1".to_string(),
+            ..input.clone()
+        };
+        assert!(state.create_webhook(1, &invalid, 1).await.is_err());
+        let invalid = CreateWebhook {
+            filter: "chat = 1 or ".repeat(MAX_FILTER_LEN / 8) + "chat = 1",
+            ..input.clone()
+        };
+        assert!(state.create_webhook(1, &invalid, 1).await.is_err());
+        let invalid = CreateWebhook {
+            url: "ftp://hooks.acme.org".to_string(),
+            ..input
+        };
+        assert!(state.create_webhook(1, &invalid, 1).await.is_err());
+
+        assert!(state.delete_webhook(1, webhook.id as _).await?);
+        assert!(!state.delete_webhook(1, webhook.id as _).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dispatch_webhooks_should_apply_filters() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        for filter in ["", r#"content contains "deploy""#, "chat = 2"] {
+            let input = CreateWebhook {
+                url: "https://hooks.acme.org/chat".to_string(),
+                filter: filter.to_string(),
+            };
+            state.create_webhook(1, &input, 1).await?;
+        }
+
+        let message = state
+            .list_messages(
+                crate::ListMessages {
+                    last_id: None,
+                    limit: 1,
+                    fields: Default::default(),
+                },
+                1,
+            )
+            .await?
+            .remove(0);
+        let count = state
+            .dispatch_webhooks(MESSAGE_CREATED_EVENT, &message, 1, json!({}))
+            .await?;
+        assert_eq!(count, 1);
+
+        let message = Message {
+            content: "Deploy finished".to_string(),
+            ..message
+        };
+        let count = state
+            .dispatch_webhooks(MESSAGE_CREATED_EVENT, &message, 1, json!({}))
+            .await?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn membership_changes_should_dispatch_workspace_webhooks() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let input = CreateWebhook {
+            url: "https://hooks.acme.org/hr".to_string(),
+            filter: "event in (user.joined, user.role_changed)".to_string(),
+        };
+        state.create_webhook(1, &input, 1).await?;
+        let ws = state.update_workspace_owner(1, 1).await?;
+
+        state.add_workspace_admin(&ws, 2).await?;
+        // adding an admin again changes nothing
+        state.add_workspace_admin(&ws, 2).await?;
+        assert!(state.remove_workspace_admin(1, 2).await?);
+        let input = crate::CreateUser::new("acme", "Dave Doe", "dave@acme.org", "123456");
+        state.create_user(&input).await?;
+
+        let payloads: Vec<(Value,)> =
+            sqlx::query_as("SELECT payload FROM jobs WHERE kind = $1 ORDER BY id")
+                .bind(WEBHOOK_JOB)
+                .fetch_all(&state.pool)
+                .await?;
+        let events: Vec<_> = payloads
+            .iter()
+            .map(|(p,)| {
+                (
+                    p["event"].as_str().unwrap_or_default(),
+                    p["user"]["email"].as_str().unwrap_or_default(),
+                    p["role"].as_str().unwrap_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (USER_ROLE_CHANGED_EVENT, "alice@acme.org", "admin"),
+                (USER_ROLE_CHANGED_EVENT, "alice@acme.org", "member"),
+                (USER_JOINED_EVENT, "dave@acme.org", "member"),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,9 @@
 use crate::{AppError, AppState};
-use chat_core::Workspace;
+use chat_core::{User, Workspace, WorkspaceSettings};
+use sqlx::types::Json;
+
+const MAX_LOGO_LEN: usize = 512;
+const MAX_WELCOME_MESSAGE_LEN: usize = 1024;
 
 impl AppState {
     pub async fn create_workspace(&self, name: &str, user_id: u64) -> Result<Workspace, AppError> {
@@ -7,7 +11,7 @@ impl AppState {
             r#"
         INSERT INTO workspaces (name, owner_id)
         VALUES ($1, $2)
-        RETURNING id, name, owner_id, created_at
+        RETURNING id, name, owner_id, settings, created_at
         "#,
         )
         .bind(name)
@@ -21,7 +25,7 @@ impl AppState {
     pub async fn find_workspace_by_name(&self, name: &str) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-        SELECT id, name, owner_id, created_at
+        SELECT id, name, owner_id, settings, created_at
         FROM workspaces
         WHERE name = $1
         "#,
@@ -33,11 +37,10 @@ impl AppState {
         Ok(ws)
     }
 
-    #[allow(dead_code)]
     pub async fn find_workspace_by_id(&self, id: u64) -> Result<Option<Workspace>, AppError> {
         let ws = sqlx::query_as(
             r#"
-        SELECT id, name, owner_id, created_at
+        SELECT id, name, owner_id, settings, created_at
         FROM workspaces
         WHERE id = $1
         "#,
@@ -49,6 +52,26 @@ impl AppState {
         Ok(ws)
     }
 
+    /// Find the user's workspace, failing unless the user owns it. `action` is used in
+    /// the error message, e.g. "only the workspace owner can update settings".
+    pub async fn verify_workspace_owner(
+        &self,
+        user: &User,
+        action: &str,
+    ) -> Result<Workspace, AppError> {
+        let ws_id = user.ws_id as u64;
+        let ws = self
+            .find_workspace_by_id(ws_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("workspace id {ws_id}")))?;
+        if ws.owner_id != user.id {
+            return Err(AppError::PermissionDenied(format!(
+                "only the workspace owner can {action}"
+            )));
+        }
+        Ok(ws)
+    }
+
     pub async fn update_workspace_owner(
         &self,
         id: u64,
@@ -60,7 +83,7 @@ impl AppState {
         UPDATE workspaces
         SET owner_id = $1
         WHERE id = $2 and (SELECT ws_id FROM users WHERE id = $1) = $2
-        RETURNING id, name, owner_id, created_at
+        RETURNING id, name, owner_id, settings, created_at
         "#,
         )
         .bind(owner_id as i64)
@@ -70,6 +93,60 @@ impl AppState {
 
         Ok(ws)
     }
+
+    pub async fn update_workspace_settings(
+        &self,
+        id: u64,
+        settings: &WorkspaceSettings,
+    ) -> Result<Option<Workspace>, AppError> {
+        valid_workspace_settings(settings)?;
+        let ws = sqlx::query_as(
+            r#"
+        UPDATE workspaces
+        SET settings = $1
+        WHERE id = $2
+        RETURNING id, name, owner_id, settings, created_at
+        "#,
+        )
+        .bind(Json(settings))
+        .bind(id as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(ws)
+    }
+}
+
+fn valid_workspace_settings(settings: &WorkspaceSettings) -> Result<(), AppError> {
+    if let Some(logo) = &settings.logo {
+        let valid_url = ["/files/", "http://", "https://"]
+            .iter()
+            .any(|prefix| logo.starts_with(prefix));
+        if !valid_url || logo.len() > MAX_LOGO_LEN {
+            return Err(AppError::WorkspaceError(
+                "Logo must be an uploaded file or a http(s) url".to_string(),
+            ));
+        }
+    }
+    if let Some(color) = &settings.accent_color {
+        let valid_color = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if !valid_color {
+            return Err(AppError::WorkspaceError(
+                "Accent color must be in #rrggbb format".to_string(),
+            ));
+        }
+    }
+    if let Some(msg) = &settings.welcome_message {
+        if msg.chars().count() > MAX_WELCOME_MESSAGE_LEN {
+            return Err(AppError::WorkspaceError(format!(
+                "Welcome message must be at most {} characters",
+                MAX_WELCOME_MESSAGE_LEN
+            )));
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -105,6 +182,44 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn workspace_settings_should_update() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let ws = state.find_workspace_by_id(1).await?.unwrap();
+        assert_eq!(ws.settings, WorkspaceSettings::default());
+
+        let settings = WorkspaceSettings {
+            logo: Some("https://acme.org/logo.png".to_string()),
+            accent_color: Some("#ff8800".to_string()),
+            welcome_message: Some("Welcome to acme!".to_string()),
+            audit_file_access: true,
+        };
+        let ws = state
+            .update_workspace_settings(1, &settings)
+            .await?
+            .unwrap();
+        assert_eq!(ws.settings, settings);
+
+        let ws = state.find_workspace_by_name("acme").await?.unwrap();
+        assert_eq!(ws.settings, settings);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_workspace_settings_should_fail() {
+        let settings = WorkspaceSettings {
+            accent_color: Some("orange".to_string()),
+            ..Default::default()
+        };
+        assert!(valid_workspace_settings(&settings).is_err());
+
+        let settings = WorkspaceSettings {
+            logo: Some("javascript:alert(1)".to_string()),
+            ..Default::default()
+        };
+        assert!(valid_workspace_settings(&settings).is_err());
+    }
+
     #[tokio::test]
     async fn workspace_should_fetch_all_chat_users() -> Result<()> {
         let (_tdb, state) = AppState::new_for_test().await?;
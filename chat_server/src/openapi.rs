@@ -1,7 +1,35 @@
 use crate::handlers::*;
-use crate::{AppState, ChatDTO, CreateMessage, CreateUser, ErrorOutput, ListMessages, SigninUser};
+use crate::{
+    AddChatMember, AppState, ArchiveStatus, AuditLog, BulkMessage, BulkMessageReport,
+    BulkMessageTarget, BulkTargetStatus, Capability, CapabilityStat, ChannelFromTemplate,
+    ChannelReactions, ChannelTemplate, ChatArchivalWebhook, ChatBan, ChatDTO, ChatDeletion,
+    ChatDraft, ChatExport, ChatFolder, ChatHistoryQuery, ChatInvite, ChatMember, ChatMetadata,
+    ChatNotificationSettings, ChatPage, ChatPatchDTO, ChatRead, ChatRole, ChatSettings,
+    ChatSnapshot, ChatSort, Cohort, CohortMetrics, ConvertChat, CreateBulkMessage,
+    CreateChannelTemplate, CreateChatInvite, CreateGuestLink, CreateJoinRequest, CreateLegalHold,
+    CreateMessage, CreatePersonalToken, CreatePlan, CreateReactionTrigger, CreateTask, CreateUser,
+    CreateWebhook, CreateWorkspaceDomain, DailyEmojiCount, DemoWorkspace, DirectoryChannel,
+    DomainEmailChallenge, EmojiCount, EndpointHealth, ErrorOutput, ExportChat, ExportFormat,
+    ExportPolicy, ExportRecord, ExportSettings, ExportedMessage, Feature, FeatureConfig,
+    FieldError, FileAccess, FindSignupWorkspace, GuestAccess, GuestLink, HydratedMessage,
+    JoinRequest, JoinRequestStatus, LegalHold, ListAuditLogs, ListCapabilityStats, ListChannels,
+    ListChats, ListMessages, ListRealtimeEndpoints, ListTasks, Locale, MarkChatRead,
+    MessageChangeOp, MessageFields, MessagePin, MessageReactions, NewPersonalToken,
+    NotificationSound, Onboarding, OnboardingProgress, OnboardingStep, OrphanReport, PersonalToken,
+    PinLimit, PinList, PinMessage, Plan, PostPolicy, QuotaResource, QuotaStatus, QuotaUsage,
+    ReactionAnalytics, ReactionAnalyticsQuery, ReactionCount, ReactionTrigger, ReadState,
+    RealtimeEndpoint, RedeemGuestLink, RemoveChatMember, ReorderPins, SaveChatDraft, SearchChats,
+    SearchReindex, SearchReindexStatus, SecurityPolicy, SessionMethod, SetChatArchivalWebhook,
+    SetWorkspacePlan, SigninUser, SignupWorkspace, TimeFormat, TransferChat, TransferWorkspace,
+    TriggerAction, TriggerRun, UpdateChatRole, UpdateTask, UserPreferences, VerifyDomain,
+    Watermark, Webhook, WorkspaceAdmin, WorkspaceArchive, WorkspaceDomain, WorkspaceTransfer,
+    WorkspaceUsage,
+};
 use axum::Router;
-use chat_core::{Chat, ChatType, ChatUser, Message, User, Workspace};
+use chat_core::{
+    Chat, ChatType, ChatUser, LastMessage, Message, MessageTranslation, Task, TaskStatus, User,
+    Workspace, WorkspaceSettings,
+};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -19,27 +47,186 @@ pub(crate) trait OpenApiRouter {
         paths(
             signup_handler,
             signin_handler,
+            create_stream_token_handler,
             list_chat_handler,
             create_chat_handler,
             get_chat_handler,
+            get_chat_by_handle_handler,
             update_chat_handler,
             delete_chat_handler,
             send_message_handler,
             list_message_handler,
             file_handler,
             upload_handler,
-            list_chat_users_handler
+            list_chat_users_handler,
+            bootstrap_handler,
+            update_workspace_settings_handler,
+            get_preferences_handler,
+            update_preferences_handler,
+            system_messages_handler,
+            get_maintenance_handler,
+            set_maintenance_handler,
+            list_pins_handler,
+            pin_message_handler,
+            unpin_message_handler,
+            reorder_pins_handler,
+            set_pin_limit_handler,
+            create_guest_link_handler,
+            list_guest_links_handler,
+            revoke_guest_link_handler,
+            redeem_guest_link_handler,
+            create_chat_invite_handler,
+            list_chat_invites_handler,
+            revoke_chat_invite_handler,
+            accept_chat_invite_handler,
+            request_to_join_chat_handler,
+            list_join_requests_handler,
+            approve_join_request_handler,
+            deny_join_request_handler,
+            get_chat_settings_handler,
+            update_chat_settings_handler,
+            export_chat_handler,
+            list_audit_logs_handler,
+            get_security_policy_handler,
+            update_security_policy_handler,
+            list_chat_members_handler,
+            add_chat_member_handler,
+            remove_chat_member_handler,
+            list_chat_bans_handler,
+            unban_chat_member_handler,
+            list_channel_directory_handler,
+            search_chats_handler,
+            join_chat_handler,
+            pin_chat_handler,
+            unpin_chat_handler,
+            list_unread_handler,
+            read_all_chats_handler,
+            read_folder_handler,
+            mark_chat_read_handler,
+            get_chat_draft_handler,
+            save_chat_draft_handler,
+            get_chat_metadata_handler,
+            set_chat_metadata_handler,
+            get_chat_notifications_handler,
+            update_chat_notifications_handler,
+            upload_chat_avatar_handler,
+            archive_chat_handler,
+            unarchive_chat_handler,
+            restore_chat_handler,
+            cancel_chat_deletion_handler,
+            leave_chat_handler,
+            update_chat_role_handler,
+            transfer_chat_handler,
+            convert_chat_handler,
+            get_chat_archival_webhook_handler,
+            set_chat_archival_webhook_handler,
+            delete_chat_archival_webhook_handler,
+            list_user_chats_handler,
+            list_domains_handler,
+            add_domain_handler,
+            verify_domain_handler,
+            domain_email_challenge_handler,
+            delete_domain_handler,
+            signup_workspace_handler,
+            list_reactions_handler,
+            add_reaction_handler,
+            remove_reaction_handler,
+            reaction_analytics_handler,
+            list_reaction_triggers_handler,
+            create_reaction_trigger_handler,
+            delete_reaction_trigger_handler,
+            list_chat_tasks_handler,
+            create_task_handler,
+            update_task_handler,
+            list_my_tasks_handler,
+            file_access_handler,
+            list_personal_tokens_handler,
+            create_personal_token_handler,
+            revoke_personal_token_handler,
+            list_webhooks_handler,
+            create_webhook_handler,
+            delete_webhook_handler,
+            list_channel_templates_handler,
+            create_channel_template_handler,
+            delete_channel_template_handler,
+            create_channel_from_template_handler,
+            get_workspace_archive_handler,
+            archive_workspace_handler,
+            unarchive_workspace_handler,
+            download_workspace_archive_handler,
+            create_bulk_message_handler,
+            get_bulk_message_handler,
+            get_onboarding_handler,
+            list_realtime_endpoints_handler,
+            list_plans_handler,
+            create_plan_handler,
+            set_workspace_plan_handler,
+            get_workspace_usage_handler,
+            list_features_handler,
+            list_capability_stats_handler,
+            get_orphan_report_handler,
+            create_demo_workspace_handler,
+            get_demo_workspace_handler,
+            delete_demo_workspace_handler,
+            reset_demo_workspace_handler,
+            update_feature_handler,
+            get_chat_history_handler,
+            create_search_reindex_handler,
+            get_search_reindex_handler,
+            list_workspace_admins_handler,
+            add_workspace_admin_handler,
+            remove_workspace_admin_handler,
+            list_legal_holds_handler,
+            create_legal_hold_handler,
+            release_legal_hold_handler,
+            get_workspace_transfer_handler,
+            create_workspace_transfer_handler,
+            cancel_workspace_transfer_handler,
+            accept_workspace_transfer_handler,
         ),
         components(
-            schemas(User, Chat, ChatType, ChatUser, Message, Workspace,
+            schemas(User, Chat, ChatType, ChatUser, LastMessage, Message, Workspace,
                  SigninUser, CreateUser, ChatDTO, CreateMessage, ListMessages,
-                  Message, AuthOutput, ErrorOutput, UploadFile),
+                  Message, AuthOutput, StreamTokenOutput, ErrorOutput, FieldError, UploadFile,
+                  Maintenance,
+                  WorkspaceSettings, BootstrapOutput, UserPreferences, Locale,
+                  TimeFormat, SystemMessagesOutput, MessagePin, PinList, PinMessage,
+                  ReorderPins, PinLimit, GuestAccess, GuestLink, CreateGuestLink,
+                  RedeemGuestLink, ChatSettings, ExportSettings, ExportPolicy, PostPolicy, ChatExport,
+                  ExportedMessage, Watermark, AuditLog, ListAuditLogs, ChatMember, ChatRole,
+                  AddChatMember, UpdateChatRole, ChatPatchDTO, ListChats,
+                  WorkspaceDomain, CreateWorkspaceDomain, FindSignupWorkspace, SignupWorkspace,
+                  DomainEmailChallenge, VerifyDomain, MessageReactions, ReactionCount,
+                  ReactionTrigger, CreateReactionTrigger, TriggerAction, TriggerRun,
+                  Task, TaskStatus, CreateTask, UpdateTask, ListTasks,
+                  ChannelTemplate, CreateChannelTemplate, ChannelFromTemplate, FileAccess,
+                  Webhook, CreateWebhook, PersonalToken, CreatePersonalToken, NewPersonalToken,
+                  WorkspaceArchive, ArchiveStatus, BulkMessage, BulkMessageReport,
+                  BulkMessageTarget, BulkTargetStatus, CreateBulkMessage, Onboarding,
+                  OnboardingProgress, OnboardingStep, Plan, CreatePlan, SetWorkspacePlan,
+                  QuotaResource, QuotaStatus, QuotaUsage, WorkspaceUsage, ChatHistoryQuery,
+                  ChatSnapshot, MessageChangeOp, WorkspaceAdmin, WorkspaceTransfer,
+                  TransferWorkspace, SearchReindex, SearchReindexStatus, NotificationSound,
+                  Feature, FeatureConfig, Cohort, CohortMetrics, ReactionAnalytics,
+                  ReactionAnalyticsQuery, EmojiCount, DailyEmojiCount, ChannelReactions,
+                  ChatNotificationSettings, ChatFolder, ChatRead, ReadState, LegalHold,
+                  CreateLegalHold, MessageTranslation, ChatPage, ChatSort, SecurityPolicy,
+                  SessionMethod, Capability, CapabilityStat, ListCapabilityStats,
+                  MarkChatRead, ChatInvite, CreateChatInvite, OrphanReport, TransferChat,
+                  ChatArchivalWebhook, SetChatArchivalWebhook,
+                  ConvertChat, HydratedMessage, MessageFields, DemoWorkspace,
+                  RealtimeEndpoint, EndpointHealth, ListRealtimeEndpoints,
+                  DirectoryChannel, ListChannels, SearchChats, ExportChat, ExportFormat,
+                  ExportRecord, ChatDeletion, JoinRequest, JoinRequestStatus,
+                  CreateJoinRequest, ChatBan, RemoveChatMember, ChatDraft, SaveChatDraft,
+                  ChatMetadata),
         ),
         modifiers(&SecurityAddon),
         tags(
             (name = "chat", description = "Chat related operations"),
             (name = "message", description = "Message related operations"),
             (name = "user", description = "User related operations"),
+            (name = "admin", description = "Server administration"),
         )
     )]
 pub(crate) struct ApiDoc;
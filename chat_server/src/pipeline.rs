@@ -0,0 +1,502 @@
+//! Stages a new message goes through before and after it is stored. The default pipeline
+//! validates the input, enforces the post policy and slow mode, parses mentions, persists the message, hands it to the outbox
+//! (webhooks), enqueues the follow up jobs and schedules its archival. Features such as moderation plug in as
+//! another stage, e.g. a content filter before mention parsing:
+//!
+//! ```ignore
+//! let pipeline = state.message_pipeline().insert_before(MENTION_STAGE, ProfanityFilter);
+//! state.set_message_pipeline(pipeline);
+//! ```
+
+use crate::{
+    AppError, AppState, ChatAction, ChatFile, ChatRole, CreateMessage, PostPolicy, QuotaResource,
+    Resource, TranslateMessage, MESSAGE_CREATED_EVENT, TRANSLATE_MESSAGE_JOB,
+};
+use chat_core::{decode_id, Message};
+use serde_json::json;
+use std::{future::Future, pin::Pin, str::FromStr, sync::Arc};
+
+pub type StageFuture<'a> = Pin<Box<dyn Future<Output = Result<(), AppError>> + Send + 'a>>;
+
+pub const VALIDATE_STAGE: &str = "validate";
+pub const POST_POLICY_STAGE: &str = "post_policy";
+pub const SLOW_MODE_STAGE: &str = "slow_mode";
+pub const MENTION_STAGE: &str = "mentions";
+pub const PERSIST_STAGE: &str = "persist";
+pub const OUTBOX_STAGE: &str = "outbox";
+pub const ENQUEUE_STAGE: &str = "enqueue";
+pub const ARCHIVAL_STAGE: &str = "archival";
+
+/// A message being sent, stages fill it in as it goes through the pipeline.
+#[derive(Debug, Clone)]
+pub struct SendContext {
+    pub chat_id: u64,
+    pub sender_id: u64,
+    pub input: CreateMessage,
+    /// workspace of the chat, set by the validation stage
+    pub ws_id: u64,
+    /// members mentioned as `<@user_id>`, set by the mention stage
+    pub mentions: Vec<i64>,
+    /// None until the persistence stage stored the message
+    pub message: Option<Message>,
+}
+
+/// One step of sending a message. A stage failing stops the send, stages after the
+/// persistence stage run once the message is stored.
+pub trait MessageStage: Send + Sync + 'static {
+    /// Name other stages are inserted relative to, must be unique within a pipeline.
+    fn name(&self) -> &'static str;
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a>;
+}
+
+/// Ordered stages of `AppState::create_message`.
+#[derive(Clone)]
+pub struct MessagePipeline {
+    stages: Vec<Arc<dyn MessageStage>>,
+}
+
+pub struct ValidateStage;
+pub struct PostPolicyStage;
+pub struct SlowModeStage;
+pub struct MentionStage;
+pub struct PersistStage;
+pub struct OutboxStage;
+pub struct EnqueueStage;
+pub struct ArchivalStage;
+
+impl MessagePipeline {
+    /// A pipeline without stages, see `Default` for the standard one.
+    pub fn empty() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    pub fn stage(mut self, stage: impl MessageStage) -> Self {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    /// Insert `stage` before the stage named `before`, or at the end if there is none.
+    pub fn insert_before(mut self, before: &str, stage: impl MessageStage) -> Self {
+        let pos = self
+            .stages
+            .iter()
+            .position(|s| s.name() == before)
+            .unwrap_or(self.stages.len());
+        self.stages.insert(pos, Arc::new(stage));
+        self
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+
+    pub async fn run(&self, state: &AppState, mut ctx: SendContext) -> Result<Message, AppError> {
+        for stage in &self.stages {
+            stage.run(state, &mut ctx).await?;
+        }
+        ctx.message
+            .ok_or_else(|| AppError::CreateMessageError("Message wasn't persisted".to_string()))
+    }
+}
+
+impl Default for MessagePipeline {
+    fn default() -> Self {
+        Self::empty()
+            .stage(ValidateStage)
+            .stage(PostPolicyStage)
+            .stage(SlowModeStage)
+            .stage(MentionStage)
+            .stage(PersistStage)
+            .stage(OutboxStage)
+            .stage(EnqueueStage)
+            .stage(ArchivalStage)
+    }
+}
+
+impl SendContext {
+    pub fn new(input: CreateMessage, chat_id: u64, sender_id: u64) -> Self {
+        Self {
+            chat_id,
+            sender_id,
+            input,
+            ws_id: 0,
+            mentions: Vec::new(),
+            message: None,
+        }
+    }
+
+    fn message(&self) -> Result<&Message, AppError> {
+        self.message.as_ref().ok_or_else(|| {
+            AppError::CreateMessageError("Message hasn't been persisted yet".to_string())
+        })
+    }
+}
+
+impl MessageStage for ValidateStage {
+    fn name(&self) -> &'static str {
+        VALIDATE_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let base_dir = &state.config.server.base_dir;
+            // verify content - not empty
+            if ctx.input.content.is_empty() {
+                return Err(AppError::CreateMessageError(
+                    "Content cannot be empty".to_string(),
+                ));
+            }
+
+            // verify files exist
+            for s in &ctx.input.files {
+                let file = ChatFile::from_str(s)?;
+                if !file.path(base_dir).exists() {
+                    return Err(AppError::CreateMessageError(format!(
+                        "File {} doesn't exist",
+                        s
+                    )));
+                }
+            }
+
+            let chat: Option<(i64, bool)> = sqlx::query_as(
+                "SELECT ws_id, archived_at IS NOT NULL FROM chats WHERE id = $1 AND deleted_at IS NULL",
+            )
+            .bind(ctx.chat_id as i64)
+            .fetch_optional(&state.pool)
+            .await?;
+            let Some((ws_id, archived)) = chat else {
+                return Err(AppError::NotFound(format!("chat id {}", ctx.chat_id)));
+            };
+            if archived {
+                return Err(AppError::CreateMessageError("Chat is archived".to_string()));
+            }
+            state
+                .check_quota(ws_id as _, QuotaResource::Messages, 1)
+                .await?;
+            ctx.ws_id = ws_id as _;
+            Ok(())
+        })
+    }
+}
+
+impl MessageStage for PostPolicyStage {
+    fn name(&self) -> &'static str {
+        POST_POLICY_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let policy = state.get_chat_settings(ctx.chat_id).await?.post_policy;
+            if policy == PostPolicy::Everyone {
+                return Ok(());
+            }
+            state
+                .authorize(ctx.sender_id, ChatAction::Post, Resource::chat(ctx.chat_id))
+                .await
+        })
+    }
+}
+
+impl MessageStage for SlowModeStage {
+    fn name(&self) -> &'static str {
+        SLOW_MODE_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let seconds = state
+                .get_chat_settings(ctx.chat_id)
+                .await?
+                .slow_mode_seconds;
+            if seconds == 0 {
+                return Ok(());
+            }
+            let role = state.get_chat_role(ctx.chat_id, ctx.sender_id).await?;
+            if matches!(role, Some(ChatRole::Owner | ChatRole::Admin)) {
+                return Ok(());
+            }
+            // NULL if the sender hasn't posted in the chat yet
+            let wait: Option<f64> = sqlx::query_scalar(
+                r#"
+                SELECT EXTRACT(EPOCH FROM MAX(created_at) + make_interval(secs => $3) - NOW())::float8
+                FROM messages
+                WHERE chat_id = $1 AND sender_id = $2
+                "#,
+            )
+            .bind(ctx.chat_id as i64)
+            .bind(ctx.sender_id as i64)
+            .bind(seconds as f64)
+            .fetch_one(&state.pool)
+            .await?;
+            match wait {
+                Some(wait) if wait > 0.0 => Err(AppError::SlowMode(wait.ceil() as u64)),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+impl MessageStage for MentionStage {
+    fn name(&self) -> &'static str {
+        MENTION_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let ids = parse_mentions(&ctx.input.content);
+            if ids.is_empty() {
+                return Ok(());
+            }
+            // mentions of users outside the chat are left as plain text
+            ctx.mentions = sqlx::query_scalar(
+                r#"
+                SELECT user_id FROM chat_members
+                WHERE chat_id = $1 AND user_id = ANY($2)
+                ORDER BY user_id
+                "#,
+            )
+            .bind(ctx.chat_id as i64)
+            .bind(&ids)
+            .fetch_all(&state.pool)
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+impl MessageStage for PersistStage {
+    fn name(&self) -> &'static str {
+        PERSIST_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let message: Message = sqlx::query_as(
+                r#"
+                INSERT INTO messages (chat_id, sender_id, content, files)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, chat_id, sender_id, content, files, created_at
+                "#,
+            )
+            .bind(ctx.chat_id as i64)
+            .bind(ctx.sender_id as i64)
+            .bind(&ctx.input.content)
+            .bind(&ctx.input.files)
+            .fetch_one(&state.pool)
+            .await?;
+            ctx.message = Some(message);
+            Ok(())
+        })
+    }
+}
+
+impl MessageStage for OutboxStage {
+    fn name(&self) -> &'static str {
+        OUTBOX_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let extra = json!({ "mentions": ctx.mentions });
+            state
+                .dispatch_webhooks(MESSAGE_CREATED_EVENT, ctx.message()?, ctx.sender_id, extra)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+impl MessageStage for EnqueueStage {
+    fn name(&self) -> &'static str {
+        ENQUEUE_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move {
+            let message_id = ctx.message()?.id;
+            let settings = state.get_chat_settings(ctx.chat_id).await?;
+            if settings.translate_to.is_some() && !settings.encrypted {
+                let job = TranslateMessage { message_id };
+                state.enqueue_job(TRANSLATE_MESSAGE_JOB, job, None).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl MessageStage for ArchivalStage {
+    fn name(&self) -> &'static str {
+        ARCHIVAL_STAGE
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+        Box::pin(async move { state.enqueue_message_archival(ctx.message()?).await })
+    }
+}
+
+impl AppState {
+    /// The pipeline new messages currently go through.
+    pub fn message_pipeline(&self) -> MessagePipeline {
+        self.message_pipeline.read().unwrap().clone()
+    }
+
+    pub fn set_message_pipeline(&self, pipeline: MessagePipeline) {
+        *self.message_pipeline.write().unwrap() = pipeline;
+    }
+}
+
+// user ids of the `<@user_id>` mentions in the content by public or plain id, the forms
+// notify_server matches
+fn parse_mentions(content: &str) -> Vec<i64> {
+    let mut ids: Vec<i64> = content
+        .split("<@")
+        .skip(1)
+        .filter_map(|s| s.split_once('>'))
+        .filter_map(|(id, _)| decode_id(id).or_else(|| id.parse().ok()))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ChatSettings;
+    use anyhow::Result;
+    use axum::{
+        http::{header::RETRY_AFTER, StatusCode},
+        response::IntoResponse,
+    };
+
+    struct RejectLinks;
+
+    impl MessageStage for RejectLinks {
+        fn name(&self) -> &'static str {
+            "reject_links"
+        }
+
+        fn run<'a>(&'a self, _state: &'a AppState, ctx: &'a mut SendContext) -> StageFuture<'a> {
+            Box::pin(async move {
+                if ctx.input.content.contains("http") {
+                    return Err(AppError::CreateMessageError("No links".to_string()));
+                }
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn parse_mentions_should_work() {
+        assert_eq!(parse_mentions("hi <@2> and <@3>, <@2>"), [2, 3]);
+        assert_eq!(parse_mentions("<@> <@x> <@4"), Vec::<i64>::new());
+    }
+
+    #[tokio::test]
+    async fn message_pipeline_should_run_plugged_stages() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let pipeline = state
+            .message_pipeline()
+            .insert_before(MENTION_STAGE, RejectLinks);
+        assert_eq!(
+            pipeline.names(),
+            [
+                VALIDATE_STAGE,
+                POST_POLICY_STAGE,
+                SLOW_MODE_STAGE,
+                "reject_links",
+                MENTION_STAGE,
+                PERSIST_STAGE,
+                OUTBOX_STAGE,
+                ENQUEUE_STAGE,
+                ARCHIVAL_STAGE
+            ]
+        );
+        state.set_message_pipeline(pipeline);
+
+        let input = CreateMessage {
+            content: "see http://example.com".to_string(),
+            files: vec![],
+        };
+        let err = state.create_message(input, 1, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "create message error: No links");
+
+        // user 6 isn't a member of chat 4
+        let input = CreateMessage {
+            content: "hi <@3> <@6> <@3>".to_string(),
+            files: vec![],
+        };
+        let mut ctx = SendContext::new(input, 4, 1);
+        ValidateStage.run(&state, &mut ctx).await?;
+        MentionStage.run(&state, &mut ctx).await?;
+        assert_eq!(ctx.mentions, [3]);
+        assert!(ctx.message.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_mode_should_hold_back_members() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let settings = ChatSettings {
+            slow_mode_seconds: 60 * 60 * 24,
+            ..Default::default()
+        };
+        assert!(state.update_chat_settings(4, &settings).await.is_err());
+        let settings = ChatSettings {
+            slow_mode_seconds: 60,
+            ..Default::default()
+        };
+        state.update_chat_settings(4, &settings).await?;
+
+        let send = |user_id| {
+            let input = CreateMessage {
+                content: "slow".to_string(),
+                files: vec![],
+            };
+            state.create_message(input, 4, user_id)
+        };
+        send(3).await?;
+        let err = send(3).await.unwrap_err();
+        assert!(matches!(err, AppError::SlowMode(secs) if secs > 50 && secs <= 60));
+        send(4).await?;
+        // user 1 owns the chat
+        send(1).await?;
+        send(1).await?;
+
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key(RETRY_AFTER));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn post_policy_should_only_let_admins_post() -> Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let settings = ChatSettings {
+            post_policy: PostPolicy::AdminsOnly,
+            ..Default::default()
+        };
+        state.update_chat_settings(1, &settings).await?;
+        state.update_chat_member_role(1, 2, ChatRole::Admin).await?;
+
+        let send = |user_id| {
+            let input = CreateMessage {
+                content: "announcement".to_string(),
+                files: vec![],
+            };
+            state.create_message(input, 1, user_id)
+        };
+        // user 1 owns the chat
+        send(1).await?;
+        send(2).await?;
+        let err = send(3).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "permission denied: only chat owners and admins can post in the chat"
+        );
+        // members can still read it
+        assert!(state.is_chat_member(1, 3).await?);
+        Ok(())
+    }
+}
@@ -0,0 +1,330 @@
+use crate::{config::PolicyHookConfig, AppConfig, AppError, AppState, ChatRole};
+use serde::{Deserialize, Serialize};
+use std::{future::Future, pin::Pin, sync::Arc};
+
+pub type PolicyFuture = Pin<Box<dyn Future<Output = Result<Option<bool>, AppError>> + Send>>;
+
+/// What a user can be allowed to do in a chat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatAction {
+    UpdateChat,
+    DeleteChat,
+    KeepChat,
+    ChangeAvatar,
+    ArchiveChat,
+    UnarchiveChat,
+    RestoreChat,
+    /// post in a chat only its owners and admins post in
+    Post,
+    RemoveMembers,
+    ManageBans,
+    ChangeMemberRoles,
+    TransferChat,
+    ManageJoinRequests,
+    ManageArchivalWebhook,
+}
+
+/// The user asking, with their role in the chat of the resource.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Subject {
+    pub user_id: i64,
+    /// None if they aren't a member
+    pub chat_role: Option<ChatRole>,
+}
+
+/// The chat, or the member of the chat, an action is done to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Resource {
+    pub chat_id: i64,
+    pub member_id: Option<i64>,
+    pub member_role: Option<ChatRole>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PolicyRequest {
+    pub subject: Subject,
+    pub action: ChatAction,
+    pub resource: Resource,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// with the reason, e.g. "only the chat owner can delete the chat"
+    Deny(String),
+}
+
+/// Asked about each decision after the built-in rules, e.g. to let more roles archive
+/// chats. Returns None to keep the decision of the rules, given as `allowed`.
+pub trait PolicyHook: Send + Sync + 'static {
+    fn check(&self, req: &PolicyRequest, allowed: bool) -> PolicyFuture;
+}
+
+pub struct NoPolicyHook;
+
+/// Open Policy Agent's data api: `{"input": request}` is posted to the url of a rule and
+/// its boolean `result` is the decision, an undefined rule has none.
+pub struct OpaPolicyHook {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Serialize)]
+struct OpaInput<'a> {
+    #[serde(flatten)]
+    request: &'a PolicyRequest,
+    allowed: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct OpaRequest<'a> {
+    input: OpaInput<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpaResponse {
+    result: Option<bool>,
+}
+
+impl ChatAction {
+    /// Lowest role in the chat the action is allowed to.
+    pub fn min_role(self) -> ChatRole {
+        match self {
+            Self::DeleteChat | Self::KeepChat | Self::ChangeMemberRoles | Self::TransferChat => {
+                ChatRole::Owner
+            }
+            _ => ChatRole::Admin,
+        }
+    }
+
+    // as in "only the chat owner can delete the chat"
+    fn describe(self) -> &'static str {
+        match self {
+            Self::UpdateChat => "update the chat",
+            Self::DeleteChat => "delete the chat",
+            Self::KeepChat => "keep the chat",
+            Self::ChangeAvatar => "change the chat avatar",
+            Self::ArchiveChat => "archive the chat",
+            Self::UnarchiveChat => "unarchive the chat",
+            Self::RestoreChat => "restore the chat",
+            Self::Post => "post in the chat",
+            Self::RemoveMembers => "remove members",
+            Self::ManageBans => "manage bans",
+            Self::ChangeMemberRoles => "change member roles",
+            Self::TransferChat => "transfer the chat",
+            Self::ManageJoinRequests => "manage join requests",
+            Self::ManageArchivalWebhook => "manage the archival webhook",
+        }
+    }
+}
+
+impl Resource {
+    pub fn chat(chat_id: u64) -> Self {
+        Self {
+            chat_id: chat_id as _,
+            member_id: None,
+            member_role: None,
+        }
+    }
+
+    pub fn member(chat_id: u64, member_id: u64, member_role: Option<ChatRole>) -> Self {
+        Self {
+            chat_id: chat_id as _,
+            member_id: Some(member_id as _),
+            member_role,
+        }
+    }
+}
+
+/// The built-in rules: each action needs at least its `min_role` in the chat, and only
+/// the owner removes admins.
+pub fn decide(req: &PolicyRequest) -> Decision {
+    let action = req.action;
+    let min = action.min_role();
+    let role = req.subject.chat_role;
+    if !role.is_some_and(|role| role.rank() >= min.rank()) {
+        return match min {
+            ChatRole::Owner => {
+                Decision::Deny(format!("only the chat owner can {}", action.describe()))
+            }
+            _ => Decision::Deny(format!(
+                "only chat owners and admins can {}",
+                action.describe()
+            )),
+        };
+    }
+    if action == ChatAction::RemoveMembers
+        && req.resource.member_role == Some(ChatRole::Admin)
+        && role != Some(ChatRole::Owner)
+    {
+        return Decision::Deny("only the chat owner can remove admins".to_string());
+    }
+    Decision::Allow
+}
+
+impl PolicyHook for NoPolicyHook {
+    fn check(&self, _req: &PolicyRequest, _allowed: bool) -> PolicyFuture {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+impl OpaPolicyHook {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl PolicyHook for OpaPolicyHook {
+    fn check(&self, req: &PolicyRequest, allowed: bool) -> PolicyFuture {
+        let body = OpaRequest {
+            input: OpaInput {
+                request: req,
+                allowed,
+            },
+        };
+        let req = self.client.post(&self.url).json(&body);
+        Box::pin(async move {
+            let res = req.send().await.map_err(anyhow::Error::from)?;
+            if !res.status().is_success() {
+                let status = res.status();
+                return Err(AppError::AnyError(anyhow::anyhow!(
+                    "policy hook failed: {status}"
+                )));
+            }
+            let res: OpaResponse = res.json().await.map_err(anyhow::Error::from)?;
+            Ok(res.result)
+        })
+    }
+}
+
+/// Build the hook configured in `policy.hook`.
+pub(crate) fn build_policy_hook(config: &AppConfig) -> Arc<dyn PolicyHook> {
+    match &config.policy.hook {
+        PolicyHookConfig::Disabled => Arc::new(NoPolicyHook),
+        PolicyHookConfig::Opa { url } => Arc::new(OpaPolicyHook::new(url)),
+    }
+}
+
+impl AppState {
+    /// Fail unless `user_id` may do `action` to `resource`, by the built-in rules and then
+    /// the hook in `policy.hook`. A failing hook fails the request.
+    pub async fn authorize(
+        &self,
+        user_id: u64,
+        action: ChatAction,
+        resource: Resource,
+    ) -> Result<(), AppError> {
+        let chat_role = self.get_chat_role(resource.chat_id as _, user_id).await?;
+        let req = PolicyRequest {
+            subject: Subject {
+                user_id: user_id as _,
+                chat_role,
+            },
+            action,
+            resource,
+        };
+        let decision = decide(&req);
+        let allowed = decision == Decision::Allow;
+        match (self.policy_hook.check(&req, allowed).await?, decision) {
+            (Some(true), _) | (None, Decision::Allow) => Ok(()),
+            (_, Decision::Deny(reason)) => Err(AppError::PermissionDenied(reason)),
+            (Some(false), Decision::Allow) => Err(AppError::PermissionDenied(format!(
+                "the access policy doesn't allow to {}",
+                action.describe()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use axum::{routing::post, Json, Router};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    fn request(role: Option<ChatRole>, action: ChatAction, resource: Resource) -> PolicyRequest {
+        PolicyRequest {
+            subject: Subject {
+                user_id: 2,
+                chat_role: role,
+            },
+            action,
+            resource,
+        }
+    }
+
+    #[test]
+    fn decide_should_follow_chat_roles() {
+        let chat = Resource::chat(1);
+        let req = request(Some(ChatRole::Admin), ChatAction::UpdateChat, chat.clone());
+        assert_eq!(decide(&req), Decision::Allow);
+        let req = request(Some(ChatRole::Member), ChatAction::UpdateChat, chat.clone());
+        assert_eq!(
+            decide(&req),
+            Decision::Deny("only chat owners and admins can update the chat".to_string())
+        );
+        let req = request(None, ChatAction::ArchiveChat, chat.clone());
+        assert!(matches!(decide(&req), Decision::Deny(_)));
+        let req = request(Some(ChatRole::Admin), ChatAction::DeleteChat, chat.clone());
+        assert_eq!(
+            decide(&req),
+            Decision::Deny("only the chat owner can delete the chat".to_string())
+        );
+        let req = request(Some(ChatRole::Owner), ChatAction::TransferChat, chat);
+        assert_eq!(decide(&req), Decision::Allow);
+    }
+
+    #[test]
+    fn decide_should_let_only_owners_remove_admins() {
+        let admin = Resource::member(1, 3, Some(ChatRole::Admin));
+        let member = Resource::member(1, 3, Some(ChatRole::Member));
+        let req = request(Some(ChatRole::Admin), ChatAction::RemoveMembers, member);
+        assert_eq!(decide(&req), Decision::Allow);
+        let req = request(
+            Some(ChatRole::Admin),
+            ChatAction::RemoveMembers,
+            admin.clone(),
+        );
+        assert_eq!(
+            decide(&req),
+            Decision::Deny("only the chat owner can remove admins".to_string())
+        );
+        let req = request(Some(ChatRole::Owner), ChatAction::RemoveMembers, admin);
+        assert_eq!(decide(&req), Decision::Allow);
+    }
+
+    #[tokio::test]
+    async fn opa_policy_hook_should_return_result() -> Result<()> {
+        // members may archive, nothing is said about the other actions
+        let app = Router::new().route(
+            "/v1/data/chat/allow",
+            post(|Json(body): Json<Value>| async move {
+                let input = &body["input"];
+                match input["action"].as_str() {
+                    Some("archive_chat") => Json(json!({ "result": true })),
+                    _ if input["allowed"] == true => Json(json!({ "result": true })),
+                    _ => Json(json!({})),
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let hook = OpaPolicyHook::new(&format!("http://{addr}/v1/data/chat/allow"));
+        let member = Some(ChatRole::Member);
+        let req = request(member, ChatAction::ArchiveChat, Resource::chat(1));
+        assert_eq!(hook.check(&req, false).await?, Some(true));
+        let req = request(member, ChatAction::DeleteChat, Resource::chat(1));
+        assert_eq!(hook.check(&req, false).await?, None);
+        assert_eq!(hook.check(&req, true).await?, Some(true));
+        assert_eq!(NoPolicyHook.check(&req, false).await?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+//! Gradual rollouts of risky rewrites. A feature sends a share of the workspaces to a canary
+//! implementation of its handlers and counts requests of both cohorts apart, so they can be
+//! compared before the canary takes all traffic, e.g.
+//!
+//! ```ignore
+//! .route(
+//!     "/:id/messages",
+//!     canary(state.clone(), "message_pipeline", post(send_message_v2), post(send_message)),
+//! )
+//! ```
+//!
+//! Cohorts are assigned by a hash of the feature and the workspace id, so a workspace stays
+//! in its cohort across requests and servers while the percentage only grows.
+
+use crate::{config::FeatureConfig, AppError, AppState};
+use axum::{
+    extract::{Request, State},
+    response::Response,
+    routing::{any, MethodRouter},
+    Extension,
+};
+use chat_core::User;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+use tower::ServiceExt;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cohort {
+    Stable,
+    Canary,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct CohortMetrics {
+    pub requests: u64,
+    /// responses with a 5xx status
+    pub errors: u64,
+    pub avg_latency_ms: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, ToSchema, Serialize, Deserialize)]
+pub struct Feature {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: FeatureConfig,
+    pub stable: CohortMetrics,
+    pub canary: CohortMetrics,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FeatureRollout {
+    config: FeatureConfig,
+    stable: CohortCounters,
+    canary: CohortCounters,
+}
+
+#[derive(Debug, Default)]
+struct CohortCounters {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    total_latency_ms: AtomicU64,
+}
+
+/// Route requests of workspaces in the canary cohort of `feature` to `canary` and the
+/// others to `stable`. Needs the user of the request, so it goes behind `verify_token`.
+pub fn canary(
+    state: AppState,
+    feature: &'static str,
+    canary: MethodRouter<AppState>,
+    stable: MethodRouter<AppState>,
+) -> MethodRouter<AppState> {
+    let canary = canary.with_state(state.clone());
+    let stable = stable.with_state(state);
+    any(
+        move |State(state): State<AppState>, Extension(user): Extension<User>, req: Request| {
+            let (canary, stable) = (canary.clone(), stable.clone());
+            async move {
+                let cohort = state.feature_cohort(feature, user.ws_id);
+                let start = Instant::now();
+                let ret = match cohort {
+                    Cohort::Canary => canary.oneshot(req).await,
+                    Cohort::Stable => stable.oneshot(req).await,
+                };
+                let res = match ret {
+                    Ok(res) => res,
+                    Err(e) => match e {},
+                };
+                state.record_feature_request(feature, cohort, &res, start);
+                res
+            }
+        },
+    )
+}
+
+pub(crate) fn load_features(
+    features: &HashMap<String, FeatureConfig>,
+) -> HashMap<String, FeatureRollout> {
+    features
+        .iter()
+        .map(|(name, config)| {
+            let rollout = FeatureRollout {
+                config: config.clone(),
+                ..Default::default()
+            };
+            (name.clone(), rollout)
+        })
+        .collect()
+}
+
+impl AppState {
+    /// Cohort of the workspace for `feature`, unknown features are stable for everyone.
+    pub fn feature_cohort(&self, feature: &str, ws_id: i64) -> Cohort {
+        let features = self.features.read().expect("features poisoned");
+        let Some(rollout) = features.get(feature) else {
+            return Cohort::Stable;
+        };
+        let config = &rollout.config;
+        if config.workspaces.contains(&ws_id) || bucket(feature, ws_id) < config.percent {
+            Cohort::Canary
+        } else {
+            Cohort::Stable
+        }
+    }
+
+    pub fn list_features(&self) -> Vec<Feature> {
+        let features = self.features.read().expect("features poisoned");
+        let mut ret: Vec<_> = features
+            .iter()
+            .map(|(name, rollout)| rollout.to_feature(name))
+            .collect();
+        ret.sort_by(|a, b| a.name.cmp(&b.name));
+        ret
+    }
+
+    /// Change the rollout of a feature until the next restart, metrics are kept.
+    pub fn update_feature(&self, name: &str, config: FeatureConfig) -> Result<Feature, AppError> {
+        if config.percent > 100 {
+            return Err(AppError::FeatureError(
+                "Percent must be between 0 and 100".to_string(),
+            ));
+        }
+        let mut features = self.features.write().expect("features poisoned");
+        let rollout = features.entry(name.to_string()).or_default();
+        rollout.config = config;
+        Ok(rollout.to_feature(name))
+    }
+
+    fn record_feature_request(
+        &self,
+        feature: &str,
+        cohort: Cohort,
+        res: &Response,
+        start: Instant,
+    ) {
+        let features = self.features.read().expect("features poisoned");
+        let Some(rollout) = features.get(feature) else {
+            return;
+        };
+        let counters = match cohort {
+            Cohort::Stable => &rollout.stable,
+            Cohort::Canary => &rollout.canary,
+        };
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+        if res.status().is_server_error() {
+            counters.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let elapsed = start.elapsed().as_millis() as u64;
+        counters
+            .total_latency_ms
+            .fetch_add(elapsed, Ordering::Relaxed);
+    }
+}
+
+impl FeatureRollout {
+    fn to_feature(&self, name: &str) -> Feature {
+        Feature {
+            name: name.to_string(),
+            config: self.config.clone(),
+            stable: self.stable.metrics(),
+            canary: self.canary.metrics(),
+        }
+    }
+}
+
+impl CohortCounters {
+    fn metrics(&self) -> CohortMetrics {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let total = self.total_latency_ms.load(Ordering::Relaxed);
+        CohortMetrics {
+            requests,
+            errors: self.errors.load(Ordering::Relaxed),
+            avg_latency_ms: if requests == 0 {
+                0.0
+            } else {
+                total as f64 / requests as f64
+            },
+        }
+    }
+}
+
+// 0..100, stable for a feature and workspace
+fn bucket(feature: &str, ws_id: i64) -> u8 {
+    let hash = Sha256::digest(format!("{feature}:{ws_id}"));
+    (u16::from_be_bytes([hash[0], hash[1]]) % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, routing::get, Router};
+    use chrono::Utc;
+
+    fn user(ws_id: i64) -> User {
+        User {
+            id: 1,
+            ws_id,
+            fullname: "Tyr Chen".to_string(),
+            email: "tchen@acme.org".to_string(),
+            password_hash: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn feature_cohorts_should_follow_the_percent() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        assert_eq!(state.feature_cohort("pipeline", 1), Cohort::Stable);
+
+        let config = FeatureConfig {
+            percent: 30,
+            workspaces: vec![1],
+        };
+        state.update_feature("pipeline", config)?;
+        assert_eq!(state.feature_cohort("pipeline", 1), Cohort::Canary);
+        let canary = (2..1002)
+            .filter(|ws_id| state.feature_cohort("pipeline", *ws_id) == Cohort::Canary)
+            .count();
+        assert!((200..400).contains(&canary), "{canary} canary workspaces");
+
+        let config = FeatureConfig {
+            percent: 101,
+            workspaces: vec![],
+        };
+        assert!(state.update_feature("pipeline", config).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn canary_should_route_by_cohort() -> anyhow::Result<()> {
+        let (_tdb, state) = AppState::new_for_test().await?;
+        let config = FeatureConfig {
+            percent: 0,
+            workspaces: vec![1],
+        };
+        state.update_feature("pipeline", config)?;
+        let route = canary(
+            state.clone(),
+            "pipeline",
+            get(|| async { "canary" }),
+            get(|| async { "stable" }),
+        );
+        let app = Router::new().route("/", route).with_state(state.clone());
+
+        for ws_id in [1, 2, 2] {
+            let req = Request::builder()
+                .uri("/")
+                .extension(user(ws_id))
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            let body = http_body_util::BodyExt::collect(res.into_body()).await?;
+            let expected = if ws_id == 1 { "canary" } else { "stable" };
+            assert_eq!(body.to_bytes(), expected);
+        }
+        let feature = &state.list_features()[0];
+        assert_eq!((feature.canary.requests, feature.stable.requests), (1, 2));
+        Ok(())
+    }
+}
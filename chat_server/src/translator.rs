@@ -0,0 +1,105 @@
+use crate::{config::TranslationProvider, AppConfig, AppError, Locale};
+use serde::Deserialize;
+use serde_json::json;
+use std::{future::Future, pin::Pin, sync::Arc};
+
+pub type TranslateFuture =
+    Pin<Box<dyn Future<Output = Result<Option<Translation>, AppError>> + Send>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translation {
+    /// detected language of the text, e.g. `en`
+    pub source_lang: String,
+    pub content: String,
+}
+
+/// Machine translates message contents, one implementation per provider in
+/// `translation.provider`. Returns None if the text can't be translated.
+pub trait Translator: Send + Sync + 'static {
+    fn translate(&self, text: &str, target: Locale) -> TranslateFuture;
+}
+
+pub struct DisabledTranslator;
+
+pub struct LibreTranslator {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LibreResponse {
+    translated_text: String,
+    detected_language: Option<LibreLanguage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LibreLanguage {
+    language: String,
+}
+
+impl Translator for DisabledTranslator {
+    fn translate(&self, _text: &str, _target: Locale) -> TranslateFuture {
+        Box::pin(async { Ok(None) })
+    }
+}
+
+impl LibreTranslator {
+    pub fn new(url: &str, api_key: Option<String>) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl Translator for LibreTranslator {
+    fn translate(&self, text: &str, target: Locale) -> TranslateFuture {
+        let body = json!({
+            "q": text,
+            "source": "auto",
+            "target": primary_lang(target.as_str()),
+            "format": "text",
+            "api_key": self.api_key,
+        });
+        let req = self
+            .client
+            .post(format!("{}/translate", self.url))
+            .json(&body);
+        Box::pin(async move {
+            let res = req.send().await.map_err(anyhow::Error::from)?;
+            if !res.status().is_success() {
+                let status = res.status();
+                let text = res.text().await.unwrap_or_default();
+                return Err(AppError::AnyError(anyhow::anyhow!(
+                    "translation rejected: {status} {text}"
+                )));
+            }
+            let res: LibreResponse = res.json().await.map_err(anyhow::Error::from)?;
+            let Some(source) = res.detected_language else {
+                return Ok(None);
+            };
+            Ok(Some(Translation {
+                source_lang: source.language,
+                content: res.translated_text,
+            }))
+        })
+    }
+}
+
+/// Language without its region, e.g. `zh` for `zh-CN`.
+pub(crate) fn primary_lang(lang: &str) -> &str {
+    lang.split(['-', '_']).next().unwrap_or(lang)
+}
+
+/// Build the translator configured in `translation.provider`.
+pub(crate) fn build_translator(config: &AppConfig) -> Arc<dyn Translator> {
+    match &config.translation.provider {
+        TranslationProvider::Disabled => Arc::new(DisabledTranslator),
+        TranslationProvider::Libre { url, api_key } => {
+            Arc::new(LibreTranslator::new(url, api_key.clone()))
+        }
+    }
+}
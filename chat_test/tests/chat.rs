@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chat_core::{Chat, ChatType, Message};
+use chat_core::{encode_id, Chat, ChatType, Message};
 use futures::StreamExt;
 use reqwest::{
     multipart::{Form, Part},
@@ -47,7 +47,8 @@ async fn chat_server_should_work() -> Result<()> {
     let (tdb, state) = chat_server::AppState::new_for_test().await?;
     let chat_server = ChatServer::new(state).await?;
     let db_url = tdb.url();
-    NotifyServer::new(&db_url, &chat_server.token).await?;
+    let stream_token = chat_server.create_stream_token().await?;
+    NotifyServer::new(&db_url, &stream_token).await?;
     let chat = chat_server.create_chat().await?;
     let _msg = chat_server.create_message(chat.id as u64).await?;
     sleep(Duration::from_secs(10)).await;
@@ -68,13 +69,14 @@ impl NotifyServer {
                 .unwrap();
         });
 
-        let mut es = EventSource::get(format!("http://{}/events?access_token={}", addr, token));
+        let mut es = EventSource::get(format!("http://{}/events?stream_token={}", addr, token));
 
         tokio::spawn(async move {
             while let Some(event) = es.next().await {
                 match event {
                     Ok(Event::Open) => println!("Connection Open!"),
                     Ok(Event::Message(message)) => match message.event.as_str() {
+                        "Connected" | "KeepAlive" => {}
                         "NewChat" => {
                             let chat: Chat = serde_json::from_str(&message.data).unwrap();
                             assert_eq!(chat.name.as_ref().unwrap(), "test");
@@ -87,6 +89,10 @@ impl NotifyServer {
                             assert_eq!(msg.content, "hello");
                             assert_eq!(msg.files.len(), 1);
                             assert_eq!(msg.sender_id, 1);
+                            let data: serde_json::Value =
+                                serde_json::from_str(&message.data).unwrap();
+                            let key = format!("chat:{}", encode_id(msg.chat_id));
+                            assert_eq!(data["hints"]["collapse_key"], key.as_str());
                         }
                         _ => {
                             panic!("unexpected event: {:?}", message);
@@ -134,7 +140,7 @@ impl ChatServer {
     async fn signin(&self) -> Result<String> {
         let res = self
             .client
-            .post(&format!("http://{}/api/signin", self.addr))
+            .post(format!("http://{}/api/signin", self.addr))
             .header("Content-Type", "application/json")
             .body(r#"{"email": "tchen@acme.org","password":"123456"}"#)
             .send()
@@ -145,6 +151,19 @@ impl ChatServer {
         Ok(ret.token)
     }
 
+    async fn create_stream_token(&self) -> Result<String> {
+        let res = self
+            .client
+            .post(format!("http://{}/api/events/token", self.addr))
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send()
+            .await?;
+
+        assert_eq!(res.status(), 200);
+        let ret: AuthToken = res.json().await?;
+        Ok(ret.token)
+    }
+
     async fn create_chat(&self) -> Result<Chat> {
         let res = self
             .client
@@ -172,7 +191,7 @@ impl ChatServer {
 
         let res = self
             .client
-            .post(&format!("http://{}/api/upload", self.addr))
+            .post(format!("http://{}/api/upload", self.addr))
             .header("Authorization", format!("Bearer {}", self.token))
             .multipart(form)
             .send()
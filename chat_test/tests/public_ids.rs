@@ -0,0 +1,203 @@
+use anyhow::Result;
+use chat_core::{encode_id, set_id_codec, IdObfuscator};
+use futures::StreamExt;
+use reqwest::StatusCode;
+use reqwest_eventsource::{Event, EventSource};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{net::TcpListener, time::timeout};
+
+// its own test binary, the codec is installed for the whole process
+
+#[derive(Debug, Deserialize)]
+struct AuthToken {
+    token: String,
+}
+
+struct ChatServer {
+    addr: SocketAddr,
+    client: reqwest::Client,
+}
+
+#[tokio::test]
+async fn plain_ids_should_be_rejected_with_codec() -> Result<()> {
+    set_id_codec(IdObfuscator::new(0x5EED));
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let token = server.signin("tchen@acme.org").await?;
+
+    let chat = encode_id(1);
+    let res = server.get(&format!("/api/chats/{chat}"), &token).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = server.get("/api/chats/1", &token).await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = server
+        .client
+        .post(server.url("/api/chats/2/join"))
+        .bearer_auth(&token)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    Ok(())
+}
+
+#[tokio::test]
+async fn guests_should_use_public_ids_with_codec() -> Result<()> {
+    set_id_codec(IdObfuscator::new(0x5EED));
+    let (_tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let token = server.signin("tchen@acme.org").await?;
+
+    let chat = encode_id(1);
+    let res = server
+        .client
+        .post(server.url(&format!("/api/chats/{chat}/guest-links")))
+        .bearer_auth(&token)
+        .json(&json!({ "expires_in_secs": 3600 }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let link: serde_json::Value = res.json().await?;
+    let res = server
+        .client
+        .post(server.url(&format!(
+            "/api/guest-links/{}/redeem",
+            link["token"].as_str().unwrap()
+        )))
+        .json(&json!({ "fullname": "Customer" }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let guest: AuthToken = res.json().await?;
+
+    let res = server
+        .get(
+            &format!("/api/chats/{chat}/messages?limit=10"),
+            &guest.token,
+        )
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = server
+        .get(&format!("/api/chats/{chat}"), &guest.token)
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let other = encode_id(2);
+    let res = server
+        .get(&format!("/api/chats/{other}"), &guest.token)
+        .await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    Ok(())
+}
+
+#[tokio::test]
+async fn mentions_should_use_public_ids_with_codec() -> Result<()> {
+    set_id_codec(IdObfuscator::new(0x5EED));
+    let (tdb, state) = chat_server::AppState::new_for_test().await?;
+    let server = ChatServer::new(state).await?;
+    let token = server.signin("tchen@acme.org").await?;
+    let alice = server.signin("alice@acme.org").await?;
+    let res = server
+        .client
+        .post(server.url("/api/events/token"))
+        .bearer_auth(&alice)
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let stream_token: AuthToken = res.json().await?;
+
+    let mut config = notify_server::AppConfig::load()?;
+    config.server.db_url = tdb.url();
+    let app = notify_server::get_router(config).await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service())
+            .await
+            .unwrap();
+    });
+    let mut es = EventSource::get(format!(
+        "http://{addr}/events?stream_token={}",
+        stream_token.token
+    ));
+    // messages are only delivered once the stream is connected
+    let connected = async {
+        while let Some(event) = es.next().await {
+            if let Ok(Event::Message(message)) = event {
+                if message.event == "Connected" {
+                    return;
+                }
+            }
+        }
+    };
+    timeout(Duration::from_secs(10), connected).await?;
+
+    // alice is user 2, a member of chat 1
+    let res = server
+        .client
+        .post(server.url(&format!("/api/chats/{}", encode_id(1))))
+        .bearer_auth(&token)
+        .json(&json!({ "content": format!("hi <@{}>", encode_id(2)), "files": [] }))
+        .send()
+        .await?;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    let message = async {
+        while let Some(event) = es.next().await {
+            if let Ok(Event::Message(message)) = event {
+                if message.event == "NewMessage" {
+                    return Some(message.data);
+                }
+            }
+        }
+        None
+    };
+    let data = timeout(Duration::from_secs(10), message).await?.unwrap();
+    let data: Value = serde_json::from_str(&data)?;
+    assert_eq!(data["sender_id"], encode_id(1).as_str());
+    assert_eq!(data["hints"]["is_mention"], true);
+    assert_eq!(data["hints"]["priority"], "high");
+    Ok(())
+}
+
+impl ChatServer {
+    async fn new(state: chat_server::AppState) -> Result<Self> {
+        let app = chat_server::get_router(state).await?;
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service())
+                .await
+                .unwrap();
+        });
+        Ok(Self {
+            addr,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    async fn signin(&self, email: &str) -> Result<String> {
+        let res = self
+            .client
+            .post(self.url("/api/signin"))
+            .json(&json!({ "email": email, "password": "123456" }))
+            .send()
+            .await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        let ret: AuthToken = res.json().await?;
+        Ok(ret.token)
+    }
+
+    async fn get(&self, path: &str, token: &str) -> Result<reqwest::Response> {
+        Ok(self
+            .client
+            .get(self.url(path))
+            .bearer_auth(token)
+            .send()
+            .await?)
+    }
+}
@@ -0,0 +1,25 @@
+-- Add migration script here
+-- create job status: pending, running, done, failed
+CREATE TYPE job_status AS ENUM(
+  'pending',
+  'running',
+  'done',
+  'failed'
+);
+
+-- background jobs, claimed by workers with FOR UPDATE SKIP LOCKED
+CREATE TABLE IF NOT EXISTS jobs(
+  id bigserial PRIMARY KEY,
+  kind varchar(64) NOT NULL,
+  payload jsonb NOT NULL DEFAULT '{}',
+  status job_status NOT NULL DEFAULT 'pending',
+  attempts int NOT NULL DEFAULT 0,
+  max_attempts int NOT NULL DEFAULT 5,
+  run_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_error text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  updated_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- create index for jobs waiting to be picked up
+CREATE INDEX IF NOT EXISTS jobs_status_run_at_index ON jobs(status, run_at);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- per workspace settings (branding etc.), see chat_core::WorkspaceSettings
+ALTER TABLE workspaces
+  ADD COLUMN settings jsonb NOT NULL DEFAULT '{}';
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- create time format: 12h, 24h
+CREATE TYPE time_format AS ENUM(
+  '12h',
+  '24h'
+);
+
+-- per user locale and time format, locale is validated by chat_server::Locale
+ALTER TABLE users
+  ADD COLUMN locale varchar(16) NOT NULL DEFAULT 'en',
+  ADD COLUMN time_format time_format NOT NULL DEFAULT '24h';
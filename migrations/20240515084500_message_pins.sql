@@ -0,0 +1,14 @@
+-- Add migration script here
+-- per chat pin limit, NULL means the server default (chat.max_pins)
+ALTER TABLE chats
+  ADD COLUMN pin_limit int;
+
+-- pinned messages of a chat, ordered by position (0 is the top)
+CREATE TABLE IF NOT EXISTS message_pins(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  position int NOT NULL,
+  pinned_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, message_id)
+);
@@ -0,0 +1,29 @@
+-- Add migration script here
+-- create guest access: read_only, read_write
+CREATE TYPE guest_access AS ENUM(
+  'read_only',
+  'read_write'
+);
+
+-- public links to a channel, redeemed by external users as guest accounts
+CREATE TABLE IF NOT EXISTS guest_links(
+  id bigserial PRIMARY KEY,
+  token varchar(32) NOT NULL UNIQUE DEFAULT replace(gen_random_uuid()::text, '-', ''),
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  created_by bigint NOT NULL REFERENCES users(id),
+  access guest_access NOT NULL,
+  expires_at timestamptz NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+-- guest accounts, access is copied from the link so a guest stays restricted
+-- even if the link or the chat is deleted
+CREATE TABLE IF NOT EXISTS guests(
+  user_id bigint PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  link_id bigint REFERENCES guest_links(id) ON DELETE SET NULL,
+  chat_id bigint NOT NULL,
+  access guest_access NOT NULL,
+  expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS guests_link_id_index ON guests(link_id);
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- per chat settings (export policy etc.), see chat_server::ChatSettings
+ALTER TABLE chats
+  ADD COLUMN settings jsonb NOT NULL DEFAULT '{}';
+
+-- audit log for sensitive operations, e.g. exporting chat history
+CREATE TABLE IF NOT EXISTS audit_logs(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  actor_id bigint NOT NULL REFERENCES users(id),
+  action varchar(64) NOT NULL,
+  -- id of the chat, file etc. the action applies to
+  target_id bigint,
+  details jsonb NOT NULL DEFAULT '{}',
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS audit_logs_ws_id_index ON audit_logs(ws_id, id DESC);
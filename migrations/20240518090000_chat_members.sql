@@ -0,0 +1,210 @@
+-- Add migration script here
+-- create chat role: owner, admin, member
+CREATE TYPE chat_role AS ENUM(
+  'owner',
+  'admin',
+  'member'
+);
+
+-- replaces chats.members
+CREATE TABLE IF NOT EXISTS chat_members(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  role chat_role NOT NULL DEFAULT 'member',
+  joined_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  invited_by bigint REFERENCES users(id),
+  PRIMARY KEY (chat_id, user_id)
+);
+
+-- reverse lookup: all chats of a user
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members(user_id);
+
+-- the first member of an existing chat becomes its owner
+INSERT INTO chat_members(chat_id, user_id, role, joined_at)
+SELECT
+  c.id,
+  m.user_id,
+  CASE WHEN m.ord = 1 THEN 'owner'::chat_role ELSE 'member'::chat_role END,
+  COALESCE(c.created_at, CURRENT_TIMESTAMP)
+FROM
+  chats c,
+  unnest(c.members) WITH ORDINALITY AS m(user_id, ord)
+ON CONFLICT DO NOTHING;
+
+-- member ids of a chat, used wherever chats.members was used
+CREATE OR REPLACE FUNCTION chat_member_ids(cid bigint)
+  RETURNS bigint[]
+  AS $$
+  SELECT
+    COALESCE(array_agg(user_id ORDER BY user_id), '{}')
+  FROM
+    chat_members
+  WHERE
+    chat_id = cid;
+$$
+LANGUAGE sql
+STABLE;
+
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+DROP FUNCTION IF EXISTS add_to_chat();
+
+ALTER TABLE chats
+  DROP COLUMN members;
+
+-- chat row as json in the same shape as chat_core::Chat
+CREATE OR REPLACE FUNCTION chat_json(c chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    jsonb_build_object('id', c.id, 'ws_id', c.ws_id, 'name', c.name, 'type', c.type, 'members', members, 'created_at', c.created_at);
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+-- members are added after the chat row, so notify at commit time when they are visible
+CREATE OR REPLACE FUNCTION chat_created()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  chat chats;
+BEGIN
+  SELECT
+    * INTO chat
+  FROM
+    chats
+  WHERE
+    id = NEW.id;
+  IF FOUND THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'INSERT', 'old', NULL, 'new', chat_json(chat, chat_member_ids(chat.id)))::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER chat_created_trigger
+  AFTER INSERT ON chats DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_created();
+
+CREATE OR REPLACE FUNCTION chat_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  members bigint[];
+BEGIN
+  IF OLD.name IS DISTINCT FROM NEW.name THEN
+    members := chat_member_ids(NEW.id);
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'UPDATE', 'old', chat_json(OLD, members), 'new', chat_json(NEW, members))::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_updated_trigger
+  AFTER UPDATE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_updated();
+
+-- before delete, so the members haven't been removed by the cascade yet
+CREATE OR REPLACE FUNCTION chat_deleted()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  PERFORM
+    pg_notify('chat_updated', json_build_object('op', 'DELETE', 'old', chat_json(OLD, chat_member_ids(OLD.id)), 'new', NULL)::text);
+  RETURN OLD;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_deleted_trigger
+  BEFORE DELETE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_deleted();
+
+-- member changes are notified as an update of the chat, once per chat and statement
+CREATE OR REPLACE FUNCTION chat_members_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  chat chats;
+  changed bigint[];
+  members bigint[];
+BEGIN
+  FOR chat IN
+  SELECT
+    c.*
+  FROM
+    chats c
+  WHERE
+    c.id IN (
+      SELECT
+        chat_id
+      FROM
+        changed_members)
+      -- members inserted together with a new chat are part of its INSERT notification
+      AND (TG_OP = 'DELETE'
+        OR c.created_at < CURRENT_TIMESTAMP)
+      LOOP
+        SELECT
+          array_agg(user_id) INTO changed
+        FROM
+          changed_members
+        WHERE
+          chat_id = chat.id;
+        members := chat_member_ids(chat.id);
+        PERFORM
+          pg_notify('chat_updated', json_build_object('op', 'UPDATE', 'old', chat_json(chat, CASE WHEN TG_OP = 'INSERT' THEN
+                ARRAY (
+                  SELECT
+                    unnest(members)
+                  EXCEPT
+                  SELECT
+                    unnest(changed)
+                  ORDER BY
+                    1)
+              ELSE
+                ARRAY (
+                  SELECT
+                    unnest(members)
+                  UNION
+                  SELECT
+                    unnest(changed)
+                  ORDER BY
+                    1)
+              END), 'new', chat_json(chat, members))::text);
+      END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_members_added_trigger
+  AFTER INSERT ON chat_members REFERENCING NEW TABLE AS changed_members
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_members_changed();
+
+CREATE TRIGGER chat_members_removed_trigger
+  AFTER DELETE ON chat_members REFERENCING OLD TABLE AS changed_members
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_members_changed();
+
+-- if new message added, notify with message data
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
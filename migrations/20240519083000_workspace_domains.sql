@@ -0,0 +1,18 @@
+-- Add migration script here
+-- email domains claimed by a workspace, verified with a DNS TXT record
+CREATE TABLE IF NOT EXISTS workspace_domains(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  domain varchar(253) NOT NULL,
+  token varchar(32) NOT NULL DEFAULT replace(gen_random_uuid()::text, '-', ''),
+  -- add new signups with this domain to the workspace instead of just suggesting it
+  auto_join boolean NOT NULL DEFAULT FALSE,
+  verified_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (ws_id, domain)
+);
+
+-- a domain can only be verified by one workspace
+CREATE UNIQUE INDEX IF NOT EXISTS workspace_domains_verified_index ON workspace_domains(domain)
+WHERE
+  verified_at IS NOT NULL;
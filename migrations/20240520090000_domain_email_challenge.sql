@@ -0,0 +1,6 @@
+-- Add migration script here
+-- alternative to the DNS TXT record: a code mailed to an address on the domain
+ALTER TABLE workspace_domains
+  ADD COLUMN challenge_email varchar(255),
+  ADD COLUMN challenge_code varchar(8),
+  ADD COLUMN challenge_expires_at timestamptz;
@@ -0,0 +1,36 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS message_reactions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  emoji varchar(64) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- workflows started by reacting with an emoji, configured by the workspace owner
+CREATE TABLE IF NOT EXISTS reaction_triggers(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  emoji varchar(64) NOT NULL,
+  -- e.g. {"type": "pin"} or {"type": "webhook", "url": "https://..."}
+  action jsonb NOT NULL,
+  -- max runs per minute, further reactions don't fire the trigger
+  rate_limit_per_min integer NOT NULL DEFAULT 10,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS reaction_triggers_ws_id_emoji_index ON reaction_triggers(ws_id, emoji);
+
+CREATE TABLE IF NOT EXISTS reaction_trigger_runs(
+  id bigserial PRIMARY KEY,
+  trigger_id bigint NOT NULL REFERENCES reaction_triggers(id) ON DELETE CASCADE,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  -- fired, rate_limited or failed
+  status varchar(16) NOT NULL,
+  error text,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS reaction_trigger_runs_trigger_id_index ON reaction_trigger_runs(trigger_id, created_at);
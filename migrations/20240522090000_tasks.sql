@@ -0,0 +1,23 @@
+-- Add migration script here
+CREATE TYPE task_status AS ENUM(
+  'open',
+  'done'
+);
+
+-- a message converted into an action item
+CREATE TABLE IF NOT EXISTS tasks(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  message_id bigint NOT NULL UNIQUE REFERENCES messages(id) ON DELETE CASCADE,
+  title varchar(256) NOT NULL,
+  assignee_id bigint REFERENCES users(id),
+  due_at timestamptz,
+  status task_status NOT NULL DEFAULT 'open',
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  completed_at timestamptz
+);
+
+CREATE INDEX IF NOT EXISTS tasks_chat_id_index ON tasks(chat_id, status);
+
+CREATE INDEX IF NOT EXISTS tasks_assignee_id_index ON tasks(assignee_id, status);
@@ -0,0 +1,19 @@
+-- Add migration script here
+-- reusable setup for the channels of a workspace, e.g. one per new team
+CREATE TABLE IF NOT EXISTS channel_templates(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  name varchar(64) NOT NULL,
+  -- name of created channels, `{name}` is replaced by the name given on creation
+  name_pattern varchar(64) NOT NULL,
+  topic text,
+  public boolean NOT NULL DEFAULT TRUE,
+  members bigint[] NOT NULL DEFAULT '{}',
+  -- chats whose members are added as well
+  groups bigint[] NOT NULL DEFAULT '{}',
+  -- posted by the creator and pinned in created channels
+  welcome_message text,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (ws_id, name)
+);
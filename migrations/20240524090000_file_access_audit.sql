@@ -0,0 +1,5 @@
+-- Add migration script here
+-- access history of a file, see chat_server::FileAccess
+CREATE INDEX IF NOT EXISTS audit_logs_file_index ON audit_logs(ws_id, (details ->> 'file'))
+WHERE
+  action = 'file.download';
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- outgoing webhooks of a workspace, `filter` is an expression of chat_server::Filter
+CREATE TABLE IF NOT EXISTS webhooks(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  url varchar(2048) NOT NULL,
+  filter text NOT NULL DEFAULT '',
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_ws_id_index ON webhooks(ws_id);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- personal access tokens users mint for their scripts, only the sha256 of a token is kept
+CREATE TABLE IF NOT EXISTS personal_tokens(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name varchar(64) NOT NULL,
+  token_hash char(64) NOT NULL UNIQUE,
+  -- first characters of the token, so users can tell their tokens apart
+  prefix varchar(12) NOT NULL,
+  scopes varchar(32)[] NOT NULL,
+  expires_at timestamptz NOT NULL,
+  last_used_at timestamptz,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS personal_tokens_user_id_index ON personal_tokens(user_id);
@@ -0,0 +1,26 @@
+-- Add migration script here
+CREATE TYPE workspace_archive_status AS ENUM(
+  'archiving',
+  'archived',
+  'restoring'
+);
+
+-- NULL while the workspace is active
+ALTER TABLE workspaces
+  ADD COLUMN archive_status workspace_archive_status,
+  ADD COLUMN archived_at timestamptz;
+
+-- messages restored from cold storage are not new, don't notify them
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' AND current_setting('chat.restoring', TRUE) IS DISTINCT FROM 'on' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', NEW, 'members', chat_member_ids(NEW.chat_id))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
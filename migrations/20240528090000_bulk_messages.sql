@@ -0,0 +1,34 @@
+-- Add migration script here
+-- one message posted by an admin to many chats, sent by the bulk_message job
+CREATE TABLE IF NOT EXISTS bulk_messages(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  sender_id bigint NOT NULL REFERENCES users(id),
+  content text NOT NULL,
+  files text[] NOT NULL DEFAULT '{}',
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- set once every target is sent or failed
+  finished_at timestamptz
+);
+
+CREATE TYPE bulk_target_status AS ENUM(
+  'pending',
+  'sent',
+  'failed'
+);
+
+CREATE TABLE IF NOT EXISTS bulk_message_targets(
+  id bigserial PRIMARY KEY,
+  bulk_id bigint NOT NULL REFERENCES bulk_messages(id) ON DELETE CASCADE,
+  -- either a chat, or a user receiving a direct message
+  chat_id bigint,
+  user_id bigint,
+  status bulk_target_status NOT NULL DEFAULT 'pending',
+  -- the sent message, no foreign key so deleting it keeps the result
+  message_id bigint,
+  error text,
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  CHECK ((chat_id IS NULL) <> (user_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS bulk_message_targets_bulk_id_index ON bulk_message_targets(bulk_id, status);
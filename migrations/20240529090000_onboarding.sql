@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TYPE onboarding_step AS ENUM(
+  'created_first_channel',
+  'invited_teammates',
+  'sent_first_message'
+);
+
+-- onboarding steps completed by a user of a workspace, recorded by the handlers
+CREATE TABLE IF NOT EXISTS onboarding_steps(
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  user_id bigint NOT NULL REFERENCES users(id),
+  step onboarding_step NOT NULL,
+  completed_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id, step)
+);
@@ -0,0 +1,18 @@
+-- Add migration script here
+-- quotas of hosted workspaces, NULL limits are unlimited
+CREATE TABLE IF NOT EXISTS plans(
+  id bigserial PRIMARY KEY,
+  name varchar(64) NOT NULL UNIQUE,
+  max_messages bigint,
+  max_storage_bytes bigint,
+  max_members bigint,
+  -- how far above a limit creates and uploads are still accepted, in percent
+  grace_percent int NOT NULL DEFAULT 10,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- workspaces without a plan are unlimited
+ALTER TABLE workspaces
+  ADD COLUMN plan_id bigint REFERENCES plans(id),
+  -- bytes of the files uploaded to the workspace
+  ADD COLUMN storage_bytes bigint NOT NULL DEFAULT 0;
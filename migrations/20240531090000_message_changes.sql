@@ -0,0 +1,79 @@
+-- Add migration script here
+CREATE TYPE message_change_op AS ENUM(
+  'insert',
+  'update',
+  'delete'
+);
+
+-- append only log of every version of a message, kept after the message is deleted
+CREATE TABLE IF NOT EXISTS message_changes(
+  id bigserial PRIMARY KEY,
+  message_id bigint NOT NULL,
+  chat_id bigint NOT NULL,
+  sender_id bigint NOT NULL,
+  op message_change_op NOT NULL,
+  -- the message after the change, or before it for a delete
+  content text NOT NULL,
+  files text[] NOT NULL DEFAULT '{}',
+  message_created_at timestamptz NOT NULL,
+  changed_at timestamptz NOT NULL DEFAULT clock_timestamp()
+);
+
+CREATE INDEX IF NOT EXISTS message_changes_chat_id_changed_at_index ON message_changes(chat_id, changed_at);
+
+-- the current version of existing messages
+INSERT INTO message_changes(message_id, chat_id, sender_id, op, content, files, message_created_at, changed_at)
+SELECT
+  id,
+  chat_id,
+  sender_id,
+  'insert',
+  content,
+  COALESCE(files, '{}'),
+  COALESCE(created_at, CURRENT_TIMESTAMP),
+  COALESCE(created_at, CURRENT_TIMESTAMP)
+FROM
+  messages
+ORDER BY
+  id;
+
+-- messages moved to or from cold storage by workspace archiving are not changed
+CREATE OR REPLACE FUNCTION log_message_change()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  msg messages;
+BEGIN
+  IF current_setting('chat.restoring', TRUE) = 'on' OR current_setting('chat.archiving', TRUE) = 'on' THEN
+    RETURN NULL;
+  END IF;
+  IF TG_OP = 'DELETE' THEN
+    msg := OLD;
+  ELSE
+    msg := NEW;
+  END IF;
+  INSERT INTO message_changes(message_id, chat_id, sender_id, op, content, files, message_created_at)
+    VALUES (msg.id, msg.chat_id, msg.sender_id, lower(TG_OP)::message_change_op, msg.content, COALESCE(msg.files, '{}'), COALESCE(msg.created_at, CURRENT_TIMESTAMP));
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_changes_trigger
+  AFTER INSERT OR UPDATE OR DELETE ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION log_message_change();
+
+CREATE OR REPLACE FUNCTION reject_message_change_update()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  RAISE EXCEPTION 'message_changes is append only';
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_changes_append_only_trigger
+  BEFORE UPDATE OR DELETE ON message_changes
+  FOR EACH ROW
+  EXECUTE FUNCTION reject_message_change_update();
@@ -0,0 +1,54 @@
+-- Add migration script here
+-- the member with the owner role, kept in sync by chat_owner_trigger
+ALTER TABLE chats
+  ADD COLUMN owner_id bigint REFERENCES users(id);
+
+UPDATE
+  chats c
+SET
+  owner_id = m.user_id
+FROM
+  chat_members m
+WHERE
+  m.chat_id = c.id
+  AND m.role = 'owner';
+
+CREATE OR REPLACE FUNCTION chat_owner_changed()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'DELETE' THEN
+    UPDATE
+      chats
+    SET
+      owner_id = NULL
+    WHERE
+      id = OLD.chat_id
+      AND owner_id = OLD.user_id;
+  ELSIF NEW.role = 'owner' THEN
+    UPDATE
+      chats
+    SET
+      owner_id = NEW.user_id
+    WHERE
+      id = NEW.chat_id;
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_owner_trigger
+  AFTER INSERT OR UPDATE OF role OR DELETE ON chat_members
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_owner_changed();
+
+-- chat row as json in the same shape as chat_core::Chat
+CREATE OR REPLACE FUNCTION chat_json(c chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    jsonb_build_object('id', c.id, 'ws_id', c.ws_id, 'owner_id', c.owner_id, 'name', c.name, 'type', c.type, 'members', members, 'created_at', c.created_at);
+$$
+LANGUAGE sql
+IMMUTABLE;
@@ -0,0 +1,84 @@
+-- Add migration script here
+-- admins of a workspace, the owner is not one of them
+CREATE TABLE IF NOT EXISTS workspace_admins(
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, user_id)
+);
+
+-- an ownership transfer waiting for the new owner to accept it, one per workspace
+CREATE TABLE IF NOT EXISTS workspace_transfers(
+  ws_id bigint PRIMARY KEY REFERENCES workspaces(id) ON DELETE CASCADE,
+  from_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  to_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- when an owner is deleted the oldest admin takes over, then the oldest member, and the
+-- workspace goes back to no owner (0) if nobody is left
+CREATE OR REPLACE FUNCTION succeed_workspace_owner()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  successor bigint;
+BEGIN
+  IF NOT EXISTS (
+    SELECT
+      1
+    FROM
+      workspaces
+    WHERE
+      id = OLD.ws_id
+      AND owner_id = OLD.id) THEN
+    RETURN OLD;
+  END IF;
+  SELECT
+    a.user_id INTO successor
+  FROM
+    workspace_admins a
+  WHERE
+    a.ws_id = OLD.ws_id
+    AND a.user_id <> OLD.id
+  ORDER BY
+    a.created_at,
+    a.user_id
+  LIMIT 1;
+  IF successor IS NULL THEN
+    SELECT
+      u.id INTO successor
+    FROM
+      users u
+    WHERE
+      u.ws_id = OLD.ws_id
+      AND u.id <> OLD.id
+      AND NOT EXISTS (
+        SELECT
+          1
+        FROM
+          guests g
+        WHERE
+          g.user_id = u.id)
+    ORDER BY
+      u.created_at,
+      u.id
+    LIMIT 1;
+  END IF;
+  UPDATE
+    workspaces
+  SET
+    owner_id = COALESCE(successor, 0)
+  WHERE
+    id = OLD.ws_id;
+  DELETE FROM workspace_admins
+  WHERE ws_id = OLD.ws_id
+    AND user_id = successor;
+  RETURN OLD;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER workspace_owner_succession_trigger
+  BEFORE DELETE ON users
+  FOR EACH ROW
+  EXECUTE FUNCTION succeed_workspace_owner();
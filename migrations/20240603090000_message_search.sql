@@ -0,0 +1,64 @@
+-- Add migration script here
+-- full text search index of messages, NULL until indexed and for encrypted chats
+ALTER TABLE messages
+  ADD COLUMN search_vector tsvector;
+
+CREATE INDEX IF NOT EXISTS messages_search_vector_index ON messages USING GIN (search_vector);
+
+CREATE OR REPLACE FUNCTION message_search_vector(chat_id bigint, content text)
+  RETURNS tsvector
+  AS $$
+  SELECT
+    CASE WHEN COALESCE((
+        SELECT
+          (settings ->> 'encrypted')::boolean
+        FROM chats
+        WHERE
+          id = chat_id), FALSE) THEN
+      NULL
+    ELSE
+      to_tsvector('simple', content)
+    END;
+$$
+LANGUAGE sql
+STABLE;
+
+-- new and edited messages are indexed right away, existing ones by a reindex
+CREATE OR REPLACE FUNCTION index_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  NEW.search_vector := message_search_vector(NEW.chat_id, NEW.content);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_search_trigger
+  BEFORE INSERT OR UPDATE OF content ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION index_message();
+
+-- writing the index is not a change of the message
+DROP TRIGGER IF EXISTS message_changes_trigger ON messages;
+
+CREATE TRIGGER message_changes_trigger
+  AFTER INSERT OR DELETE OR UPDATE OF content, files ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION log_message_change();
+
+CREATE TYPE search_reindex_status AS ENUM(
+  'running',
+  'finished'
+);
+
+-- progress of rebuilding the search index, messages are indexed in id order
+CREATE TABLE IF NOT EXISTS search_reindexes(
+  id bigserial PRIMARY KEY,
+  status search_reindex_status NOT NULL DEFAULT 'running',
+  total bigint NOT NULL,
+  indexed bigint NOT NULL DEFAULT 0,
+  last_id bigint NOT NULL DEFAULT 0,
+  started_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  finished_at timestamptz
+);
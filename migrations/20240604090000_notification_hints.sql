@@ -0,0 +1,40 @@
+-- Add migration script here
+CREATE TYPE notification_sound AS ENUM(
+  'default',
+  'chime',
+  'ping',
+  'none'
+);
+
+-- sound clients should play for notifications of the user
+ALTER TABLE users
+  ADD COLUMN notification_sound notification_sound NOT NULL DEFAULT 'default';
+
+-- notify_server computes per member notification hints from the chat type and the sounds of
+-- members who changed theirs, the search index stays out of the payload
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  members bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' AND current_setting('chat.restoring', TRUE) IS DISTINCT FROM 'on' THEN
+    RAISE NOTICE 'add_to_message: %', NEW.id;
+    members := chat_member_ids(NEW.chat_id);
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', to_jsonb(NEW) - 'search_vector', 'members', members, 'chat_type',(
+            SELECT
+              type FROM chats
+            WHERE
+              id = NEW.chat_id), 'sounds',(
+            SELECT
+              COALESCE(json_object_agg(id, notification_sound), '{}'::json)
+            FROM users
+            WHERE
+              id = ANY (members)
+              AND notification_sound <> 'default'))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- archived chats are read only and hidden from the chat list by default
+ALTER TABLE chats
+  ADD COLUMN archived_at timestamptz;
+
+-- chat row as json in the same shape as chat_core::Chat
+CREATE OR REPLACE FUNCTION chat_json(c chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    jsonb_build_object('id', c.id, 'ws_id', c.ws_id, 'owner_id', c.owner_id, 'name', c.name, 'type', c.type, 'members', members, 'created_at', c.created_at, 'archived_at', c.archived_at);
+$$
+LANGUAGE sql
+IMMUTABLE;
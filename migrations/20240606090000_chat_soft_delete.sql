@@ -0,0 +1,45 @@
+-- Add migration script here
+-- deleted chats are hidden and can be restored until they are purged
+ALTER TABLE chats
+  ADD COLUMN deleted_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS chats_deleted_at_index ON chats(deleted_at)
+WHERE
+  deleted_at IS NOT NULL;
+
+-- deleting and restoring a chat look like a delete and a new chat to clients
+CREATE OR REPLACE FUNCTION chat_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  members bigint[];
+BEGIN
+  IF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'DELETE', 'old', chat_json(OLD, chat_member_ids(OLD.id)), 'new', NULL)::text);
+  ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'INSERT', 'old', NULL, 'new', chat_json(NEW, chat_member_ids(NEW.id)))::text);
+  ELSIF NEW.deleted_at IS NULL AND OLD.name IS DISTINCT FROM NEW.name THEN
+    members := chat_member_ids(NEW.id);
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'UPDATE', 'old', chat_json(OLD, members), 'new', chat_json(NEW, members))::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- purged chats were already notified when they were deleted
+CREATE OR REPLACE FUNCTION chat_deleted()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF OLD.deleted_at IS NULL THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'DELETE', 'old', chat_json(OLD, chat_member_ids(OLD.id)), 'new', NULL)::text);
+  END IF;
+  RETURN OLD;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,74 @@
+-- Add migration script here
+-- reactions per chat, emoji and day, kept up to date by reaction_counts_trigger for analytics
+CREATE TABLE IF NOT EXISTS reaction_daily_counts(
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  emoji varchar(64) NOT NULL,
+  day date NOT NULL,
+  count bigint NOT NULL DEFAULT 0,
+  PRIMARY KEY (chat_id, emoji, day)
+);
+
+CREATE INDEX IF NOT EXISTS reaction_daily_counts_ws_id_day_index ON reaction_daily_counts(ws_id, day);
+
+INSERT INTO reaction_daily_counts(ws_id, chat_id, emoji, day, count)
+SELECT
+  c.ws_id,
+  c.id,
+  r.emoji,
+  (r.created_at AT TIME ZONE 'UTC')::date,
+  COUNT(*)
+FROM
+  message_reactions r
+  JOIN messages m ON m.id = r.message_id
+  JOIN chats c ON c.id = m.chat_id
+GROUP BY
+  c.ws_id,
+  c.id,
+  r.emoji,
+  (r.created_at AT TIME ZONE 'UTC')::date;
+
+-- a removed reaction is taken off the day it was added. Reactions removed together with
+-- their message can't be traced to a chat anymore and keep counting, as do reactions of
+-- workspaces moved to or from cold storage
+CREATE OR REPLACE FUNCTION reaction_counts_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  r message_reactions;
+  delta bigint;
+BEGIN
+  IF current_setting('chat.restoring', TRUE) = 'on' OR current_setting('chat.archiving', TRUE) = 'on' THEN
+    RETURN NULL;
+  END IF;
+  IF TG_OP = 'DELETE' THEN
+    r := OLD;
+    delta := -1;
+  ELSE
+    r := NEW;
+    delta := 1;
+  END IF;
+  INSERT INTO reaction_daily_counts(ws_id, chat_id, emoji, day, count)
+  SELECT
+    c.ws_id,
+    c.id,
+    r.emoji,
+    (r.created_at AT TIME ZONE 'UTC')::date,
+    delta
+  FROM
+    messages m
+    JOIN chats c ON c.id = m.chat_id
+  WHERE
+    m.id = r.message_id
+  ON CONFLICT (chat_id, emoji, day)
+    DO UPDATE SET
+      count = GREATEST(reaction_daily_counts.count + EXCLUDED.count, 0);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER reaction_counts_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION reaction_counts_changed();
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- chats a user pinned to the top of their chat list
+CREATE TABLE IF NOT EXISTS chat_pins(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, chat_id)
+);
@@ -0,0 +1,49 @@
+-- Add migration script here
+-- how a user wants to be notified of new messages in a chat, members without a row get
+-- every message
+CREATE TABLE IF NOT EXISTS chat_notification_settings(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  mute boolean NOT NULL DEFAULT FALSE,
+  -- the mute ends at this time, a mute without it lasts until it is turned off
+  mute_until timestamptz,
+  mentions_only boolean NOT NULL DEFAULT FALSE,
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, chat_id)
+);
+
+-- notify_server skips members who muted the chat, so their settings go with the message
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  members bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' AND current_setting('chat.restoring', TRUE) IS DISTINCT FROM 'on' THEN
+    RAISE NOTICE 'add_to_message: %', NEW.id;
+    members := chat_member_ids(NEW.chat_id);
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message', to_jsonb(NEW) - 'search_vector', 'members', members, 'chat_type',(
+            SELECT
+              type FROM chats
+            WHERE
+              id = NEW.chat_id), 'sounds',(
+            SELECT
+              COALESCE(json_object_agg(id, notification_sound), '{}'::json)
+            FROM users
+            WHERE
+              id = ANY (members)
+              AND notification_sound <> 'default'), 'settings',(
+            SELECT
+              COALESCE(json_object_agg(user_id, json_build_object('mute', mute, 'mute_until', mute_until, 'mentions_only', mentions_only)), '{}'::json)
+            FROM chat_notification_settings
+            WHERE
+              chat_id = NEW.chat_id
+              AND user_id = ANY (members)
+              AND (mute
+                OR mentions_only)))::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- the two members of a single chat in ascending order, so a pair has at most one single
+-- chat. Existing duplicates keep the oldest chat as the pair's chat
+ALTER TABLE chats
+  ADD COLUMN dm_pair bigint[] CHECK (dm_pair IS NULL OR (cardinality(dm_pair) = 2 AND dm_pair[1] < dm_pair[2]));
+
+UPDATE
+  chats
+SET
+  dm_pair = chat_member_ids(id)
+WHERE
+  id IN (
+    SELECT DISTINCT ON (ws_id, chat_member_ids(id))
+      id
+    FROM
+      chats
+    WHERE
+      type = 'single'
+      AND deleted_at IS NULL
+      AND cardinality(chat_member_ids(id)) = 2
+    ORDER BY
+      ws_id,
+      chat_member_ids(id),
+      id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_dm_pair_index ON chats(ws_id, dm_pair)
+WHERE
+  dm_pair IS NOT NULL AND deleted_at IS NULL;
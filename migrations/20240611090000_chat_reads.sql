@@ -0,0 +1,9 @@
+-- Add migration script here
+-- the last message a member has read in a chat, later messages of others are unread
+CREATE TABLE IF NOT EXISTS chat_reads(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  last_read_id bigint NOT NULL,
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, chat_id)
+);
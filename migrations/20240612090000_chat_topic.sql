@@ -0,0 +1,36 @@
+-- Add migration script here
+-- what the chat is about, shown under its name
+ALTER TABLE chats
+  ADD COLUMN topic varchar(250);
+
+CREATE OR REPLACE FUNCTION chat_json(c chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    jsonb_build_object('id', c.id, 'ws_id', c.ws_id, 'owner_id', c.owner_id, 'name', c.name, 'topic', c.topic, 'type', c.type, 'members', members, 'created_at', c.created_at, 'archived_at', c.archived_at);
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+-- topic changes are notified like renames
+CREATE OR REPLACE FUNCTION chat_updated()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  members bigint[];
+BEGIN
+  IF OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'DELETE', 'old', chat_json(OLD, chat_member_ids(OLD.id)), 'new', NULL)::text);
+  ELSIF OLD.deleted_at IS NOT NULL AND NEW.deleted_at IS NULL THEN
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'INSERT', 'old', NULL, 'new', chat_json(NEW, chat_member_ids(NEW.id)))::text);
+  ELSIF NEW.deleted_at IS NULL AND (OLD.name IS DISTINCT FROM NEW.name OR OLD.topic IS DISTINCT FROM NEW.topic) THEN
+    members := chat_member_ids(NEW.id);
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', 'UPDATE', 'old', chat_json(OLD, members), 'new', chat_json(NEW, members))::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
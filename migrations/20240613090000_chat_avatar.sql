@@ -0,0 +1,13 @@
+-- Add migration script here
+-- url of the chat's avatar in the workspace files
+ALTER TABLE chats
+  ADD COLUMN avatar_url varchar(256);
+
+CREATE OR REPLACE FUNCTION chat_json(c chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    jsonb_build_object('id', c.id, 'ws_id', c.ws_id, 'owner_id', c.owner_id, 'name', c.name, 'topic', c.topic, 'avatar_url', c.avatar_url, 'type', c.type, 'members', members, 'created_at', c.created_at, 'archived_at', c.archived_at);
+$$
+LANGUAGE sql
+IMMUTABLE;
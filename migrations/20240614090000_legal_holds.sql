@@ -0,0 +1,13 @@
+-- Add migration script here
+-- legal holds of a workspace, a hold without user_id covers the whole workspace. Held
+-- chats are neither deleted by their members nor purged after the retention period.
+CREATE TABLE IF NOT EXISTS legal_holds(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id),
+  user_id bigint REFERENCES users(id),
+  reason text NOT NULL DEFAULT '',
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS legal_holds_subject_index ON legal_holds(ws_id, COALESCE(user_id, 0));
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- machine translations of messages in chats with auto translation
+CREATE TABLE IF NOT EXISTS message_translations(
+  message_id bigint PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+  source_lang varchar(16) NOT NULL,
+  lang varchar(16) NOT NULL,
+  content text NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
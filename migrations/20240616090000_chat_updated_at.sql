@@ -0,0 +1,55 @@
+-- Add migration script here
+-- last change of a chat or its latest message, chat lists are paginated by it
+ALTER TABLE chats
+  ADD COLUMN updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+UPDATE
+  chats c
+SET
+  updated_at = COALESCE((
+    SELECT
+      max(m.created_at)
+    FROM messages m
+    WHERE
+      m.chat_id = c.id), c.created_at);
+
+CREATE INDEX IF NOT EXISTS chats_ws_id_updated_at_index ON chats(ws_id, updated_at DESC, id DESC);
+
+CREATE OR REPLACE FUNCTION chat_touched()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  NEW.updated_at := NOW();
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_touched_trigger ON chats;
+
+CREATE TRIGGER chat_touched_trigger
+  BEFORE UPDATE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_touched();
+
+CREATE OR REPLACE FUNCTION chat_message_added()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    chats
+  SET
+    updated_at = NEW.created_at
+  WHERE
+    id = NEW.chat_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_message_added_trigger ON messages;
+
+CREATE TRIGGER chat_message_added_trigger
+  AFTER INSERT ON messages
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_message_added();
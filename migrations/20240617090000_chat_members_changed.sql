@@ -0,0 +1,8 @@
+-- Add migration script here
+-- member changes are notified by chat_server on chat_members_changed, with who was added
+-- or removed and by whom, instead of as an update of the chat
+DROP TRIGGER IF EXISTS chat_members_added_trigger ON chat_members;
+
+DROP TRIGGER IF EXISTS chat_members_removed_trigger ON chat_members;
+
+DROP FUNCTION IF EXISTS chat_members_changed();
@@ -0,0 +1,32 @@
+-- Add migration script here
+-- time of the latest message of a chat, chat lists can be sorted by it
+ALTER TABLE chats
+  ADD COLUMN last_message_at timestamptz;
+
+UPDATE
+  chats c
+SET
+  last_message_at =(
+    SELECT
+      max(m.created_at)
+    FROM messages m
+    WHERE
+      m.chat_id = c.id);
+
+CREATE INDEX IF NOT EXISTS chats_ws_id_last_message_at_index ON chats(ws_id, COALESCE(last_message_at, created_at) DESC, id DESC);
+
+CREATE OR REPLACE FUNCTION chat_message_added()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    chats
+  SET
+    updated_at = NEW.created_at,
+    last_message_at = NEW.created_at
+  WHERE
+    id = NEW.chat_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- messages of others after the last one the user has read, see chat_reads, 0 for
+-- chats the user isn't a member of
+CREATE OR REPLACE FUNCTION chat_unread_count(cid bigint, uid bigint)
+  RETURNS bigint
+  AS $$
+  SELECT
+    COUNT(*)
+  FROM
+    messages m
+  WHERE
+    m.chat_id = cid
+    AND m.sender_id <> uid
+    AND EXISTS (
+      SELECT
+        1
+      FROM chat_members
+      WHERE
+        chat_id = cid AND user_id = uid)
+    AND m.id > COALESCE((
+        SELECT
+          last_read_id
+        FROM chat_reads
+        WHERE
+          chat_id = cid AND user_id = uid), 0);
+$$
+LANGUAGE sql
+STABLE;
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- per workspace restrictions on sign in and sessions, no row means no restrictions
+CREATE TABLE IF NOT EXISTS workspace_security_policies(
+  ws_id bigint PRIMARY KEY REFERENCES workspaces(id) ON DELETE CASCADE,
+  require_sso boolean NOT NULL DEFAULT FALSE,
+  max_session_secs integer,
+  idle_timeout_secs integer,
+  ip_allowlist text[] NOT NULL DEFAULT '{}',
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TYPE session_method AS ENUM(
+  'password',
+  'guest_link',
+  'sso'
+);
+
+-- session tokens issued by sign in, the token itself is never stored
+CREATE TABLE IF NOT EXISTS sessions(
+  token_hash char(64) PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  method session_method NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  last_seen_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  expires_at timestamptz NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_index ON sessions(user_id);
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- client bootstraps per day and set of declared capabilities
+CREATE TABLE IF NOT EXISTS client_capability_stats(
+  day date NOT NULL,
+  -- false for clients which don't declare capabilities
+  declared boolean NOT NULL,
+  capabilities text[] NOT NULL,
+  clients bigint NOT NULL DEFAULT 0,
+  PRIMARY KEY (day, declared, capabilities)
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- links members of a workspace can use to join a chat
+CREATE TABLE IF NOT EXISTS chat_invites(
+  id bigserial PRIMARY KEY,
+  token varchar(32) NOT NULL UNIQUE DEFAULT replace(gen_random_uuid()::text, '-', ''),
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  created_by bigint NOT NULL REFERENCES users(id),
+  -- NULL for no limit
+  max_uses integer,
+  uses integer NOT NULL DEFAULT 0,
+  expires_at timestamptz NOT NULL,
+  revoked_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS chat_invites_chat_id_index ON chat_invites(chat_id);
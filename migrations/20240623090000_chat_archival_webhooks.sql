@@ -0,0 +1,11 @@
+-- Add migration script here
+-- a webhook per chat receiving every message once its edits settle, signed with the secret
+CREATE TABLE IF NOT EXISTS chat_archival_webhooks(
+  chat_id bigint PRIMARY KEY REFERENCES chats(id) ON DELETE CASCADE,
+  url varchar(2048) NOT NULL,
+  secret varchar(64) NOT NULL DEFAULT replace(gen_random_uuid()::text, '-', '') || replace(gen_random_uuid()::text, '-', ''),
+  -- how long after it is sent a message is delivered
+  delay_secs integer NOT NULL DEFAULT 300,
+  created_by bigint NOT NULL REFERENCES users(id),
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- workspaces reset to a snapshot of their users, chats and messages every night, for
+-- product demos and trials
+CREATE TABLE IF NOT EXISTS demo_workspaces(
+  ws_id bigint PRIMARY KEY REFERENCES workspaces(id) ON DELETE CASCADE,
+  snapshot jsonb NOT NULL,
+  created_by bigint NOT NULL REFERENCES users(id),
+  reset_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
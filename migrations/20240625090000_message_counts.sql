@@ -0,0 +1,120 @@
+-- Add migration script here
+-- messages of a chat and of each of its members, kept up to date by triggers so chat
+-- stats don't have to count them. Existing messages are counted by `chat_server backfill-counts`
+ALTER TABLE chats
+  ADD COLUMN message_count bigint NOT NULL DEFAULT 0;
+
+ALTER TABLE chat_members
+  ADD COLUMN sent_count bigint NOT NULL DEFAULT 0;
+
+-- recounting messages is not a change of the chat
+CREATE OR REPLACE FUNCTION chat_touched()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF to_jsonb(NEW) - 'message_count' - 'updated_at' = to_jsonb(OLD) - 'message_count' - 'updated_at' THEN
+    RETURN NEW;
+  END IF;
+  NEW.updated_at := NOW();
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- the chat is updated before its member, the backfill locks the chat to count both
+CREATE OR REPLACE FUNCTION chat_message_added()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    chats
+  SET
+    updated_at = NEW.created_at,
+    last_message_at = NEW.created_at,
+    message_count = message_count + 1
+  WHERE
+    id = NEW.chat_id;
+  UPDATE
+    chat_members
+  SET
+    sent_count = sent_count + 1
+  WHERE
+    chat_id = NEW.chat_id
+    AND user_id = NEW.sender_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION chat_messages_removed()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    chats c
+  SET
+    message_count = GREATEST(c.message_count - r.count, 0)
+  FROM (
+    SELECT
+      chat_id,
+      COUNT(*) AS count
+    FROM
+      removed
+    GROUP BY
+      chat_id) r
+  WHERE
+    c.id = r.chat_id;
+  UPDATE
+    chat_members m
+  SET
+    sent_count = GREATEST(m.sent_count - r.count, 0)
+  FROM (
+    SELECT
+      chat_id,
+      sender_id,
+      COUNT(*) AS count
+    FROM
+      removed
+    GROUP BY
+      chat_id,
+      sender_id) r
+  WHERE
+    m.chat_id = r.chat_id
+    AND m.user_id = r.sender_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_messages_removed_trigger ON messages;
+
+CREATE TRIGGER chat_messages_removed_trigger
+  AFTER DELETE ON messages
+  REFERENCING OLD TABLE AS removed
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_messages_removed();
+
+-- members rejoining a chat get back the messages they sent before
+CREATE OR REPLACE FUNCTION chat_member_sent_count()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  NEW.sent_count :=(
+    SELECT
+      COUNT(*)
+    FROM
+      messages
+    WHERE
+      chat_id = NEW.chat_id
+      AND sender_id = NEW.user_id);
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS chat_member_sent_count_trigger ON chat_members;
+
+CREATE TRIGGER chat_member_sent_count_trigger
+  BEFORE INSERT ON chat_members
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_member_sent_count();
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- chats are searched by the words of their names
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS chats_name_trgm_index ON chats USING GIN (lower(name) gin_trgm_ops);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- chats scheduled for deletion stay usable until delete_at, then they are purged
+ALTER TABLE chats
+  ADD COLUMN delete_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS chats_delete_at_index ON chats(delete_at)
+WHERE
+  delete_at IS NOT NULL;
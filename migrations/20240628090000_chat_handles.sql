@@ -0,0 +1,61 @@
+-- Add migration script here
+-- channels are referenced by a handle derived from their name, `#general` → general
+ALTER TABLE chats
+  ADD COLUMN handle text;
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_ws_id_handle_index ON chats(ws_id, handle)
+WHERE
+  handle IS NOT NULL;
+
+-- the handles a chat had before it was renamed, so old links keep working until the
+-- handle is taken by another chat
+CREATE TABLE IF NOT EXISTS chat_handle_redirects(
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  handle text NOT NULL,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (ws_id, handle)
+);
+
+-- handles follow names, and renames already touch the chat
+CREATE OR REPLACE FUNCTION chat_touched()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF to_jsonb(NEW) - 'message_count' - 'handle' - 'updated_at' = to_jsonb(OLD) - 'message_count' - 'handle' - 'updated_at' THEN
+    RETURN NEW;
+  END IF;
+  NEW.updated_at := NOW();
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
+
+-- existing channels get the handle chat_server would give them, the same name in a
+-- workspace gets -2, -3, ... in the order the channels were created
+WITH bases AS (
+  SELECT
+    id,
+    ws_id,
+    COALESCE(NULLIF(trim(BOTH '-' FROM left(trim(BOTH '-' FROM regexp_replace(lower(name), '[^[:alnum:]]+', '-', 'g')), 80)), ''), 'channel') AS base
+  FROM
+    chats
+  WHERE
+    type IN ('public_channel', 'private_channel')
+    AND name IS NOT NULL
+),
+numbered AS (
+  SELECT
+    id,
+    base,
+    row_number() OVER (PARTITION BY ws_id, base ORDER BY id) AS n
+  FROM
+    bases)
+UPDATE
+  chats c
+SET
+  handle = CASE WHEN n = 1 THEN base ELSE base || '-' || n END
+FROM
+  numbered
+WHERE
+  c.id = numbered.id;
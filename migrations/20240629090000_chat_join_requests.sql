@@ -0,0 +1,24 @@
+-- Add migration script here
+-- create join request status: pending, approved, denied
+CREATE TYPE join_request_status AS ENUM(
+  'pending',
+  'approved',
+  'denied'
+);
+
+-- users asking the owner and admins of a private channel to be added to it
+CREATE TABLE IF NOT EXISTS chat_join_requests(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  message text,
+  status join_request_status NOT NULL DEFAULT 'pending',
+  decided_by bigint REFERENCES users(id),
+  decided_at timestamptz,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- a user has at most one pending request per chat
+CREATE UNIQUE INDEX IF NOT EXISTS chat_join_requests_pending_index ON chat_join_requests(chat_id, user_id)
+WHERE
+  status = 'pending';
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- signups which passed the captcha, counted to throttle signups per client address and
+-- email domain. Attempts older than the throttle window are removed on the next signup
+CREATE TABLE IF NOT EXISTS signup_attempts(
+  id bigserial PRIMARY KEY,
+  ip varchar(64),
+  email_domain varchar(255) NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS signup_attempts_created_at_index ON signup_attempts(created_at);
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- users removed from a chat who can't be added back, or join it again, until unbanned
+CREATE TABLE IF NOT EXISTS chat_bans(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  banned_by bigint NOT NULL REFERENCES users(id),
+  reason text,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, user_id)
+);
@@ -0,0 +1,20 @@
+-- Add migration script here
+-- latest message of a chat with its sender, its content cut to len characters, json
+-- null for chats without messages
+CREATE OR REPLACE FUNCTION chat_last_message(cid bigint, len int)
+  RETURNS jsonb
+  AS $$
+  SELECT
+    COALESCE((
+      SELECT
+        jsonb_build_object('id', m.id, 'sender_id', m.sender_id, 'sender_name', u.fullname,
+          'content', LEFT(m.content, len), 'created_at', m.created_at)
+      FROM messages m
+      JOIN users u ON u.id = m.sender_id
+      WHERE
+        m.chat_id = cid
+      ORDER BY m.created_at DESC, m.id DESC
+      LIMIT 1), 'null'::jsonb);
+$$
+LANGUAGE sql
+STABLE;
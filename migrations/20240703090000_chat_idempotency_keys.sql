@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Idempotency-Key of chat creation requests with a hash of their body, chat_id is null
+-- while the chat is being created. Keys can be reused after a day.
+CREATE TABLE IF NOT EXISTS chat_idempotency_keys(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  key varchar(255) NOT NULL,
+  fingerprint char(64) NOT NULL,
+  chat_id bigint REFERENCES chats(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, key)
+);
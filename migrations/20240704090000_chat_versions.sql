@@ -0,0 +1,4 @@
+-- Add migration script here
+-- bumped by each update of a chat, updates can require the version they were made on
+ALTER TABLE chats
+  ADD COLUMN version bigint NOT NULL DEFAULT 1;
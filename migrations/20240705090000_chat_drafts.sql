@@ -0,0 +1,12 @@
+-- Add migration script here
+-- the unsent message of a member in a chat, shared by their devices. `seq` counts the
+-- saves of `device_id`, the device which saved last
+CREATE TABLE IF NOT EXISTS chat_drafts(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  content text NOT NULL,
+  device_id varchar(64) NOT NULL,
+  seq bigint NOT NULL,
+  updated_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (user_id, chat_id)
+);
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- structured data integrations attach to a chat (bot state, CRM links, ticket ids etc.)
+ALTER TABLE chats
+  ADD COLUMN IF NOT EXISTS metadata jsonb NOT NULL DEFAULT '{}';
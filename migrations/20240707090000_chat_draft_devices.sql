@@ -0,0 +1,14 @@
+-- Add migration script here
+-- the last save of each device of a member in a chat, saves of a device arriving after a
+-- later one of it are ignored whichever device saved last
+CREATE TABLE IF NOT EXISTS chat_draft_devices(
+  user_id bigint NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  device_id varchar(64) NOT NULL,
+  seq bigint NOT NULL,
+  PRIMARY KEY (user_id, chat_id, device_id)
+);
+
+INSERT INTO chat_draft_devices (user_id, chat_id, device_id, seq)
+  SELECT user_id, chat_id, device_id, seq FROM chat_drafts
+  ON CONFLICT DO NOTHING;
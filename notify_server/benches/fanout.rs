@@ -0,0 +1,101 @@
+//! Time to build the SSE frames of one new message for N subscribers, serializing it once
+//! and sharing the bytes against serializing it again for each connection.
+//!
+//! `cargo bench -p notify-server --bench fanout`
+
+use std::{
+    hint::black_box,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chat_core::Message;
+use notify_server::{
+    AppEvent, MessageHints, NotificationHints, NotificationPriority, SharedEvent, UserEvent,
+};
+use serde::Serialize;
+
+const ROUNDS: u32 = 20;
+
+/// The payload as it was built before, the message with the member's hints in it.
+#[derive(Serialize)]
+#[serde(tag = "event")]
+enum PerConnection<'a> {
+    NewMessage {
+        #[serde(flatten)]
+        message: &'a Message,
+        hints: &'a NotificationHints,
+    },
+}
+
+fn main() {
+    let message = Message {
+        id: 1,
+        chat_id: 2,
+        sender_id: 1,
+        content: "deploy of v1.2 is done, see the release notes ".repeat(20),
+        files: vec![],
+        created_at: chrono::Utc::now(),
+        translation: None,
+    };
+    let hints: Vec<_> = (0..10_000).map(hints).collect();
+
+    println!("subscribers  shared      per connection  speedup");
+    for subscribers in [10, 100, 1_000, 10_000] {
+        let hints = &hints[..subscribers];
+        let shared = measure(|| shared(&message, hints));
+        let per_connection = measure(|| per_connection(&message, hints));
+        println!(
+            "{subscribers:>11}  {:>10?}  {:>14?}  {:>6.1}x",
+            shared,
+            per_connection,
+            per_connection.as_secs_f64() / shared.as_secs_f64()
+        );
+    }
+}
+
+fn hints(user_id: u64) -> NotificationHints {
+    NotificationHints {
+        is_mention: user_id % 100 == 0,
+        is_dm: false,
+        priority: NotificationPriority::Normal,
+        suggested_sound: Some("default".to_string()),
+        collapse_key: "chat:2".to_string(),
+    }
+}
+
+/// The message is serialized by the first frame, the others reuse its bytes.
+fn shared(message: &Message, hints: &[NotificationHints]) -> usize {
+    let event = Arc::new(SharedEvent::new(AppEvent::NewMessage(message.clone())));
+    hints
+        .iter()
+        .map(|hints| {
+            let event = UserEvent {
+                shared: event.clone(),
+                hints: Some(Arc::new(MessageHints::new(hints.clone()))),
+            };
+            black_box(event.frame()).len()
+        })
+        .sum()
+}
+
+fn per_connection(message: &Message, hints: &[NotificationHints]) -> usize {
+    hints
+        .iter()
+        .map(|hints| {
+            let event = PerConnection::NewMessage { message, hints };
+            let data = serde_json::to_string(&event).expect("Failed to serialize event");
+            black_box(format!("event: NewMessage\ndata: {data}\n\n")).len()
+        })
+        .sum()
+}
+
+/// Mean time of a round, after one round to warm up.
+fn measure(mut f: impl FnMut() -> usize) -> Duration {
+    black_box(f());
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        black_box(f());
+    }
+    start.elapsed() / ROUNDS
+}
@@ -6,17 +6,64 @@ use std::{env, fs::File};
 pub struct AppConfig {
     pub server: ServerConfig,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[cfg(feature = "test-util")]
+    #[serde(default)]
+    pub faults: FaultConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthConfig {
     pub pk: String,
+    /// user ids allowed to watch the health stream and metrics
+    #[serde(default)]
+    pub admins: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// how often the database latency is probed
+    pub probe_interval_secs: u64,
+    /// a probe slower than this is reported as a latency spike
+    pub db_latency_threshold_ms: u64,
+    /// `host:port` of chat_server, probed with a tcp connect for the status page
+    pub api_addr: String,
+    /// chat_server's `server.base_dir`, probed for the status page
+    pub storage_dir: String,
+    /// requests per minute a client can make to `/status`
+    pub status_rate_limit_per_min: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval_secs: 10,
+            db_latency_threshold_ms: 500,
+            api_addr: "localhost:6688".to_string(),
+            storage_dir: "/tmp/chat_server".to_string(),
+            status_rate_limit_per_min: 30,
+        }
+    }
+}
+
+/// Faults injected by resilience tests, only built with the `test-util` feature.
+#[cfg(feature = "test-util")]
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FaultConfig {
+    /// how long each database notification is held before its events are published
+    pub event_delay_ms: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub port: u16,
     pub db_url: String,
+    /// chat_server's `server.public_id_key`, so events carry the same ids as its api
+    #[serde(default)]
+    pub public_id_key: Option<u64>,
 }
 
 impl AppConfig {
@@ -16,6 +16,15 @@ pub enum AppError {
 
     #[error("jwt error: {0}")]
     JwtError(#[from] jwt_simple::Error),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("not found: {0}")]
+    NotFound(String),
 }
 
 impl ErrorOutput {
@@ -31,6 +40,9 @@ impl IntoResponse for AppError {
         let status = match &self {
             Self::JwtError(_) => StatusCode::FORBIDDEN,
             Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
         };
 
         (status, Json(ErrorOutput::new(self.to_string()))).into_response()
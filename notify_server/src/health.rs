@@ -0,0 +1,259 @@
+use crate::{AppError, AppState, Component, ComponentStatus, StatusBoard};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{sse::Event, IntoResponse, Response, Sse},
+};
+use chat_core::User;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgPoolOptions;
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tracing::warn;
+
+const HEALTH_CHANNEL_CAPACITY: usize = 64;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Internal degradation of the server, streamed to admins.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event")]
+pub enum HealthEvent {
+    /// a subscriber couldn't keep up and missed `skipped` events
+    ChannelLag {
+        user_id: u64,
+        skipped: u64,
+    },
+    /// an event was not delivered, `user_id` is unknown if the notification couldn't be parsed
+    EventDropped {
+        user_id: Option<u64>,
+        reason: String,
+    },
+    DbLatencySpike {
+        latency_ms: u64,
+        threshold_ms: u64,
+    },
+    DbUnavailable {
+        error: String,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub events_received: AtomicU64,
+    pub events_sent: AtomicU64,
+    pub events_dropped: AtomicU64,
+    pub events_lagged: AtomicU64,
+    pub db_latency_ms: AtomicU64,
+    pub db_latency_spikes: AtomicU64,
+    pub db_errors: AtomicU64,
+}
+
+pub struct Health {
+    pub metrics: Metrics,
+    pub status: StatusBoard,
+    tx: broadcast::Sender<Arc<HealthEvent>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(HEALTH_CHANNEL_CAPACITY);
+        Self {
+            metrics: Metrics::default(),
+            status: StatusBoard::new(),
+            tx,
+        }
+    }
+
+    /// Count the event in the metrics and send it to the admins watching.
+    pub fn report(&self, event: HealthEvent) {
+        let metrics = &self.metrics;
+        match &event {
+            HealthEvent::ChannelLag { skipped, .. } => {
+                metrics.events_lagged.fetch_add(*skipped, Ordering::Relaxed);
+            }
+            HealthEvent::EventDropped { .. } => {
+                metrics.events_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            HealthEvent::DbLatencySpike { .. } => {
+                metrics.db_latency_spikes.fetch_add(1, Ordering::Relaxed);
+            }
+            HealthEvent::DbUnavailable { .. } => {
+                metrics.db_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        warn!("Health: {:?}", event);
+        // no admin watching is fine, the metrics still have it
+        let _ = self.tx.send(Arc::new(event));
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Arc<HealthEvent>> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Periodically time a trivial query and report slow or failed ones, then probe the
+/// other components for the status page.
+pub fn setup_db_probe(state: AppState) -> anyhow::Result<()> {
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect_lazy(&state.config.server.db_url)?;
+    let interval = Duration::from_secs(state.config.health.probe_interval_secs.max(1));
+    let threshold_ms = state.config.health.db_latency_threshold_ms;
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut last_lagged = 0;
+        loop {
+            ticker.tick().await;
+            let status = &state.health.status;
+            let start = Instant::now();
+            let db = match sqlx::query("SELECT 1").execute(&pool).await {
+                Ok(_) => {
+                    let latency_ms = start.elapsed().as_millis() as u64;
+                    state
+                        .health
+                        .metrics
+                        .db_latency_ms
+                        .store(latency_ms, Ordering::Relaxed);
+                    if latency_ms > threshold_ms {
+                        state.health.report(HealthEvent::DbLatencySpike {
+                            latency_ms,
+                            threshold_ms,
+                        });
+                        ComponentStatus::Degraded
+                    } else {
+                        ComponentStatus::Operational
+                    }
+                }
+                Err(e) => {
+                    state.health.report(HealthEvent::DbUnavailable {
+                        error: e.to_string(),
+                    });
+                    ComponentStatus::Down
+                }
+            };
+            status.record(Component::Database, db);
+
+            // subscribers lagging since the last probe degrade realtime delivery, drops
+            // are mostly users who disconnected so they don't count
+            let lagged = state.health.metrics.events_lagged.load(Ordering::Relaxed);
+            let realtime = if !status.listening.load(Ordering::Relaxed) {
+                ComponentStatus::Down
+            } else if lagged > last_lagged {
+                ComponentStatus::Degraded
+            } else {
+                ComponentStatus::Operational
+            };
+            last_lagged = lagged;
+            status.record(Component::Realtime, realtime);
+
+            let config = &state.config.health;
+            status.record(Component::Api, probe_api(&config.api_addr).await);
+            status.record(Component::Storage, probe_storage(&config.storage_dir).await);
+            status.prune_requests();
+        }
+    });
+
+    Ok(())
+}
+
+async fn probe_api(addr: &str) -> ComponentStatus {
+    let connect = tokio::net::TcpStream::connect(addr);
+    match tokio::time::timeout(PROBE_TIMEOUT, connect).await {
+        Ok(Ok(_)) => ComponentStatus::Operational,
+        _ => ComponentStatus::Down,
+    }
+}
+
+async fn probe_storage(dir: &str) -> ComponentStatus {
+    match tokio::fs::metadata(dir).await {
+        Ok(m) if m.is_dir() && !m.permissions().readonly() => ComponentStatus::Operational,
+        // files can still be served but not uploaded
+        Ok(m) if m.is_dir() => ComponentStatus::Degraded,
+        _ => ComponentStatus::Down,
+    }
+}
+
+/// Only allow users listed in `auth.admins`, must run after `verify_token`.
+pub(crate) async fn verify_admin(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let user = req.extensions().get::<User>().unwrap();
+    if !state.config.auth.admins.contains(&user.id) {
+        let err = AppError::PermissionDenied(format!("user {} is not an admin", user.id));
+        return err.into_response();
+    }
+
+    next.run(req).await
+}
+
+pub(crate) async fn health_events_handler(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let rx = state.health.subscribe();
+    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
+        let name = match v.as_ref() {
+            HealthEvent::ChannelLag { .. } => "ChannelLag",
+            HealthEvent::EventDropped { .. } => "EventDropped",
+            HealthEvent::DbLatencySpike { .. } => "DbLatencySpike",
+            HealthEvent::DbUnavailable { .. } => "DbUnavailable",
+        };
+        let v = serde_json::to_string(&v).expect("Failed to serialize event");
+        Ok(Event::default().data(v).event(name))
+    });
+
+    Sse::new(stream).keep_alive(
+        axum::response::sse::KeepAlive::new()
+            .interval(Duration::from_secs(1))
+            .text("keep-alive-text"),
+    )
+}
+
+/// Metrics in the prometheus text format.
+pub(crate) async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let m = &state.health.metrics;
+    let metrics = [
+        (
+            "notify_events_received_total",
+            "counter",
+            &m.events_received,
+        ),
+        ("notify_events_sent_total", "counter", &m.events_sent),
+        ("notify_events_dropped_total", "counter", &m.events_dropped),
+        ("notify_events_lagged_total", "counter", &m.events_lagged),
+        ("notify_db_latency_ms", "gauge", &m.db_latency_ms),
+        (
+            "notify_db_latency_spikes_total",
+            "counter",
+            &m.db_latency_spikes,
+        ),
+        ("notify_db_errors_total", "counter", &m.db_errors),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, value) in metrics {
+        let _ = writeln!(body, "# TYPE {name} {kind}");
+        let _ = writeln!(body, "{name} {}", value.load(Ordering::Relaxed));
+    }
+    let _ = writeln!(body, "# TYPE notify_subscribers gauge");
+    let _ = writeln!(body, "notify_subscribers {}", state.users.len());
+    ([("content-type", "text/plain; version=0.0.4")], body)
+}
@@ -1,28 +1,41 @@
 mod config;
 mod error;
+mod health;
 mod notif;
 mod sse;
+mod status;
 
 use axum::{
     middleware::from_fn_with_state,
     response::{Html, IntoResponse},
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use chat_core::{
     middlewares::{verify_token, TokenVerify},
-    DecodingKey, User,
+    DecodingKey, IdObfuscator, User,
 };
 use dashmap::DashMap;
-use sse::sse_handler;
-use std::{ops::Deref, sync::Arc};
+use health::{health_events_handler, metrics_handler, verify_admin};
+use sse::{sse_handler, update_subscriptions_handler, verify_stream_token, Connection};
+use status::status_handler;
+use std::{
+    ops::Deref,
+    sync::{atomic::AtomicU64, Arc},
+};
 use tokio::sync::broadcast;
 
 pub use config::AppConfig;
+#[cfg(feature = "test-util")]
+pub use config::FaultConfig;
 pub use error::AppError;
-pub use notif::AppEvent;
+pub use health::{Health, HealthEvent, Metrics};
+pub use notif::{
+    AppEvent, MessageHints, NotificationHints, NotificationPriority, SharedEvent, UserEvent,
+};
+pub use status::{Component, ComponentReport, ComponentStatus, StatusBoard, StatusReport};
 
-pub type UserMap = Arc<DashMap<u64, broadcast::Sender<Arc<AppEvent>>>>;
+pub type UserMap = Arc<DashMap<u64, broadcast::Sender<UserEvent>>>;
 
 #[derive(Clone)]
 pub struct AppState(Arc<AppStateInner>);
@@ -30,7 +43,11 @@ pub struct AppState(Arc<AppStateInner>);
 pub struct AppStateInner {
     pub config: AppConfig,
     users: UserMap,
+    // open event streams by connection id, with what each is subscribed to
+    connections: Arc<DashMap<u64, Connection>>,
+    next_connection_id: AtomicU64,
     dk: DecodingKey,
+    pub health: Health,
 }
 
 const INDEX_HTML: &str = include_str!("../index.html");
@@ -38,10 +55,24 @@ const INDEX_HTML: &str = include_str!("../index.html");
 pub async fn get_router(config: AppConfig) -> anyhow::Result<Router> {
     let state = AppState::new(config);
     notif::setup_pg_listener(state.clone()).await?;
+    health::setup_db_probe(state.clone())?;
+
+    let admin = Router::new()
+        .route("/health/events", get(health_events_handler))
+        .route("/metrics", get(metrics_handler))
+        .layer(from_fn_with_state(state.clone(), verify_admin));
+
     let app = Router::new()
         .route("/events", get(sse_handler))
+        .route(
+            "/events/:connection_id/subscriptions",
+            post(update_subscriptions_handler),
+        )
+        .nest("/admin", admin)
         .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+        .layer(from_fn_with_state(state.clone(), verify_stream_token))
         .route("/", get(index_handler))
+        .route("/status", get(status_handler))
         .with_state(state);
 
     Ok(app)
@@ -70,7 +101,17 @@ impl Deref for AppState {
 impl AppState {
     pub fn new(config: AppConfig) -> Self {
         let dk = DecodingKey::load(&config.auth.pk).expect("Failed to load public key");
+        if let Some(key) = config.server.public_id_key {
+            chat_core::set_id_codec(IdObfuscator::new(key));
+        }
         let users = Arc::new(DashMap::new());
-        Self(Arc::new(AppStateInner { config, dk, users }))
+        Self(Arc::new(AppStateInner {
+            config,
+            dk,
+            users,
+            connections: Arc::new(DashMap::new()),
+            next_connection_id: AtomicU64::new(1),
+            health: Health::new(),
+        }))
     }
 }
@@ -1,7 +1,12 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{atomic::Ordering, Arc, OnceLock},
+};
 
-use crate::AppState;
-use chat_core::{Chat, Message};
+use crate::{AppState, HealthEvent};
+use axum::body::Bytes;
+use chat_core::{encode_id, Chat, ChatType, Message, Task};
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
@@ -13,15 +18,171 @@ pub enum AppEvent {
     NewChat(Chat),
     AddToChat(Chat),
     UpdateChatName(Chat),
+    UpdateChatTopic(Chat),
     RemoveFromChat(Chat),
+    ChatMembersChanged(ChatMembersChanged),
+    /// sent with the hints of each member added as `hints`, see [`UserEvent`]
     NewMessage(Message),
+    TaskReminder(Task),
+    QuotaWarning(QuotaWarning),
+    UnreadCountChanged(UnreadCountChanged),
+    ChatRead(ChatUnread),
+    ChatScheduledForDeletion(ChatScheduledForDeletion),
+    ChatDeletionCanceled(Chat),
+    JoinRequest(JoinRequest),
+    DraftUpdated(DraftUpdated),
+}
+
+/// An event as sent to every connection of the users it is for. It is serialized once,
+/// by the first connection sending it, and the others send the same bytes.
+#[derive(Debug)]
+pub struct SharedEvent {
+    pub event: AppEvent,
+    data: OnceLock<Bytes>,
+}
+
+/// An event on the channel of a user. New messages carry the user's hints, which are
+/// appended to the shared payload when it is sent, so it stays the same for every member.
+#[derive(Debug, Clone)]
+pub struct UserEvent {
+    pub shared: Arc<SharedEvent>,
+    pub hints: Option<Arc<MessageHints>>,
+}
+
+/// Hints of a new message for one member, encoded once for all their connections.
+#[derive(Debug)]
+pub struct MessageHints {
+    pub hints: NotificationHints,
+    // `,"hints":{...}}`, closing the shared payload
+    data: Bytes,
+}
+
+/// A user asked to join a private channel, sent to its owner and admins. Once it is
+/// approved or denied they get it again with the decision, and so does the requester.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub id: i64,
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+    pub message: Option<String>,
+    /// pending, approved or denied
+    pub status: String,
+    #[serde(with = "chat_core::id::option")]
+    pub decided_by: Option<i64>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The member's draft in a chat was saved on one of their devices, so the others show it
+/// as it is typed. Devices skip the ones with their own `device_id` and lower `seq`s than
+/// the last one they got from a device.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DraftUpdated {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    pub content: String,
+    pub device_id: String,
+    pub seq: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The owner scheduled the chat for deletion, it is purged at `delete_at` unless the owner
+/// keeps it. Members get `RemoveFromChat` once it is purged.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatScheduledForDeletion {
+    pub chat: Chat,
+    pub delete_at: DateTime<Utc>,
+    #[serde(with = "chat_core::id")]
+    pub actor_id: i64,
+}
+
+/// Other members were added to or removed from one of the member's chats. The added and
+/// removed members get `AddToChat` and `RemoveFromChat` instead.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatMembersChanged {
+    pub chat: Chat,
+    #[serde(with = "chat_core::id::list")]
+    pub added: Vec<i64>,
+    #[serde(with = "chat_core::id::list")]
+    pub removed: Vec<i64>,
+    pub system_messages: Vec<SystemMessage>,
+}
+
+/// A line to show in the chat, rendered by clients with the template of `key` from
+/// `/api/i18n/system-messages`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SystemMessage {
+    pub key: String,
+    #[serde(with = "chat_core::id")]
+    pub actor_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub user_id: i64,
+}
+
+/// Read state of the member's chats changed on another client, e.g. after marking a folder
+/// as read. One event covers all changed chats.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnreadCountChanged {
+    pub chats: Vec<ChatUnread>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatUnread {
+    #[serde(with = "chat_core::id")]
+    pub chat_id: i64,
+    #[serde(with = "chat_core::id")]
+    pub last_read_id: i64,
+    pub unread: i64,
+}
+
+/// A workspace reached the warning threshold of a plan limit or went over it, sent to the
+/// workspace owner and admins before the limit is enforced.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuotaWarning {
+    pub ws_id: i64,
+    /// messages, storage or members
+    pub resource: String,
+    /// warning or grace
+    pub status: String,
+    pub used: i64,
+    pub limit: Option<i64>,
+    pub warning_threshold: Option<i64>,
+    pub hard_limit: Option<i64>,
+}
+
+/// How the client of a member should notify them of a new message.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotificationHints {
+    /// the message mentions the member as `<@user_id>`, by public or plain id
+    pub is_mention: bool,
+    pub is_dm: bool,
+    pub priority: NotificationPriority,
+    /// key of the sound to play, None to notify silently
+    pub suggested_sound: Option<String>,
+    /// same for all messages of the chat, so notifications of a chat can be shown
+    /// grouped as one
+    pub collapse_key: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationPriority {
+    /// the member's own messages and public channel traffic
+    Low,
+    Normal,
+    /// mentions and direct messages
+    High,
 }
 
 #[derive(Debug)]
 struct Notification {
     // users being impacted, so we should send the notification to them
     user_ids: HashSet<u64>,
-    event: Arc<AppEvent>,
+    event: AppEvent,
+    // of new messages, by user
+    hints: HashMap<u64, NotificationHints>,
 }
 
 // pg_notify('chat_updated', json_build_object('op', TG_OP, 'old', OLD, 'new', NEW)::text);
@@ -32,46 +193,254 @@ struct ChatUpdated {
     new: Option<Chat>,
 }
 
+// sent by chat_server when members are added or removed, `chat` has the new members
+#[derive(Debug, Serialize, Deserialize)]
+struct MembersChanged {
+    chat: Chat,
+    added: Vec<i64>,
+    removed: Vec<i64>,
+    #[serde(default)]
+    system_messages: Vec<SystemMessage>,
+}
+
 // pg_notify('chat_message_created', row_to_json(NEW)::text);
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatMessageCreated {
     message: Message,
     members: Vec<i64>,
+    chat_type: ChatType,
+    // notification sounds of the members who changed theirs
+    #[serde(default)]
+    sounds: HashMap<i64, String>,
+    // chat notification settings of the members who muted the chat or only want mentions
+    #[serde(default)]
+    settings: HashMap<i64, NotificationSettings>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NotificationSettings {
+    mute: bool,
+    mute_until: Option<DateTime<Utc>>,
+    mentions_only: bool,
+}
+
+// sent by the task_reminder job of chat_server when a task is due
+#[derive(Debug, Serialize, Deserialize)]
+struct TaskReminder {
+    task: Task,
+    user_ids: Vec<i64>,
+}
+
+// sent by chat_server when a member marks chats as read
+#[derive(Debug, Serialize, Deserialize)]
+struct ReadStateChanged {
+    user_id: u64,
+    #[serde(flatten)]
+    changed: UnreadCountChanged,
+}
+
+// sent by chat_server when a member's read marker of a chat moved
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatReadChanged {
+    user_id: u64,
+    read: ChatUnread,
+}
+
+// sent by chat_server when a chat is scheduled for deletion, `delete_at` is null when the
+// owner keeps it
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatDeletionChanged {
+    chat: Chat,
+    delete_at: Option<DateTime<Utc>>,
+    actor_id: i64,
+}
+
+// sent by chat_server when a join request is created or decided
+#[derive(Debug, Serialize, Deserialize)]
+struct JoinRequestChanged {
+    request: JoinRequest,
+    user_ids: Vec<i64>,
+}
+
+// sent by chat_server when a member saves a draft
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatDraftChanged {
+    user_id: u64,
+    draft: DraftUpdated,
+}
+
+// sent by chat_server's quota check when a workspace gets closer to a limit
+#[derive(Debug, Serialize, Deserialize)]
+struct QuotaWarningCreated {
+    warning: QuotaWarning,
+    user_ids: Vec<i64>,
 }
 
 pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
     let mut listener = PgListener::connect(&state.config.server.db_url).await?;
     listener.listen("chat_updated").await?;
+    listener.listen("chat_members_changed").await?;
     listener.listen("chat_message_created").await?;
+    listener.listen("task_reminder").await?;
+    listener.listen("quota_warning").await?;
+    listener.listen("unread_count_changed").await?;
+    listener.listen("chat_read").await?;
+    listener.listen("chat_deletion").await?;
+    listener.listen("chat_join_request").await?;
+    listener.listen("chat_draft_updated").await?;
 
     let mut stream = listener.into_stream();
+    state.health.status.listening.store(true, Ordering::Relaxed);
 
     tokio::spawn(async move {
         while let Some(Ok(notif)) = stream.next().await {
             info!("Received notification: {:?}", notif);
-            let notification = Notification::load(notif.channel(), notif.payload())?;
-            let users = &state.users;
-            for user_id in notification.user_ids {
-                if let Some(tx) = users.get(&user_id) {
-                    info!("Sending notification to user {}", user_id);
-                    if let Err(e) = tx.send(notification.event.clone()) {
-                        warn!(
-                            "Failed to send notification to user {}: {}, remove from users",
-                            user_id, e
-                        );
-                        users.remove(&user_id);
+            let metrics = &state.health.metrics;
+            metrics.events_received.fetch_add(1, Ordering::Relaxed);
+            #[cfg(feature = "test-util")]
+            {
+                let delay = state.config.faults.event_delay_ms;
+                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+            }
+            let notifications = match Notification::load(notif.channel(), notif.payload()) {
+                Ok(notifications) => notifications,
+                Err(e) => {
+                    state.health.report(HealthEvent::EventDropped {
+                        user_id: None,
+                        reason: format!("invalid {} notification: {}", notif.channel(), e),
+                    });
+                    continue;
+                }
+            };
+            for mut notification in notifications {
+                let users = &state.users;
+                let event = Arc::new(SharedEvent::new(notification.event));
+                for user_id in notification.user_ids {
+                    let event = UserEvent {
+                        shared: event.clone(),
+                        hints: notification
+                            .hints
+                            .remove(&user_id)
+                            .map(|hints| Arc::new(MessageHints::new(hints))),
+                    };
+                    let ret = users.get(&user_id).map(|tx| tx.send(event));
+                    match ret {
+                        Some(Ok(_)) => {
+                            info!("Sending notification to user {}", user_id);
+                            metrics.events_sent.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Some(Err(e)) => {
+                            warn!(
+                                "Failed to send notification to user {}: {}, remove from users",
+                                user_id, e
+                            );
+                            users.remove(&user_id);
+                            state.health.report(HealthEvent::EventDropped {
+                                user_id: Some(user_id),
+                                reason: "no active subscription".to_string(),
+                            });
+                        }
+                        None => {}
                     }
                 }
             }
         }
+        state
+            .health
+            .status
+            .listening
+            .store(false, Ordering::Relaxed);
         Ok::<_, anyhow::Error>(())
     });
 
     Ok(())
 }
 
+impl SharedEvent {
+    pub fn new(event: AppEvent) -> Self {
+        Self {
+            event,
+            data: OnceLock::new(),
+        }
+    }
+
+    /// Name of the SSE event.
+    pub fn name(&self) -> &'static str {
+        match self.event {
+            AppEvent::NewChat(_) => "NewChat",
+            AppEvent::AddToChat(_) => "AddToChat",
+            AppEvent::UpdateChatName(_) => "UpdateChatName",
+            AppEvent::UpdateChatTopic(_) => "UpdateChatTopic",
+            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+            AppEvent::ChatMembersChanged(_) => "ChatMembersChanged",
+            AppEvent::NewMessage(_) => "NewMessage",
+            AppEvent::TaskReminder(_) => "TaskReminder",
+            AppEvent::QuotaWarning(_) => "QuotaWarning",
+            AppEvent::UnreadCountChanged(_) => "UnreadCountChanged",
+            AppEvent::ChatRead(_) => "ChatRead",
+            AppEvent::ChatScheduledForDeletion(_) => "ChatScheduledForDeletion",
+            AppEvent::ChatDeletionCanceled(_) => "ChatDeletionCanceled",
+            AppEvent::JoinRequest(_) => "JoinRequest",
+            AppEvent::DraftUpdated(_) => "DraftUpdated",
+        }
+    }
+
+    /// The event as json.
+    pub fn data(&self) -> &Bytes {
+        self.data.get_or_init(|| {
+            let data = serde_json::to_vec(&self.event).expect("Failed to serialize event");
+            Bytes::from(data)
+        })
+    }
+}
+
+impl MessageHints {
+    pub fn new(hints: NotificationHints) -> Self {
+        let json = serde_json::to_string(&hints).expect("Failed to serialize hints");
+        Self {
+            hints,
+            data: Bytes::from(format!(r#","hints":{json}}}"#)),
+        }
+    }
+}
+
+impl UserEvent {
+    /// The event as SSE frame, in chunks sharing the payload of the other connections.
+    pub fn frame(&self) -> Vec<Bytes> {
+        let data = self.shared.data();
+        match &self.hints {
+            // in place of the closing brace of the message
+            Some(hints) => sse_frame(
+                self.shared.name(),
+                [data.slice(..data.len() - 1), hints.data.clone()],
+            ),
+            None => sse_frame(self.shared.name(), [data.clone()]),
+        }
+    }
+}
+
+/// `event: {name}\ndata: {data}\n\n`, the data must be json without raw newlines.
+pub(crate) fn sse_frame(name: &'static str, data: impl IntoIterator<Item = Bytes>) -> Vec<Bytes> {
+    let mut frame = vec![
+        Bytes::from_static(b"event: "),
+        Bytes::from_static(name.as_bytes()),
+        Bytes::from_static(b"\ndata: "),
+    ];
+    frame.extend(data);
+    frame.push(Bytes::from_static(b"\n\n"));
+    frame
+}
+
 impl Notification {
-    fn load(r#type: &str, payload: &str) -> anyhow::Result<Self> {
+    fn new(user_ids: HashSet<u64>, event: AppEvent) -> Self {
+        Self {
+            user_ids,
+            event,
+            hints: HashMap::new(),
+        }
+    }
+
+    fn load(r#type: &str, payload: &str) -> anyhow::Result<Vec<Self>> {
         match r#type {
             "chat_updated" => {
                 let payload: ChatUpdated = serde_json::from_str(payload)?;
@@ -80,43 +449,193 @@ impl Notification {
                     get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
                 let event = match payload.op.as_str() {
                     "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
+                    // only renames and topic changes are updates, members changes come
+                    // as chat_members_changed
                     "UPDATE" => {
                         let new_chat = payload.new.expect("new should exist");
                         match payload.old {
-                            Some(old_chat) if old_chat.name != new_chat.name => {
-                                AppEvent::UpdateChatName(new_chat)
+                            Some(old_chat) if old_chat.name == new_chat.name => {
+                                AppEvent::UpdateChatTopic(new_chat)
                             }
-                            _ => AppEvent::AddToChat(new_chat),
+                            _ => AppEvent::UpdateChatName(new_chat),
                         }
                     }
                     "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
                     _ => return Err(anyhow::anyhow!("Invalid operation")),
                 };
-                Ok(Self {
-                    user_ids,
-                    event: Arc::new(event),
-                })
+                Ok(vec![Self::new(user_ids, event)])
+            }
+            "chat_members_changed" => {
+                let payload: MembersChanged = serde_json::from_str(payload)?;
+                info!("MembersChanged: {:?}", payload);
+                // each member only hears about their own change, the others get the diff
+                let ids = |ids: &[i64]| ids.iter().map(|v| *v as u64).collect::<HashSet<_>>();
+                let added = ids(&payload.added);
+                let removed = ids(&payload.removed);
+                let others: HashSet<_> = ids(&payload.chat.members)
+                    .difference(&added)
+                    .copied()
+                    .collect();
+                let chat = payload.chat;
+                let notifications = [
+                    (added, AppEvent::AddToChat(chat.clone())),
+                    (removed, AppEvent::RemoveFromChat(chat.clone())),
+                    (
+                        others,
+                        AppEvent::ChatMembersChanged(ChatMembersChanged {
+                            chat,
+                            added: payload.added,
+                            removed: payload.removed,
+                            system_messages: payload.system_messages,
+                        }),
+                    ),
+                ];
+                Ok(notifications
+                    .into_iter()
+                    .filter(|(user_ids, _)| !user_ids.is_empty())
+                    .map(|(user_ids, event)| Self::new(user_ids, event))
+                    .collect())
             }
             "chat_message_created" => {
                 let payload: ChatMessageCreated = serde_json::from_str(payload)?;
-                let user_ids = payload.members.iter().map(|v| *v as u64).collect();
-                Ok(Self {
+                // one event for all members, only the hints differ
+                let now = Utc::now();
+                let hints: HashMap<_, _> = payload
+                    .members
+                    .iter()
+                    .filter_map(|user_id| {
+                        let hints = get_notification_hints(&payload, *user_id);
+                        should_notify(&payload, *user_id, &hints, now)
+                            .then_some((*user_id as u64, hints))
+                    })
+                    .collect();
+                Ok(vec![Self {
+                    user_ids: hints.keys().copied().collect(),
+                    event: AppEvent::NewMessage(payload.message),
+                    hints,
+                }])
+            }
+            "task_reminder" => {
+                let payload: TaskReminder = serde_json::from_str(payload)?;
+                let user_ids = payload.user_ids.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(
                     user_ids,
-                    event: Arc::new(AppEvent::NewMessage(payload.message)),
-                })
+                    AppEvent::TaskReminder(payload.task),
+                )])
+            }
+            "quota_warning" => {
+                let payload: QuotaWarningCreated = serde_json::from_str(payload)?;
+                let user_ids = payload.user_ids.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(
+                    user_ids,
+                    AppEvent::QuotaWarning(payload.warning),
+                )])
+            }
+            "unread_count_changed" => {
+                let payload: ReadStateChanged = serde_json::from_str(payload)?;
+                Ok(vec![Self::new(
+                    HashSet::from([payload.user_id]),
+                    AppEvent::UnreadCountChanged(payload.changed),
+                )])
+            }
+            "chat_read" => {
+                let payload: ChatReadChanged = serde_json::from_str(payload)?;
+                Ok(vec![Self::new(
+                    HashSet::from([payload.user_id]),
+                    AppEvent::ChatRead(payload.read),
+                )])
+            }
+            "chat_deletion" => {
+                let payload: ChatDeletionChanged = serde_json::from_str(payload)?;
+                let user_ids = payload.chat.members.iter().map(|v| *v as u64).collect();
+                let event = match payload.delete_at {
+                    Some(delete_at) => {
+                        AppEvent::ChatScheduledForDeletion(ChatScheduledForDeletion {
+                            chat: payload.chat,
+                            delete_at,
+                            actor_id: payload.actor_id,
+                        })
+                    }
+                    None => AppEvent::ChatDeletionCanceled(payload.chat),
+                };
+                Ok(vec![Self::new(user_ids, event)])
+            }
+            "chat_join_request" => {
+                let payload: JoinRequestChanged = serde_json::from_str(payload)?;
+                let user_ids = payload.user_ids.iter().map(|v| *v as u64).collect();
+                Ok(vec![Self::new(
+                    user_ids,
+                    AppEvent::JoinRequest(payload.request),
+                )])
+            }
+            "chat_draft_updated" => {
+                let payload: ChatDraftChanged = serde_json::from_str(payload)?;
+                Ok(vec![Self::new(
+                    HashSet::from([payload.user_id]),
+                    AppEvent::DraftUpdated(payload.draft),
+                )])
             }
             _ => Err(anyhow::anyhow!("Invalid notification type")),
         }
     }
 }
 
+fn get_notification_hints(payload: &ChatMessageCreated, user_id: i64) -> NotificationHints {
+    let message = &payload.message;
+    // clients mention by public id, older ones by the plain id
+    let is_mention = message
+        .content
+        .contains(&format!("<@{}>", encode_id(user_id)))
+        || message.content.contains(&format!("<@{user_id}>"));
+    let is_dm = payload.chat_type == ChatType::Single;
+    let priority = if message.sender_id == user_id {
+        NotificationPriority::Low
+    } else if is_mention || is_dm {
+        NotificationPriority::High
+    } else if payload.chat_type == ChatType::PublicChannel {
+        NotificationPriority::Low
+    } else {
+        NotificationPriority::Normal
+    };
+    let suggested_sound = match payload.sounds.get(&user_id).map(|s| s.as_str()) {
+        _ if priority == NotificationPriority::Low => None,
+        Some("none") => None,
+        Some(sound) => Some(sound.to_string()),
+        None => Some("default".to_string()),
+    };
+    NotificationHints {
+        is_mention,
+        is_dm,
+        priority,
+        suggested_sound,
+        collapse_key: format!("chat:{}", encode_id(message.chat_id)),
+    }
+}
+
+// members who muted the chat still get their own messages, so their other clients keep up
+fn should_notify(
+    payload: &ChatMessageCreated,
+    user_id: i64,
+    hints: &NotificationHints,
+    now: DateTime<Utc>,
+) -> bool {
+    if payload.message.sender_id == user_id {
+        return true;
+    }
+    match payload.settings.get(&user_id) {
+        Some(settings) if settings.mute && settings.mute_until.is_none_or(|t| t > now) => false,
+        Some(settings) if settings.mentions_only => hints.is_mention,
+        _ => true,
+    }
+}
+
 fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
     match (old, new) {
         (Some(old), Some(new)) => {
             // diff old/new members, if identical, no need to notify, otherwise notify the union of both
             let old_user_ids: HashSet<_> = old.members.iter().map(|v| *v as u64).collect();
             let new_user_ids: HashSet<_> = new.members.iter().map(|v| *v as u64).collect();
-            if old_user_ids == new_user_ids && old.name == new.name {
+            if old_user_ids == new_user_ids && old.name == new.name && old.topic == new.topic {
                 HashSet::new()
             } else {
                 old_user_ids.union(&new_user_ids).copied().collect()
@@ -127,3 +646,71 @@ fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet
         _ => HashSet::new(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use serde_json::{json, Value};
+
+    const SUBSCRIBERS: i64 = 1000;
+
+    fn user_events(payload: &str) -> Result<Vec<UserEvent>> {
+        let mut events = vec![];
+        for mut notification in Notification::load("chat_message_created", payload)? {
+            let shared = Arc::new(SharedEvent::new(notification.event));
+            for user_id in notification.user_ids {
+                events.push(UserEvent {
+                    shared: shared.clone(),
+                    hints: notification
+                        .hints
+                        .remove(&user_id)
+                        .map(|hints| Arc::new(MessageHints::new(hints))),
+                });
+            }
+        }
+        Ok(events)
+    }
+
+    #[test]
+    fn new_message_should_be_serialized_once_for_all_members() -> Result<()> {
+        let payload = json!({
+            "message": {
+                "id": 1,
+                "chat_id": 2,
+                "sender_id": 1,
+                "content": "hello <@2>",
+                "files": [],
+                "created_at": "2024-07-01T10:00:00Z",
+            },
+            "members": (1..=SUBSCRIBERS).collect::<Vec<_>>(),
+            "chat_type": "group",
+        });
+        let events = user_events(&payload.to_string())?;
+        assert_eq!(events.len(), SUBSCRIBERS as usize);
+
+        // every frame points into the same payload bytes, so they were serialized and
+        // allocated once
+        let frames: Vec<_> = events.iter().map(|e| e.frame()).collect();
+        let payloads: HashSet<_> = frames.iter().map(|f| f[3].as_ptr()).collect();
+        assert_eq!(payloads.len(), 1);
+
+        for (event, frame) in events.iter().zip(&frames) {
+            let text: Vec<u8> = frame.iter().flat_map(|c| c.iter().copied()).collect();
+            let text = String::from_utf8(text)?;
+            let data = text
+                .strip_prefix("event: NewMessage\ndata: ")
+                .and_then(|t| t.strip_suffix("\n\n"))
+                .expect("frame should be an sse event");
+            let data: Value = serde_json::from_str(data)?;
+            assert_eq!(data["content"], "hello <@2>");
+            let hints = &event.hints.as_ref().unwrap().hints;
+            assert_eq!(data["hints"], serde_json::to_value(hints)?);
+        }
+        let mentioned = events
+            .iter()
+            .filter(|e| e.hints.as_ref().unwrap().hints.is_mention);
+        assert_eq!(mentioned.count(), 1);
+        Ok(())
+    }
+}
@@ -1,19 +1,90 @@
-use crate::{AppEvent, AppState};
+use crate::{notif::sse_frame, AppError, AppEvent, AppState, HealthEvent, UserEvent};
 use axum::{
-    extract::State,
-    response::{sse::Event, Sse},
-    Extension,
+    body::{Body, Bytes},
+    extract::{Path, Query, Request, State},
+    http::header::{CACHE_CONTROL, CONTENT_TYPE, ORIGIN},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
 };
 use chat_core::User;
+use chrono::Utc;
+use dashmap::DashMap;
 use futures::Stream;
-use std::{convert::Infallible, time::Duration};
-use tokio::sync::broadcast;
-use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::broadcast, time::Instant};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    StreamExt,
+};
 use tracing::info;
 
 const CHANNEL_CAPACITY: usize = 256;
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Chats a connection gets new messages of, it starts with all of them. Mentions of the
+/// user and chat changes are always delivered.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Subscriptions {
+    /// None for all chats
+    #[serde(default, with = "id_set::option")]
+    pub chat_ids: Option<HashSet<i64>>,
+    /// chats left out while the connection gets all chats
+    #[serde(default, with = "id_set")]
+    pub excluded_chat_ids: HashSet<i64>,
+}
+
+/// Changes sent by a client to narrow or widen what its connection receives, e.g. to only
+/// the visible chat while a mobile app is in the foreground.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SubscriptionControl {
+    /// add chats, the first subscribe narrows the connection from all chats to these,
+    /// unless chats were unsubscribed from all chats, which are then included again
+    Subscribe {
+        #[serde(with = "chat_core::id::list")]
+        chat_ids: Vec<i64>,
+    },
+    /// leave chats out, from all chats as well
+    Unsubscribe {
+        #[serde(with = "chat_core::id::list")]
+        chat_ids: Vec<i64>,
+    },
+    /// back to all chats
+    Reset,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamParams {
+    stream_token: Option<String>,
+    access_token: Option<String>,
+}
+
+pub(crate) struct Connection {
+    user_id: u64,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+}
+
+// removes the connection once axum drops the stream of a closed response
+struct ConnectionGuard {
+    id: u64,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    connections: Arc<DashMap<u64, Connection>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.remove(&self.id);
+    }
+}
 
-/// not working to detect the channel closed.
+// not working to detect the channel closed.
 // struct Guard {
 //     user_id: u64,
 //     user_map: UserMap,
@@ -31,10 +102,46 @@ const CHANNEL_CAPACITY: usize = 256;
 //     }
 // }
 
+/// Authenticates streams opened with a stream token from chat_server's
+/// `POST /api/events/token`, it has to be used from the origin it was issued to. Session
+/// tokens are long-lived and only accepted in the Authorization header, not in urls.
+pub(crate) async fn verify_stream_token(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let params = Query::<StreamParams>::try_from_uri(req.uri())
+        .map(|q| q.0)
+        .unwrap_or_default();
+    if params.access_token.is_some() {
+        let err = AppError::PermissionDenied(
+            "session tokens are not accepted in urls, use a stream token".to_string(),
+        );
+        return err.into_response();
+    }
+    // stream tokens open streams and nothing else
+    if let Some(token) = params
+        .stream_token
+        .filter(|_| req.uri().path() == "/events")
+    {
+        let origin = req.headers().get(ORIGIN).and_then(|v| v.to_str().ok());
+        match state.dk.verify_stream_token(&token, origin) {
+            Ok(user) => {
+                req.extensions_mut().insert(user);
+            }
+            Err(e) => return AppError::from(e).into_response(),
+        }
+    }
+
+    next.run(req).await
+}
+
+/// The event stream of the caller. Frames are written as chunks of the shared payloads
+/// instead of through `axum::response::Sse`, which would copy them into every connection.
 pub(crate) async fn sse_handler(
     Extension(user): Extension<User>,
     State(state): State<AppState>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+) -> impl IntoResponse {
     let user_id = user.id as u64;
     let users = &state.users;
 
@@ -49,21 +156,278 @@ pub(crate) async fn sse_handler(
     };
     info!("User {} subscribed", user_id);
 
-    let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
-        let name = match v.as_ref() {
-            AppEvent::NewChat(_) => "NewChat",
-            AppEvent::AddToChat(_) => "AddToChat",
-            AppEvent::UpdateChatName(_) => "UpdateChatName",
-            AppEvent::RemoveFromChat(_) => "RemoveFromChat",
-            AppEvent::NewMessage(_) => "NewMessage",
-        };
-        let v = serde_json::to_string(&v).expect("Failed to serialize event");
-        Ok(Event::default().data(v).event(name))
+    // chats the user was removed from while connected, events of them still in the
+    // channel are not delivered anymore
+    let mut removed_chats = HashSet::new();
+
+    let connection_id = state.next_connection_id.fetch_add(1, Ordering::Relaxed);
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
+    state.connections.insert(
+        connection_id,
+        Connection {
+            user_id,
+            subscriptions: subscriptions.clone(),
+        },
+    );
+    let guard = ConnectionGuard {
+        id: connection_id,
+        subscriptions,
+        connections: state.connections.clone(),
+    };
+    // the client needs the id to send subscription changes
+    let connected = sse_frame(
+        "Connected",
+        [Bytes::from(
+            serde_json::json!({ "connection_id": connection_id, "server_time": Utc::now() })
+                .to_string(),
+        )],
+    );
+
+    let stream = BroadcastStream::new(rx)
+        .filter_map(move |v| match v {
+            Ok(v) => Some(v),
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                state
+                    .health
+                    .report(HealthEvent::ChannelLag { user_id, skipped });
+                None
+            }
+        })
+        .filter(move |v| {
+            let allowed = allow_event(&v.shared.event, user.id, &mut removed_chats);
+            if !allowed {
+                info!("Dropping event of a chat user {} was removed from", user_id);
+                return false;
+            }
+            let subscriptions = guard.subscriptions.lock().expect("subscriptions poisoned");
+            subscriptions.allow_event(v)
+        })
+        .map(|v| v.frame());
+    let frames = tokio_stream::once(connected)
+        .chain(stream)
+        .merge(keep_alives());
+    let chunks = futures::StreamExt::flat_map(frames, |frame| {
+        futures::stream::iter(frame.into_iter().map(Ok::<_, Infallible>))
     });
 
-    Sse::new(stream).keep_alive(
-        axum::response::sse::KeepAlive::new()
-            .interval(Duration::from_secs(1))
-            .text("keep-alive-text"),
+    (
+        [
+            (CONTENT_TYPE, "text/event-stream"),
+            (CACHE_CONTROL, "no-cache"),
+        ],
+        Body::from_stream(chunks),
     )
 }
+
+// keep-alives carry the server clock, clients correct the relative timestamps they render
+// for the skew of the device clock with it
+fn keep_alives() -> impl Stream<Item = Vec<Bytes>> {
+    let ticker =
+        tokio::time::interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+    futures::stream::unfold(ticker, |mut ticker| async move {
+        ticker.tick().await;
+        let data = serde_json::json!({ "server_time": Utc::now() }).to_string();
+        Some((sse_frame("KeepAlive", [Bytes::from(data)]), ticker))
+    })
+}
+
+// update the removed chats of a connection and check if the event can be sent
+fn allow_event(event: &AppEvent, user_id: i64, removed_chats: &mut HashSet<i64>) -> bool {
+    match event {
+        AppEvent::RemoveFromChat(chat) => {
+            // a deleted chat still lists the members it had
+            if !chat.members.contains(&user_id) {
+                removed_chats.insert(chat.id);
+            }
+            true
+        }
+        AppEvent::NewChat(chat)
+        | AppEvent::AddToChat(chat)
+        | AppEvent::UpdateChatName(chat)
+        | AppEvent::UpdateChatTopic(chat) => {
+            if chat.members.contains(&user_id) {
+                removed_chats.remove(&chat.id);
+                true
+            } else {
+                !removed_chats.contains(&chat.id)
+            }
+        }
+        AppEvent::ChatMembersChanged(changed) => !removed_chats.contains(&changed.chat.id),
+        AppEvent::NewMessage(message) => !removed_chats.contains(&message.chat_id),
+        AppEvent::TaskReminder(task) => !removed_chats.contains(&task.chat_id),
+        AppEvent::ChatRead(read) => !removed_chats.contains(&read.chat_id),
+        AppEvent::DraftUpdated(draft) => !removed_chats.contains(&draft.chat_id),
+        AppEvent::ChatScheduledForDeletion(scheduled) => {
+            !removed_chats.contains(&scheduled.chat.id)
+        }
+        AppEvent::ChatDeletionCanceled(chat) => !removed_chats.contains(&chat.id),
+        // requesters aren't members of the chat yet
+        AppEvent::QuotaWarning(_) | AppEvent::UnreadCountChanged(_) | AppEvent::JoinRequest(_) => {
+            true
+        }
+    }
+}
+
+/// Apply a subscription change to one of the caller's connections.
+pub(crate) async fn update_subscriptions_handler(
+    Extension(user): Extension<User>,
+    State(state): State<AppState>,
+    Path(connection_id): Path<u64>,
+    Json(input): Json<SubscriptionControl>,
+) -> Result<impl IntoResponse, AppError> {
+    let subscriptions = match state.connections.get(&connection_id) {
+        Some(c) if c.user_id == user.id as u64 => c.subscriptions.clone(),
+        _ => return Err(AppError::NotFound(format!("connection id {connection_id}"))),
+    };
+    let mut subscriptions = subscriptions.lock().expect("subscriptions poisoned");
+    subscriptions.apply(input);
+    Ok(Json(Subscriptions {
+        chat_ids: subscriptions.chat_ids.clone(),
+        excluded_chat_ids: subscriptions.excluded_chat_ids.clone(),
+    }))
+}
+
+// sets of chat ids as sorted lists of public ids
+mod id_set {
+    use chat_core::id::{list, option_list};
+    use serde::{Deserializer, Serializer};
+    use std::collections::HashSet;
+
+    pub fn serialize<S: Serializer>(ids: &HashSet<i64>, serializer: S) -> Result<S::Ok, S::Error> {
+        list::serialize(&sorted(ids), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashSet<i64>, D::Error> {
+        Ok(list::deserialize(deserializer)?.into_iter().collect())
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            ids: &Option<HashSet<i64>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            option_list::serialize(&ids.as_ref().map(sorted), serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<HashSet<i64>>, D::Error> {
+            let ids = option_list::deserialize(deserializer)?;
+            Ok(ids.map(|ids| ids.into_iter().collect()))
+        }
+    }
+
+    fn sorted(ids: &HashSet<i64>) -> Vec<i64> {
+        let mut ids: Vec<i64> = ids.iter().copied().collect();
+        ids.sort();
+        ids
+    }
+}
+
+impl Subscriptions {
+    fn apply(&mut self, control: SubscriptionControl) {
+        match control {
+            SubscriptionControl::Subscribe { chat_ids } if self.chat_ids.is_none() => {
+                if self.excluded_chat_ids.is_empty() {
+                    self.chat_ids = Some(chat_ids.into_iter().collect());
+                } else {
+                    for id in chat_ids {
+                        self.excluded_chat_ids.remove(&id);
+                    }
+                }
+            }
+            SubscriptionControl::Subscribe { chat_ids } => self
+                .chat_ids
+                .get_or_insert_with(HashSet::new)
+                .extend(chat_ids),
+            SubscriptionControl::Unsubscribe { chat_ids } => match self.chat_ids.as_mut() {
+                Some(subscribed) => {
+                    for id in chat_ids {
+                        subscribed.remove(&id);
+                    }
+                }
+                None => self.excluded_chat_ids.extend(chat_ids),
+            },
+            SubscriptionControl::Reset => *self = Self::default(),
+        }
+    }
+
+    fn allow_event(&self, event: &UserEvent) -> bool {
+        let AppEvent::NewMessage(message) = &event.shared.event else {
+            return true;
+        };
+        let is_mention = event.hints.as_ref().is_some_and(|h| h.hints.is_mention);
+        let subscribed = match &self.chat_ids {
+            Some(chat_ids) => chat_ids.contains(&message.chat_id),
+            None => !self.excluded_chat_ids.contains(&message.chat_id),
+        };
+        is_mention || subscribed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SharedEvent;
+    use chat_core::Message;
+
+    fn new_message(chat_id: i64) -> UserEvent {
+        let message = Message {
+            id: 1,
+            chat_id,
+            sender_id: 1,
+            content: "hello".to_string(),
+            files: vec![],
+            created_at: chrono::Utc::now(),
+            translation: None,
+        };
+        UserEvent {
+            shared: Arc::new(SharedEvent::new(AppEvent::NewMessage(message))),
+            hints: None,
+        }
+    }
+
+    #[test]
+    fn unsubscribe_should_leave_chats_out_of_all_chats() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.apply(SubscriptionControl::Unsubscribe { chat_ids: vec![2] });
+        assert!(subscriptions.chat_ids.is_none());
+        assert!(!subscriptions.allow_event(&new_message(2)));
+        assert!(subscriptions.allow_event(&new_message(1)));
+
+        // subscribing again includes the chat, without narrowing to it
+        subscriptions.apply(SubscriptionControl::Subscribe { chat_ids: vec![2] });
+        assert_eq!(subscriptions, Subscriptions::default());
+        assert!(subscriptions.allow_event(&new_message(2)));
+
+        subscriptions.apply(SubscriptionControl::Subscribe { chat_ids: vec![1] });
+        assert!(!subscriptions.allow_event(&new_message(2)));
+        subscriptions.apply(SubscriptionControl::Unsubscribe { chat_ids: vec![1] });
+        assert!(!subscriptions.allow_event(&new_message(1)));
+
+        subscriptions.apply(SubscriptionControl::Unsubscribe { chat_ids: vec![3] });
+        subscriptions.apply(SubscriptionControl::Reset);
+        assert_eq!(subscriptions, Subscriptions::default());
+    }
+
+    #[test]
+    fn subscriptions_should_round_trip_as_sorted_lists() -> anyhow::Result<()> {
+        let subscriptions = Subscriptions {
+            chat_ids: Some(HashSet::from([3, 1, 2])),
+            excluded_chat_ids: HashSet::new(),
+        };
+        let json = serde_json::to_value(&subscriptions)?;
+        assert_eq!(json["chat_ids"], serde_json::json!([1, 2, 3]));
+        assert_eq!(
+            serde_json::from_value::<Subscriptions>(json)?,
+            subscriptions
+        );
+        let all: Subscriptions = serde_json::from_str("{}")?;
+        assert_eq!(all, Subscriptions::default());
+        Ok(())
+    }
+}
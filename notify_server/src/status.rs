@@ -0,0 +1,181 @@
+use crate::{AppError, AppState};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentStatus {
+    Operational,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    Api,
+    Database,
+    Realtime,
+    Storage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentReport {
+    pub name: Component,
+    pub status: ComponentStatus,
+    /// share of the checks since start where the component was up, degraded counts as up
+    pub uptime_percent: f64,
+    pub checked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    /// the worst status of the components
+    pub status: ComponentStatus,
+    pub started_at: DateTime<Utc>,
+    pub components: Vec<ComponentReport>,
+}
+
+#[derive(Debug, Default)]
+struct ComponentState {
+    checks: AtomicU64,
+    up: AtomicU64,
+    last: Mutex<Option<(ComponentStatus, DateTime<Utc>)>>,
+}
+
+/// Results of the periodic probes, summarized on the public status page.
+#[derive(Debug)]
+pub struct StatusBoard {
+    started_at: DateTime<Utc>,
+    components: [(Component, ComponentState); 4],
+    /// set while the postgres listener delivers events
+    pub listening: AtomicBool,
+    requests: DashMap<IpAddr, (Instant, u32)>,
+}
+
+impl StatusBoard {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            components: [
+                (Component::Api, ComponentState::default()),
+                (Component::Database, ComponentState::default()),
+                (Component::Realtime, ComponentState::default()),
+                (Component::Storage, ComponentState::default()),
+            ],
+            listening: AtomicBool::new(false),
+            requests: DashMap::new(),
+        }
+    }
+
+    pub fn record(&self, component: Component, status: ComponentStatus) {
+        let Some((_, state)) = self.components.iter().find(|(c, _)| *c == component) else {
+            return;
+        };
+        state.checks.fetch_add(1, Ordering::Relaxed);
+        if status != ComponentStatus::Down {
+            state.up.fetch_add(1, Ordering::Relaxed);
+        }
+        *state.last.lock().unwrap() = Some((status, Utc::now()));
+    }
+
+    pub fn report(&self) -> StatusReport {
+        let components: Vec<_> = self
+            .components
+            .iter()
+            .map(|(name, state)| {
+                let checks = state.checks.load(Ordering::Relaxed);
+                let up = state.up.load(Ordering::Relaxed);
+                let last = *state.last.lock().unwrap();
+                let uptime_percent = if checks == 0 {
+                    100.0
+                } else {
+                    (up as f64 * 10000.0 / checks as f64).round() / 100.0
+                };
+                ComponentReport {
+                    name: *name,
+                    // not probed yet, assume it is fine rather than alarming visitors
+                    status: last.map_or(ComponentStatus::Operational, |(s, _)| s),
+                    uptime_percent,
+                    checked_at: last.map(|(_, t)| t),
+                }
+            })
+            .collect();
+        let status = components
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(ComponentStatus::Operational);
+
+        StatusReport {
+            status,
+            started_at: self.started_at,
+            components,
+        }
+    }
+
+    /// Count a request from `ip`, failing once it made more than `limit` in the window.
+    fn check_rate(&self, ip: IpAddr, limit: u32) -> Result<(), AppError> {
+        let now = Instant::now();
+        let mut entry = self.requests.entry(ip).or_insert((now, 0));
+        let (start, count) = entry.value_mut();
+        if now.duration_since(*start) >= RATE_LIMIT_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        if *count > limit {
+            return Err(AppError::TooManyRequests(format!(
+                "at most {limit} status requests per minute"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Forget clients whose window has passed.
+    pub fn prune_requests(&self) {
+        self.requests
+            .retain(|_, (start, _)| start.elapsed() < RATE_LIMIT_WINDOW);
+    }
+}
+
+impl Default for StatusBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Public summary of the components' health, rate limited per client ip.
+pub(crate) async fn status_handler(
+    State(state): State<AppState>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+) -> Result<impl IntoResponse, AppError> {
+    // without connect info all clients share a single budget
+    let ip = addr.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |c| c.0.ip());
+    let board = &state.health.status;
+    board.check_rate(ip, state.config.health.status_rate_limit_per_min)?;
+
+    let max_age = state.config.health.probe_interval_secs.max(1);
+    let headers = [
+        (header::ACCESS_CONTROL_ALLOW_ORIGIN, "*".to_string()),
+        (header::CACHE_CONTROL, format!("public, max-age={max_age}")),
+    ];
+    Ok((headers, Json(board.report())))
+}